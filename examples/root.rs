@@ -3,17 +3,22 @@ use std::process::Command;
 use tiffin::Container;
 
 fn main() {
-    let mut container = Container::new("chroot".into()); // you can even add the system's rootfs to the container
+    let mut container = Container::builder()
+        .root("chroot")
+        // you can even add the system's rootfs to the container
+        .bind("/", "/run/host")
+        .build()
+        .unwrap();
 
-    container.host_bind_mount();
     container.mount().unwrap();
 
     // or just do
-    // Container::new("chroot".into())
-    //    .host_bind_mount()
+    // Container::builder()
+    //    .root("chroot")
+    //    .build()?
     //    .run(|| {
     //       // your code to execute inside chroot here
-    // }).unwrap();
+    // })?;
 
     Command::new("/bin/findmnt")
         .arg("-l")
@@ -0,0 +1,196 @@
+use std::{ffi::OsString, path::PathBuf, time::Duration};
+
+/// Errors returned by tiffin
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(std::io::Error),
+
+    /// The requested user does not exist in the container's user database
+    #[error("user {user:?} not found in {}", file.display())]
+    UserNotFound { user: String, file: PathBuf },
+
+    /// The syscall name is not known on the target architecture
+    #[error("unknown syscall {0:?}")]
+    UnknownSyscall(String),
+
+    /// The container root can't be used
+    #[error("invalid container root {}: {reason}", root.display())]
+    InvalidRoot { root: PathBuf, reason: String },
+
+    /// A path inside the container, such as a mount target or an entry of a rootfs archive,
+    /// would end up outside the container root, because it contains `..`
+    #[error("{} escapes the container root", target.display())]
+    PathEscape { target: PathBuf },
+
+    /// Mounts of the mount table can't be mounted, see [`crate::MountTable::validate`]
+    #[cfg(target_os = "linux")]
+    #[error("invalid mount table:{}", .0.iter().map(|d| format!("\n  {d}")).collect::<String>())]
+    InvalidMounts(Vec<crate::MountDiagnostic>),
+
+    /// A mount option would break the comma-separated data string passed to the kernel
+    #[error("invalid mount option {option:?}: {reason}")]
+    InvalidMountOption {
+        option: String,
+        reason: &'static str,
+    },
+
+    /// Nothing from the mount table is mounted at the target
+    #[error("{} is not mounted", target.display())]
+    NotMounted { target: PathBuf },
+
+    /// A mount of the table is already active, see [`crate::MountTable::set_strict_mounts`]
+    #[cfg(target_os = "linux")]
+    #[error("{} is already mounted", target.display())]
+    AlreadyMounted { target: PathBuf },
+
+    /// Another mount is nested under a mount being unmounted by tag,
+    /// see [`crate::MountTable::umount_tagged`]
+    #[cfg(target_os = "linux")]
+    #[error("{} is mounted under {}, which is tagged {tag:?}", child.display(), parent.display())]
+    NestedMount {
+        tag: String,
+        parent: PathBuf,
+        child: PathBuf,
+    },
+
+    /// The mount table can't be replaced while it's mounted,
+    /// see [`crate::MountTable::restore`]
+    #[cfg(target_os = "linux")]
+    #[error("the mount table is still mounted at {}", mounts.iter().map(|m| m.display().to_string()).collect::<Vec<_>>().join(", "))]
+    StillMounted { mounts: Vec<PathBuf> },
+
+    /// A container id can't be used, see [`crate::Container::with_id`]
+    #[cfg(target_os = "linux")]
+    #[error(
+        "invalid container id {id:?}: only ASCII letters, digits, '-', '_' and '.' are allowed"
+    )]
+    InvalidId { id: String },
+
+    /// No device has the identifier of a mount source, see [`crate::MountSource`]
+    #[cfg(target_os = "linux")]
+    #[error("no device found for {device} in /dev/disk")]
+    DeviceNotFound { device: String },
+
+    /// The source of a mount didn't appear in time, so it wasn't mounted,
+    /// see [`crate::MountTarget::wait_for_source`]
+    #[cfg(target_os = "linux")]
+    #[error("mount source {} didn't appear within {timeout:?}", path.display())]
+    SourceTimeout { path: PathBuf, timeout: Duration },
+
+    /// The kernel refused to mount a procfs with options, see [`crate::ProcMountOptions`]
+    #[cfg(target_os = "linux")]
+    #[error("failed to mount procfs with {options}: {reason}")]
+    ProcMount { options: String, reason: String },
+
+    /// The root is `/` or contains critical host paths,
+    /// see [`crate::Container::try_new`] and [`crate::cleanup_stale`]
+    #[cfg(target_os = "linux")]
+    #[error("refusing to use {} as a container root, as it contains critical host paths", root.display())]
+    DangerousRoot { root: PathBuf },
+
+    /// Mounts merged into a mount table conflict with mounts already in it,
+    /// see [`crate::MergeStrategy::Error`]
+    #[cfg(target_os = "linux")]
+    #[error("conflicting mounts:{}", .0.iter().map(|c| format!("\n  {c}")).collect::<String>())]
+    MountConflicts(Vec<crate::MergeConflict>),
+
+    /// An SELinux context is not of the form `user:role:type:level`
+    #[error("invalid SELinux context {context:?}: {reason}")]
+    InvalidSelinuxContext {
+        context: String,
+        reason: &'static str,
+    },
+
+    /// A directory can't be deleted, because filesystems are still mounted under it
+    #[error("refusing to delete {}, which still has mounts: {}", root.display(), mounts.iter().map(|m| m.display().to_string()).collect::<Vec<_>>().join(", "))]
+    MountsRemain { root: PathBuf, mounts: Vec<PathBuf> },
+
+    /// A shared library needed by a program can't be found on the host,
+    /// see [`crate::rootfs::from_host_binaries`]
+    #[error("library {library:?} needed by {} not found", needed_by.display())]
+    MissingLibrary { library: String, needed_by: PathBuf },
+
+    /// The container can't exit its chroot, because another container was chrooted
+    /// inside it and hasn't exited yet
+    #[error("a container nested in {} is still chrooted", root.display())]
+    NestedChroot { root: PathBuf },
+
+    /// A lifecycle hook tried to mount, unmount, enter or exit the container running it,
+    /// see [`crate::Container::on`]
+    #[cfg(target_os = "linux")]
+    #[error("a {phase:?} hook can't mount, unmount, enter or exit its container")]
+    ReentrantHook { phase: crate::Phase },
+
+    /// Containers can't be used on this platform, see [`crate::Container`]
+    #[error("containers are not supported on {}", std::env::consts::OS)]
+    UnsupportedPlatform,
+
+    /// The requested feature is not supported on this host
+    #[error("unsupported: {0}")]
+    Unsupported(String),
+
+    /// The program could not be started inside the container,
+    /// either because entering the container or exec failed
+    #[error("failed to spawn {program:?} in the container: {source}")]
+    Spawn {
+        program: OsString,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// The program ran longer than its timeout and was terminated
+    ///
+    /// For programs whose output was captured, `output` holds what was read until then.
+    #[cfg(target_os = "linux")]
+    #[error("timed out after {timeout:?}")]
+    TimedOut {
+        timeout: Duration,
+        output: Option<Box<crate::Output>>,
+    },
+
+    /// A persisted mount record could not be read or written
+    #[error("invalid mount record: {0}")]
+    Json(#[from] serde_json::Error),
+
+    /// The soft limit of a resource is above its hard limit
+    #[cfg(target_os = "linux")]
+    #[error("soft limit {soft} is above hard limit {hard} for {resource:?}")]
+    InvalidRlimit {
+        resource: crate::Resource,
+        soft: crate::Limit,
+        hard: crate::Limit,
+    },
+}
+
+impl From<std::io::Error> for Error {
+    /// Unwraps errors of tiffin carried by I/O errors, such as [`Error::PathEscape`]
+    /// returned from [`crate::MountTarget::mount`]
+    fn from(err: std::io::Error) -> Self {
+        if err.get_ref().is_some_and(|inner| inner.is::<Self>()) {
+            let inner = err.into_inner().and_then(|inner| inner.downcast().ok());
+            return *inner.expect("checked above");
+        }
+        Self::Io(err)
+    }
+}
+
+/// The other way around, so errors of tiffin can be returned by functions returning
+/// I/O errors, and unwrapped again by the conversion above
+impl From<Error> for std::io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(err) => err,
+            err => Self::other(err),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl From<nix::Error> for Error {
+    fn from(err: nix::Error) -> Self {
+        Self::Io(err.into())
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -0,0 +1,716 @@
+//! Stand-ins for the container types on platforms other than Linux
+//!
+//! Containers can be created and configured as usual, so code using tiffin compiles
+//! everywhere, but whatever would mount, chroot or run code fails with
+//! [`Error::UnsupportedPlatform`]. The configuration methods of the Linux API are
+//! mirrored with the same signatures, the Linux-only subsystems such as cgroups,
+//! seccomp or the async API are not.
+
+// on Linux, only built for the tests
+#![cfg_attr(target_os = "linux", allow(dead_code))]
+
+use crate::{Error, Result};
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fmt,
+    ops::{BitAnd, BitOr, BitOrAssign, Deref, DerefMut, Not, Sub},
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
+
+fn unsupported() -> std::io::Error {
+    Error::UnsupportedPlatform.into()
+}
+
+/// Stand-in for the mount flags of `sys_mount`, with the same names and bits as on Linux
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MountFlags(u64);
+
+impl MountFlags {
+    pub const RDONLY: Self = Self(1);
+    pub const NOSUID: Self = Self(1 << 1);
+    pub const NODEV: Self = Self(1 << 2);
+    pub const NOEXEC: Self = Self(1 << 3);
+    pub const SYNCHRONOUS: Self = Self(1 << 4);
+    pub const REMOUNT: Self = Self(1 << 5);
+    pub const MANDLOCK: Self = Self(1 << 6);
+    pub const DIRSYNC: Self = Self(1 << 7);
+    pub const NOATIME: Self = Self(1 << 10);
+    pub const NODIRATIME: Self = Self(1 << 11);
+    pub const BIND: Self = Self(1 << 12);
+    pub const MOVE: Self = Self(1 << 13);
+    pub const REC: Self = Self(1 << 14);
+    pub const SILENT: Self = Self(1 << 15);
+    pub const UNBINDABLE: Self = Self(1 << 17);
+    pub const PRIVATE: Self = Self(1 << 18);
+    pub const SLAVE: Self = Self(1 << 19);
+    pub const SHARED: Self = Self(1 << 20);
+    pub const RELATIME: Self = Self(1 << 21);
+    pub const STRICTATIME: Self = Self(1 << 24);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn bits(&self) -> u64 {
+        self.0
+    }
+
+    pub const fn from_bits_truncate(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn intersects(&self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+}
+
+impl BitOr for MountFlags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for MountFlags {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+impl BitAnd for MountFlags {
+    type Output = Self;
+
+    fn bitand(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl Sub for MountFlags {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+
+impl Not for MountFlags {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self(!self.0)
+    }
+}
+
+/// Mount object struct
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MountTarget {
+    pub target: PathBuf,
+    pub fstype: Option<String>,
+    pub flags: MountFlags,
+    /// Data passed to the kernel as is
+    pub data: Option<String>,
+    /// Label to mount and unmount a subset of the table,
+    /// see [`MountTable::mount_tagged`] and [`MountTable::umount_tagged`]
+    pub tag: Option<String>,
+    /// How long to wait for the source to exist before mounting
+    pub wait_for_source: Option<Duration>,
+}
+
+impl MountTarget {
+    /// Create a new mount object
+    pub fn new(
+        target: impl Into<PathBuf>,
+        fstype: Option<String>,
+        flags: MountFlags,
+        data: Option<String>,
+    ) -> Self {
+        Self {
+            target: target.into(),
+            fstype,
+            flags,
+            data,
+            ..Self::default()
+        }
+    }
+
+    /// Set the tag of the mount, see [`MountTarget::tag`]
+    pub fn tagged(mut self, tag: impl Into<String>) -> Self {
+        self.tag = Some(tag.into());
+        self
+    }
+}
+
+/// Mount Table Struct
+/// This is used to mount filesystems inside the container. It is essentially an fstab, for the container.
+#[derive(Debug, Default)]
+pub struct MountTable {
+    inner: HashMap<PathBuf, MountTarget>,
+}
+
+impl MountTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a mount to the table
+    pub fn add_mount(&mut self, mount: MountTarget, source: impl Into<PathBuf>) {
+        self.inner.insert(source.into(), mount);
+    }
+
+    /// Whether the table has no mounts configured
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Does nothing, as nothing can be mounted
+    pub fn set_strict_mounts(&mut self, _strict: bool) {}
+
+    /// Does nothing, as nothing can be mounted
+    pub fn set_device_timeout(&mut self, _timeout: Duration) {}
+
+    /// Does nothing, as nothing can be mounted
+    pub fn set_wait_for_source(&mut self, _timeout: Option<Duration>) {}
+
+    /// Always empty, as nothing can be mounted
+    pub fn active_targets(&self) -> Vec<PathBuf> {
+        Vec::new()
+    }
+
+    /// Fails with [`Error::UnsupportedPlatform`]
+    pub fn mount_chroot(&mut self, _root: &Path) -> std::io::Result<()> {
+        Err(unsupported())
+    }
+
+    /// Fails with [`Error::UnsupportedPlatform`]
+    pub fn mount_tagged(&mut self, _root: &Path, _tag: &str) -> std::io::Result<()> {
+        Err(unsupported())
+    }
+
+    /// Fails with [`Error::UnsupportedPlatform`]
+    pub fn remount(
+        &mut self,
+        _target: &Path,
+        _new_flags: MountFlags,
+        _new_data: Option<&str>,
+    ) -> std::io::Result<()> {
+        Err(unsupported())
+    }
+
+    /// Fails with [`Error::UnsupportedPlatform`]
+    pub fn umount_chroot(&mut self) -> std::io::Result<()> {
+        Err(unsupported())
+    }
+
+    /// Fails with [`Error::UnsupportedPlatform`]
+    pub fn umount_tagged(&mut self, _tag: &str, _cascade: bool) -> std::io::Result<()> {
+        Err(unsupported())
+    }
+}
+
+/// Container Struct
+/// A tiffin container is a simple chroot jail that can be used to run code inside.
+///
+/// Only a stand-in on this platform, see the [crate documentation](crate).
+#[derive(Debug)]
+pub struct Container {
+    pub root: PathBuf,
+    pub mount_table: MountTable,
+}
+
+impl Container {
+    /// Start building a container, see [`ContainerBuilder`]
+    pub fn builder() -> ContainerBuilder {
+        ContainerBuilder::new()
+    }
+
+    /// Create a new tiffin container
+    pub fn new(chrootpath: impl Into<PathBuf>) -> Self {
+        let mut container = Self::new_bare(chrootpath);
+        container.add_default_mounts();
+        container
+    }
+
+    /// Like [`Container::new`], there are no host paths to protect on this platform
+    pub fn try_new(chrootpath: impl Into<PathBuf>) -> Result<Self> {
+        Ok(Self::new(chrootpath))
+    }
+
+    /// Create a new tiffin container with nothing in its mount table
+    pub fn new_bare(chrootpath: impl Into<PathBuf>) -> Self {
+        Self {
+            root: chrootpath.into(),
+            mount_table: MountTable::new(),
+        }
+    }
+
+    /// Fails with [`Error::UnsupportedPlatform`]
+    pub fn mount(&mut self) -> std::io::Result<()> {
+        self.mount_table.mount_chroot(&self.root)
+    }
+
+    /// Fails with [`Error::UnsupportedPlatform`]
+    pub fn umount(&mut self) -> std::io::Result<()> {
+        self.mount_table.umount_chroot()
+    }
+
+    /// Fails with [`Error::UnsupportedPlatform`]
+    pub fn mount_tagged(&mut self, tag: &str) -> std::io::Result<()> {
+        self.mount_table.mount_tagged(&self.root, tag)
+    }
+
+    /// Fails with [`Error::UnsupportedPlatform`]
+    pub fn umount_tagged(&mut self, tag: &str, cascade: bool) -> std::io::Result<()> {
+        self.mount_table.umount_tagged(tag, cascade)
+    }
+
+    /// Fails with [`Error::UnsupportedPlatform`]
+    pub fn remount(
+        &mut self,
+        target: impl AsRef<Path>,
+        flags: MountFlags,
+        data: Option<&str>,
+    ) -> Result<()> {
+        self.mount_table.remount(target.as_ref(), flags, data)?;
+        Ok(())
+    }
+
+    /// Always empty, as nothing can be mounted
+    pub fn active_targets(&self) -> Vec<PathBuf> {
+        self.mount_table.active_targets()
+    }
+
+    /// Fails with [`Error::UnsupportedPlatform`]
+    pub fn chroot(&mut self) -> std::io::Result<()> {
+        Err(unsupported())
+    }
+
+    /// Fails with [`Error::UnsupportedPlatform`]
+    pub fn try_chroot(&mut self) -> std::io::Result<()> {
+        Err(unsupported())
+    }
+
+    /// Fails with [`Error::UnsupportedPlatform`]
+    pub fn exit_chroot(&mut self) -> std::io::Result<()> {
+        Err(unsupported())
+    }
+
+    /// Fails with [`Error::UnsupportedPlatform`], without calling `f`
+    pub fn run<F, T>(&mut self, _f: F) -> std::io::Result<T>
+    where
+        F: FnOnce() -> T,
+    {
+        Err(unsupported())
+    }
+
+    /// Fails with [`Error::UnsupportedPlatform`], without calling `f`
+    pub fn try_run<F, T>(&mut self, _f: F) -> std::io::Result<T>
+    where
+        F: FnOnce() -> T,
+    {
+        Err(unsupported())
+    }
+
+    /// Fails with [`Error::UnsupportedPlatform`], without calling `f`
+    pub fn run_forked<F>(&mut self, _f: F) -> Result<i32>
+    where
+        F: FnOnce() -> i32,
+    {
+        Err(Error::UnsupportedPlatform)
+    }
+
+    /// Fails with [`Error::UnsupportedPlatform`], without calling `f`
+    pub fn run_forked_with_timeout<F>(&mut self, _timeout: Duration, _f: F) -> Result<i32>
+    where
+        F: FnOnce() -> i32,
+    {
+        Err(Error::UnsupportedPlatform)
+    }
+
+    /// Fails with [`Error::UnsupportedPlatform`], as there is no user to switch to
+    pub fn set_user(&mut self, _name: &str) -> Result<&mut Self> {
+        Err(Error::UnsupportedPlatform)
+    }
+
+    /// Fails with [`Error::UnsupportedPlatform`]
+    pub fn command(&mut self, _program: impl AsRef<OsStr>) -> Result<ContainerCommand<'_>> {
+        Err(Error::UnsupportedPlatform)
+    }
+
+    /// Does nothing, as nothing runs in the container
+    pub fn set_shell(&mut self, _shell: impl Into<PathBuf>) -> &mut Self {
+        self
+    }
+
+    /// Does nothing, as nothing runs in the container
+    pub fn workdir(&mut self, _path: impl Into<PathBuf>) -> &mut Self {
+        self
+    }
+
+    /// Does nothing, as nothing runs in the container
+    pub fn create_workdir(&mut self, _create: bool) -> &mut Self {
+        self
+    }
+
+    /// Does nothing, as nothing runs in the container
+    pub fn env(&mut self, _key: impl Into<String>, _value: impl Into<String>) -> &mut Self {
+        self
+    }
+
+    /// Does nothing, as nothing can be mounted
+    pub fn set_strict_mounts(&mut self, strict: bool) -> &mut Self {
+        self.mount_table.set_strict_mounts(strict);
+        self
+    }
+
+    /// Does nothing, as nothing can be mounted
+    pub fn set_device_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.mount_table.set_device_timeout(timeout);
+        self
+    }
+
+    /// Does nothing, as nothing can be mounted
+    pub fn set_wait_for_source(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.mount_table.set_wait_for_source(timeout);
+        self
+    }
+
+    /// Does nothing, as nothing can be mounted
+    #[allow(clippy::result_large_err)]
+    pub fn close(self) -> std::result::Result<(), (Self, Error)> {
+        Ok(())
+    }
+
+    /// Adds a bind mount for the system's root filesystem to
+    /// the container's root filesystem at `/run/host`
+    pub fn host_bind_mount(&mut self) -> &mut Self {
+        self.host_bind_mount_at("/run/host", false)
+    }
+
+    /// Adds a bind mount for the system's root filesystem at `target` in the container
+    pub fn host_bind_mount_at(&mut self, target: impl Into<PathBuf>, read_only: bool) -> &mut Self {
+        self.host_bind_paths(target, ["/"], read_only)
+    }
+
+    /// Like [`Container::host_bind_mount_at`], but only bind the given host paths under
+    /// `target`
+    pub fn host_bind_paths<I, P>(
+        &mut self,
+        target: impl Into<PathBuf>,
+        paths: I,
+        read_only: bool,
+    ) -> &mut Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let target = target.into();
+        let flags = if read_only {
+            MountFlags::BIND | MountFlags::REC | MountFlags::RDONLY
+        } else {
+            MountFlags::BIND
+        };
+        for path in paths {
+            let source = path.as_ref();
+            let relative = source.strip_prefix("/").unwrap_or(source);
+            let mount = MountTarget {
+                target: target.join(relative).components().collect(),
+                flags,
+                ..MountTarget::default()
+            };
+            self.mount_table.add_mount(mount, source);
+        }
+        self
+    }
+
+    /// Adds a bind mount of `source` on the host at `target` in the container
+    pub fn bind_mount(
+        &mut self,
+        source: impl Into<PathBuf>,
+        target: impl Into<PathBuf>,
+    ) -> &mut Self {
+        self.mount_table.add_mount(
+            MountTarget {
+                target: target.into(),
+                flags: MountFlags::BIND,
+                ..MountTarget::default()
+            },
+            source,
+        );
+        self
+    }
+
+    /// Adds an additional mount target to the container mount table
+    pub fn add_mount(&mut self, mount: MountTarget, source: impl Into<PathBuf>) -> &mut Self {
+        self.mount_table.add_mount(mount, source);
+        self
+    }
+
+    /// Adds the default mounts of Linux, `/proc`, `/sys`, `/dev` and `/dev/pts`,
+    /// so the mount table looks the same as there
+    pub fn add_default_mounts(&mut self) -> &mut Self {
+        for (source, target, fstype) in [
+            ("/proc", "proc", Some("proc")),
+            ("/sys", "sys", Some("sysfs")),
+            ("/dev", "dev", None),
+            ("/dev/pts", "dev/pts", None),
+        ] {
+            let mount = MountTarget {
+                target: target.into(),
+                fstype: fstype.map(String::from),
+                ..MountTarget::default()
+            };
+            self.mount_table.add_mount(mount, source);
+        }
+        self
+    }
+}
+
+/// Stand-in for the command returned by [`Container::command`], which always fails
+/// on this platform
+pub struct ContainerCommand<'a> {
+    container: &'a mut Container,
+    command: Command,
+}
+
+impl ContainerCommand<'_> {
+    /// The container the command runs in
+    pub fn container(&self) -> &Container {
+        self.container
+    }
+
+    /// Release the container borrow, keeping only the command
+    pub fn into_inner(self) -> Command {
+        self.command
+    }
+}
+
+impl fmt::Debug for ContainerCommand<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ContainerCommand")
+            .field("root", &self.container.root)
+            .field("command", &self.command)
+            .finish()
+    }
+}
+
+impl Deref for ContainerCommand<'_> {
+    type Target = Command;
+
+    fn deref(&self) -> &Command {
+        &self.command
+    }
+}
+
+impl DerefMut for ContainerCommand<'_> {
+    fn deref_mut(&mut self) -> &mut Command {
+        &mut self.command
+    }
+}
+
+/// Builder for [`Container`], with the options available on every platform
+#[derive(Debug, Clone, Default)]
+pub struct ContainerBuilder {
+    root: Option<PathBuf>,
+    no_default_mounts: bool,
+    /// Sources and mounts, in the order they were added
+    mounts: Vec<(PathBuf, MountTarget)>,
+}
+
+impl ContainerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the directory used as the root of the container
+    pub fn root(mut self, path: impl Into<PathBuf>) -> Self {
+        self.root = Some(path.into());
+        self
+    }
+
+    /// Whether to add the mounts of [`Container::add_default_mounts`], enabled by default
+    pub fn include_default_mounts(mut self, include: bool) -> Self {
+        self.no_default_mounts = !include;
+        self
+    }
+
+    /// Bind mount a host file or directory to `target` inside the container
+    pub fn bind(self, source: impl Into<PathBuf>, target: impl Into<PathBuf>) -> Self {
+        self.bind_with_flags(source, target, MountFlags::BIND)
+    }
+
+    /// Like [`ContainerBuilder::bind`], but the mount is read-only
+    pub fn bind_ro(self, source: impl Into<PathBuf>, target: impl Into<PathBuf>) -> Self {
+        self.bind_with_flags(source, target, MountFlags::BIND | MountFlags::RDONLY)
+    }
+
+    fn bind_with_flags(
+        mut self,
+        source: impl Into<PathBuf>,
+        target: impl Into<PathBuf>,
+        flags: MountFlags,
+    ) -> Self {
+        let mount = MountTarget {
+            target: target.into(),
+            flags,
+            ..MountTarget::default()
+        };
+        self.mounts.push((source.into(), mount));
+        self
+    }
+
+    /// Mount a tmpfs at `target` inside the container
+    pub fn tmpfs(mut self, target: impl Into<PathBuf>, options: impl Into<String>) -> Self {
+        let target = target.into();
+        let options = options.into();
+        let relative = target.strip_prefix("/").unwrap_or(&target);
+        let source = PathBuf::from(format!("tmpfs:/{}", relative.display()));
+        let mount = MountTarget {
+            target,
+            fstype: Some("tmpfs".to_string()),
+            data: (!options.is_empty()).then_some(options),
+            ..MountTarget::default()
+        };
+        self.mounts.push((source, mount));
+        self
+    }
+
+    /// Does nothing, as nothing runs in the container
+    pub fn hostname(self, _hostname: impl Into<String>) -> Self {
+        self
+    }
+
+    /// Does nothing, as nothing runs in the container
+    pub fn workdir(self, _path: impl Into<PathBuf>) -> Self {
+        self
+    }
+
+    /// Does nothing, as nothing runs in the container
+    pub fn rootless(self, _rootless: bool) -> Self {
+        self
+    }
+
+    /// Does nothing, as there are no host paths to protect on this platform
+    pub fn allow_dangerous_root(self, _allow: bool) -> Self {
+        self
+    }
+
+    /// Create the container
+    ///
+    /// Fails with [`Error::InvalidRoot`] if no root was set.
+    pub fn build(self) -> Result<Container> {
+        let root = self.root.ok_or_else(|| Error::InvalidRoot {
+            root: PathBuf::new(),
+            reason: "no root directory was set".to_string(),
+        })?;
+        let mut container = Container::new_bare(root);
+        if !self.no_default_mounts {
+            container.add_default_mounts();
+        }
+        for (source, mount) in self.mounts {
+            container.add_mount(mount, source);
+        }
+        Ok(container)
+    }
+}
+
+/// Never inside a container, since there are none on this platform
+pub fn inside_chroot() -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_unsupported(err: std::io::Error) -> bool {
+        matches!(Error::from(err), Error::UnsupportedPlatform)
+    }
+
+    #[test]
+    fn test_stub_container() {
+        let mut container = Container::new("/tmp/tiffin-fallback");
+        assert!(!container.mount_table.is_empty());
+        container.bind_mount("/srv", "srv").host_bind_mount();
+        assert_eq!(container.mount_table.inner.len(), 6);
+
+        assert!(is_unsupported(container.mount().unwrap_err()));
+        assert!(is_unsupported(container.umount().unwrap_err()));
+        assert!(is_unsupported(container.chroot().unwrap_err()));
+        assert!(is_unsupported(container.exit_chroot().unwrap_err()));
+        let mut called = false;
+        assert!(is_unsupported(container.run(|| called = true).unwrap_err()));
+        assert!(!called);
+        assert!(matches!(
+            container.run_forked(|| 0),
+            Err(Error::UnsupportedPlatform)
+        ));
+        assert!(matches!(
+            container.command("/bin/true"),
+            Err(Error::UnsupportedPlatform)
+        ));
+        assert!(matches!(
+            container.set_user("nobody"),
+            Err(Error::UnsupportedPlatform)
+        ));
+        assert!(container.active_targets().is_empty());
+        assert!(container.close().is_ok());
+        assert!(!inside_chroot());
+    }
+
+    #[test]
+    fn test_stub_builder() {
+        assert!(matches!(
+            Container::builder().build(),
+            Err(Error::InvalidRoot { .. })
+        ));
+        let mut container = Container::builder()
+            .root("/tmp/tiffin-fallback")
+            .include_default_mounts(false)
+            .bind("/", "/run/host")
+            .bind_ro("/etc/resolv.conf", "/etc/resolv.conf")
+            .tmpfs("/tmp", "mode=1777")
+            .build()
+            .unwrap();
+        assert_eq!(container.mount_table.inner.len(), 3);
+        let flags = |source: &str| container.mount_table.inner[Path::new(source)].flags;
+        assert_eq!(flags("/"), MountFlags::BIND);
+        assert_eq!(
+            flags("/etc/resolv.conf"),
+            MountFlags::BIND | MountFlags::RDONLY
+        );
+        assert!(flags("tmpfs:/tmp").is_empty());
+        assert!(is_unsupported(container.mount().unwrap_err()));
+        assert!(is_unsupported(container.mount_tagged("cache").unwrap_err()));
+    }
+
+    #[test]
+    fn test_unsupported_message() {
+        let err = Error::UnsupportedPlatform;
+        assert_eq!(
+            err.to_string(),
+            format!("containers are not supported on {}", std::env::consts::OS)
+        );
+        // survives going through an I/O error and back
+        assert!(is_unsupported(err.into()));
+    }
+}
@@ -0,0 +1,5549 @@
+//! The container runtime, which only works on Linux
+
+#[cfg(feature = "tokio")]
+mod asynchronous;
+mod backup;
+mod builder;
+mod cache;
+mod caps;
+mod certs;
+mod cgroup;
+mod cleanup;
+mod command;
+mod copy;
+mod danger;
+mod desktop;
+mod elf;
+mod env;
+mod ephemeral;
+mod etc;
+mod gpu;
+mod hooks;
+mod host;
+mod journal;
+mod lock;
+mod marker;
+mod merge;
+pub mod mountinfo;
+mod namespace;
+mod netconf;
+mod options;
+mod persist;
+mod pool;
+mod preset;
+mod process;
+mod procfs;
+mod progress;
+mod pty;
+mod readonly;
+pub mod replay;
+mod resolve;
+mod rlimit;
+pub mod rootfs;
+mod seal;
+#[cfg(feature = "seccomp")]
+mod seccomp;
+mod selinux;
+mod snapshot;
+mod source;
+mod stale;
+mod status;
+#[cfg(feature = "tarball")]
+mod tarball;
+#[cfg(test)]
+mod tempdir;
+mod user;
+mod validate;
+mod wait;
+
+pub use builder::ContainerBuilder;
+pub use cache::{CacheKind, MountLock};
+pub use caps::{Capability, CapabilitySet};
+pub use cgroup::{Cgroup, CgroupConfig};
+pub use command::{
+    CommandSpec, ContainerChild, ContainerCommand, Output, OutputOptions, StdioMode,
+    TiffinCommandExt,
+};
+pub use desktop::{DisplayReport, SocketReport};
+pub use env::{EnvPolicy, Environment};
+pub use ephemeral::{EphemeralBuilder, EphemeralContainer};
+pub use etc::{EtcPrepOptions, Localtime, MachineId};
+pub use gpu::{DriverLibs, GpuOptions, GpuReport};
+pub use hooks::Phase;
+pub use host::HOST_PATHS;
+pub use journal::{JournalOp, JournalRecord};
+pub use marker::ContainerMarker;
+pub use merge::{MergeConflict, MergeReport, MergeStrategy};
+pub use namespace::Namespaces;
+pub use netconf::NetworkConfig;
+pub use options::MountOptions;
+pub use persist::{unmount_persisted, PersistedMount, PersistedMounts};
+pub use pool::{ContainerReport, Pool, PoolReport};
+pub use preset::MountPreset;
+pub use process::LingeringProcess;
+pub use procfs::{HidePid, ProcMountOptions};
+pub use progress::ProgressEvent;
+pub use rlimit::{Limit, Resource, Rlimits};
+pub use seal::SealOptions;
+#[cfg(feature = "seccomp")]
+pub use seccomp::{syscall_number, SeccompMode, SeccompPolicy};
+pub use selinux::SelinuxContext;
+pub use snapshot::MountTableSnapshot;
+pub use source::MountSource;
+pub use stale::{
+    cleanup_stale, cleanup_stale_with, CleanupOptions, CleanupReport, StaleMount, UnmountMethod,
+};
+pub use status::{ActiveMount, ContainerStatus};
+#[cfg(feature = "tarball")]
+pub use tarball::{unpack_tarball, UnpackReport};
+pub use user::User;
+pub use validate::{MountDiagnostic, MountProblem};
+// named the same as the stand-in of other platforms
+pub use sys_mount::MountFlags;
+
+use crate::{Error, Result};
+use itertools::Itertools;
+use nix::unistd::{Gid, Pid, Uid};
+use progress::Progress;
+use resolve::{resolve_in_root, Create};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    ffi::{CString, OsString},
+    fs::File,
+    os::{
+        fd::AsRawFd,
+        unix::{
+            ffi::OsStringExt,
+            fs::{FileTypeExt, MetadataExt, PermissionsExt},
+            process::CommandExt,
+        },
+    },
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Instant,
+};
+use sys_mount::{FilesystemType, Mount, Unmount, UnmountDrop, UnmountFlags};
+/// How [`MountTarget::mount`] creates a mountpoint missing from the container
+///
+/// Only what tiffin creates is changed, existing directories are left alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MountpointOptions {
+    /// Create missing directories, or fail if the mountpoint doesn't exist. Defaults to true
+    pub create: bool,
+    /// Mode of created directories, instead of 0755
+    pub mode: Option<u32>,
+    /// Owner of created directories, and of the empty file created for a file bind mount,
+    /// instead of the calling user
+    pub owner: Option<(Uid, Gid)>,
+}
+
+impl Default for MountpointOptions {
+    fn default() -> Self {
+        Self {
+            create: true,
+            mode: None,
+            owner: None,
+        }
+    }
+}
+
+impl MountpointOptions {
+    fn key(&self) -> (bool, Option<u32>, Option<(u32, u32)>) {
+        let owner = self.owner.map(|(uid, gid)| (uid.as_raw(), gid.as_raw()));
+        (self.create, self.mode, owner)
+    }
+}
+
+impl PartialOrd for MountpointOptions {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MountpointOptions {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+/// Mount object struct
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct MountTarget {
+    pub target: PathBuf,
+    pub fstype: Option<String>,
+    pub flags: MountFlags,
+    /// Data passed to the kernel as is
+    pub data: Option<String>,
+    /// Typed alternative to `data`, both are used if set
+    pub data_options: Option<MountOptions>,
+    pub mountpoint: MountpointOptions,
+    /// Label to mount and unmount a subset of the table,
+    /// see [`MountTable::mount_tagged`] and [`MountTable::umount_tagged`]
+    pub tag: Option<String>,
+    /// File locked while the mount is active, see [`MountLock`]
+    pub lock: Option<MountLock>,
+    /// How long to wait for the source to exist before mounting, for sources set up
+    /// asynchronously such as device nodes created by udev, or
+    /// [`MountTable::set_wait_for_source`] if `None`
+    pub wait_for_source: Option<std::time::Duration>,
+}
+
+impl Default for MountTarget {
+    fn default() -> Self {
+        Self {
+            target: Default::default(),
+            fstype: Default::default(),
+            flags: MountFlags::empty(),
+            data: Default::default(),
+            data_options: Default::default(),
+            mountpoint: Default::default(),
+            tag: None,
+            lock: None,
+            wait_for_source: None,
+        }
+    }
+}
+
+impl MountTarget {
+    /// Create a new mount object
+    pub fn new(
+        target: impl Into<PathBuf>,
+        fstype: Option<String>,
+        flags: MountFlags,
+        data: Option<String>,
+    ) -> Self {
+        Self {
+            target: target.into(),
+            fstype,
+            flags,
+            data,
+            data_options: None,
+            mountpoint: MountpointOptions::default(),
+            tag: None,
+            lock: None,
+            wait_for_source: None,
+        }
+    }
+
+    /// Set the tag of the mount, see [`MountTarget::tag`]
+    pub fn tagged(mut self, tag: impl Into<String>) -> Self {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    /// Label every file of the filesystem with `context`, as the `context=` mount option
+    pub fn context(&mut self, context: SelinuxContext) -> &mut Self {
+        self.set_context("context", context)
+    }
+
+    /// Label the filesystem itself with `context`, as the `fscontext=` mount option
+    pub fn fscontext(&mut self, context: SelinuxContext) -> &mut Self {
+        self.set_context("fscontext", context)
+    }
+
+    /// Label files that have no label with `context`, as the `defcontext=` mount option
+    pub fn defcontext(&mut self, context: SelinuxContext) -> &mut Self {
+        self.set_context("defcontext", context)
+    }
+
+    /// Label the root directory of the filesystem with `context`,
+    /// as the `rootcontext=` mount option
+    pub fn rootcontext(&mut self, context: SelinuxContext) -> &mut Self {
+        self.set_context("rootcontext", context)
+    }
+
+    fn set_context(&mut self, key: &str, context: SelinuxContext) -> &mut Self {
+        self.data_options
+            .get_or_insert_with(MountOptions::new)
+            .insert(key.to_string(), Some(context.to_string()));
+        self
+    }
+
+    /// The mount's flags, with those of [`MountTarget::data_options`]
+    fn all_flags(&self) -> MountFlags {
+        match &self.data_options {
+            Some(options) => self.flags | options.flags,
+            None => self.flags,
+        }
+    }
+
+    /// The data string passed to the kernel, from `data` and `data_options`
+    fn all_data(&self) -> Option<String> {
+        let rendered = self
+            .data_options
+            .as_ref()
+            .filter(|options| !options.is_empty())
+            .map(MountOptions::render);
+        match (
+            self.data.as_deref().filter(|data| !data.is_empty()),
+            rendered,
+        ) {
+            (Some(data), Some(rendered)) => Some(format!("{data},{rendered}")),
+            (data, rendered) => data.map(String::from).or(rendered),
+        }
+    }
+
+    /// Mount `source` at the target inside `root`, looking up devices given by
+    /// identifier, see [`MountSource`]
+    pub fn mount(
+        &self,
+        source: &Path,
+        root: &Path,
+    ) -> std::io::Result<UnmountDrop<ContainerMount>> {
+        let source = &MountSource::from(source).resolve()?;
+        tracing::info!(?root, "Mounting {source:?} to {:?}", self.target);
+        let create = if !self.mountpoint.create {
+            Create::Nothing
+        } else if self.flags.contains(MountFlags::BIND) && !source.is_dir() {
+            // a file can only be bind mounted over a file
+            Create::File
+        } else {
+            Create::Dir
+        };
+        // symlinks in the image are resolved inside the root, never on the host
+        let resolved = resolve::resolve(root, &self.target, create).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound && create == Create::Nothing {
+                let target = self.target.display();
+                std::io::Error::new(
+                    e.kind(),
+                    format!("mountpoint {target} doesn't exist in the container"),
+                )
+            } else {
+                e
+            }
+        })?;
+        for path in &resolved.created {
+            if let Some(mode) = self.mountpoint.mode.filter(|_| path.is_dir()) {
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+            }
+            if let Some((uid, gid)) = self.mountpoint.owner {
+                nix::unistd::fchownat(
+                    None,
+                    path,
+                    Some(uid),
+                    Some(gid),
+                    nix::unistd::FchownatFlags::NoFollowSymlink,
+                )?;
+            }
+        }
+        let target = &resolved.path;
+
+        // nix::mount::mount(
+        //     source,
+        //     &target,
+        //     self.fstype.as_deref(),
+        //     self.flags,
+        //     self.data.as_deref(),
+        // )?;
+        let flags = self.all_flags();
+        let mut mount = Mount::builder().flags(flags);
+        if let Some(fstype) = &self.fstype {
+            mount = mount.fstype(FilesystemType::Manual(fstype));
+        }
+
+        let data = self.all_data();
+        if let Some(data) = &data {
+            mount = mount.data(data);
+        }
+
+        // through the resolved file, so a symlink swapped in since can't redirect the mount
+        let mount = mount
+            .mount(source, resolved.fd_path())
+            .map_err(|e| procfs::explain_error(self, e))?;
+        let mount = ContainerMount {
+            target: target.clone(),
+            mount,
+        }
+        .into_unmount_drop(UnmountFlags::empty());
+        if flags.contains(MountFlags::BIND | MountFlags::RDONLY) {
+            // the kernel ignores the read-only flag when creating a bind mount,
+            // dropping the guard unmounts it again if this fails
+            let remount = nix::mount::MsFlags::MS_BIND
+                | nix::mount::MsFlags::MS_REMOUNT
+                | nix::mount::MsFlags::MS_RDONLY;
+            let mounted = resolved.reopen()?;
+            let path = resolve::fd_path(&mounted);
+            nix::mount::mount(None::<&str>, &path, None::<&str>, remount, None::<&str>)?;
+            if flags.contains(MountFlags::REC) {
+                readonly::make_submounts_read_only(target)?;
+            }
+        }
+        Ok(mount)
+    }
+
+    pub fn umount(&self, root: &Path) -> std::io::Result<()> {
+        let target = resolve_in_root(root, &self.target, Create::Nothing)?;
+        nix::mount::umount(&target)?;
+        Ok(())
+    }
+
+    /// The path of the mount in `root` if the same mount is already active there
+    ///
+    /// The topmost mount at the target must be of the same filesystem type, from the
+    /// same device if the source is a block device, or for bind mounts, show the same
+    /// file as the source.
+    fn active_path(
+        &self,
+        source: &Path,
+        root: &Path,
+        active: &[mountinfo::MountInfo],
+    ) -> Option<PathBuf> {
+        let path = resolve_in_root(root, &self.target, Create::Nothing).ok()?;
+        // what the kernel shows, without symlinks in the root or trailing slashes
+        let canonical = path.canonicalize().ok()?;
+        let info = active
+            .iter()
+            .rev()
+            .find(|info| info.mount_point == canonical)?;
+        let identical = if self.flags.contains(MountFlags::BIND) {
+            let id = |path: &Path| std::fs::metadata(path).map(|m| (m.dev(), m.ino())).ok();
+            id(source).is_some() && id(source) == id(&path)
+        } else {
+            let block_device =
+                std::fs::metadata(source).is_ok_and(|m| m.file_type().is_block_device());
+            self.fstype.as_deref() == Some(info.fstype.as_str())
+                && (!block_device
+                    || Path::new(&info.source).canonicalize().ok() == source.canonicalize().ok())
+        };
+        identical.then_some(path)
+    }
+}
+
+/// Whether the calling process is inside a chroot, be it a tiffin container or not
+///
+/// Besides containers chrooted by this process, this compares the root directory with
+/// the one of PID 1, which requires `/proc` and privileges, and can't tell inside a PID
+/// namespace whose init shares the root of the process.
+pub fn inside_chroot() -> bool {
+    if lock::ChrootLock::depth() > 0 {
+        return true;
+    }
+    let id = |path| nix::sys::stat::stat(path).map(|stat| (stat.st_dev, stat.st_ino));
+    matches!((id("/"), id("/proc/1/root")), (Ok(root), Ok(init)) if root != init)
+}
+
+/// A short random id telling containers apart in logs
+fn generate_id() -> String {
+    use std::hash::{BuildHasher, Hasher};
+    static COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+    // RandomState is seeded randomly once per thread, so mix in more to vary it
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    hasher.write_u64(COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed));
+    if let Ok(now) = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        hasher.write_u128(now.as_nanos());
+    }
+    format!("{:08x}", hasher.finish() as u32)
+}
+
+/// `path` without its leading `/`, as mount targets are relative to the container root
+fn relative(path: &Path) -> &Path {
+    path.strip_prefix("/").unwrap_or(path)
+}
+
+/// A filesystem mounted by [`MountTarget::mount`], unmounted when the [`UnmountDrop`]
+/// holding it is dropped
+#[derive(Debug)]
+pub struct ContainerMount {
+    /// Where it's mounted on the host
+    target: PathBuf,
+    /// Mounted through a file descriptor rather than at `target`, so only what it
+    /// knows about its loop device is used
+    mount: Mount,
+}
+
+impl ContainerMount {
+    /// Where the filesystem is mounted on the host
+    pub fn target_path(&self) -> &Path {
+        &self.target
+    }
+
+    /// The loop device the source was attached to, if it's an image file
+    pub fn backing_loop_device(&self) -> Option<&Path> {
+        self.mount.backing_loop_device()
+    }
+}
+
+impl Unmount for ContainerMount {
+    fn unmount(&self, flags: UnmountFlags) -> std::io::Result<()> {
+        sys_mount::unmount(&self.target, flags)?;
+        if let Some(device) = self.backing_loop_device() {
+            detach_loop_device(device)?;
+        }
+        Ok(())
+    }
+}
+
+/// Detach the backing file of a loop device, as `losetup --detach` does
+fn detach_loop_device(device: &Path) -> std::io::Result<()> {
+    /// From `linux/loop.h`, missing from the libc crate
+    const LOOP_CLR_FD: libc::c_ulong = 0x4C01;
+    let device = File::open(device)?;
+    // SAFETY: LOOP_CLR_FD takes no argument, and the fd is valid while device is open
+    if unsafe { libc::ioctl(device.as_raw_fd(), LOOP_CLR_FD as _, 0) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A mount of a [`MountTable`]
+enum MountGuard {
+    /// Mounted by the table, and unmounted when dropped
+    Owned(UnmountDrop<ContainerMount>),
+    /// Mounted by the caller and handed to [`MountTable::add_sysmount`], unmounted
+    /// when dropped
+    System(UnmountDrop<Mount>),
+    /// Already active when the table was mounted, only unmounted by
+    /// [`MountTable::umount_chroot`]
+    Adopted(PathBuf),
+}
+
+/// An active mount of a [`MountTable`], with the tag of its entry
+struct Tracked {
+    guard: MountGuard,
+    tag: Option<String>,
+    /// The [`MountTarget::lock`] of its entry, only held so it's released once the
+    /// mount is dropped
+    _lock: Option<File>,
+}
+
+impl MountGuard {
+    fn target_path(&self) -> &Path {
+        match self {
+            Self::Owned(mount) => mount.target_path(),
+            Self::System(mount) => mount.target_path(),
+            Self::Adopted(path) => path,
+        }
+    }
+
+    /// Stop tracking the mount, without ever unmounting it
+    fn forget(self) {
+        match self {
+            Self::Owned(mount) => std::mem::forget(mount),
+            Self::System(mount) => std::mem::forget(mount),
+            Self::Adopted(_) => {}
+        }
+    }
+
+    fn unmount(self) -> std::io::Result<()> {
+        match self {
+            Self::Owned(mount) => mount.unmount(UnmountFlags::DETACH),
+            Self::System(mount) => mount.unmount(UnmountFlags::DETACH),
+            Self::Adopted(path) => {
+                nix::mount::umount2(&path, nix::mount::MntFlags::MNT_DETACH)?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Mount Table Struct
+/// This is used to mount filesystems inside the container. It is essentially an fstab, for the container.
+#[derive(Default)]
+pub struct MountTable {
+    /// The table of mounts
+    /// The key is the device name, and value is the mount object
+    inner: HashMap<PathBuf, MountTarget>,
+    mounts: Vec<Tracked>,
+    /// Where the table was last mounted
+    root: Option<PathBuf>,
+    tmpfs_context: Option<SelinuxContext>,
+    selinux_strict: bool,
+    strict_mounts: bool,
+    /// How long to wait for devices given by identifier, see [`MountSource`]
+    device_timeout: std::time::Duration,
+    /// Default of [`MountTarget::wait_for_source`]
+    wait_for_source: Option<std::time::Duration>,
+    progress: Progress,
+    journal: journal::Journal,
+    /// Host paths of mounts made by someone else, which are never mounted over or
+    /// unmounted, see [`Container::adopt`]
+    foreign: Vec<PathBuf>,
+}
+
+impl MountTable {
+    pub fn new() -> Self {
+        Self {
+            inner: HashMap::new(),
+            mounts: Vec::new(),
+            root: None,
+            tmpfs_context: None,
+            selinux_strict: false,
+            strict_mounts: false,
+            device_timeout: std::time::Duration::ZERO,
+            wait_for_source: None,
+            progress: Progress::default(),
+            journal: journal::Journal::default(),
+            foreign: Vec::new(),
+        }
+    }
+
+    /// Sets the `context=` option of every tmpfs in the table that has no context option
+    pub fn set_tmpfs_context(&mut self, context: Option<SelinuxContext>) {
+        self.tmpfs_context = context;
+    }
+
+    /// Sets whether mounts with SELinux context options fail to mount when SELinux is
+    /// disabled on the host, rather than being mounted without them
+    pub fn set_selinux_strict(&mut self, strict: bool) {
+        self.selinux_strict = strict;
+    }
+
+    /// Sets whether [`MountTable::mount_chroot`] fails with [`Error::AlreadyMounted`] when a
+    /// mount is already active, rather than adopting it
+    pub fn set_strict_mounts(&mut self, strict: bool) {
+        self.strict_mounts = strict;
+    }
+
+    /// Sets how long to wait for a device given by identifier, such as `UUID=`, to show up
+    /// in `/dev/disk` when mounting, instead of failing right away, see [`MountSource`]
+    pub fn set_device_timeout(&mut self, timeout: std::time::Duration) {
+        self.device_timeout = timeout;
+    }
+
+    /// Sets how long to wait for the source of each mount to exist before mounting it,
+    /// for mounts whose [`MountTarget::wait_for_source`] isn't set
+    pub fn set_wait_for_source(&mut self, timeout: Option<std::time::Duration>) {
+        self.wait_for_source = timeout;
+    }
+
+    /// Call `callback` with the progress of mounting and unmounting
+    pub fn on_progress(&mut self, callback: impl FnMut(ProgressEvent) + Send + 'static) {
+        self.progress = Progress::new(callback);
+    }
+
+    /// Append every mount, unmount and remount to the journal at `path` as a line of
+    /// JSON, see [`JournalRecord`] and [`replay`]
+    ///
+    /// The journal is created if needed, and appended to otherwise.
+    pub fn record_to(&mut self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        self.journal = journal::Journal::open(path.as_ref())?;
+        Ok(())
+    }
+
+    /// `mount` as it is mounted, with the table's SELinux settings applied
+    fn prepare<'a>(&self, mount: &'a MountTarget, selinux: bool) -> Cow<'a, MountTarget> {
+        let mut mount = Cow::Borrowed(mount);
+        if let Some(context) = &self.tmpfs_context {
+            if mount.fstype.as_deref() == Some("tmpfs") && !selinux::has_context(&mount) {
+                mount.to_mut().context(context.clone());
+            }
+        }
+        if !selinux && !self.selinux_strict && selinux::has_context(&mount) {
+            tracing::debug!(target = ?mount.target, "SELinux is disabled, dropping context options");
+            selinux::strip_context(mount.to_mut());
+        }
+        mount
+    }
+    /// Sets the mount table
+    pub fn set_table(&mut self, table: HashMap<PathBuf, MountTarget>) {
+        self.inner = table;
+    }
+
+    /// Adds a mount to the table
+    pub fn add_mount(&mut self, mount: MountTarget, source: impl Into<PathBuf>) {
+        self.inner.insert(source.into(), mount);
+    }
+
+    /// Adds the mounts of `other` to the table, handling conflicts according to `strategy`
+    ///
+    /// Only the configured mounts of `other` are taken, in the order of
+    /// [`MountTable::mount_chroot`], its settings are ignored. Whatever it has mounted is
+    /// unmounted when it's dropped. With [`MergeStrategy::Error`], the table is left
+    /// unchanged if there is any conflict.
+    pub fn merge(&mut self, other: MountTable, strategy: MergeStrategy) -> Result<MergeReport> {
+        let entries: Vec<_> = other
+            .sort_mounts()
+            .map(|(source, mount)| (source.clone(), mount.clone()))
+            .collect();
+        self.extend_from_iter(entries, strategy)
+    }
+
+    /// Adds pairs of source and mount to the table, handling conflicts according to
+    /// `strategy`, see [`MountTable::merge`]
+    ///
+    /// The pairs are added in order, so later ones conflict with earlier ones too.
+    pub fn extend_from_iter<S: Into<PathBuf>>(
+        &mut self,
+        entries: impl IntoIterator<Item = (S, MountTarget)>,
+        strategy: MergeStrategy,
+    ) -> Result<MergeReport> {
+        let mut table = self.inner.clone();
+        let mut report = MergeReport::default();
+        let mut conflicts = Vec::new();
+        for (source, mount) in entries {
+            let source = source.into();
+            let target = merge::sanitize(&mount.target);
+            let existing: Vec<_> = table
+                .iter()
+                .filter(|(existing, existing_mount)| {
+                    **existing == source || merge::sanitize(&existing_mount.target) == target
+                })
+                .map(|(existing, _)| existing.clone())
+                .collect();
+            if existing.is_empty() {
+                report.added.push(source.clone());
+                table.insert(source, mount);
+                continue;
+            }
+
+            for existing in existing {
+                let conflict = MergeConflict {
+                    target: target.clone(),
+                    existing,
+                    incoming: source.clone(),
+                };
+                match strategy {
+                    MergeStrategy::ReplaceExisting => {
+                        table.remove(&conflict.existing);
+                        report.replaced.push(conflict);
+                    }
+                    MergeStrategy::KeepExisting => report.kept.push(conflict),
+                    MergeStrategy::Error => conflicts.push(conflict),
+                }
+            }
+            if strategy == MergeStrategy::ReplaceExisting {
+                table.insert(source, mount);
+            }
+        }
+
+        if !conflicts.is_empty() {
+            return Err(Error::MountConflicts(conflicts));
+        }
+        tracing::debug!(?report, "Merged mounts");
+        self.inner = table;
+        Ok(report)
+    }
+
+    /// Whether the table has no mounts configured
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Copy the configured mounts, to go back to them with [`MountTable::restore`]
+    ///
+    /// The settings of the table and what it has mounted are not part of the snapshot.
+    pub fn snapshot(&self) -> MountTableSnapshot {
+        MountTableSnapshot {
+            entries: self
+                .sort_mounts()
+                .map(|(source, mount)| (source.clone(), mount.clone()))
+                .collect(),
+        }
+    }
+
+    /// Replace the configured mounts with those of `snapshot`
+    ///
+    /// Fails with [`Error::StillMounted`] if anything is mounted, unless `force` is set:
+    /// everything is then unmounted, and the restored table mounted again at the same root.
+    pub fn restore(&mut self, snapshot: MountTableSnapshot, force: bool) -> std::io::Result<()> {
+        let root = match &self.root {
+            Some(root) if !self.mounts.is_empty() => root.clone(),
+            _ => {
+                self.inner = snapshot.entries.into_iter().collect();
+                return Ok(());
+            }
+        };
+        if !force {
+            return Err(std::io::Error::other(Error::StillMounted {
+                mounts: self.mounted_paths(),
+            }));
+        }
+        tracing::debug!(?root, "Remounting to restore the mount table");
+        self.umount_chroot()?;
+        self.inner = snapshot.entries.into_iter().collect();
+        self.mount_chroot(&root)
+    }
+
+    /// Stop owning the active mount at `target`, a path inside the container, and hand it
+    /// over, so it stays mounted when the table is unmounted or dropped
+    ///
+    /// The mount is unmounted when the returned handle is dropped. Returns `None` if the
+    /// table didn't mount anything at `target`, if the mount was adopted or added with
+    /// [`MountTable::add_sysmount`] rather than mounted by the table, if it holds a
+    /// [`MountTarget::lock`], which the handle couldn't keep, or if the table has mounted
+    /// something else under it, which would keep it busy or be hidden by it: those have
+    /// to be taken first.
+    pub fn take_mount(&mut self, target: &Path) -> Option<UnmountDrop<ContainerMount>> {
+        let root = self.root.as_deref()?;
+        let path = resolve_in_root(root, target, Create::Nothing).ok()?;
+        // the topmost, if several are stacked
+        let index = self
+            .mounts
+            .iter()
+            .rposition(|mount| mount.guard.target_path() == path)?;
+        if let Some(child) = self.mounts[index + 1..]
+            .iter()
+            .map(|mount| mount.guard.target_path())
+            .find(|child| child.starts_with(&path))
+        {
+            tracing::warn!(
+                ?path,
+                ?child,
+                "Not taking a mount with another one under it"
+            );
+            return None;
+        }
+        if !matches!(self.mounts[index].guard, MountGuard::Owned(_)) {
+            tracing::warn!(?path, "Not taking a mount the table didn't mount");
+            return None;
+        }
+        if self.mounts[index]._lock.is_some() {
+            tracing::warn!(?path, "Not taking a mount holding a lock");
+            return None;
+        }
+        tracing::debug!(?path, "Handing the mount over");
+        match self.mounts.remove(index).guard {
+            MountGuard::Owned(mount) => Some(mount),
+            MountGuard::System(_) | MountGuard::Adopted(_) => None,
+        }
+    }
+
+    /// Paths on the host of the active mounts of the table, in mount order, which are
+    /// unmounted by [`MountTable::umount_chroot`]
+    pub fn active_targets(&self) -> Vec<PathBuf> {
+        self.mounted_paths()
+    }
+
+    pub fn add_sysmount(&mut self, mount: UnmountDrop<Mount>) {
+        self.mounts.push(Tracked {
+            guard: MountGuard::System(mount),
+            tag: None,
+            _lock: None,
+        });
+    }
+
+    /// Paths of the active mounts, in mount order
+    fn mounted_paths(&self) -> Vec<PathBuf> {
+        self.mounts
+            .iter()
+            .map(|mount| mount.guard.target_path().to_path_buf())
+            .collect()
+    }
+
+    /// Sort mounts by mountpoint and depth
+    ///
+    /// Targets with fewer components come first, so the root is first and parents are
+    /// always mounted before their children. Targets of the same depth are sorted by path,
+    /// then by source.
+    fn sort_mounts(&self) -> impl Iterator<Item = (&PathBuf, &MountTarget)> {
+        self.inner.iter().sorted_by(|(a_source, a), (b_source, b)| {
+            let (a, b) = (relative(&a.target), relative(&b.target));
+            (a.components().count(), a, a_source).cmp(&(b.components().count(), b, b_source))
+        })
+    }
+
+    /// Check every mount before mounting anything, returning what is wrong with each
+    ///
+    /// Sources of bind mounts, and devices of filesystems that need one, must exist and be
+    /// readable, and filesystems must be supported by the kernel or a module. This is done
+    /// by [`MountTable::mount_chroot`], and doesn't need root.
+    ///
+    /// Missing sources that are waited for, see [`MountTarget::wait_for_source`], are
+    /// [`MountProblem::PendingSource`], which doesn't keep the table from being mounted.
+    pub fn validate(&self) -> Vec<MountDiagnostic> {
+        let filesystems = validate::Filesystems::host();
+        let selinux = selinux::enabled();
+        self.sort_mounts()
+            .filter_map(|(source, mount)| {
+                let problem = match validate::check(source, mount, filesystems.as_ref()) {
+                    Some(MountProblem::MissingSource) => match self.source_wait(mount) {
+                        Some(timeout) => MountProblem::PendingSource(timeout),
+                        None => MountProblem::MissingSource,
+                    },
+                    Some(problem) => problem,
+                    None => {
+                        // only left in strict mode
+                        let context = selinux::has_context(&self.prepare(mount, selinux));
+                        (!selinux && context).then_some(MountProblem::SelinuxDisabled)?
+                    }
+                };
+                Some(MountDiagnostic {
+                    source: source.clone(),
+                    target: mount.target.clone(),
+                    problem,
+                })
+            })
+            .collect()
+    }
+
+    /// Mounts everything to the root
+    ///
+    /// Fails with [`Error::InvalidMounts`] without mounting anything if [`MountTable::validate`] finds problems.
+    ///
+    /// Mounts that are already active, such as those left by an earlier attempt, aren't
+    /// mounted again. Those of the table are kept as they are, and others are adopted, so
+    /// they are unmounted by [`MountTable::umount_chroot`] but not when the table is dropped.
+    /// With [`MountTable::set_strict_mounts`], this fails with [`Error::AlreadyMounted`]
+    /// instead of adopting them.
+    pub fn mount_chroot(&mut self, root: &Path) -> std::io::Result<()> {
+        self.mount_filtered(root, None)
+    }
+
+    /// Like [`MountTable::mount_chroot`], but only mount the entries tagged `tag`,
+    /// see [`MountTarget::tag`]
+    ///
+    /// They are mounted in the same order as by [`MountTable::mount_chroot`].
+    pub fn mount_tagged(&mut self, root: &Path, tag: &str) -> std::io::Result<()> {
+        self.mount_filtered(root, Some(tag))
+    }
+
+    /// How long to wait for the source of `mount`, see [`MountTarget::wait_for_source`]
+    fn source_wait(&self, mount: &MountTarget) -> Option<std::time::Duration> {
+        mount
+            .wait_for_source
+            .or(self.wait_for_source)
+            .filter(|timeout| !timeout.is_zero())
+    }
+
+    fn mount_filtered(&mut self, root: &Path, tag: Option<&str>) -> std::io::Result<()> {
+        let diagnostics: Vec<_> = self
+            .validate()
+            .into_iter()
+            .filter(|diagnostic| !diagnostic.problem.is_pending())
+            .collect();
+        if !diagnostics.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                Error::InvalidMounts(diagnostics),
+            ));
+        }
+        // canonical, so what is unmounted can be checked against it, and mounts are
+        // recorded under the same root they are looked up in later
+        let root = &root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+        // let ordered = self.sort_mounts();
+        // for (source, mount) in ordered {
+        //     let m = mount.mount(source, root)?;
+        //     self.mounts.push(m);
+        // }
+        //
+        let active = mountinfo::read().unwrap_or_else(|e| {
+            tracing::debug!(?e, "Can't read mountinfo, not looking for active mounts");
+            Vec::new()
+        });
+        let span = tracing::debug_span!(
+            "mount_all",
+            root = %root.display(),
+            count = self.inner.len(),
+            tag = tracing::field::Empty,
+        );
+        if let Some(tag) = tag {
+            span.record("tag", tag);
+        }
+        let _entered = span.enter();
+        let mut progress = std::mem::take(&mut self.progress);
+        let result = self.mount_entries(root, tag, &active, &mut progress);
+        self.progress = progress;
+        self.mounts.extend(result?);
+        self.root = Some(root.to_path_buf());
+        Ok(())
+    }
+
+    /// Mount the entries of the table in order, each in its own span
+    fn mount_entries(
+        &self,
+        root: &Path,
+        tag: Option<&str>,
+        active: &[mountinfo::MountInfo],
+        progress: &mut Progress,
+    ) -> std::io::Result<Vec<Tracked>> {
+        let selinux = selinux::enabled();
+        let owned = self.mounted_paths();
+        let mut mounts = Vec::new();
+        let entries = self
+            .sort_mounts()
+            .filter(|(_, mount)| tag.is_none() || mount.tag.as_deref() == tag);
+        for (source, mount) in entries {
+            let mount = self.prepare(mount, selinux);
+            let span = tracing::debug_span!(
+                "mount",
+                source = %source.display(),
+                target = %mount.target.display(),
+                fstype = tracing::field::Empty,
+                duration_ms = tracing::field::Empty,
+                outcome = tracing::field::Empty,
+            );
+            if let Some(fstype) = &mount.fstype {
+                span.record("fstype", fstype.as_str());
+            }
+            let _entered = span.enter();
+            let start = Instant::now();
+            progress.emit(ProgressEvent::MountStarted {
+                source: source.clone(),
+                target: mount.target.clone(),
+            });
+            let result = self.mount_entry(source, &mount, root, active, &owned);
+            self.record_mount(source, &mount, root, &result);
+            let outcome = match &result {
+                Ok(Some(Tracked {
+                    guard: MountGuard::Owned(_) | MountGuard::System(_),
+                    ..
+                })) => "mounted",
+                Ok(Some(Tracked {
+                    guard: MountGuard::Adopted(_),
+                    ..
+                })) => "adopted",
+                Ok(None) => "kept",
+                Err(_) => "failed",
+            };
+            progress::finish(&span, start, outcome);
+            progress.emit(ProgressEvent::MountFinished {
+                source: source.clone(),
+                target: mount.target.clone(),
+                ok: result.is_ok(),
+            });
+            mounts.extend(result?);
+        }
+        Ok(mounts)
+    }
+
+    /// Write the result of [`MountTable::mount_entry`] to the journal
+    fn record_mount(
+        &self,
+        source: &Path,
+        mount: &MountTarget,
+        root: &Path,
+        result: &std::io::Result<Option<Tracked>>,
+    ) {
+        let (op, path) = match result {
+            Ok(None) => return,
+            Ok(Some(Tracked {
+                guard: guard @ (MountGuard::Owned(_) | MountGuard::System(_)),
+                ..
+            })) => (JournalOp::Mount, guard.target_path().to_path_buf()),
+            Ok(Some(Tracked {
+                guard: MountGuard::Adopted(path),
+                ..
+            })) => (JournalOp::Adopt, path.clone()),
+            Err(_) => (
+                JournalOp::Mount,
+                resolve_in_root(root, &mount.target, Create::Nothing)
+                    .unwrap_or_else(|_| root.join(relative(&mount.target))),
+            ),
+        };
+        self.journal
+            .record(&JournalRecord::mount(op, source, mount, path).outcome(result));
+    }
+
+    /// Mount a single entry, or adopt it if it's already active, holding its lock
+    ///
+    /// Returns `None` for entries the table has already mounted.
+    fn mount_entry(
+        &self,
+        source: &Path,
+        mount: &MountTarget,
+        root: &Path,
+        active: &[mountinfo::MountInfo],
+        owned: &[PathBuf],
+    ) -> std::io::Result<Option<Tracked>> {
+        let wait = self.source_wait(mount);
+        let source = &match MountSource::from(source) {
+            MountSource::Path(path) => {
+                // only paths on the host, not the names of virtual filesystems
+                if let Some(timeout) = wait.filter(|_| path.is_absolute()) {
+                    wait::wait_for_path(&path, timeout)?;
+                }
+                path
+            }
+            source => source.resolve_timeout(self.device_timeout.max(wait.unwrap_or_default()))?,
+        };
+        let active_path = mount.active_path(source, root, active);
+        if let Some(path) = &active_path {
+            if owned.contains(path) {
+                tracing::trace!(?path, "Already mounted by the table");
+                return Ok(None);
+            }
+            if self.foreign.contains(path) {
+                tracing::trace!(?path, "Already mounted by someone else, leaving it alone");
+                return Ok(None);
+            }
+            if self.strict_mounts {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AlreadyExists,
+                    Error::AlreadyMounted {
+                        target: mount.target.clone(),
+                    },
+                ));
+            }
+        }
+        // taken before mounting, so nothing is mounted while another holds it
+        let lock = mount.lock.as_ref().map(MountLock::acquire).transpose()?;
+        let guard = match active_path {
+            Some(path) => {
+                tracing::debug!(?path, ?source, "Adopting active mount");
+                MountGuard::Adopted(path)
+            }
+            None => {
+                tracing::trace!(?mount, ?source, "Mounting");
+                MountGuard::Owned(mount.mount(source, root)?)
+            }
+        };
+        Ok(Some(Tracked {
+            guard,
+            tag: mount.tag.clone(),
+            _lock: lock,
+        }))
+    }
+
+    /// Change the flags and data of the active mount at `target`, without unmounting it
+    ///
+    /// Bind mounts stay bind mounts, only their per-mount flags such as read-only can be
+    /// changed. The table is updated to match, replacing the mount's data and data options.
+    /// Fails with [`Error::NotMounted`] if nothing from the table is mounted at `target`.
+    pub fn remount(
+        &mut self,
+        target: &Path,
+        new_flags: MountFlags,
+        new_data: Option<&str>,
+    ) -> std::io::Result<()> {
+        let not_mounted = || {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                Error::NotMounted {
+                    target: target.to_path_buf(),
+                },
+            )
+        };
+        let root = self.root.as_deref().ok_or_else(not_mounted)?;
+        let path = resolve_in_root(root, target, Create::Nothing)?;
+        if !self.mounted_paths().contains(&path) {
+            return Err(not_mounted());
+        }
+        let mount = self
+            .inner
+            .values_mut()
+            .find(|mount| relative(&mount.target) == relative(target))
+            .ok_or_else(not_mounted)?;
+
+        let bind = mount.flags & (MountFlags::BIND | MountFlags::REC);
+        let new_flags = new_flags - (MountFlags::BIND | MountFlags::REC);
+        let mut flags = nix::mount::MsFlags::from_bits_truncate(new_flags.bits())
+            | nix::mount::MsFlags::MS_REMOUNT;
+        if bind.contains(MountFlags::BIND) {
+            flags |= nix::mount::MsFlags::MS_BIND;
+        }
+        tracing::debug!(?path, ?flags, ?new_data, "Remounting");
+        let result = nix::mount::mount(None::<&str>, &path, None::<&str>, flags, new_data)
+            .map_err(std::io::Error::from);
+        self.journal.record(
+            &JournalRecord {
+                target: Some(target.to_path_buf()),
+                flags: flags.bits(),
+                data: new_data.map(String::from),
+                ..JournalRecord::new(JournalOp::Remount, &path)
+            }
+            .outcome(&result),
+        );
+        result?;
+
+        mount.flags = new_flags | bind;
+        mount.data = new_data.map(String::from);
+        mount.data_options = None;
+        Ok(())
+    }
+
+    /// Disarm the unmount guards, leaving everything mounted, and return what was mounted
+    fn leak(&mut self, root: &Path) -> Vec<PersistedMount> {
+        let mounted = self.mounted_paths();
+        let record = self
+            .plan(root)
+            .into_iter()
+            .filter(|mount| mounted.contains(&mount.target))
+            .collect();
+        for mount in self.mounts.drain(..) {
+            mount.guard.forget();
+        }
+        record
+    }
+
+    /// The mounts of the table in mount order, with their mountpoints on the host
+    fn plan(&self, root: &Path) -> Vec<PersistedMount> {
+        self.sort_mounts()
+            .map(|(source, mount)| PersistedMount {
+                source: source.clone(),
+                target: resolve_in_root(root, &mount.target, Create::Nothing)
+                    .unwrap_or_else(|_| root.join(relative(&mount.target))),
+                fstype: mount.fstype.clone(),
+            })
+            .collect()
+    }
+
+    pub fn umount_chroot(&mut self) -> std::io::Result<()> {
+        let span = tracing::debug_span!("unmount_all", count = self.mounts.len());
+        let _entered = span.enter();
+        let mounts = self.mounts.drain(..).collect();
+        self.unmount_each(mounts)
+    }
+
+    /// Unmount only the active mounts tagged `tag`, in reverse mount order, see
+    /// [`MountTarget::tag`]
+    ///
+    /// Other mounts nested under them, such as a mount of another tag inside a tagged
+    /// bind mount, would keep them busy or be hidden, so this fails with
+    /// [`Error::NestedMount`] without unmounting anything, unless `cascade` is set to
+    /// unmount those too.
+    pub fn umount_tagged(&mut self, tag: &str, cascade: bool) -> std::io::Result<()> {
+        let tagged: Vec<usize> = (0..self.mounts.len())
+            .filter(|&i| self.mounts[i].tag.as_deref() == Some(tag))
+            .collect();
+        let mut selected = tagged.clone();
+        for (i, mount) in self.mounts.iter().enumerate() {
+            if tagged.contains(&i) {
+                continue;
+            }
+            let path = mount.guard.target_path();
+            // only those mounted later can be on top of a tagged mount
+            let Some(parent) = tagged
+                .iter()
+                .filter(|&&parent| parent < i)
+                .map(|&parent| self.mounts[parent].guard.target_path())
+                .find(|parent| path.starts_with(parent))
+            else {
+                continue;
+            };
+            if !cascade {
+                return Err(std::io::Error::other(Error::NestedMount {
+                    tag: tag.to_string(),
+                    parent: parent.to_path_buf(),
+                    child: path.to_path_buf(),
+                }));
+            }
+            selected.push(i);
+        }
+        selected.sort_unstable();
+
+        let span = tracing::debug_span!("unmount_tagged", tag, count = selected.len());
+        let _entered = span.enter();
+        // removed from the end, so the indices before stay valid
+        let mut mounts: Vec<Tracked> = selected
+            .into_iter()
+            .rev()
+            .map(|i| self.mounts.remove(i))
+            .collect();
+        mounts.reverse();
+        self.unmount_each(mounts)
+    }
+
+    /// Unmount `mounts`, given in mount order, from the last one
+    ///
+    /// Stops at the first failure, the mounts left are then unmounted as they're dropped.
+    /// Mounts that now resolve outside the root, e.g. as a directory of the container
+    /// was replaced by a symlink to the host's `/proc`, are left alone and reported
+    /// with [`Error::PathEscape`] once the others are unmounted.
+    fn unmount_each(&mut self, mounts: Vec<Tracked>) -> std::io::Result<()> {
+        let labels = self.labels();
+        let mut progress = std::mem::take(&mut self.progress);
+        let mut escaped = None;
+        let result = mounts.into_iter().rev().try_for_each(|mount| {
+            let target = mount.guard.target_path();
+            if let Some(root) = self
+                .root
+                .as_deref()
+                .filter(|root| danger::escapes(root, target))
+            {
+                tracing::error!(
+                    ?target,
+                    ?root,
+                    "Refusing to unmount outside the container root"
+                );
+                escaped.get_or_insert_with(|| target.to_path_buf());
+                mount.guard.forget();
+                return Ok(());
+            }
+            Self::unmount_guard(mount.guard, &labels, &mut progress, &self.journal)
+        });
+        self.progress = progress;
+        result?;
+        match escaped {
+            Some(target) => Err(std::io::Error::other(Error::PathEscape { target })),
+            None => Ok(()),
+        }
+    }
+
+    /// Unmount a single mount in its own span, `labels` being the table's [`MountTable::labels`]
+    fn unmount_guard(
+        mount: MountGuard,
+        labels: &HashMap<PathBuf, (PathBuf, Option<String>)>,
+        progress: &mut Progress,
+        journal: &journal::Journal,
+    ) -> std::io::Result<()> {
+        let target = mount.target_path().to_path_buf();
+        let span = tracing::debug_span!(
+            "unmount",
+            target = %target.display(),
+            source = tracing::field::Empty,
+            fstype = tracing::field::Empty,
+            duration_ms = tracing::field::Empty,
+            outcome = tracing::field::Empty,
+        );
+        if let Some((source, fstype)) = labels.get(&target) {
+            span.record("source", source.display().to_string().as_str());
+            if let Some(fstype) = fstype {
+                span.record("fstype", fstype.as_str());
+            }
+        }
+        let _entered = span.enter();
+        let start = Instant::now();
+        progress.emit(ProgressEvent::UnmountStarted {
+            target: target.clone(),
+        });
+        tracing::trace!("Unmounting {target:?}");
+        // this causes ENOENT when not chrooting properly
+        let result = mount.unmount();
+        let outcome = if result.is_ok() {
+            "unmounted"
+        } else {
+            "failed"
+        };
+        progress::finish(&span, start, outcome);
+        let (source, fstype) = labels.get(&target).cloned().unzip();
+        journal.record(
+            &JournalRecord {
+                source,
+                fstype: fstype.flatten(),
+                ..JournalRecord::new(JournalOp::Unmount, &target)
+            }
+            .outcome(&result),
+        );
+        progress.emit(ProgressEvent::UnmountFinished {
+            target,
+            ok: result.is_ok(),
+        });
+        result
+    }
+
+    /// Source and filesystem type of the mounts of the table, by their path on the host
+    fn labels(&self) -> HashMap<PathBuf, (PathBuf, Option<String>)> {
+        let Some(root) = &self.root else {
+            return HashMap::new();
+        };
+        self.inner
+            .iter()
+            .filter_map(|(source, mount)| {
+                let path = resolve_in_root(root, &mount.target, Create::Nothing).ok()?;
+                Some((path, (source.clone(), mount.fstype.clone())))
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Debug for MountTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MountTable")
+            .field("inner", &self.inner)
+            .field("mounts", &self.mounted_paths())
+            .field("root", &self.root)
+            .field("tmpfs_context", &self.tmpfs_context)
+            .field("selinux_strict", &self.selinux_strict)
+            .field("strict_mounts", &self.strict_mounts)
+            .field("progress", &self.progress)
+            .field("foreign", &self.foreign)
+            .finish()
+    }
+}
+
+/// What to do with processes started by [`Container::spawn`] that are
+/// still running when the container is unmounted
+///
+/// Dropping the container always kills them, as it can't fail.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChildPolicy {
+    /// Return an error from [`Container::umount`]
+    #[default]
+    Refuse,
+    /// Kill them with SIGKILL
+    Kill,
+    /// Wait up to the given duration for them to exit, then return an error
+    Wait(std::time::Duration),
+}
+
+/// What to do before unmounting the container
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnmountPolicy {
+    /// Unmount right away, even if other processes still use the container
+    #[default]
+    Plain,
+    /// Terminate any process still using the container with SIGTERM first,
+    /// then SIGKILL those that survive, see [`Container::kill_lingering`]
+    KillUsers,
+}
+
+/// Called with errors a container can't return as it's dropped,
+/// see [`Container::on_drop_error`]
+type DropErrorCallback = Box<dyn FnMut(&Error) + Send>;
+
+/// Container Struct
+/// A tiffin container is a simple chroot jail that can be used to run code inside.
+///
+/// May require root permissions to use.
+pub struct Container {
+    pub root: PathBuf,
+    pub mount_table: MountTable,
+    id: String,
+    /// Parent of the spans of everything the container does
+    span: tracing::Span,
+    _initialized: bool,
+    chroot: bool,
+    sysroot: File,
+    pwd: File,
+    workdir: Option<PathBuf>,
+    create_workdir: bool,
+    user: Option<User>,
+    capabilities: CapabilitySet,
+    rlimits: Rlimits,
+    env: Environment,
+    cgroup_config: Option<CgroupConfig>,
+    cgroup: Option<Cgroup>,
+    children: Vec<process::SpawnedChild>,
+    child_policy: ChildPolicy,
+    unmount_policy: UnmountPolicy,
+    pid_namespace: bool,
+    namespaces: Namespaces,
+    hostname: Option<String>,
+    shell: Option<PathBuf>,
+    network_files: netconf::NetworkFiles,
+    etc_files: backup::Backups,
+    gpu_files: backup::Backups,
+    /// Mountpoints created in the image by tiffin, removed at teardown
+    provisioned: Vec<PathBuf>,
+    /// The marker written at the root while mounted, see [`ContainerMarker`]
+    marker: Option<PathBuf>,
+    cleanup_id: Option<usize>,
+    chroot_lock: Option<lock::ChrootLock>,
+    /// How many containers are chrooted into each other, this one included, while chrooted
+    chroot_depth: usize,
+    on_drop_error: Option<DropErrorCallback>,
+    hooks: hooks::Hooks,
+    seal: Option<SealOptions>,
+    #[cfg(feature = "seccomp")]
+    seccomp: Option<SeccompPolicy>,
+}
+
+/// Everything but the file descriptors of the host root and working directory
+impl std::fmt::Debug for Container {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("Container");
+        debug
+            .field("root", &self.root)
+            .field("id", &self.id)
+            .field("mount_table", &self.mount_table)
+            .field("initialized", &self._initialized)
+            .field("chroot", &self.chroot)
+            .field("workdir", &self.workdir)
+            .field("create_workdir", &self.create_workdir)
+            .field("user", &self.user)
+            .field("capabilities", &self.capabilities)
+            .field("rlimits", &self.rlimits)
+            .field("env", &self.env)
+            .field("cgroup_config", &self.cgroup_config)
+            .field("cgroup", &self.cgroup)
+            .field("children", &self.children)
+            .field("child_policy", &self.child_policy)
+            .field("unmount_policy", &self.unmount_policy)
+            .field("pid_namespace", &self.pid_namespace)
+            .field("namespaces", &self.namespaces)
+            .field("hostname", &self.hostname)
+            .field("shell", &self.shell)
+            .field("network_files", &self.network_files)
+            .field("etc_files", &self.etc_files)
+            .field("gpu_files", &self.gpu_files)
+            .field("provisioned", &self.provisioned)
+            .field("marker", &self.marker)
+            .field("cleanup_id", &self.cleanup_id)
+            .field("chroot_lock", &self.chroot_lock)
+            .field("chroot_depth", &self.chroot_depth)
+            .field("on_drop_error", &self.on_drop_error.is_some())
+            .field("hooks", &self.hooks)
+            .field("seal", &self.seal);
+        #[cfg(feature = "seccomp")]
+        debug.field("seccomp", &self.seccomp);
+        debug.finish_non_exhaustive()
+    }
+}
+
+impl Container {
+    /// Enter chroot jail
+    ///
+    /// This makes use of the `chroot` syscall to enter the chroot jail.
+    ///
+    /// The root directory is shared by all threads, so this waits until no other
+    /// container is chrooted in this process. See [`Container::try_chroot`] to fail instead.
+    ///
+    /// Containers can be nested: a container chrooted by the thread that chrooted another
+    /// one doesn't wait, and its root is a path inside the other container. The inner
+    /// container has to exit its chroot, or be dropped, before the outer one exits its own.
+    #[inline(always)]
+    pub fn chroot(&mut self) -> std::io::Result<()> {
+        self.hooks.check_reentrancy()?;
+        if self.chroot_lock.is_none() {
+            self.chroot_lock = Some(lock::ChrootLock::acquire());
+        }
+        self.enter_chroot()
+    }
+
+    /// Like [`Container::chroot`], but fails with [`std::io::ErrorKind::WouldBlock`]
+    /// if another container is chrooted in this process
+    pub fn try_chroot(&mut self) -> std::io::Result<()> {
+        self.hooks.check_reentrancy()?;
+        if self.chroot_lock.is_none() {
+            self.chroot_lock = Some(lock::ChrootLock::try_acquire()?);
+        }
+        self.enter_chroot()
+    }
+
+    /// Mount if needed and chroot, with the lock already held
+    fn enter_chroot(&mut self) -> std::io::Result<()> {
+        let result = self.enter_chroot_locked();
+        if result.is_err() && !self.chroot {
+            self.chroot_lock = None;
+        }
+        result
+    }
+
+    fn enter_chroot_locked(&mut self) -> std::io::Result<()> {
+        if !self._initialized {
+            // mount the tmpfs first, idiot proofing in case the
+            // programmer forgets to mount it before chrooting
+            //
+            // This should be fine as it's going to be dismounted after dropping regardless
+            self.mount()?;
+        }
+        self.run_hooks(Phase::PreEnter)?;
+
+        // where to go back to, which is inside another container when nested
+        self.sysroot = File::open("/")?;
+        self.pwd = File::open(".")?;
+        self.chroot_depth = lock::ChrootLock::depth();
+        if self.chroot_depth > 1 {
+            tracing::debug!(depth = self.chroot_depth, root = ?self.root, "Entering nested chroot");
+        }
+        let result = nix::unistd::chroot(&self.root).map_err(std::io::Error::from);
+        self.mount_table
+            .journal
+            .record(&JournalRecord::new(JournalOp::ChrootEnter, &self.root).outcome(&result));
+        result?;
+        self.chroot = true;
+        nix::unistd::chdir("/")?;
+        if let Err(e) = process::enter_workdir(self.workdir.as_deref(), self.create_workdir) {
+            self.leave_chroot()?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Exits the chroot
+    ///
+    /// This works by changing the current working directory
+    /// to a raw file descriptor of the sysroot we saved earlier
+    /// in `[Container::new]`, and then chrooting to the directory
+    /// we just moved to.
+    ///
+    /// We then also take the pwd stored earlier and move back to it,
+    /// for good measure.
+    #[inline(always)]
+    pub fn exit_chroot(&mut self) -> std::io::Result<()> {
+        self.hooks.check_reentrancy()?;
+        self.leave_chroot()?;
+        self.chroot_lock = None;
+        self.run_hooks(Phase::PostExit)
+    }
+
+    /// Exit the chroot, without releasing the lock
+    fn leave_chroot(&mut self) -> std::io::Result<()> {
+        if lock::ChrootLock::depth() > self.chroot_depth {
+            return Err(std::io::Error::other(Error::NestedChroot {
+                root: self.root.clone(),
+            }));
+        }
+        let result = nix::unistd::fchdir(self.sysroot.as_raw_fd())
+            .and_then(|()| nix::unistd::chroot("."))
+            .map_err(std::io::Error::from);
+        self.mount_table
+            .journal
+            .record(&JournalRecord::new(JournalOp::ChrootExit, &self.root).outcome(&result));
+        result?;
+        self.chroot = false;
+
+        // Let's return back to pwd
+        nix::unistd::fchdir(self.pwd.as_raw_fd())?;
+        Ok(())
+    }
+
+    /// Create a new tiffin container
+    ///
+    /// To use it, you need to create a new container with `root`
+    /// set to the location of the chroot you'd like to use.
+    ///
+    /// The default mounts from [`Container::add_default_mounts`] are added.
+    ///
+    /// Panics if the current directory or the host root can't be opened.
+    /// [`Container::builder`] is the preferred way to create a container.
+    /// The root isn't checked, see [`Container::try_new`].
+    pub fn new(chrootpath: impl Into<PathBuf>) -> Self {
+        let mut container = Self::new_bare(chrootpath);
+        container.add_default_mounts();
+        container
+    }
+
+    /// Like [`Container::new`], but fails instead of panicking, and refuses dangerous roots
+    ///
+    /// Fails with [`Error::DangerousRoot`] if the root resolves to `/`, or to a path
+    /// containing critical host paths such as `/usr` or `/proc`, as unmounting the
+    /// container would then unmount them on the host. Use
+    /// [`ContainerBuilder::allow_dangerous_root`] to allow it anyway.
+    pub fn try_new(chrootpath: impl Into<PathBuf>) -> Result<Self> {
+        let root = chrootpath.into();
+        danger::check_root(&root)?;
+        let mut container = Self::open(root)?;
+        container.add_default_mounts();
+        Ok(container)
+    }
+
+    /// Create a new tiffin container with nothing in its mount table
+    ///
+    /// Useful when the root shouldn't be touched, e.g. to inspect an image offline.
+    /// Mounting and dropping a bare container is a no-op, unless mounts are added.
+    pub fn new_bare(chrootpath: impl Into<PathBuf>) -> Self {
+        Self::open(chrootpath.into()).unwrap()
+    }
+
+    /// Configure a new container, see [`ContainerBuilder`]
+    pub fn builder() -> ContainerBuilder {
+        ContainerBuilder::new()
+    }
+
+    /// Take over a chroot prepared by another tool, such as mock, with its mounts in place
+    ///
+    /// The mounts at or under `root` are foreign: they are listed by [`Container::status`],
+    /// but tiffin never mounts over them or unmounts them, not even when the container is
+    /// dropped. The mount table starts empty and the container counts as mounted, so
+    /// [`Container::run`] doesn't mount anything. Mounts added afterwards are owned as
+    /// usual, mounted by [`Container::mount`] and unmounted by [`Container::umount`].
+    ///
+    /// The root of the container is `root` with symlinks resolved, as in mountinfo.
+    pub fn adopt(root: &Path) -> Result<Self> {
+        let root = root.canonicalize()?;
+        let foreign: Vec<PathBuf> = mountinfo::mounts_under(&root)?
+            .into_iter()
+            .map(|mount| mount.mount_point)
+            .collect();
+        tracing::debug!(?root, ?foreign, "Adopting chroot");
+        let mut container = Self::open(root)?;
+        container.mount_table.foreign = foreign;
+        container._initialized = true;
+        Ok(container)
+    }
+
+    /// Random id of the container, generated when it is created, which its `tracing`
+    /// spans carry
+    ///
+    /// While mounted, the container writes a [`ContainerMarker`] named after it at its root.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Use `id` instead of a random id, see [`Container::id`]
+    ///
+    /// It can only contain ASCII letters, digits, `-`, `_` and `.`, and should be unique
+    /// among the containers sharing a root. Set it before mounting, as the marker of a
+    /// mounted container keeps its previous name.
+    pub fn with_id(&mut self, id: impl Into<String>) -> Result<&mut Self> {
+        let id = id.into();
+        if !marker::valid_id(&id) {
+            return Err(Error::InvalidId { id });
+        }
+        self.span = tracing::info_span!("container", id = %id, root = %self.root.display());
+        self.id = id;
+        Ok(self)
+    }
+
+    /// Create a container at `dest` from a rootfs archive, see [`unpack_tarball`]
+    ///
+    /// The container has the default mounts. Use [`unpack_tarball`] directly to follow
+    /// the progress, or to know which device nodes couldn't be created.
+    #[cfg(feature = "tarball")]
+    pub fn from_tarball(archive: impl AsRef<Path>, dest: impl Into<PathBuf>) -> Result<Self> {
+        let dest = dest.into();
+        let report = unpack_tarball(archive, &dest, |_| {})?;
+        if !report.skipped_nodes.is_empty() {
+            tracing::warn!(skipped = ?report.skipped_nodes, "Some device nodes were not created");
+        }
+        Ok(Self::new(dest))
+    }
+
+    /// Unpack a rootfs archive into the container root, see [`unpack_tarball`]
+    ///
+    /// With a callback from [`Container::on_progress`], the archive is read twice so
+    /// [`ProgressEvent::ExtractEntry`] can tell how many entries there are in total.
+    #[cfg(feature = "tarball")]
+    pub fn unpack_tarball(&mut self, archive: impl AsRef<Path>) -> Result<UnpackReport> {
+        let span = tracing::info_span!(parent: &self.span, "unpack", archive = ?archive.as_ref());
+        let _entered = span.enter();
+        let progress = &mut self.mount_table.progress;
+        let total = if progress.is_set() {
+            tarball::count_entries(&archive)?
+        } else {
+            0
+        };
+        unpack_tarball(archive, &self.root, |report| {
+            progress.emit(ProgressEvent::ExtractEntry {
+                n: report.entries,
+                total,
+            });
+        })
+    }
+
+    /// Create a container in a new temporary directory, deleted when the container is dropped
+    ///
+    /// The container has the default mounts. See [`EphemeralContainer::builder`]
+    /// to choose where the directory is created, or to populate it.
+    pub fn ephemeral() -> Result<EphemeralContainer> {
+        EphemeralContainer::builder().build()
+    }
+
+    /// Create a container with an empty mount table
+    fn open(chrootpath: PathBuf) -> std::io::Result<Self> {
+        // /proc may not be mounted when nested in another container
+        let pwd = std::fs::File::open(".")?;
+        let sysroot = std::fs::File::open("/")?;
+        if inside_chroot() {
+            tracing::debug!(root = ?chrootpath, "Creating a container inside a chroot");
+        }
+
+        let id = generate_id();
+        let span = tracing::info_span!("container", id = %id, root = %chrootpath.display());
+        Ok(Self {
+            pwd,
+            root: chrootpath,
+            mount_table: MountTable::new(),
+            id,
+            span,
+            sysroot,
+            _initialized: false,
+            chroot: false,
+            workdir: None,
+            create_workdir: false,
+            user: None,
+            capabilities: CapabilitySet::default(),
+            rlimits: Rlimits::default(),
+            env: Environment::default(),
+            cgroup_config: None,
+            cgroup: None,
+            children: Vec::new(),
+            child_policy: ChildPolicy::default(),
+            unmount_policy: UnmountPolicy::default(),
+            pid_namespace: false,
+            namespaces: Namespaces::default(),
+            hostname: None,
+            shell: None,
+            network_files: netconf::NetworkFiles::default(),
+            etc_files: backup::Backups::default(),
+            gpu_files: backup::Backups::default(),
+            provisioned: Vec::new(),
+            marker: None,
+            cleanup_id: None,
+            chroot_lock: None,
+            chroot_depth: 0,
+            on_drop_error: None,
+            hooks: hooks::Hooks::default(),
+            seal: None,
+            #[cfg(feature = "seccomp")]
+            seccomp: None,
+        })
+    }
+
+    /// Run a function inside the container chroot
+    ///
+    /// Only one container at a time can run in-process, so this waits until no other
+    /// container is chrooted in this process. See [`Container::try_run`] to fail instead,
+    /// or [`Container::run_forked`] which doesn't chroot the calling process at all.
+    ///
+    /// A container can be run inside `f`, nested in this one, see [`Container::chroot`].
+    #[inline(always)]
+    pub fn run<F, T>(&mut self, f: F) -> std::io::Result<T>
+    where
+        F: FnOnce() -> T,
+    {
+        self.hooks.check_reentrancy()?;
+        let lock = match self.chroot_lock.take() {
+            Some(lock) => lock,
+            None => lock::ChrootLock::acquire(),
+        };
+        self.run_locked(lock, f)
+    }
+
+    /// Like [`Container::run`], but fails with [`std::io::ErrorKind::WouldBlock`]
+    /// if another container is chrooted in this process
+    pub fn try_run<F, T>(&mut self, f: F) -> std::io::Result<T>
+    where
+        F: FnOnce() -> T,
+    {
+        self.hooks.check_reentrancy()?;
+        let lock = match self.chroot_lock.take() {
+            Some(lock) => lock,
+            None => lock::ChrootLock::try_acquire()?,
+        };
+        self.run_locked(lock, f)
+    }
+
+    /// Run a function inside the chroot, holding the lock until the container is unmounted again
+    fn run_locked<F, T>(&mut self, lock: lock::ChrootLock, f: F) -> std::io::Result<T>
+    where
+        F: FnOnce() -> T,
+    {
+        let result = self.run_inner(f);
+        if self.chroot {
+            // still inside after a failure, keep the lock until exit_chroot
+            self.chroot_lock = Some(lock);
+        }
+        result
+    }
+
+    fn run_inner<F, T>(&mut self, f: F) -> std::io::Result<T>
+    where
+        F: FnOnce() -> T,
+    {
+        let _span = self.span.clone().entered();
+        // Only mount and chroot if we're not already initialized
+        if !self._initialized {
+            self.mount()?;
+        }
+        if !self.chroot {
+            self.enter_chroot_locked()?;
+        }
+        tracing::trace!("Running function inside container");
+        let ret = self.rlimits.apply_saved().map(|rlimit_guard| {
+            let env_guard = self.env.apply();
+            let ret = f();
+            drop(env_guard);
+            (ret, rlimit_guard.restore())
+        });
+        // even if the limits couldn't be applied or restored, which is reported afterwards
+        if self.chroot {
+            self.leave_chroot()?;
+            self.run_hooks(Phase::PostExit)?;
+        }
+        if self._initialized {
+            self.umount()?;
+        }
+        let (ret, restored) = ret?;
+        restored?;
+        Ok(ret)
+    }
+
+    /// Run a function inside the container chroot in a forked child process
+    ///
+    /// Only the child enters the chroot, so the parent process is never affected.
+    /// The child is sandboxed according to the container's configuration
+    /// (e.g. [`Container::set_capabilities`]) before the function runs.
+    ///
+    /// Returns the exit code of the child, which is the value returned by the function,
+    /// or `128 + signal` if the child was killed by a signal.
+    pub fn run_forked<F>(&mut self, f: F) -> Result<i32>
+    where
+        F: FnOnce() -> i32,
+    {
+        self.fork_and_wait(f, None)
+    }
+
+    /// Like [`Container::run_forked`], but terminate the child if it runs longer than `timeout`
+    ///
+    /// The child runs in its own process group, which gets SIGTERM once the timeout is hit,
+    /// then SIGKILL if it's still running 5 seconds later. The container is cleaned up
+    /// as usual and [`Error::TimedOut`] is returned.
+    pub fn run_forked_with_timeout<F>(&mut self, timeout: std::time::Duration, f: F) -> Result<i32>
+    where
+        F: FnOnce() -> i32,
+    {
+        self.fork_and_wait(f, Some(timeout))
+    }
+
+    fn fork_and_wait<F>(&mut self, f: F, timeout: Option<std::time::Duration>) -> Result<i32>
+    where
+        F: FnOnce() -> i32,
+    {
+        let _span = self.span.clone().entered();
+        if !self._initialized {
+            self.mount()?;
+        }
+        let child = self.fork_payload(f, timeout.is_some())?;
+        let (code, timed_out) = match timeout {
+            Some(timeout) => {
+                process::wait_timeout(child, timeout, process::DEFAULT_GRACE_PERIOD, || {
+                    process::try_wait_pid(child)
+                })?
+            }
+            None => (process::wait_pid(child)?, false),
+        };
+        self.finish_forked()?;
+        match timeout {
+            Some(timeout) if timed_out => Err(Error::TimedOut {
+                timeout,
+                output: None,
+            }),
+            _ => Ok(code),
+        }
+    }
+
+    /// Fork a child that enters the container and runs `f`, returning its pid
+    ///
+    /// With `own_group`, the child leads a new process group so it can be terminated as a whole.
+    fn fork_payload<F>(&mut self, f: F, own_group: bool) -> Result<Pid>
+    where
+        F: FnOnce() -> i32,
+    {
+        self.ensure_cgroup()?;
+        let setup = self.child_setup()?;
+        let env = setup.environment();
+        let pid_namespace = self.pid_namespace;
+
+        // SAFETY: the child only enters the container and runs `f` before exiting
+        match unsafe { nix::unistd::fork() }? {
+            nix::unistd::ForkResult::Child => process::run_child(|| {
+                if own_group {
+                    if let Err(e) = nix::unistd::setpgid(Pid::from_raw(0), Pid::from_raw(0)) {
+                        tracing::error!(?e, "Failed to create process group");
+                        return 127;
+                    }
+                }
+                env.replace();
+                let payload = || match setup.enter() {
+                    Ok(()) => f(),
+                    Err(e) => {
+                        tracing::error!(?e, "Failed to enter container");
+                        127
+                    }
+                };
+                if pid_namespace {
+                    process::run_in_pid_namespace(payload)
+                } else {
+                    payload()
+                }
+            }),
+            nix::unistd::ForkResult::Parent { child } => {
+                tracing::trace!(?child, "Running function in forked child");
+                // also done here, as shells do, so the group exists as soon as this returns
+                // even if the child hasn't run yet, and a timeout can kill it
+                if own_group {
+                    if let Err(e) = nix::unistd::setpgid(child, child) {
+                        // the child may have exited or exec'd already, having done it itself
+                        tracing::trace!(?e, ?child, "Failed to create process group");
+                    }
+                }
+                Ok(child)
+            }
+        }
+    }
+
+    /// Clean up after a forked child was reaped
+    fn finish_forked(&mut self) -> std::io::Result<()> {
+        if let Some(cgroup) = &self.cgroup {
+            cgroup.kill()?;
+        }
+        if self._initialized {
+            self.umount()?;
+        }
+        Ok(())
+    }
+
+    /// Snapshot of the configuration needed to enter the container from a forked child
+    pub(crate) fn child_setup(&self) -> std::io::Result<process::ChildSetup> {
+        let workdir = self
+            .workdir
+            .as_ref()
+            .map(|workdir| Path::new("/").join(workdir));
+        let create_dirs = match &workdir {
+            Some(workdir) if self.create_workdir => process::workdir_dirs(workdir)?,
+            _ => Vec::new(),
+        };
+        if self.seal.is_some() && !Path::new("/proc/self/fd").is_dir() {
+            tracing::warn!("Can't list descriptors, only the container's are closed");
+        }
+        Ok(process::ChildSetup {
+            root: process::c_path(&self.root)?,
+            workdir: workdir.as_deref().map(process::c_path).transpose()?,
+            create_dirs,
+            env: self.env.clone(),
+            rlimits: self.rlimits.clone(),
+            capabilities: self.capabilities,
+            user: self.user.clone(),
+            groups: self.user.as_ref().map(User::all_groups).unwrap_or_default(),
+            cgroup_procs: self
+                .cgroup
+                .as_ref()
+                .map(|cgroup| process::c_path(&cgroup.path().join("cgroup.procs")))
+                .transpose()?,
+            namespaces: self.namespaces,
+            id_maps: namespace::IdMaps::current(),
+            hostname: self.hostname.clone(),
+            seal: self.seal,
+            escape_fds: vec![self.sysroot.as_raw_fd(), self.pwd.as_raw_fd()],
+            #[cfg(feature = "seccomp")]
+            seccomp: self.seccomp.as_ref().map(SeccompPolicy::filter),
+        })
+    }
+
+    /// Create the cgroup if one is configured and it doesn't exist yet
+    fn ensure_cgroup(&mut self) -> Result<()> {
+        if self.cgroup.is_none() {
+            if let Some(config) = &self.cgroup_config {
+                self.cgroup = Some(Cgroup::create(config)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the capabilities kept by code running in [`Container::run_forked`]
+    ///
+    /// By default every capability is kept. See [`CapabilitySet::build_sandbox`]
+    /// for a preset suitable for running builds. With [`Container::set_user`], the user
+    /// is switched to first, so the set doesn't need to keep CAP_SETUID and CAP_SETGID.
+    pub fn set_capabilities(&mut self, capabilities: CapabilitySet) -> &mut Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Sets the user that commands and forked runs switch to, from the container's `/etc/passwd`
+    ///
+    /// Unlike [`Container::run_as_user`], the switch is permanent, so it only applies to
+    /// code running in a forked child such as [`Container::run_forked`] and [`Container::command`].
+    pub fn set_user(&mut self, name: &str) -> Result<&mut Self> {
+        self.user = Some(User::lookup(&self.root, name)?);
+        Ok(self)
+    }
+
+    /// Prepare a command that runs inside the container
+    ///
+    /// The container is mounted if it isn't already. The returned command enters
+    /// the container in the forked child right before exec, applying the configured
+    /// user, working directory, environment and sandboxing, so the calling process never
+    /// chroots. Its arguments, stdio and environment can be set as with any other
+    /// [`Command`](std::process::Command).
+    ///
+    /// The command borrows the container so the mounts stay alive while it's used.
+    /// If you [`spawn`](std::process::Command::spawn) it, make sure the child is reaped
+    /// before the container is unmounted or dropped.
+    pub fn command(
+        &mut self,
+        program: impl AsRef<std::ffi::OsStr>,
+    ) -> Result<ContainerCommand<'_>> {
+        if !self._initialized {
+            self.mount()?;
+        }
+        self.ensure_cgroup()?;
+
+        let setup = self.child_setup()?;
+        // the environment is set through the command itself, rather than in the child
+        let env = setup.environment();
+        let mut command = std::process::Command::new(program);
+        if !env.is_inherit() {
+            command.env_clear().envs(env.resolve(std::env::vars_os()));
+        }
+        // SAFETY: the hook only makes syscalls and touches memory owned by the child
+        unsafe {
+            command.pre_exec(move || setup.enter());
+        }
+        Ok(ContainerCommand::new(self, command))
+    }
+
+    /// Spawn a long-running process inside the container
+    ///
+    /// The container is mounted if it isn't already, and stays mounted while the process runs:
+    /// unmounting handles it according to the [`ChildPolicy`].
+    pub fn spawn(&mut self, spec: CommandSpec) -> Result<ContainerChild> {
+        let child = {
+            let mut command = self.command(&spec.program)?;
+            spec.configure(&mut command);
+            command.spawn()?
+        };
+        tracing::debug!(pid = child.id(), program = ?spec.program, "Spawned process in container");
+        self.children.push(process::SpawnedChild::open(child.id())?);
+        Ok(ContainerChild::new(child))
+    }
+
+    /// Run a program inside the container and capture its output
+    ///
+    /// A non-zero exit status is not an error, check [`Output::status`].
+    /// If the program can't be started at all, e.g. because it doesn't exist
+    /// inside the container, [`Error::Spawn`] is returned instead.
+    pub fn run_output<I, S>(&mut self, program: impl Into<OsString>, args: I) -> Result<Output>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        let spec = CommandSpec::new(program).args(args).stdin(StdioMode::Null);
+        self.run_output_with(spec, OutputOptions::default())
+    }
+
+    /// Run a program inside the container and capture its output, with options
+    ///
+    /// The stdout and stderr modes of `spec` are ignored.
+    ///
+    /// If the program runs longer than the timeout of `spec`, its process group is
+    /// terminated and [`Error::TimedOut`] is returned with the output captured so far.
+    pub fn run_output_with(&mut self, spec: CommandSpec, options: OutputOptions) -> Result<Output> {
+        let (mut child, merged) = {
+            let mut command = self.command(&spec.program)?;
+            spec.configure(&mut command);
+            let merged = if options.merge_stderr {
+                let (read, write) = process::pipe()?;
+                command.stdout(write.try_clone()?).stderr(write);
+                Some(read)
+            } else {
+                command.stdout(Stdio::piped()).stderr(Stdio::piped());
+                None
+            };
+            let child = command.spawn().map_err(|source| Error::Spawn {
+                program: spec.program.clone(),
+                source,
+            })?;
+            // dropping the command closes our copy of the write end of the pipe
+            (child, merged)
+        };
+
+        let stderr = child
+            .stderr
+            .take()
+            .map(|stderr| process::read_limited_thread(stderr, options.limit));
+        let stdout = match (merged, child.stdout.take()) {
+            (Some(merged), _) => Some(process::read_limited_thread(merged, options.limit)),
+            (None, Some(stdout)) => Some(process::read_limited_thread(stdout, options.limit)),
+            (None, None) => None,
+        };
+
+        drop(child.stdin.take());
+        let (status, timed_out) = match spec.timeout {
+            Some(timeout) => process::wait_timeout(
+                Pid::from_raw(child.id() as i32),
+                timeout,
+                spec.grace_period.unwrap_or(process::DEFAULT_GRACE_PERIOD),
+                || child.try_wait(),
+            )?,
+            None => (child.wait()?, false),
+        };
+
+        // the pipes are closed once the whole process group is gone, even after a timeout
+        let (stdout, stdout_truncated) = process::join_reader(stdout)?;
+        let (stderr, stderr_truncated) = process::join_reader(stderr)?;
+        let truncated = stdout_truncated || stderr_truncated;
+
+        let output = Output {
+            status,
+            stdout,
+            stderr,
+            truncated,
+        };
+        match spec.timeout {
+            Some(timeout) if timed_out => Err(Error::TimedOut {
+                timeout,
+                output: Some(Box::new(output)),
+            }),
+            _ => Ok(output),
+        }
+    }
+
+    /// Replace the current process with a program running inside the container
+    ///
+    /// The container is mounted if it isn't already, then this process enters it
+    /// with the configured user, working directory, environment and sandboxing, and
+    /// `program` is executed, looked up in the container's `PATH` if it has no slash.
+    /// This never returns on success.
+    ///
+    /// Taking the container by value opts into leaking it: the mounts stay in place
+    /// after exec, as there is nobody left to unmount them. If exec fails, the
+    /// process is already inside the container and the mounts are still leaked,
+    /// so the caller should report the error and exit.
+    pub fn exec_into<I, S>(
+        mut self,
+        program: impl Into<OsString>,
+        args: I,
+    ) -> Result<std::convert::Infallible>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        let program = program.into();
+        let argv = std::iter::once(program.clone())
+            .chain(args.into_iter().map(Into::into))
+            .map(|arg| CString::new(arg.into_vec()))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+        if !self._initialized {
+            self.mount()?;
+        }
+        self.ensure_cgroup()?;
+        let setup = self.child_setup()?;
+        tracing::debug!(?program, root = ?self.root, "Executing program in container");
+        // no destructors may run, or they would unmount the container from under the program
+        std::mem::forget(self);
+
+        setup.environment().replace();
+        let source = match setup.enter() {
+            Ok(()) => match nix::unistd::execvp(&argv[0], &argv) {
+                Ok(never) => match never {},
+                Err(e) => std::io::Error::from(e),
+            },
+            Err(e) => e,
+        };
+        Err(Error::Spawn { program, source })
+    }
+
+    /// Sets the shell used by [`Container::shell`], as a path inside the container
+    pub fn set_shell(&mut self, shell: impl Into<PathBuf>) -> &mut Self {
+        self.shell = Some(shell.into());
+        self
+    }
+
+    /// Start an interactive shell inside the container, attached to the calling terminal
+    ///
+    /// The shell runs on its own pseudo-terminal as a session leader, while bytes
+    /// are proxied between it and the host terminal, which is switched to raw mode
+    /// and restored when the shell exits. Window size changes are forwarded.
+    ///
+    /// Uses the shell from [`Container::set_shell`], or `/bin/bash`, falling back
+    /// to `/bin/sh` if the container doesn't have bash.
+    pub fn shell(&mut self) -> Result<std::process::ExitStatus> {
+        let shell = self.shell.clone().unwrap_or_else(|| {
+            if self.root.join("bin/bash").exists() {
+                PathBuf::from("/bin/bash")
+            } else {
+                PathBuf::from("/bin/sh")
+            }
+        });
+
+        let (pty, slave) = pty::Pty::open()?;
+        let stdin = std::io::stdin().as_raw_fd();
+        // SAFETY: isatty only inspects the fd
+        let interactive = unsafe { libc::isatty(stdin) } == 1;
+        if interactive {
+            pty.copy_window_size(stdin)?;
+        }
+
+        let mut child = {
+            let mut command = self.command(&shell)?;
+            command
+                .stdin(slave.try_clone()?)
+                .stdout(slave.try_clone()?)
+                .stderr(slave);
+            // SAFETY: setsid and ioctl are async-signal-safe
+            unsafe {
+                command.pre_exec(|| {
+                    nix::unistd::setsid()?;
+                    // make the pty, which is already our stdin, the controlling terminal
+                    if libc::ioctl(0, libc::TIOCSCTTY, 0) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+            command.spawn().map_err(|source| Error::Spawn {
+                program: shell.clone().into(),
+                source,
+            })?
+            // dropping the command closes our copies of the slave
+        };
+        tracing::debug!(pid = child.id(), ?shell, "Started shell in container");
+
+        let result = {
+            let _raw = interactive
+                .then(|| pty::RawMode::enable(stdin))
+                .transpose()?;
+            let _winch = pty::WinchForwarder::install()?;
+            pty.proxy(stdin, &mut std::io::stdout())
+        };
+        let status = child.wait()?;
+        result?;
+        Ok(status)
+    }
+
+    /// Sets what happens to spawned processes that are still running when the container is unmounted
+    pub fn set_child_policy(&mut self, policy: ChildPolicy) -> &mut Self {
+        self.child_policy = policy;
+        self
+    }
+
+    /// Leave code running in forked children no way back out of the container
+    ///
+    /// To come back out of the chroot, [`Container::exit_chroot`] keeps descriptors of the
+    /// host root and working directory, and a process holding a descriptor of a directory
+    /// outside its root can `fchdir` to it and reach the whole host from there. A process
+    /// with `CAP_SYS_CHROOT` can also chroot into a subdirectory, which leaves its working
+    /// directory outside its new root. When sealed, children close these descriptors and
+    /// every other inherited directory descriptor before entering the container, mark their
+    /// other descriptors close-on-exec, and drop `CAP_SYS_CHROOT` unless disabled in
+    /// `options`. They enter the root by path and change to its `/`, so their working
+    /// directory never points outside it.
+    ///
+    /// This covers [`Container::run_forked`], [`Container::command`] and everything else
+    /// that forks, not [`Container::run`], whose code runs in the calling process which
+    /// has to get back out. It doesn't protect against a payload running as root with
+    /// other capabilities, which can mount filesystems, load modules or ptrace processes
+    /// outside the container, nor against the host's processes visible in `/proc`. Drop
+    /// capabilities with [`Container::set_capabilities`], switch to an unprivileged user
+    /// with [`Container::set_user`], and use [`Container::pid_namespace`] for that.
+    pub fn enter_sealed(&mut self, options: SealOptions) -> &mut Self {
+        self.seal = Some(options);
+        self
+    }
+
+    /// Run the function of [`Container::run_forked`] in a new pid namespace
+    ///
+    /// The function runs as pid 2, under a built-in init as pid 1 which reaps orphaned
+    /// processes and forwards SIGTERM, SIGINT and SIGHUP to it. Once the function returns,
+    /// every process left in the namespace is killed.
+    pub fn pid_namespace(&mut self, enable: bool) -> &mut Self {
+        self.pid_namespace = enable;
+        self
+    }
+
+    /// Label every tmpfs mounted from the mount table with `context`,
+    /// unless it has a context option of its own
+    pub fn set_tmpfs_context(&mut self, context: SelinuxContext) -> &mut Self {
+        self.mount_table.set_tmpfs_context(Some(context));
+        self
+    }
+
+    /// Fail to mount when mounts have SELinux context options but SELinux is disabled
+    /// on the host, instead of dropping the options
+    pub fn set_selinux_strict(&mut self, strict: bool) -> &mut Self {
+        self.mount_table.set_selinux_strict(strict);
+        self
+    }
+
+    /// Fail to mount when a mount of the table is already active, instead of adopting it,
+    /// see [`MountTable::mount_chroot`]
+    pub fn set_strict_mounts(&mut self, strict: bool) -> &mut Self {
+        self.mount_table.set_strict_mounts(strict);
+        self
+    }
+
+    /// Wait for devices given by identifier to show up when mounting,
+    /// see [`MountTable::set_device_timeout`]
+    pub fn set_device_timeout(&mut self, timeout: std::time::Duration) -> &mut Self {
+        self.mount_table.set_device_timeout(timeout);
+        self
+    }
+
+    /// Wait for the sources of mounts to exist when mounting,
+    /// see [`MountTable::set_wait_for_source`]
+    pub fn set_wait_for_source(&mut self, timeout: Option<std::time::Duration>) -> &mut Self {
+        self.mount_table.set_wait_for_source(timeout);
+        self
+    }
+
+    /// Call `callback` with the progress of long operations: mounting, unmounting and
+    /// [`Container::unpack_tarball`]
+    ///
+    /// Every operation also runs in a `tracing` span, child of the container's span which
+    /// carries its [`Container::id`] and root. Each mount and unmount has its own span
+    /// with its `source`, `target`, `fstype`, `duration_ms` and `outcome`.
+    pub fn on_progress(
+        &mut self,
+        callback: impl FnMut(ProgressEvent) + Send + 'static,
+    ) -> &mut Self {
+        self.mount_table.on_progress(callback);
+        self
+    }
+
+    /// Append everything the container does to the system, each mount, unmount, remount,
+    /// and entering and exiting the chroot, to the journal at `path`
+    ///
+    /// Each operation is a line of JSON with when it happened, the paths it applied to
+    /// once resolved, the flags and data passed to the kernel, and how it ended, with the
+    /// errno of failures, see [`JournalRecord`]. Journals can be turned back into a mount
+    /// table and replayed elsewhere with [`replay`].
+    pub fn record_to(&mut self, path: impl AsRef<Path>) -> Result<&mut Self> {
+        self.mount_table.record_to(path)?;
+        Ok(self)
+    }
+
+    /// Sets what happens to other processes using the container when it's unmounted
+    pub fn set_unmount_policy(&mut self, policy: UnmountPolicy) -> &mut Self {
+        self.unmount_policy = policy;
+        self
+    }
+
+    /// Send a signal to every process using the container, and report which ones were signalled
+    ///
+    /// A process uses the container if its root, working directory or any of its open files
+    /// are under the container root, e.g. a daemon started by a package script.
+    /// The calling process and pid 1 are never signalled, and processes whose `/proc`
+    /// entries can't be read are skipped. Waits up to a second for them to exit.
+    ///
+    /// Fails with [`Error::DangerousRoot`] without signalling anything if the root is
+    /// `/` or contains a critical host path, even if
+    /// [`crate::ContainerBuilder::allow_dangerous_root`] was set.
+    pub fn kill_lingering(
+        &mut self,
+        signal: nix::sys::signal::Signal,
+    ) -> std::io::Result<Vec<LingeringProcess>> {
+        process::signal_processes_using(&self.root, signal)
+    }
+
+    /// Sets the working directory inside the container
+    ///
+    /// The path is relative to the container root. [`Container::chroot`] and the run methods
+    /// change to it after entering the container, and fail if it doesn't exist unless
+    /// [`Container::create_workdir`] is set.
+    pub fn workdir(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.workdir = Some(path.into());
+        self
+    }
+
+    /// Create the working directory if it doesn't exist yet
+    pub fn create_workdir(&mut self, create: bool) -> &mut Self {
+        self.create_workdir = create;
+        self
+    }
+
+    /// Sets the policy for which host environment variables are visible inside the container
+    ///
+    /// In [`Container::run`] the environment of the whole process is replaced while the
+    /// function runs and restored afterwards. As modifying the environment is not thread-safe,
+    /// no other thread may touch environment variables during the run; use
+    /// [`Container::run_forked`] when that can't be guaranteed.
+    pub fn set_env_policy(&mut self, policy: EnvPolicy) -> &mut Self {
+        self.env.policy = policy;
+        self
+    }
+
+    /// Sets an environment variable for code running inside the container
+    pub fn env(&mut self, key: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.env.set(key, value);
+        self
+    }
+
+    /// Sets the whole environment configuration, e.g. [`Environment::minimal`]
+    pub fn set_environment(&mut self, env: Environment) -> &mut Self {
+        self.env = env;
+        self
+    }
+
+    /// Sets cgroup v2 limits for code running in [`Container::run_forked`]
+    ///
+    /// The cgroup is created on the first forked run, and removed when the container is dropped.
+    /// Any processes left in it after a run are killed.
+    pub fn set_cgroup(&mut self, config: CgroupConfig) -> &mut Self {
+        self.cgroup_config = Some(config);
+        self
+    }
+
+    /// The cgroup of the container, if it has been created
+    ///
+    /// Useful for reading usage statistics such as `memory.peak`.
+    pub fn cgroup(&self) -> Option<&Cgroup> {
+        self.cgroup.as_ref()
+    }
+
+    /// Sets a resource limit for code running inside the container
+    ///
+    /// The limits apply to the child in [`Container::run_forked`]. In [`Container::run`]
+    /// they are set right before the function runs and restored afterwards.
+    pub fn limit(
+        &mut self,
+        resource: Resource,
+        soft: impl Into<Limit>,
+        hard: impl Into<Limit>,
+    ) -> Result<&mut Self> {
+        self.rlimits.set(resource, soft, hard)?;
+        Ok(self)
+    }
+
+    /// Sets all resource limits for code running inside the container
+    pub fn set_rlimits(&mut self, rlimits: Rlimits) -> &mut Self {
+        self.rlimits = rlimits;
+        self
+    }
+
+    /// Sets the seccomp filter installed in the child of [`Container::run_forked`]
+    ///
+    /// The filter is only ever installed in the forked child, never in the calling process.
+    #[cfg(feature = "seccomp")]
+    pub fn set_seccomp(&mut self, policy: SeccompPolicy) -> &mut Self {
+        self.seccomp = Some(policy);
+        self
+    }
+
+    /// Run a function inside the container chroot with a different effective uid and gid
+    ///
+    /// The previous identity is restored once the function returns, so this requires root.
+    pub fn run_as<F, T>(&mut self, uid: Uid, gid: Gid, f: F) -> Result<T>
+    where
+        F: FnOnce() -> T,
+    {
+        Ok(self.run(|| user::with_identity(uid, gid, &[gid], f))??)
+    }
+
+    /// Run a function inside the container chroot as a user from the container's `/etc/passwd`
+    ///
+    /// The user is resolved from the container's own user database, not the host's.
+    /// Supplementary groups are taken from the container's `/etc/group`, and
+    /// `HOME` and `SHELL` are set for the duration of the function.
+    pub fn run_as_user<F, T>(&mut self, name: &str, f: F) -> Result<T>
+    where
+        F: FnOnce() -> T,
+    {
+        let user = User::lookup(&self.root, name)?;
+        tracing::trace!(?user, "Resolved container user");
+
+        let ret = self.run(|| {
+            let mut env = Environment::new(EnvPolicy::Inherit);
+            env.set("HOME", user.home.to_string_lossy())
+                .set("SHELL", user.shell.to_string_lossy());
+            let _env = env.apply();
+            user::with_identity(user.uid, user.gid, &user.all_groups(), f)
+        });
+
+        Ok(ret??)
+    }
+
+    /// Start mounting files inside the container
+    pub fn mount(&mut self) -> std::io::Result<()> {
+        let _span = self.span.clone().entered();
+        self.hooks.check_reentrancy()?;
+        self.run_hooks(Phase::PreMount)?;
+        self.write_marker();
+        let result = self.mount_table.mount_chroot(&self.root);
+        self.mount_finished(result)?;
+        self.run_post_mount_hooks()
+    }
+
+    /// Write the [`ContainerMarker`] at the root, before mounting so it's under any
+    /// mount of the root itself
+    ///
+    /// Failing to write it is only logged, e.g. for a root on a read-only filesystem.
+    fn write_marker(&mut self) {
+        if self.mount_table.is_empty() {
+            return;
+        }
+        let root = self
+            .root
+            .canonicalize()
+            .unwrap_or_else(|_| self.root.clone());
+        let marker = ContainerMarker::new(&self.id, self.mount_table.plan(&root));
+        match marker.write(&self.root) {
+            Ok(path) => self.marker = Some(path),
+            Err(e) => tracing::warn!(?e, root = ?self.root, "Failed to write container marker"),
+        }
+    }
+
+    fn remove_marker(&mut self) {
+        let Some(path) = self.marker.take() else {
+            return;
+        };
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                tracing::warn!(?e, ?path, "Failed to remove container marker");
+            }
+            _ => {}
+        }
+    }
+
+    /// Record the outcome of mounting the mount table
+    fn mount_finished(&mut self, result: std::io::Result<()>) -> std::io::Result<()> {
+        if result.is_err() && !self._initialized {
+            self.remove_marker();
+        }
+        result?;
+        self.mounted();
+        Ok(())
+    }
+
+    /// Record that the mount table was mounted
+    fn mounted(&mut self) {
+        self._initialized = true;
+        if let Some(id) = self.cleanup_id {
+            cleanup::set_mounts(id, self.mount_table.mounted_paths());
+        }
+    }
+
+    /// Unmounts all mountpoints inside the container
+    ///
+    /// Processes started with [`Container::spawn`] that are still running
+    /// are handled according to the [`ChildPolicy`] first, then other processes
+    /// using the container according to the [`UnmountPolicy`].
+    pub fn umount(&mut self) -> std::io::Result<()> {
+        let _span = self.span.clone().entered();
+        self.hooks.check_reentrancy()?;
+        self.run_hooks(Phase::PreUnmount)?;
+        prepare_umount(
+            &self.root,
+            &mut self.children,
+            self.child_policy,
+            self.unmount_policy,
+        )?;
+        let result = self.mount_table.umount_chroot();
+        self.unmounted(result)?;
+        self.run_hooks(Phase::PostUnmount)
+    }
+
+    /// Mount only the entries tagged `tag`, see [`MountTable::mount_tagged`]
+    ///
+    /// Unlike [`Container::mount`], no hooks are run, so tags can be mounted in turn
+    /// once the container is up.
+    pub fn mount_tagged(&mut self, tag: &str) -> std::io::Result<()> {
+        let _span = self.span.clone().entered();
+        self.hooks.check_reentrancy()?;
+        if self.marker.is_none() {
+            self.write_marker();
+        }
+        let result = self.mount_table.mount_tagged(&self.root, tag);
+        self.mount_finished(result)
+    }
+
+    /// Unmount only the mounts tagged `tag`, see [`MountTable::umount_tagged`]
+    ///
+    /// No hooks are run and processes using the container are left alone. The container
+    /// only counts as unmounted once no mount is left.
+    pub fn umount_tagged(&mut self, tag: &str, cascade: bool) -> std::io::Result<()> {
+        let _span = self.span.clone().entered();
+        self.hooks.check_reentrancy()?;
+        let result = self.mount_table.umount_tagged(tag, cascade);
+        if self.mount_table.mounts.is_empty() {
+            return self.unmounted(result);
+        }
+        if let Some(id) = self.cleanup_id {
+            cleanup::set_mounts(id, self.mount_table.mounted_paths());
+        }
+        result
+    }
+
+    /// Go back to the mounts of `snapshot`, see [`MountTable::restore`]
+    ///
+    /// With `force`, a mounted container is remounted without running any hooks.
+    pub fn restore_mounts(
+        &mut self,
+        snapshot: MountTableSnapshot,
+        force: bool,
+    ) -> std::io::Result<()> {
+        let _span = self.span.clone().entered();
+        self.hooks.check_reentrancy()?;
+        let result = self.mount_table.restore(snapshot, force);
+        if let Some(id) = self.cleanup_id {
+            cleanup::set_mounts(id, self.mount_table.mounted_paths());
+        }
+        result
+    }
+
+    /// Copy `host_src` from the host to `container_dest` inside the container
+    ///
+    /// Directories are copied recursively, and merged into existing ones. Missing
+    /// parents are created, and symlinks in the container are followed without ever
+    /// leaving its root. Permissions and modification times are kept, as is ownership
+    /// when running as root. This works whether the container is mounted and chrooted
+    /// or not, though files copied to a mount end up in the mounted filesystem.
+    pub fn copy_in(
+        &mut self,
+        host_src: impl AsRef<Path>,
+        container_dest: impl AsRef<Path>,
+    ) -> Result<()> {
+        let (src, dest) = (host_src.as_ref(), container_dest.as_ref());
+        self.outside_chroot(|root| copy::copy_in(src, root, dest, None))
+    }
+
+    /// Like [`Container::copy_in`], but the copied files are owned by `uid` and `gid`
+    pub fn copy_in_as(
+        &mut self,
+        host_src: impl AsRef<Path>,
+        container_dest: impl AsRef<Path>,
+        uid: Uid,
+        gid: Gid,
+    ) -> Result<()> {
+        let (src, dest) = (host_src.as_ref(), container_dest.as_ref());
+        self.outside_chroot(|root| copy::copy_in(src, root, dest, Some((uid, gid))))
+    }
+
+    /// Copy `container_src` from the container to `host_dest` on the host,
+    /// see [`Container::copy_in`]
+    pub fn copy_out(
+        &mut self,
+        container_src: impl AsRef<Path>,
+        host_dest: impl AsRef<Path>,
+    ) -> Result<()> {
+        let (src, dest) = (container_src.as_ref(), host_dest.as_ref());
+        self.outside_chroot(|root| copy::copy_out(root, src, dest))
+    }
+
+    /// Run `f` with the container root as seen from the host, leaving the chroot
+    /// meanwhile if needed
+    ///
+    /// The working directory inside the chroot is restored afterwards.
+    fn outside_chroot<T>(&mut self, f: impl FnOnce(&Path) -> Result<T>) -> Result<T> {
+        if !self.chroot {
+            return f(&self.root);
+        }
+        let cwd = File::open(".")?;
+        self.leave_chroot()?;
+        let result = f(&self.root);
+        nix::unistd::chroot(&self.root)?;
+        self.chroot = true;
+        nix::unistd::fchdir(cwd.as_raw_fd())?;
+        result
+    }
+
+    /// Report the state of the container, checking its active mounts against
+    /// `/proc/self/mountinfo`
+    ///
+    /// If mountinfo can't be read, no mount is reported as stale.
+    pub fn status(&self) -> ContainerStatus {
+        let mounted = self.mount_table.mounted_paths();
+        let mountinfo = mountinfo::read()
+            .map_err(|e| {
+                tracing::warn!(?e, "Failed to read mountinfo, can't check for stale mounts")
+            })
+            .ok();
+        let chroot = self.chroot.then_some(self.root.as_path());
+        ContainerStatus {
+            id: self.id.clone(),
+            root: self.root.clone(),
+            is_mounted: self._initialized,
+            is_chrooted: self.chroot,
+            configured_mounts: self.mount_table.inner.len(),
+            active_mounts: status::active_mounts(&mounted, mountinfo.as_deref(), chroot),
+            foreign_mounts: status::active_mounts(
+                &self.mount_table.foreign,
+                mountinfo.as_deref(),
+                chroot,
+            ),
+        }
+    }
+
+    /// Change the flags and data of the active mount at `target`, see [`MountTable::remount`]
+    pub fn remount(
+        &mut self,
+        target: impl AsRef<Path>,
+        flags: MountFlags,
+        data: Option<&str>,
+    ) -> Result<()> {
+        self.mount_table.remount(target.as_ref(), flags, data)?;
+        Ok(())
+    }
+
+    /// Take the active mount at `target` from the container, so it outlives it,
+    /// see [`MountTable::take_mount`]
+    pub fn take_mount(&mut self, target: impl AsRef<Path>) -> Option<UnmountDrop<ContainerMount>> {
+        let _span = self.span.clone().entered();
+        let mount = self.mount_table.take_mount(target.as_ref())?;
+        if let Some(id) = self.cleanup_id {
+            cleanup::set_mounts(id, self.mount_table.mounted_paths());
+        }
+        Some(mount)
+    }
+
+    /// Paths on the host of the active mounts of the container,
+    /// see [`MountTable::active_targets`]
+    pub fn active_targets(&self) -> Vec<PathBuf> {
+        self.mount_table.active_targets()
+    }
+
+    /// Record the outcome of unmounting the mount table
+    fn unmounted(&mut self, result: std::io::Result<()>) -> std::io::Result<()> {
+        if let Some(id) = self.cleanup_id {
+            cleanup::set_mounts(id, self.mount_table.mounted_paths());
+        }
+        result?;
+        self._initialized = false;
+        self.remove_marker();
+        Ok(())
+    }
+
+    /// Consume the container, leaving its mounts in place, e.g. to hand the root over to another tool
+    ///
+    /// The chroot is exited if needed, and nothing is unmounted when the container is dropped.
+    /// Its [`ContainerMarker`] is left at the root, along with the mounts. Files written
+    /// to the image, such as by [`Container::setup_network_config`] and
+    /// [`Container::prepare_etc`], are left too, while the image's own files stay moved
+    /// aside. The returned record can be saved and passed to [`unmount_persisted`] later.
+    pub fn persist(mut self) -> Result<PersistedMounts> {
+        if self.chroot {
+            self.exit_chroot()?;
+        }
+        let mounts = self.mount_table.leak(&self.root);
+        self._initialized = false;
+        // still in use by the mounts, or by whoever the root is handed to
+        self.provisioned.clear();
+        std::mem::take(&mut self.network_files);
+        std::mem::take(&mut self.etc_files);
+        std::mem::take(&mut self.gpu_files);
+        if let Some(id) = self.cleanup_id {
+            cleanup::set_mounts(id, Vec::new());
+        }
+        tracing::debug!(root = ?self.root, ?mounts, "Persisting mounts");
+        Ok(PersistedMounts {
+            root: self.root.clone(),
+            mounts,
+        })
+    }
+
+    /// Register a hook to run at `phase` of the lifecycle of the container, e.g. to write
+    /// files once the mounts are up, or to sync filesystems before unmounting
+    ///
+    /// Hooks of a phase run in the order they were registered. A failing hook stops the
+    /// others, and the operation fails with its error: a failing [`Phase::PostMount`]
+    /// hook unmounts everything again, a failing [`Phase::PreEnter`] one doesn't chroot.
+    /// When the container is dropped, failing hooks are logged and the teardown goes on.
+    ///
+    /// Hooks can change the container, but can't mount, unmount, enter or exit it,
+    /// which fails with [`Error::ReentrantHook`]. The enter and exit hooks only run when
+    /// chrooting this process, not for forked children such as [`Container::run_forked`].
+    ///
+    /// ```no_run
+    /// # fn main() -> tiffin::Result<()> {
+    /// let mut container = tiffin::Container::new("/path/to/root");
+    /// container.on(tiffin::Phase::PostMount, |container| {
+    ///     std::fs::write(container.root.join("etc/resolv.conf"), "nameserver 1.1.1.1\n")?;
+    ///     Ok(())
+    /// });
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn on(
+        &mut self,
+        phase: Phase,
+        hook: impl FnMut(&mut Container) -> Result<()> + Send + 'static,
+    ) -> &mut Self {
+        self.hooks.add(phase, Box::new(hook));
+        self
+    }
+
+    /// Sets a callback for errors while tearing down the container when it's dropped
+    ///
+    /// Dropping never panics: failures to leave the chroot or unmount are logged
+    /// and passed to this callback. Use [`Container::close`] to handle them instead.
+    pub fn on_drop_error(&mut self, callback: impl FnMut(&Error) + Send + 'static) -> &mut Self {
+        self.on_drop_error = Some(Box::new(callback));
+        self
+    }
+
+    /// Leave the chroot and unmount the container, returning it on failure so it can be retried
+    ///
+    /// Unlike dropping, processes spawned in the container are handled
+    /// according to the [`ChildPolicy`].
+    #[allow(clippy::result_large_err)]
+    pub fn close(mut self) -> std::result::Result<(), (Self, Error)> {
+        match self.teardown(false) {
+            Ok(()) => Ok(()),
+            Err(e) => Err((self, e)),
+        }
+    }
+
+    /// Leave the chroot and unmount, killing spawned processes first if `kill_children` is set
+    fn teardown(&mut self, kill_children: bool) -> Result<()> {
+        if self.chroot {
+            self.exit_chroot()?;
+        }
+        if self._initialized {
+            if kill_children {
+                if let Err(e) = process::settle_children(&mut self.children, ChildPolicy::Kill) {
+                    tracing::error!(?e, "Failed to stop processes spawned in container");
+                }
+            }
+            self.umount()?;
+        }
+        self.network_files.restore()?;
+        self.etc_files.restore()?;
+        self.gpu_files.restore()?;
+        host::remove_provisioned(&mut self.provisioned);
+        Ok(())
+    }
+
+    /// Unmount the container if the process gets SIGINT or SIGTERM
+    ///
+    /// Destructors don't run when a process is killed by a signal, which would leave
+    /// the mounts behind on the host. This installs process-wide handlers, once, that
+    /// detach the mounts of every container registered this way, leave any chroot,
+    /// and then re-raise the signal so it still terminates the process.
+    ///
+    /// Children forked by [`Container::run_forked`] share the mounts with this process,
+    /// so they never clean up themselves: they just die from the signal as usual.
+    pub fn cleanup_on_signals(&mut self) -> std::io::Result<&mut Self> {
+        cleanup::install()?;
+        if self.cleanup_id.is_none() {
+            let id = cleanup::register();
+            cleanup::set_mounts(id, self.mount_table.mounted_paths());
+            self.cleanup_id = Some(id);
+        }
+        Ok(self)
+    }
+
+    /// Make the host's DNS configuration and `/etc/hosts` available inside the container
+    ///
+    /// The host's `/etc/resolv.conf` and `/etc/hosts` are used, and `/etc/nsswitch.conf`
+    /// if the image has none. With [`NetworkConfig::CopyFromHost`] they are written right away,
+    /// while with [`NetworkConfig::BindFromHost`] they are mounted along with the rest of the
+    /// mount table. Symlinks in the image, such as one to systemd-resolved's stub file, are
+    /// replaced by regular files. The image's own files are moved aside and restored when
+    /// the container is dropped or closed. Calling this again replaces the previous setup.
+    pub fn setup_network_config(&mut self, mode: NetworkConfig) -> Result<&mut Self> {
+        self.network_files.restore()?;
+        for (source, _) in self.network_files.mounts.drain(..) {
+            self.mount_table.inner.remove(&source);
+        }
+        self.network_files = netconf::NetworkFiles::setup(Path::new("/etc"), &self.root, mode)?;
+        for (source, mount) in &self.network_files.mounts {
+            self.mount_table.add_mount(mount.clone(), source.clone());
+        }
+        Ok(self)
+    }
+
+    /// Prepare `/etc` of the container for programs that expect a booted system
+    ///
+    /// Depending on `options`, `/etc/machine-id` is written, `/etc/mtab` is made a symlink
+    /// to `/proc/self/mounts`, and `/etc/localtime` is set. With [`EtcPrepOptions::undo`],
+    /// the image's own files are moved aside and put back when the container is torn down,
+    /// along with those of a previous call, which are restored first.
+    ///
+    /// Call this before mounting, since files are written to the image directly.
+    pub fn prepare_etc(&mut self, options: EtcPrepOptions) -> Result<&mut Self> {
+        self.etc_files.restore()?;
+        let mut backups = std::mem::take(&mut self.etc_files);
+        let result = self
+            .outside_chroot(|root| etc::prepare(Path::new("/etc"), root, &options, &mut backups));
+        self.etc_files = backups;
+        result?;
+        Ok(self)
+    }
+
+    /// Let GUI applications in the container use the host's display server
+    ///
+    /// Binds the X11 socket directory `/tmp/.X11-unix`, the Wayland sockets in the
+    /// calling user's runtime directory, and the file pointed to by `XAUTHORITY`,
+    /// each to the same path inside the container. Those missing on the host are
+    /// skipped, see the returned report. The runtime directory is created inside the
+    /// container with mode 0700, owned by the user from [`Container::set_user`] or
+    /// else the calling user, and `DISPLAY`, `WAYLAND_DISPLAY`, `XAUTHORITY` and
+    /// `XDG_RUNTIME_DIR` are passed through to the container's environment.
+    pub fn enable_display_passthrough(&mut self) -> Result<DisplayReport> {
+        let session = desktop::HostSession::current();
+        let (report, mounts) = session.display()?;
+        self.add_session_mounts(&session, mounts)?;
+        for key in desktop::DISPLAY_VARS {
+            if let Some(value) = std::env::var_os(key) {
+                self.env.set(key, value.to_string_lossy());
+            }
+        }
+        Ok(report)
+    }
+
+    /// Let applications in the container use the host's D-Bus session bus,
+    /// and the system bus if `system_bus` is set
+    ///
+    /// The sockets are bound to the same paths inside the container, and
+    /// `DBUS_SESSION_BUS_ADDRESS` is set to the session bus socket. Sockets missing on
+    /// the host, e.g. on a headless machine, are skipped, see the returned report.
+    /// The runtime directory is set up as in [`Container::enable_display_passthrough`].
+    pub fn enable_session_bus(&mut self, system_bus: bool) -> Result<SocketReport> {
+        let session = desktop::HostSession::current();
+        let (report, mounts) = session.bus(system_bus);
+        self.add_session_mounts(&session, mounts)?;
+        let bus = session.session_bus();
+        if report.bound.contains(&bus) {
+            self.env.set(
+                "DBUS_SESSION_BUS_ADDRESS",
+                format!("unix:path={}", bus.display()),
+            );
+        }
+        Ok(report)
+    }
+
+    /// Let applications in the container play audio through the host's PipeWire or PulseAudio
+    ///
+    /// Binds `pipewire-0` and `pulse/native` from the runtime directory, like
+    /// [`Container::enable_session_bus`], skipping those missing on the host.
+    pub fn enable_audio(&mut self) -> Result<SocketReport> {
+        let session = desktop::HostSession::current();
+        let (report, mounts) = session.audio();
+        self.add_session_mounts(&session, mounts)?;
+        Ok(report)
+    }
+
+    /// Let code in the container use the host's GPUs
+    ///
+    /// Binds `/dev/dri` recursively and any NVIDIA device nodes, such as `/dev/nvidia0`
+    /// and `/dev/nvidiactl`. With [`GpuOptions::driver_libs`], the matching host driver
+    /// libraries are bound into the configured directory and a drop-in for it is written
+    /// to `/etc/ld.so.conf.d` inside the container. Detection only looks at the filesystem,
+    /// and the report says exactly what was passed through.
+    ///
+    /// The drop-in only takes effect once `ldconfig` runs in the container, e.g. with
+    /// [`Container::run_output`] after mounting. Otherwise, point `LD_LIBRARY_PATH` at
+    /// [`DriverLibs::target`] with [`Container::env`]. The drop-in is removed, and any file
+    /// of the image at its path put back, when the container is dropped or closed.
+    /// Calling this again replaces the previous drop-in.
+    pub fn enable_gpu(&mut self, options: GpuOptions) -> Result<GpuReport> {
+        let (mut report, mounts) = gpu::HostGpu::current().find(&options)?;
+        self.gpu_files.restore()?;
+        if let Some(libs) = &options.driver_libs {
+            if !report.libs.is_empty() {
+                let lib_dir = libs.target.strip_prefix("/").unwrap_or(&libs.target);
+                let ld_conf = gpu::write_ld_conf(&self.root, lib_dir, &mut self.gpu_files)?;
+                report.ld_conf = Some(ld_conf);
+            }
+        }
+        for (source, mount) in mounts {
+            self.mount_table.add_mount(mount, source);
+        }
+        Ok(report)
+    }
+
+    /// Create the runtime directory of `session` in the container for the target user,
+    /// and add mounts from the session
+    fn add_session_mounts(
+        &mut self,
+        session: &desktop::HostSession,
+        mounts: Vec<(PathBuf, MountTarget)>,
+    ) -> Result<()> {
+        let (uid, gid) = match &self.user {
+            Some(user) => (user.uid, user.gid),
+            None => (nix::unistd::getuid(), nix::unistd::getgid()),
+        };
+        desktop::create_runtime_dir(&self.root, &session.runtime_dir, uid, gid)?;
+        for (source, mount) in mounts {
+            self.mount_table.add_mount(mount, source);
+        }
+        self.env
+            .set("XDG_RUNTIME_DIR", session.runtime_dir.to_string_lossy());
+        Ok(())
+    }
+
+    /// Adds a bind mount for the system's root filesystem to
+    /// the container's root filesystem at `/run/host`
+    ///
+    /// This gives the container read-write access to the whole host filesystem,
+    /// see [`Container::host_bind_mount_at`] and [`Container::host_bind_paths`].
+    pub fn host_bind_mount(&mut self) -> &mut Self {
+        self.host_bind_mount_at("/run/host", false)
+    }
+
+    /// Adds a bind mount for the system's root filesystem at `target` in the container
+    ///
+    /// When `read_only`, the filesystems mounted on the host under `/` are bound too, all
+    /// read-only, while a read-write bind only has the root filesystem itself.
+    ///
+    /// The mountpoint is created with mode 0755 if the image doesn't have it, and removed
+    /// again when the container is dropped or closed.
+    pub fn host_bind_mount_at(&mut self, target: impl Into<PathBuf>, read_only: bool) -> &mut Self {
+        self.host_bind_paths(target, ["/"], read_only)
+    }
+
+    /// Like [`Container::host_bind_mount_at`], but only bind the given host paths under
+    /// `target`, such as [`HOST_PATHS`]
+    ///
+    /// `/etc/hosts` is bound at `<target>/etc/hosts`, and so on. Paths missing from the
+    /// host are skipped.
+    pub fn host_bind_paths<I, P>(
+        &mut self,
+        target: impl Into<PathBuf>,
+        paths: I,
+        read_only: bool,
+    ) -> &mut Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let target = target.into();
+        for path in paths {
+            let source = path.as_ref();
+            if !source.exists() {
+                tracing::debug!(?source, "Host path doesn't exist, not binding it");
+                continue;
+            }
+            // without a trailing slash for `/`
+            let target: PathBuf = target.join(relative(source)).components().collect();
+            self.add_host_bind(source, target, read_only);
+        }
+        self
+    }
+
+    /// Bind `source` from the host at `target`, creating the mountpoint right away so it
+    /// can be removed at teardown even if mounting fails
+    fn add_host_bind(&mut self, source: &Path, target: PathBuf, read_only: bool) {
+        self.add_host_mount(source, host::bind(target, read_only));
+    }
+
+    fn add_host_mount(&mut self, source: &Path, mount: MountTarget) {
+        match host::provision(&self.root, source, &mount.target) {
+            Ok(created) => self.provisioned.extend(created),
+            Err(e) => tracing::warn!(?e, target = ?mount.target, "Failed to create mountpoint"),
+        }
+        self.mount_table.add_mount(mount, source);
+    }
+
+    /// Make the host's CA certificates available inside the container, read-only
+    ///
+    /// The certificate directories of the host, either in the Fedora layout
+    /// (`/etc/pki/tls/certs` linking to `/etc/pki/ca-trust/extracted`) or the Debian one
+    /// (`/etc/ssl/certs` linking to `/usr/share/ca-certificates`), are bound where the
+    /// image's openssl looks for certificates. The rest of the host's openssl directory,
+    /// with its private keys, is never bound. When the image's layout differs from the
+    /// host's, `SSL_CERT_FILE` and `SSL_CERT_DIR` are set so the bundle is found too.
+    ///
+    /// Missing mountpoints are created, and removed when the container is dropped.
+    /// Fails if the host has no certificates in either layout.
+    pub fn enable_host_ca_certs(&mut self) -> Result<&mut Self> {
+        let Some(plan) = certs::plan(Path::new("/"), &self.root) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "no CA certificates found on the host",
+            )
+            .into());
+        };
+        for (source, target) in plan.binds {
+            self.add_host_bind(&source, target, true);
+        }
+        if let Some((file, dir)) = plan.env {
+            self.env
+                .set("SSL_CERT_FILE", file.to_string_lossy())
+                .set("SSL_CERT_DIR", dir.to_string_lossy());
+        }
+        Ok(self)
+    }
+
+    /// Share a package cache of the host with the container, so packages downloaded
+    /// once aren't downloaded again by every container
+    ///
+    /// The cache is bound where the container's package manager expects it, read-only
+    /// unless `writable`, and created on the host if missing. While mounted, a lock file
+    /// in the cache is held with `flock`, shared by read-only mounts and exclusive for a
+    /// writable one, so mounting waits until no other container could be writing to it.
+    /// The lock is released when the cache is unmounted.
+    pub fn share_package_cache(&mut self, kind: CacheKind, writable: bool) -> Result<&mut Self> {
+        let source = kind.host_path();
+        std::fs::create_dir_all(source)?;
+        let mut mount = host::bind(kind.container_path().to_path_buf(), !writable);
+        mount.lock = Some(MountLock {
+            path: source.join(cache::CACHE_LOCK_FILE),
+            exclusive: writable,
+        });
+        self.add_host_mount(source, mount);
+        Ok(self)
+    }
+
+    /// Adds a bind mount to a file or directory inside the container
+    pub fn bind_mount(
+        &mut self,
+        source: impl Into<PathBuf>,
+        target: impl Into<PathBuf>,
+    ) -> &mut Self {
+        self.mount_table.add_mount(
+            MountTarget {
+                target: target.into(),
+                flags: MountFlags::BIND,
+                ..MountTarget::default()
+            },
+            source,
+        );
+        self
+    }
+
+    /// Mount `/proc` with `options`, replacing the procfs of [`Container::add_default_mounts`]
+    ///
+    /// Before Linux 5.8, every procfs of a pid namespace shares the same options, so
+    /// without a pid namespace of its own this changes the host's `/proc` too, or fails
+    /// if it has different options.
+    pub fn set_proc_options(&mut self, options: ProcMountOptions) -> &mut Self {
+        if procfs::kernel_version().is_some_and(|version| version < (5, 8)) {
+            tracing::warn!(
+                "procfs options apply to every procfs of the pid namespace before Linux 5.8"
+            );
+        }
+        self.mount_table
+            .add_mount(MountTarget::proc(options), "/proc");
+        self
+    }
+
+    /// Make the root read-only, with a fresh tmpfs on `/tmp`, `/run` and `/var/tmp`,
+    /// see [`Container::read_only_with`]
+    pub fn read_only(&mut self) -> Result<&mut Self> {
+        self.read_only_with(["/tmp", "/run", "/var/tmp"])
+    }
+
+    /// Make the root read-only, with a fresh tmpfs on each of the `scratch` directories
+    /// so programs can still write temporary files
+    ///
+    /// The root is bind mounted onto itself read-only before anything else is mounted, and
+    /// unmounted with the other mounts. Mountpoints can't be created in it anymore, so those
+    /// of the other mounts must exist in the image, except for the scratch directories
+    /// which are created right away. They are world-writable with the sticky bit, like `/tmp`.
+    ///
+    /// Fails with [`Error::InvalidRoot`] if an overlay is mounted on the root, as it's the
+    /// other way to keep the image from being modified.
+    pub fn read_only_with<I, P>(&mut self, scratch: I) -> Result<&mut Self>
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        let overlay = self.mount_table.inner.values().any(|mount| {
+            mount.fstype.as_deref() == Some("overlay")
+                && relative(&mount.target).components().next().is_none()
+        });
+        if overlay {
+            return Err(Error::InvalidRoot {
+                root: self.root.clone(),
+                reason: "an overlay is mounted on it, which can't be combined with read-only mode"
+                    .to_string(),
+            });
+        }
+
+        let root = MountTarget {
+            target: "/".into(),
+            flags: MountFlags::BIND | MountFlags::REC | MountFlags::RDONLY,
+            ..MountTarget::default()
+        };
+        self.mount_table.add_mount(root, self.root.clone());
+        for target in scratch {
+            let target = target.into();
+            resolve_in_root(&self.root, &target, Create::Dir)?;
+            let mount = MountTarget {
+                target: target.clone(),
+                fstype: Some("tmpfs".to_string()),
+                data: Some("mode=1777".to_string()),
+                ..MountTarget::default()
+            };
+            self.mount_table
+                .add_mount(mount, builder::tmpfs_source(&target));
+        }
+        Ok(self)
+    }
+
+    /// Adds an additional mount target to the container mount table
+    ///
+    /// Useful for mounting disks or other filesystems
+    pub fn add_mount(&mut self, mount: MountTarget, source: impl Into<PathBuf>) -> &mut Self {
+        self.mount_table.add_mount(mount, source);
+        self
+    }
+
+    /// Adds the mounts of [`MountPreset::Minimal`]: `/proc`, `/sys`,
+    /// and bind mounts of `/dev` and `/dev/pts`
+    pub fn add_default_mounts(&mut self) -> &mut Self {
+        self.apply_preset(MountPreset::Minimal)
+    }
+
+    /// Adds the mounts of `other` to the mount table, see [`MountTable::merge`]
+    pub fn merge_mounts(
+        &mut self,
+        other: MountTable,
+        strategy: MergeStrategy,
+    ) -> Result<MergeReport> {
+        self.mount_table.merge(other, strategy)
+    }
+
+    /// Adds the mounts of a preset to the mount table
+    ///
+    /// Mounts from the same source replace each other, so applying
+    /// a preset more than once doesn't change anything.
+    pub fn apply_preset(&mut self, preset: MountPreset) -> &mut Self {
+        for (source, mount) in preset.entries() {
+            self.mount_table.add_mount(mount, source);
+        }
+        self
+    }
+}
+
+/// Deal with processes that would keep the container busy, before unmounting it
+///
+/// This doesn't borrow the container, so it can run on another thread.
+fn prepare_umount(
+    root: &Path,
+    children: &mut Vec<process::SpawnedChild>,
+    child_policy: ChildPolicy,
+    unmount_policy: UnmountPolicy,
+) -> std::io::Result<()> {
+    process::settle_children(children, child_policy)?;
+    if unmount_policy == UnmountPolicy::KillUsers {
+        let terminated = process::signal_processes_using(root, nix::sys::signal::Signal::SIGTERM)?;
+        if terminated
+            .iter()
+            .any(|process| process::is_running(process.pid))
+        {
+            process::signal_processes_using(root, nix::sys::signal::Signal::SIGKILL)?;
+        }
+    }
+    Ok(())
+}
+
+impl Drop for Container {
+    fn drop(&mut self) {
+        tracing::trace!("Dropping container, images will be unmounted");
+        // we can't refuse to drop, so make sure nothing is left running
+        self.hooks.dropping = true;
+        if let Err(e) = self.teardown(true) {
+            tracing::error!(?e, "Failed to tear down container");
+            if let Some(callback) = &mut self.on_drop_error {
+                callback(&e);
+            }
+        }
+        if let Some(id) = self.cleanup_id {
+            cleanup::unregister(id);
+        }
+    }
+}
+
+// We can't really reproduce this test in a CI environment, so let's just ignore it
+#[cfg(test)]
+// Test only if we're running as root
+mod tests {
+    use super::tempdir::TempDir;
+    use super::*;
+    use std::{path::PathBuf, time::Duration};
+
+    /// A container at `root` with the host's userland bound in, so it can run programs
+    fn host_userland(root: &str) -> Container {
+        std::fs::create_dir_all(root).unwrap();
+        let mut container = Container::new(root);
+        for dir in ["/usr", "/bin", "/lib", "/lib64", "/etc"] {
+            if Path::new(dir).exists() {
+                container.bind_mount(dir, dir);
+            }
+        }
+        container
+    }
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_container() {
+        std::fs::create_dir_all("/tmp/tiffin").unwrap();
+        let mut container = Container::new("/tmp/tiffin");
+        container
+            .run(|| std::fs::create_dir_all("/tmp/tiffin/test").unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_bare_mount() {
+        let mut container = Container::new_bare(std::env::temp_dir());
+        assert!(container.mount_table.is_empty());
+        container.mount().unwrap();
+        container.umount().unwrap();
+        container.mount().unwrap();
+        assert!(container.close().is_ok());
+    }
+
+    #[test]
+    fn test_copy_in_out() {
+        let base = TempDir::new("copy-api");
+        std::fs::create_dir(base.join("root")).unwrap();
+        std::fs::write(base.join("build.sh"), "make\n").unwrap();
+        let mut container = Container::new_bare(base.join("root"));
+
+        container
+            .copy_in(base.join("build.sh"), "/work/build.sh")
+            .unwrap();
+        container.copy_out("work", base.join("artifacts")).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(base.join("artifacts/build.sh")).unwrap(),
+            "make\n"
+        );
+    }
+
+    #[test]
+    fn test_status() {
+        let mut container = Container::new_bare(std::env::temp_dir());
+        container.bind_mount("/srv", "srv");
+        let status = container.status();
+        assert_eq!(status.root, std::env::temp_dir());
+        assert!(!status.is_mounted && !status.is_chrooted);
+        assert_eq!(status.configured_mounts, 1);
+        assert!(status.active_mounts.is_empty());
+
+        let debug = format!("{container:?}");
+        assert!(debug.starts_with("Container {") && debug.contains("\"srv\""));
+        assert!(!debug.contains("sysroot") && !debug.contains("pwd"));
+    }
+
+    fn sorted_targets(targets: &[&str]) -> Vec<PathBuf> {
+        let mut table = MountTable::new();
+        for (i, target) in targets.iter().enumerate() {
+            let mount = MountTarget {
+                target: target.into(),
+                ..MountTarget::default()
+            };
+            table.add_mount(mount, format!("source{i}"));
+        }
+        table
+            .sort_mounts()
+            .map(|(_, mount)| mount.target.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_sort_mounts() {
+        // both argument orders of the comparator must agree
+        assert_eq!(
+            sorted_targets(&["sys", "proc"]),
+            ["proc", "sys"].map(PathBuf::from)
+        );
+        assert_eq!(
+            sorted_targets(&["proc", "sys"]),
+            ["proc", "sys"].map(PathBuf::from)
+        );
+        assert_eq!(
+            sorted_targets(&["dev/pts", "/", "tmp", "/dev", "dev/shm", "proc"]),
+            ["/", "/dev", "proc", "tmp", "dev/pts", "dev/shm"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn test_sort_mounts_nested() {
+        let sorted = sorted_targets(&["var/cache/fontconfig", "var/cache", "/var", "usr/lib"]);
+        let position = |target: &str| sorted.iter().position(|t| t == Path::new(target));
+        assert!(position("/var") < position("var/cache"));
+        assert!(position("var/cache") < position("var/cache/fontconfig"));
+        assert_eq!(sorted[1], Path::new("usr/lib"));
+    }
+
+    #[test]
+    fn test_prepare_etc() {
+        let root = TempDir::new("prepare-etc");
+        std::fs::create_dir(root.join("etc")).unwrap();
+        let mut container = Container::new_bare(&*root);
+        let options = EtcPrepOptions {
+            machine_id: Some(MachineId::Uninitialized),
+            localtime: None,
+            ..EtcPrepOptions::default()
+        };
+        // preparing again restores the first changes before making them again
+        container.prepare_etc(options.clone()).unwrap();
+        container.prepare_etc(options).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.join("etc/machine-id")).unwrap(),
+            "uninitialized\n"
+        );
+        assert!(root.join("etc/mtab").is_symlink());
+
+        assert!(container.close().is_ok());
+        assert_eq!(std::fs::read_dir(root.join("etc")).unwrap().count(), 0);
+    }
+
+    fn table(entries: &[(&str, &str)]) -> MountTable {
+        let mut table = MountTable::new();
+        for (source, target) in entries {
+            let mount = MountTarget {
+                target: target.into(),
+                ..MountTarget::default()
+            };
+            table.add_mount(mount, source);
+        }
+        table
+    }
+
+    fn targets(table: &MountTable) -> Vec<(PathBuf, PathBuf)> {
+        table
+            .sort_mounts()
+            .map(|(source, mount)| (source.clone(), mount.target.clone()))
+            .collect()
+    }
+
+    fn assert_targets(table: &MountTable, expected: &[(&str, &str)]) {
+        let expected: Vec<(PathBuf, PathBuf)> = expected
+            .iter()
+            .map(|(source, target)| (source.into(), target.into()))
+            .collect();
+        assert_eq!(targets(table), expected);
+    }
+
+    #[test]
+    fn test_merge_replace() {
+        let mut base = table(&[("/srv/global", "/var"), ("tmpfs", "tmp")]);
+        let report = base
+            .merge(
+                table(&[("/srv/project", "var/"), ("/srv/cache", "/var/cache")]),
+                MergeStrategy::ReplaceExisting,
+            )
+            .unwrap();
+        // nested targets don't conflict, and are still mounted after their parents
+        assert_eq!(report.added, [PathBuf::from("/srv/cache")]);
+        assert_eq!(
+            report.replaced,
+            [MergeConflict {
+                target: "var".into(),
+                existing: "/srv/global".into(),
+                incoming: "/srv/project".into(),
+            }]
+        );
+        assert!(report.kept.is_empty());
+        assert_targets(
+            &base,
+            &[
+                ("tmpfs", "tmp"),
+                ("/srv/project", "var/"),
+                ("/srv/cache", "/var/cache"),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_merge_keep() {
+        let mut base = table(&[("/srv/global", "/var/cache")]);
+        let report = base
+            .extend_from_iter(
+                [
+                    (
+                        "/srv/var",
+                        MountTarget {
+                            target: "/var".into(),
+                            ..MountTarget::default()
+                        },
+                    ),
+                    (
+                        "/srv/cli",
+                        MountTarget {
+                            target: "./var/cache".into(),
+                            ..MountTarget::default()
+                        },
+                    ),
+                ],
+                MergeStrategy::KeepExisting,
+            )
+            .unwrap();
+        assert_eq!(report.added, [PathBuf::from("/srv/var")]);
+        assert_eq!(report.kept.len(), 1);
+        assert_eq!(report.kept[0].existing, Path::new("/srv/global"));
+        assert_targets(
+            &base,
+            &[("/srv/var", "/var"), ("/srv/global", "/var/cache")],
+        );
+    }
+
+    #[test]
+    fn test_merge_error() {
+        let mut base = table(&[("/srv/global", "/var"), ("tmpfs", "tmp")]);
+        let before = targets(&base);
+        let err = base
+            .merge(
+                table(&[
+                    ("/srv/cache", "/var/cache"),
+                    ("/srv/project", "var"),
+                    ("tmpfs", "run"),
+                ]),
+                MergeStrategy::Error,
+            )
+            .unwrap_err();
+        let Error::MountConflicts(conflicts) = &err else {
+            panic!("unexpected error {err:?}");
+        };
+        // a conflicting source counts too, the table holds one mount per source
+        assert_eq!(conflicts.len(), 2);
+        assert!(err.to_string().contains("/srv/project (mounted on /var)"));
+        // nothing was merged
+        assert_eq!(targets(&base), before);
+
+        let report = base
+            .merge(table(&[("/srv/cache", "/var/cache")]), MergeStrategy::Error)
+            .unwrap();
+        assert_eq!(report.added, [PathBuf::from("/srv/cache")]);
+
+        let mut container = Container::new_bare(std::env::temp_dir());
+        container.merge_mounts(base, MergeStrategy::Error).unwrap();
+        assert_eq!(container.status().configured_mounts, 3);
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let root = Path::new("/tmp/tiffin-snapshot");
+        let mut table = table(&[("/srv/cache", "/var/cache"), ("tmpfs", "tmp")]);
+        table.add_mount(
+            MountTarget::new("/proc", Some("proc".to_string()), MountFlags::NOSUID, None),
+            "proc",
+        );
+        let snapshot = table.snapshot();
+        let plan = table.plan(root);
+
+        table.set_table(HashMap::new());
+        table.add_mount(
+            MountTarget::new("/tmp", None, MountFlags::RDONLY, None),
+            "tmpfs",
+        );
+        assert_ne!(table.plan(root), plan);
+
+        // as if saved by another process
+        let snapshot = MountTableSnapshot::from_json(&snapshot.to_json().unwrap()).unwrap();
+        table.restore(snapshot.clone(), false).unwrap();
+        assert_eq!(table.plan(root), plan);
+        assert_eq!(table.snapshot(), snapshot);
+        assert_eq!(table.inner[Path::new("proc")].flags, MountFlags::NOSUID);
+    }
+
+    #[test]
+    fn test_data_options() {
+        let mut options = MountOptions::new();
+        options.mode(0o1777).nodev();
+        let mut mount = MountTarget {
+            flags: MountFlags::NOSUID,
+            data_options: Some(options),
+            ..MountTarget::default()
+        };
+        assert_eq!(mount.all_flags(), MountFlags::NOSUID | MountFlags::NODEV);
+        assert_eq!(mount.all_data().as_deref(), Some("mode=1777"));
+        mount.data = Some("size=64m".to_string());
+        assert_eq!(mount.all_data().as_deref(), Some("size=64m,mode=1777"));
+        mount.data_options = None;
+        assert_eq!(mount.all_data().as_deref(), Some("size=64m"));
+    }
+
+    #[test]
+    fn test_selinux_prepare() {
+        let context: SelinuxContext = "system_u:object_r:container_file_t:s0".parse().unwrap();
+        let tmpfs = MountTarget {
+            target: "tmp".into(),
+            fstype: Some("tmpfs".to_string()),
+            ..MountTarget::default()
+        };
+        let mut labelled = tmpfs.clone();
+        labelled.context("system_u:object_r:tmp_t:s0".parse().unwrap());
+
+        let mut table = MountTable::new();
+        table.set_tmpfs_context(Some(context.clone()));
+        let options = table.prepare(&tmpfs, true).into_owned().data_options;
+        assert_eq!(
+            options.unwrap().get("context"),
+            Some(Some(context.as_str()))
+        );
+        assert_eq!(*table.prepare(&labelled, true), labelled);
+        // dropped without SELinux, unless strict
+        assert!(!selinux::has_context(&table.prepare(&tmpfs, false)));
+        table.set_selinux_strict(true);
+        assert!(selinux::has_context(&table.prepare(&tmpfs, false)));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut container = Container::new_bare(std::env::temp_dir());
+        container.bind_mount("/nonexistent/a", "a");
+        container.bind_mount("/nonexistent/b", "b");
+        container.add_mount(
+            MountTarget {
+                target: "tmp".into(),
+                fstype: Some("tmpfs".to_string()),
+                ..MountTarget::default()
+            },
+            "tmpfs",
+        );
+        let targets: Vec<_> = container
+            .mount_table
+            .validate()
+            .into_iter()
+            .map(|diagnostic| diagnostic.target)
+            .collect();
+        assert_eq!(targets, [Path::new("a"), Path::new("b")]);
+
+        // nothing is mounted, so this doesn't need root
+        let err = Error::from(container.mount().unwrap_err());
+        assert!(matches!(err, Error::InvalidMounts(diagnostics) if diagnostics.len() == 2));
+    }
+
+    #[test]
+    fn test_validate_pending() {
+        let mut table = MountTable::new();
+        table.add_mount(
+            MountTarget {
+                target: "a".into(),
+                flags: MountFlags::BIND,
+                wait_for_source: Some(Duration::from_millis(50)),
+                ..MountTarget::default()
+            },
+            "/nonexistent/a",
+        );
+        let diagnostics = table.validate();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].problem,
+            MountProblem::PendingSource(Duration::from_millis(50))
+        );
+
+        // not an invalid table, but the source never appears, so nothing is mounted
+        let err = Error::from(table.mount_chroot(&std::env::temp_dir()).unwrap_err());
+        assert!(
+            matches!(err, Error::SourceTimeout { path, .. } if path == Path::new("/nonexistent/a"))
+        );
+        assert!(table.mounted_paths().is_empty());
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_bare_container() {
+        std::fs::create_dir_all("/tmp/tiffin-bare").unwrap();
+        let mut container = Container::new_bare("/tmp/tiffin-bare");
+        let proc_mounted = container.run(|| Path::new("/proc/self").exists()).unwrap();
+        assert!(!proc_mounted);
+
+        container.add_default_mounts();
+        let proc_mounted = container.run(|| Path::new("/proc/self").exists()).unwrap();
+        assert!(proc_mounted);
+        drop(container);
+        assert!(
+            crate::mountinfo::mounts_under(Path::new("/tmp/tiffin-bare"))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_enter_exit_hooks() {
+        std::fs::create_dir_all("/tmp/tiffin-hooks").unwrap();
+        let mut container = Container::new_bare("/tmp/tiffin-hooks");
+        let log = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        for phase in [Phase::PreEnter, Phase::PostExit, Phase::PostUnmount] {
+            let log = log.clone();
+            container.on(phase, move |_| {
+                log.lock().unwrap().push((phase, inside_chroot()));
+                Ok(())
+            });
+        }
+        container.run(|| ()).unwrap();
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                (Phase::PreEnter, false),
+                (Phase::PostExit, false),
+                (Phase::PostUnmount, false)
+            ]
+        );
+
+        // a failing pre-enter hook doesn't chroot
+        container.on(Phase::PreEnter, |_| {
+            Err(Error::Unsupported("not today".to_string()))
+        });
+        assert!(container.chroot().is_err());
+        assert!(!container.status().is_chrooted);
+        assert!(container.try_run(|| ()).is_err());
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_mount_escape() {
+        let root = Path::new("/tmp/tiffin-escape");
+        std::fs::create_dir_all(root.join("srv")).unwrap();
+        std::os::unix::fs::symlink("/etc", root.join("etc")).ok();
+        let mut container = Container::new_bare(root);
+
+        container.bind_mount("/srv", "etc/tiffin-escape");
+        assert!(container.mount().is_err());
+        assert!(!Path::new("/etc/tiffin-escape").exists());
+
+        container.mount_table.set_table(HashMap::new());
+        container.bind_mount("/srv", "srv/../../etc");
+        let err = Error::from(container.mount().unwrap_err());
+        assert!(
+            matches!(err, Error::PathEscape { target } if target == Path::new("srv/../../etc"))
+        );
+        assert!(crate::mountinfo::mounts_under(root).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_unmount_escape() {
+        let root = TempDir::new("unmount-escape");
+        std::fs::create_dir(root.join("proc")).unwrap();
+        // as if the target had been joined to the root without resolving it
+        let malicious = MountTarget::new(
+            "/../../../proc",
+            Some("proc".to_string()),
+            MountFlags::empty(),
+            None,
+        );
+        let mut table = MountTable::new();
+        table.root = Some(root.to_path_buf());
+        table.mounts.push(Tracked {
+            guard: MountGuard::Adopted(root.join(relative(&malicious.target))),
+            tag: None,
+            _lock: None,
+        });
+
+        // never attempted, which would fail with EPERM without root
+        let err = Error::from(table.umount_chroot().unwrap_err());
+        assert!(matches!(err, Error::PathEscape { target } if target.ends_with("../proc")));
+        assert!(table.mounted_paths().is_empty());
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_mountpoint_options() {
+        use std::os::unix::fs::MetadataExt;
+
+        let root = Path::new("/tmp/tiffin-mountpoint");
+        std::fs::create_dir_all(root.join("srv")).unwrap();
+        std::fs::set_permissions(root.join("srv"), std::fs::Permissions::from_mode(0o755)).unwrap();
+        let mut container = Container::new_bare(root);
+        container.add_mount(
+            MountTarget {
+                target: "srv/user/run".into(),
+                fstype: Some("tmpfs".to_string()),
+                mountpoint: MountpointOptions {
+                    mode: Some(0o700),
+                    owner: Some((Uid::from_raw(1000), Gid::from_raw(1000))),
+                    ..MountpointOptions::default()
+                },
+                ..MountTarget::default()
+            },
+            "tmpfs",
+        );
+        container.mount().unwrap();
+        container.umount().unwrap();
+
+        // created for the mount
+        for dir in ["srv/user", "srv/user/run"] {
+            let metadata = std::fs::metadata(root.join(dir)).unwrap();
+            assert_eq!(metadata.mode() & 0o7777, 0o700, "{dir}");
+            assert_eq!((metadata.uid(), metadata.gid()), (1000, 1000), "{dir}");
+        }
+        // already there
+        let metadata = std::fs::metadata(root.join("srv")).unwrap();
+        assert_eq!(metadata.mode() & 0o7777, 0o755);
+        assert_eq!(metadata.uid(), 0);
+
+        container.mount_table.set_table(HashMap::new());
+        container.add_mount(
+            MountTarget {
+                target: "missing".into(),
+                fstype: Some("tmpfs".to_string()),
+                mountpoint: MountpointOptions {
+                    create: false,
+                    ..MountpointOptions::default()
+                },
+                ..MountTarget::default()
+            },
+            "tmpfs",
+        );
+        let err = container.mount().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        assert!(!root.join("missing").exists());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_bind_leaves_no_source_dirs() {
+        let root = Path::new("/tmp/tiffin-source-dirs");
+        let source = Path::new("/tmp/tiffin-source-project");
+        std::fs::create_dir_all(root).unwrap();
+        std::fs::create_dir_all(source).unwrap();
+        let mut container = Container::new_bare(root);
+        container.bind_mount(source, "build");
+        container.mount().unwrap();
+        container.umount().unwrap();
+
+        let entries: Vec<_> = std::fs::read_dir(root)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, ["build"]);
+        std::fs::remove_dir_all(root).unwrap();
+        std::fs::remove_dir(source).unwrap();
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_remount() {
+        let root = Path::new("/tmp/tiffin-remount");
+        let source = Path::new("/tmp/tiffin-remount-source");
+        std::fs::create_dir_all(root).unwrap();
+        std::fs::create_dir_all(source).unwrap();
+        let mut container = Container::new_bare(root);
+        container.bind_mount(source, "data");
+
+        let err = container
+            .remount("data", MountFlags::RDONLY, None)
+            .unwrap_err();
+        assert!(matches!(err, Error::NotMounted { .. }));
+
+        container.mount().unwrap();
+        std::fs::write(root.join("data/a"), "rw").unwrap();
+        container.remount("data", MountFlags::RDONLY, None).unwrap();
+        let err = std::fs::write(root.join("data/b"), "ro").unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EROFS));
+        let mount = container.mount_table.inner.values().next().unwrap();
+        assert_eq!(mount.flags, MountFlags::BIND | MountFlags::RDONLY);
+
+        container
+            .remount("/data", MountFlags::empty(), None)
+            .unwrap();
+        std::fs::write(root.join("data/b"), "rw").unwrap();
+        assert!(container
+            .remount("elsewhere", MountFlags::RDONLY, None)
+            .is_err());
+
+        container.umount().unwrap();
+        std::fs::remove_dir_all(root).unwrap();
+        std::fs::remove_dir_all(source).unwrap();
+    }
+
+    #[test]
+    fn test_active_path() {
+        let dir = TempDir::new("active-path");
+        let root = &*dir;
+        std::fs::create_dir(root.join("proc")).unwrap();
+        let line = format!("22 1 0:21 / {}/proc rw - proc proc rw", root.display());
+        let active = mountinfo::parse(&line);
+        let fs = |fstype: &str| MountTarget {
+            target: "/proc/".into(),
+            fstype: Some(fstype.to_string()),
+            ..MountTarget::default()
+        };
+        let source = Path::new("/proc");
+        assert_eq!(
+            fs("proc").active_path(source, root, &active),
+            Some(root.join("proc"))
+        );
+        assert_eq!(fs("sysfs").active_path(source, root, &active), None);
+        let bind = MountTarget {
+            target: "proc".into(),
+            flags: MountFlags::BIND,
+            ..MountTarget::default()
+        };
+        // the same directory as the source, as if it was bind mounted
+        assert!(bind
+            .active_path(&root.join("proc"), root, &active)
+            .is_some());
+        assert_eq!(bind.active_path(Path::new("/"), root, &active), None);
+    }
+
+    /// Records the spans created while it is the default subscriber
+    #[derive(Default)]
+    struct SpanRecorder {
+        spans: std::sync::Mutex<Vec<RecordedSpan>>,
+        stack: std::sync::Mutex<Vec<u64>>,
+    }
+
+    struct RecordedSpan {
+        name: &'static str,
+        parent: Option<u64>,
+        fields: HashMap<&'static str, String>,
+    }
+
+    struct FieldVisitor<'a>(&'a mut HashMap<&'static str, String>);
+
+    impl tracing::field::Visit for FieldVisitor<'_> {
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            self.0.insert(field.name(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0.insert(field.name(), format!("{value:?}"));
+        }
+    }
+
+    impl tracing::Subscriber for SpanRecorder {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, attrs: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let parent = match attrs.parent() {
+                Some(parent) => Some(parent.into_u64()),
+                None if attrs.is_contextual() => self.stack.lock().unwrap().last().copied(),
+                None => None,
+            };
+            let mut fields = HashMap::new();
+            attrs.record(&mut FieldVisitor(&mut fields));
+            let mut spans = self.spans.lock().unwrap();
+            spans.push(RecordedSpan {
+                name: attrs.metadata().name(),
+                parent,
+                fields,
+            });
+            tracing::span::Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, span: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+            let mut spans = self.spans.lock().unwrap();
+            let span = &mut spans[span.into_u64() as usize - 1];
+            values.record(&mut FieldVisitor(&mut span.fields));
+        }
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, _: &tracing::Event<'_>) {}
+
+        fn enter(&self, span: &tracing::span::Id) {
+            self.stack.lock().unwrap().push(span.into_u64());
+        }
+
+        fn exit(&self, _: &tracing::span::Id) {
+            self.stack.lock().unwrap().pop();
+        }
+    }
+
+    #[test]
+    fn test_spans() {
+        let root = TempDir::new("spans");
+        let dispatch = tracing::Dispatch::new(SpanRecorder::default());
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let id = tracing::dispatcher::with_default(&dispatch, || {
+            let mut container = Container::new_bare(&*root);
+            let recorded = events.clone();
+            container.on_progress(move |event| recorded.lock().unwrap().push(event));
+            container.mount().unwrap();
+            container.umount().unwrap();
+
+            // fails before mounting anything
+            let missing = MountTarget {
+                target: "missing".into(),
+                fstype: Some("tmpfs".to_string()),
+                mountpoint: MountpointOptions {
+                    create: false,
+                    ..MountpointOptions::default()
+                },
+                ..MountTarget::default()
+            };
+            container.add_mount(missing, "tmpfs");
+            assert!(container.mount().is_err());
+            container.id().to_string()
+        });
+
+        let recorder = dispatch.downcast_ref::<SpanRecorder>().unwrap();
+        let spans = recorder.spans.lock().unwrap();
+        let find = |name| spans.iter().position(|span| span.name == name).unwrap() as u64 + 1;
+        let span = |id: u64| &spans[id as usize - 1];
+        let container = find("container");
+        assert_eq!(span(container).fields["id"], id);
+        assert_eq!(span(container).fields["root"], root.display().to_string());
+        assert_eq!(span(container).parent, None);
+        assert_eq!(span(find("mount_all")).parent, Some(container));
+        assert_eq!(span(find("unmount_all")).parent, Some(container));
+
+        let mount = span(find("mount"));
+        assert_eq!(span(mount.parent.unwrap()).name, "mount_all");
+        assert_eq!(mount.fields["source"], "tmpfs");
+        assert_eq!(mount.fields["target"], "missing");
+        assert_eq!(mount.fields["fstype"], "tmpfs");
+        assert_eq!(mount.fields["outcome"], "failed");
+        assert!(mount.fields.contains_key("duration_ms"));
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                ProgressEvent::MountStarted {
+                    source: "tmpfs".into(),
+                    target: "missing".into(),
+                },
+                ProgressEvent::MountFinished {
+                    source: "tmpfs".into(),
+                    target: "missing".into(),
+                    ok: false,
+                },
+            ]
+        );
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_read_only() {
+        let root = Path::new("/tmp/tiffin-read-only");
+        for dir in ["usr", "proc", "sys", "dev"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        let mut container = Container::new(root);
+        container.read_only().unwrap();
+        let (usr, tmp) = container
+            .run(|| {
+                let usr = std::fs::write("/usr/test", "ro").unwrap_err();
+                (
+                    usr.raw_os_error(),
+                    std::fs::write("/tmp/test", "rw").is_ok(),
+                )
+            })
+            .unwrap();
+        assert_eq!(usr, Some(libc::EROFS));
+        assert!(tmp);
+
+        container.umount().unwrap();
+        assert!(mountinfo::mounts_under(root).unwrap().is_empty());
+        assert!(!root.join("usr/test").exists());
+        assert!(!root.join("tmp/test").exists());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_host_bind_read_only() {
+        let root = Path::new("/tmp/tiffin-host-ro");
+        std::fs::create_dir_all(root).unwrap();
+        let mut container = Container::new_bare(root);
+        container.host_bind_mount_at("/run/host", true);
+        let mode = std::fs::metadata(root.join("run/host")).unwrap().mode();
+        assert_eq!(mode & 0o777, 0o755);
+
+        container.mount().unwrap();
+        let code = container
+            .run_forked(|| match std::fs::write("/run/host/etc/tiffin-probe", "") {
+                Err(e) if e.raw_os_error() == Some(libc::EROFS) => 0,
+                _ => 1,
+            })
+            .unwrap();
+        assert_eq!(code, 0);
+        // submounts of the host root are read-only too
+        if mountinfo::read()
+            .unwrap()
+            .iter()
+            .any(|mount| mount.mount_point == Path::new("/dev/shm"))
+        {
+            let err = std::fs::write(root.join("run/host/dev/shm/tiffin-probe"), "").unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EROFS));
+        }
+        assert!(!Path::new("/etc/tiffin-probe").exists());
+
+        // the mountpoint is removed along with its parent, both created by tiffin
+        drop(container);
+        assert!(!root.join("run").exists());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_host_bind_paths() {
+        let root = TempDir::new("host-paths");
+        let mut container = Container::new_bare(&*root);
+        container.host_bind_paths("/run/host", ["/etc/hosts", "/nonexistent"], true);
+        assert_eq!(container.mount_table.inner.len(), 1);
+        let mount = &container.mount_table.inner[Path::new("/etc/hosts")];
+        assert_eq!(mount.target, Path::new("/run/host/etc/hosts"));
+        assert!(mount.flags.contains(MountFlags::RDONLY));
+        assert!(root.join("run/host/etc/hosts").is_file());
+        drop(container);
+        assert!(!root.join("run").exists());
+    }
+
+    #[test]
+    #[ignore = "This test requires root"]
+    fn test_restore_converge() {
+        let root = Path::new("/tmp/tiffin-restore");
+        std::fs::create_dir_all(root).unwrap();
+        let mut container = Container::new_bare(root);
+        container.add_mount(
+            MountTarget::new("/proc", Some("proc".to_string()), MountFlags::empty(), None),
+            "proc",
+        );
+        let snapshot = container.mount_table.snapshot();
+        container.mount().unwrap();
+
+        container.add_mount(
+            MountTarget::new("/tmp", Some("tmpfs".to_string()), MountFlags::empty(), None),
+            "tmpfs",
+        );
+        container.mount().unwrap();
+        assert_eq!(container.status().active_mounts.len(), 2);
+
+        let err = container
+            .restore_mounts(snapshot.clone(), false)
+            .unwrap_err();
+        assert!(matches!(Error::from(err), Error::StillMounted { mounts } if mounts.len() == 2));
+        assert_eq!(container.status().configured_mounts, 2);
+
+        container.restore_mounts(snapshot, true).unwrap();
+        let status = container.status();
+        assert_eq!(status.configured_mounts, 1);
+        assert_eq!(status.active_mounts.len(), 1);
+        assert!(status.active_mounts[0].target.ends_with("proc"));
+        assert!(!status.active_mounts[0].stale);
+        container.umount().unwrap();
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    #[ignore = "This test requires root"]
+    fn test_mount_by_label() {
+        let root = Path::new("/tmp/tiffin-label");
+        let image = Path::new("/tmp/tiffin-label.img");
+        std::fs::create_dir_all(root).unwrap();
+        std::fs::File::create(image)
+            .unwrap()
+            .set_len(16 << 20)
+            .unwrap();
+        let status = std::process::Command::new("mkfs.ext4")
+            .args(["-q", "-L", "tiffin test"])
+            .arg(image)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let output = std::process::Command::new("losetup")
+            .args(["--find", "--show"])
+            .arg(image)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let device = String::from_utf8(output.stdout).unwrap();
+
+        let mut container = Container::new_bare(root);
+        // udev links the loop device asynchronously
+        container.set_device_timeout(Duration::from_secs(5));
+        container.add_mount(
+            MountTarget::new("/data", Some("ext4".to_string()), MountFlags::empty(), None),
+            MountSource::Label("tiffin test".to_string()),
+        );
+        container.mount().unwrap();
+        let status = container.status();
+        assert_eq!(status.active_mounts[0].fstype.as_deref(), Some("ext4"));
+        assert!(root.join("data/lost+found").exists());
+        container.umount().unwrap();
+
+        container.mount_table.set_table(HashMap::new());
+        container.set_device_timeout(Duration::ZERO);
+        container.add_mount(
+            MountTarget::new("/data", Some("ext4".to_string()), MountFlags::empty(), None),
+            MountSource::Label("tiffin missing".to_string()),
+        );
+        let err = Error::from(container.mount().unwrap_err());
+        assert!(
+            matches!(err, Error::DeviceNotFound { device } if device == "LABEL=tiffin missing")
+        );
+
+        std::process::Command::new("losetup")
+            .args(["--detach", device.trim()])
+            .status()
+            .unwrap();
+        std::fs::remove_file(image).unwrap();
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    #[ignore = "This test requires root"]
+    fn test_pool() {
+        let roots = [
+            "/tmp/tiffin-pool-a",
+            "/tmp/tiffin-pool-b",
+            "/tmp/tiffin-pool-c",
+        ];
+        let payloads = [
+            CommandSpec::new("/bin/sh").args(["-c", "echo a"]),
+            CommandSpec::new("/bin/sh").args(["-c", "sleep 0.2; exit 3"]),
+            CommandSpec::new("/bin/sh").args(["-c", "sleep 0.5; echo c"]),
+        ];
+        let mut pool = Pool::new();
+        pool.set_parallelism(3);
+        for (root, payload) in roots.iter().zip(payloads) {
+            pool.add(host_userland(root), payload);
+        }
+        let ids: Vec<String> = pool.containers().map(|c| c.id().to_string()).collect();
+
+        let report = pool.run();
+        assert!(!report.is_success());
+        let failures: Vec<_> = report.failures().collect();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].id, ids[1]);
+        assert!(!failures[0].cancelled);
+        let output = report.get(&ids[1]).unwrap().output.as_ref().unwrap();
+        assert_eq!(output.as_ref().unwrap().status.code(), Some(3));
+        let output = report.containers[2]
+            .output
+            .as_ref()
+            .unwrap()
+            .as_ref()
+            .unwrap();
+        assert_eq!(output.stdout, b"c\n");
+        for root in roots {
+            assert!(mountinfo::mounts_under(Path::new(root)).unwrap().is_empty());
+        }
+
+        // the slow payload is killed once the other one fails
+        pool.set_fail_fast(true);
+        let report = pool.run();
+        assert!(report.containers[2].cancelled);
+        assert!(!report.containers[1].cancelled);
+        assert!(report.containers[2].duration < Duration::from_millis(500));
+        for root in roots {
+            assert!(mountinfo::mounts_under(Path::new(root)).unwrap().is_empty());
+            std::fs::remove_dir_all(root).unwrap();
+        }
+    }
+
+    #[test]
+    #[ignore = "This test requires root"]
+    fn test_share_package_cache() {
+        let cache = Path::new("/tmp/tiffin-cache");
+        let lock = cache.join(cache::CACHE_LOCK_FILE);
+        let is_locked = || {
+            let file = std::fs::File::open(&lock).unwrap();
+            nix::fcntl::flock(
+                file.as_raw_fd(),
+                nix::fcntl::FlockArg::LockExclusiveNonblock,
+            )
+            .is_err()
+        };
+        let kind = CacheKind::Custom(cache.into(), "/var/cache/tiffin".into());
+        let container = |root: &str| {
+            std::fs::create_dir_all(root).unwrap();
+            let mut container = Container::new_bare(root);
+            container.share_package_cache(kind.clone(), true).unwrap();
+            container
+        };
+        let mut first = container("/tmp/tiffin-cache-a");
+        let mut second = container("/tmp/tiffin-cache-b");
+
+        first.mount().unwrap();
+        assert!(is_locked());
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let waiting = std::thread::spawn(move || {
+            second.mount().unwrap();
+            sender.send(()).unwrap();
+            second
+        });
+        // the second writer can't mount until the first one is unmounted
+        assert!(receiver.recv_timeout(Duration::from_millis(300)).is_err());
+        std::fs::write("/tmp/tiffin-cache-a/var/cache/tiffin/package", "").unwrap();
+        first.umount().unwrap();
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        let mut second = waiting.join().unwrap();
+        assert!(is_locked());
+        assert!(Path::new("/tmp/tiffin-cache-b/var/cache/tiffin/package").exists());
+        second.umount().unwrap();
+        assert!(!is_locked());
+
+        drop((first, second));
+        for path in [
+            "/tmp/tiffin-cache-a",
+            "/tmp/tiffin-cache-b",
+            "/tmp/tiffin-cache",
+        ] {
+            std::fs::remove_dir_all(path).unwrap();
+        }
+    }
+
+    #[test]
+    #[ignore = "This test requires root"]
+    fn test_wait_for_source() {
+        let root = Path::new("/tmp/tiffin-wait-root");
+        let source = Path::new("/tmp/tiffin-wait-source");
+        std::fs::create_dir_all(root).unwrap();
+        let _ = std::fs::remove_file(source);
+        let bind = |wait| {
+            let mut container = Container::new_bare(root);
+            container.add_mount(
+                MountTarget {
+                    target: "/source".into(),
+                    flags: MountFlags::BIND,
+                    wait_for_source: Some(wait),
+                    ..MountTarget::default()
+                },
+                source,
+            );
+            container
+        };
+
+        let mut container = bind(Duration::from_millis(50));
+        let err = Error::from(container.mount().unwrap_err());
+        assert!(matches!(err, Error::SourceTimeout { path, .. } if path == source));
+
+        let creator = std::thread::spawn(|| {
+            std::thread::sleep(Duration::from_millis(200));
+            std::fs::write("/tmp/tiffin-wait-source", "source").unwrap();
+        });
+        let mut container = bind(Duration::from_secs(1));
+        container.mount().unwrap();
+        creator.join().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.join("source")).unwrap(),
+            "source"
+        );
+        container.umount().unwrap();
+        drop(container);
+        std::fs::remove_dir_all(root).unwrap();
+        std::fs::remove_file(source).unwrap();
+    }
+
+    #[test]
+    #[ignore = "This test requires root"]
+    fn test_take_mount() {
+        let root = Path::new("/tmp/tiffin-take");
+        let link = Path::new("/tmp/tiffin-take-link");
+        std::fs::create_dir_all(root).unwrap();
+        std::os::unix::fs::symlink(root, link).unwrap();
+        // through a symlink, which the mounts are looked up without
+        let mut container = Container::new_bare(link);
+        for (target, source) in [("/a", "tmpfs-a"), ("/a/b", "tmpfs-b"), ("/c", "tmpfs-c")] {
+            container.add_mount(
+                MountTarget::new(target, Some("tmpfs".to_string()), MountFlags::empty(), None),
+                source,
+            );
+        }
+        let mut locked =
+            MountTarget::new("/d", Some("tmpfs".to_string()), MountFlags::empty(), None);
+        locked.lock = Some(MountLock {
+            path: root.with_extension("lock"),
+            exclusive: true,
+        });
+        container.add_mount(locked, "tmpfs-d");
+        container.mount().unwrap();
+        let canonical = root.canonicalize().unwrap();
+        assert_eq!(
+            container.active_targets(),
+            [
+                canonical.join("a"),
+                canonical.join("c"),
+                canonical.join("d"),
+                canonical.join("a/b"),
+            ]
+        );
+
+        // /a/b is still mounted under it
+        assert!(container.take_mount("/a").is_none());
+        // the handle can't hold the lock
+        assert!(container.take_mount("/d").is_none());
+        let taken = container.take_mount("/c").unwrap();
+        assert!(container.take_mount("/c").is_none());
+        assert_eq!(
+            container.active_targets(),
+            [
+                canonical.join("a"),
+                canonical.join("d"),
+                canonical.join("a/b")
+            ]
+        );
+
+        drop(container);
+        let mounts = mountinfo::mounts_under(&canonical).unwrap();
+        assert_eq!(mounts.len(), 1);
+        assert_eq!(mounts[0].mount_point, canonical.join("c"));
+
+        taken.unmount(UnmountFlags::empty()).unwrap();
+        assert!(mountinfo::mounts_under(&canonical).unwrap().is_empty());
+        std::fs::remove_file(link).unwrap();
+        std::fs::remove_file(root.with_extension("lock")).unwrap();
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    #[ignore = "This test requires root"]
+    fn test_tagged_mounts() {
+        let root = Path::new("/tmp/tiffin-tagged");
+        let source = Path::new("/tmp/tiffin-tagged-src");
+        std::fs::create_dir_all(root).unwrap();
+        std::fs::create_dir_all(source.join("cache")).unwrap();
+        std::fs::write(source.join("file"), "payload").unwrap();
+        let mut container = Container::new_bare(root);
+        container
+            .add_mount(
+                MountTarget::new("/proc", Some("proc".to_string()), MountFlags::empty(), None)
+                    .tagged("infra"),
+                "proc",
+            )
+            .add_mount(
+                MountTarget::new("/src", None, MountFlags::BIND, None).tagged("payload"),
+                source,
+            )
+            .add_mount(
+                MountTarget::new(
+                    "/src/cache",
+                    Some("tmpfs".to_string()),
+                    MountFlags::empty(),
+                    None,
+                ),
+                "tmpfs",
+            );
+        let mounted = |path: &str| {
+            let path = root.canonicalize().unwrap().join(path);
+            mountinfo::read()
+                .unwrap()
+                .iter()
+                .any(|mount| mount.mount_point == path)
+        };
+
+        container.mount_tagged("infra").unwrap();
+        assert!(mounted("proc"));
+        assert!(!mounted("src"));
+        container.mount().unwrap();
+        assert!(root.join("src/file").exists());
+
+        // the untagged tmpfs is nested under the payload bind
+        let err = container.umount_tagged("payload", false).unwrap_err();
+        assert!(matches!(
+            Error::from(err),
+            Error::NestedMount { ref child, .. } if child.ends_with("src/cache")
+        ));
+        assert!(mounted("src/cache"));
+
+        container.umount_tagged("payload", true).unwrap();
+        assert!(mounted("proc"));
+        assert!(!mounted("src"));
+        assert!(!mounted("src/cache"));
+        assert!(!root.join("src/file").exists());
+        assert!(container.status().is_mounted);
+
+        container.umount_tagged("infra", false).unwrap();
+        assert!(!mounted("proc"));
+        assert!(!container.status().is_mounted);
+        std::fs::remove_dir_all(root).unwrap();
+        std::fs::remove_dir_all(source).unwrap();
+    }
+
+    #[test]
+    fn test_read_only_overlay() {
+        let mut container = Container::new_bare("/tmp/tiffin-read-only-overlay");
+        container.add_mount(
+            MountTarget {
+                target: "/".into(),
+                fstype: Some("overlay".to_string()),
+                ..MountTarget::default()
+            },
+            "overlay",
+        );
+        let err = container.read_only().unwrap_err();
+        assert!(matches!(err, Error::InvalidRoot { .. }));
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_mount_twice() {
+        let root = Path::new("/tmp/tiffin-mount-twice");
+        std::fs::create_dir_all(root).unwrap();
+        let canonical = root.canonicalize().unwrap();
+        let mount_points = || {
+            let mounts = mountinfo::mounts_under(&canonical).unwrap();
+            mounts
+                .into_iter()
+                .map(|mount| mount.mount_point)
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+        let expected: Vec<_> = ["dev", "dev/pts", "proc", "sys"]
+            .iter()
+            .map(|target| canonical.join(target))
+            .collect();
+
+        let mut container = Container::new(root);
+        container.mount().unwrap();
+        container.mount().unwrap();
+        assert_eq!(mount_points(), expected);
+
+        // another container on the same root adopts the mounts
+        let mut strict = Container::new(root);
+        strict.set_strict_mounts(true);
+        let err = Error::from(strict.mount().unwrap_err());
+        assert!(matches!(err, Error::AlreadyMounted { .. }));
+        let mut other = Container::new(root);
+        other.mount().unwrap();
+        assert_eq!(mount_points(), expected);
+        other.umount().unwrap();
+        assert!(mount_points().is_empty());
+
+        // the first container has nothing left to unmount
+        drop(container);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_adopt() {
+        let root = Path::new("/tmp/tiffin-adopt");
+        std::fs::create_dir_all(root.join("scratch")).unwrap();
+        let canonical = root.canonicalize().unwrap();
+        nix::mount::mount(
+            Some("tmpfs"),
+            &root.join("scratch"),
+            Some("tmpfs"),
+            nix::mount::MsFlags::empty(),
+            None::<&str>,
+        )
+        .unwrap();
+        let mount_points = || {
+            mountinfo::mounts_under(&canonical)
+                .unwrap()
+                .into_iter()
+                .map(|mount| mount.mount_point)
+                .collect::<Vec<_>>()
+        };
+
+        let mut container = Container::adopt(root).unwrap();
+        let status = container.status();
+        assert!(status.is_mounted);
+        assert!(status.active_mounts.is_empty());
+        assert_eq!(status.foreign_mounts[0].target, canonical.join("scratch"));
+        assert_eq!(status.foreign_mounts[0].fstype.as_deref(), Some("tmpfs"));
+        assert_eq!(container.run_forked(|| 7).unwrap(), 7);
+        assert_eq!(mount_points(), [canonical.join("scratch")]);
+
+        // mounts added afterwards are owned, and the foreign one isn't mounted over
+        let tmpfs = |target: &str| MountTarget {
+            target: target.into(),
+            fstype: Some("tmpfs".to_string()),
+            ..MountTarget::default()
+        };
+        container.add_mount(tmpfs("scratch"), "scratch");
+        container.add_mount(tmpfs("owned"), "owned");
+        container.mount().unwrap();
+        assert_eq!(
+            mount_points(),
+            [canonical.join("scratch"), canonical.join("owned")]
+        );
+        drop(container);
+        assert_eq!(mount_points(), [canonical.join("scratch")]);
+
+        nix::mount::umount(&root.join("scratch")).unwrap();
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_status_transitions() {
+        let root = Path::new("/tmp/tiffin-status");
+        std::fs::create_dir_all(root).unwrap();
+        let mut container = Container::new(root);
+        let configured = container.mount_table.inner.len();
+        let find_proc = |status: &ContainerStatus| {
+            status
+                .active_mounts
+                .iter()
+                .find(|mount| mount.target == root.join("proc"))
+                .cloned()
+                .unwrap()
+        };
+
+        container.mount().unwrap();
+        let status = container.status();
+        assert!(status.is_mounted && !status.is_chrooted);
+        assert_eq!(status.active_mounts.len(), configured);
+        assert!(status.active_mounts.iter().all(|mount| !mount.stale));
+        assert_eq!(find_proc(&status).fstype.as_deref(), Some("proc"));
+
+        container.chroot().unwrap();
+        let status = container.status();
+        container.exit_chroot().unwrap();
+        assert!(status.is_chrooted);
+        assert!(status.active_mounts.iter().all(|mount| !mount.stale));
+
+        // unmounted behind tiffin's back
+        nix::mount::umount(&root.join("proc")).unwrap();
+        let proc = find_proc(&container.status());
+        assert!(proc.stale && proc.fstype.is_none());
+        nix::mount::mount(
+            Some("proc"),
+            &root.join("proc"),
+            Some("proc"),
+            nix::mount::MsFlags::empty(),
+            None::<&str>,
+        )
+        .unwrap();
+
+        container.umount().unwrap();
+        let status = container.status();
+        assert!(!status.is_mounted);
+        assert!(status.active_mounts.is_empty());
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_copy_chrooted() {
+        let root = Path::new("/tmp/tiffin-copy-chrooted");
+        std::fs::create_dir_all(root.join("work")).unwrap();
+        std::fs::write("/tmp/tiffin-copy-chrooted-src", "host").unwrap();
+        let mut container = Container::new_bare(root);
+        container.chroot().unwrap();
+        std::env::set_current_dir("/work").unwrap();
+
+        container
+            .copy_in("/tmp/tiffin-copy-chrooted-src", "script")
+            .unwrap();
+        // container paths are relative to its root, not to the working directory
+        assert!(Path::new("/script").is_file());
+        assert_eq!(std::env::current_dir().unwrap(), Path::new("/work"));
+        std::fs::write("artifact", "built").unwrap();
+        container.copy_out("/work/artifact", "/tmp").unwrap();
+
+        container.exit_chroot().unwrap();
+        assert_eq!(
+            std::fs::read_to_string("/tmp/tiffin-copy-chrooted-src").unwrap(),
+            "host"
+        );
+        assert_eq!(std::fs::read_to_string("/tmp/artifact").unwrap(), "built");
+        std::fs::remove_file("/tmp/tiffin-copy-chrooted-src").unwrap();
+        std::fs::remove_file("/tmp/artifact").unwrap();
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_ephemeral() {
+        let mut container = Container::ephemeral().unwrap();
+        let dir = container.path().to_path_buf();
+        container
+            .run(|| std::fs::write("/hello", "world"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("hello")).unwrap(), "world");
+        drop(container);
+        assert!(!dir.exists());
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_ephemeral_refuses_mounts() {
+        let container = EphemeralContainer::builder()
+            .include_default_mounts(false)
+            .build()
+            .unwrap();
+        let dir = container.path().to_path_buf();
+        std::fs::create_dir(dir.join("leftover")).unwrap();
+        nix::mount::mount(
+            Some("tmpfs"),
+            &dir.join("leftover"),
+            Some("tmpfs"),
+            nix::mount::MsFlags::empty(),
+            None::<&str>,
+        )
+        .unwrap();
+
+        let err = container.close().unwrap_err();
+        assert!(
+            matches!(err, Error::MountsRemain { mounts, .. } if mounts == [dir.join("leftover")])
+        );
+        assert!(dir.join("leftover").exists());
+
+        nix::mount::umount(&dir.join("leftover")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[ignore = "This test requires root and SELinux"]
+    #[test]
+    fn test_selinux_tmpfs() {
+        let root = Path::new("/tmp/tiffin-selinux");
+        std::fs::create_dir_all(root).unwrap();
+        let context = "system_u:object_r:container_file_t:s0:c1,c2";
+        let mut container = Container::builder()
+            .root(root)
+            .tmpfs("tmp", "")
+            .build()
+            .unwrap();
+        container
+            .set_tmpfs_context(context.parse().unwrap())
+            .set_selinux_strict(true);
+        container.mount().unwrap();
+
+        let path = CString::new(root.join("tmp").into_os_string().into_vec()).unwrap();
+        let mut label = vec![0u8; 256];
+        // SAFETY: label is valid for writes of its length
+        let len = unsafe {
+            libc::lgetxattr(
+                path.as_ptr(),
+                c"security.selinux".as_ptr(),
+                label.as_mut_ptr().cast(),
+                label.len(),
+            )
+        };
+        assert!(len > 0);
+        label.truncate(len as usize);
+        assert_eq!(
+            String::from_utf8_lossy(&label).trim_end_matches('\0'),
+            context
+        );
+        container.umount().unwrap();
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_concurrent_run() {
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                std::thread::spawn(move || {
+                    let root = format!("/tmp/tiffin-stress-{i}");
+                    std::fs::create_dir_all(&root).unwrap();
+                    std::fs::write(format!("{root}/sentinel-{i}"), "").unwrap();
+                    let mut container = Container::new(root);
+                    for _ in 0..20 {
+                        let sentinel = format!("/sentinel-{i}");
+                        let own_root = container
+                            .run(|| (0..100).all(|_| Path::new(&sentinel).exists()))
+                            .unwrap();
+                        assert!(own_root);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        std::fs::create_dir_all("/tmp/tiffin").unwrap();
+        let mut container = Container::new("/tmp/tiffin");
+        container.chroot().unwrap();
+        let mut other = std::thread::spawn(|| {
+            let mut other = Container::new("/tmp/tiffin-stress-0");
+            let err = other.try_run(|| ()).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+            other
+        })
+        .join()
+        .unwrap();
+        container.exit_chroot().unwrap();
+        other.try_run(|| ()).unwrap();
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_nested() {
+        let root = Path::new("/tmp/tiffin-outer");
+        std::fs::create_dir_all(root.join("inner")).unwrap();
+        let mut outer = Container::new(root);
+        let inner_proc = outer
+            .run(|| {
+                assert!(inside_chroot());
+                let mut inner = Container::new("/inner");
+                let proc = inner.run(|| Path::new("/proc/self").exists()).unwrap();
+                assert!(crate::mountinfo::mounts_under(Path::new("/inner"))
+                    .unwrap()
+                    .is_empty());
+                proc
+            })
+            .unwrap();
+        assert!(inner_proc);
+        assert!(crate::mountinfo::mounts_under(root).unwrap().is_empty());
+
+        // the inner container exits first
+        outer.chroot().unwrap();
+        let mut inner = Container::new_bare("/inner");
+        inner.chroot().unwrap();
+        let err = Error::from(outer.exit_chroot().unwrap_err());
+        assert!(matches!(err, Error::NestedChroot { .. }));
+        drop(inner);
+        assert!(Path::new("/inner").is_dir());
+        outer.exit_chroot().unwrap();
+        outer.umount().unwrap();
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_drop_error() {
+        std::fs::create_dir_all("/tmp/tiffin").unwrap();
+        let errors = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut container = Container::new("/tmp/tiffin");
+        let recorded = errors.clone();
+        container.on_drop_error(move |e| recorded.lock().unwrap().push(e.to_string()));
+        container.mount().unwrap();
+        // unmounted behind tiffin's back, so unmounting it again fails
+        nix::mount::umount("/tmp/tiffin/proc").unwrap();
+        drop(container);
+        assert_eq!(errors.lock().unwrap().len(), 1);
+
+        let mut container = Container::new("/tmp/tiffin");
+        container.mount().unwrap();
+        nix::mount::umount("/tmp/tiffin/proc").unwrap();
+        let (container, _) = container.close().unwrap_err();
+        assert!(container.close().is_ok());
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_persist() {
+        let root = TempDir::new("persist");
+        std::fs::create_dir(root.join("etc")).unwrap();
+        std::fs::write(root.join("etc/hosts"), "127.0.0.1 image\n").unwrap();
+        let mut container = Container::new(&*root);
+        let errors = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = errors.clone();
+        container.on_drop_error(move |e| recorded.lock().unwrap().push(e.to_string()));
+        container
+            .setup_network_config(NetworkConfig::BindFromHost)
+            .unwrap();
+        container
+            .prepare_etc(EtcPrepOptions {
+                machine_id: Some(MachineId::Uninitialized),
+                ..EtcPrepOptions::default()
+            })
+            .unwrap();
+        container.mount().unwrap();
+        let record = container.persist().unwrap();
+        assert!(!record.mounts.is_empty());
+        assert!(root.join("proc/self").exists());
+        // the files of the container are left for whoever the root was handed to
+        assert!(errors.lock().unwrap().is_empty());
+        assert_eq!(
+            std::fs::read_to_string(root.join("etc/hosts")).unwrap(),
+            std::fs::read_to_string("/etc/hosts").unwrap()
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.join("etc/machine-id")).unwrap(),
+            "uninitialized\n"
+        );
+
+        let record = PersistedMounts::from_json(&record.to_json().unwrap()).unwrap();
+        unmount_persisted(&record).unwrap();
+        assert!(mountinfo::mounts_under(&root).unwrap().is_empty());
+        // only covered by the mount
+        assert_eq!(
+            std::fs::read_to_string(root.join("etc/hosts")).unwrap(),
+            "127.0.0.1 image\n"
+        );
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_cleanup_stale() {
+        let root = Path::new("/tmp/tiffin-stale");
+        std::fs::create_dir_all(root).unwrap();
+        let mut container = Container::new(root);
+        container.with_id("leaky").unwrap();
+        container.mount().unwrap();
+        // as if the process was killed
+        let record = container.persist().unwrap();
+
+        // this process mounted them and is still running
+        let report = cleanup_stale(root, CleanupOptions::default()).unwrap();
+        assert!(!report.is_clean());
+        assert!(report.mounts.is_empty());
+        assert_eq!(report.skipped.len(), record.mounts.len());
+        assert_eq!(
+            report.skipped[0].result.as_ref().unwrap_err().kind(),
+            std::io::ErrorKind::ResourceBusy
+        );
+        assert!(ContainerMarker::path(root, "leaky").exists());
+        assert_eq!(
+            mountinfo::mounts_under(root).unwrap().len(),
+            record.mounts.len()
+        );
+
+        let options = CleanupOptions {
+            force_running: true,
+            ..CleanupOptions::default()
+        };
+        let report = cleanup_stale(root, options).unwrap();
+        assert!(report.is_clean());
+        assert_eq!(report.mounts.len(), record.mounts.len());
+        // deepest first
+        assert!(report.mounts[0].mount_point.ends_with("dev/pts"));
+        assert!(mountinfo::mounts_under(root).unwrap().is_empty());
+        // the marker tells who left them
+        for mount in &report.mounts {
+            let marker = report.container_of(mount).unwrap();
+            assert_eq!(marker.id, "leaky");
+            assert_eq!(marker.pid, std::process::id());
+        }
+        assert!(!ContainerMarker::path(root, "leaky").exists());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_marker() {
+        let root = Path::new("/tmp/tiffin-marker");
+        std::fs::create_dir_all(root).unwrap();
+        let mut container = Container::new(root);
+        container.with_id("marker-test").unwrap();
+        assert_eq!(container.status().id, "marker-test");
+        let path = ContainerMarker::path(root, "marker-test");
+
+        container.mount().unwrap();
+        let marker = ContainerMarker::load(&path).unwrap();
+        assert_eq!(marker.id, "marker-test");
+        assert_eq!(marker.pid, std::process::id());
+        assert_eq!(marker.mounts.len(), container.mount_table.inner.len());
+        assert!(marker.contains(&root.canonicalize().unwrap().join("proc")));
+
+        container.umount().unwrap();
+        assert!(!path.exists());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_marker_failed_mount() {
+        let root = TempDir::new("marker-failed");
+        let mut container = Container::new_bare(&*root);
+        assert!(container.with_id("../escape").is_err());
+        container.with_id("failed").unwrap();
+        container.mount().unwrap();
+        // nothing to mount, so no marker either
+        assert!(!ContainerMarker::path(&root, "failed").exists());
+        container.umount().unwrap();
+
+        container.add_mount(
+            MountTarget {
+                target: "missing".into(),
+                fstype: Some("tmpfs".to_string()),
+                mountpoint: MountpointOptions {
+                    create: false,
+                    ..MountpointOptions::default()
+                },
+                ..MountTarget::default()
+            },
+            "tmpfs",
+        );
+        assert!(container.mount().is_err());
+        assert!(!ContainerMarker::path(&root, "failed").exists());
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_enter_sealed() {
+        std::fs::create_dir_all("/tmp/tiffin").unwrap();
+        let host_root = File::open("/").unwrap();
+        let fd = host_root.as_raw_fd();
+        // the classic escape, through a descriptor of a directory outside the root
+        let escape = move || match nix::unistd::fchdir(fd) {
+            Ok(()) => 1,
+            Err(_) => match nix::unistd::chroot("/") {
+                Err(nix::errno::Errno::EPERM) => 0,
+                _ => 2,
+            },
+        };
+
+        let mut container = Container::new("/tmp/tiffin");
+        assert_eq!(container.run_forked(escape).unwrap(), 1);
+        container.enter_sealed(SealOptions::default());
+        assert_eq!(container.run_forked(escape).unwrap(), 0);
+        let sysroot = container.sysroot.as_raw_fd();
+        let code = container
+            .run_forked(move || i32::from(nix::unistd::fchdir(sysroot).is_ok()))
+            .unwrap();
+        assert_eq!(code, 0);
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_capabilities() {
+        std::fs::create_dir_all("/tmp/tiffin").unwrap();
+        let mut container = Container::new("/tmp/tiffin");
+        container.set_capabilities(CapabilitySet::build_sandbox());
+        let code = container
+            .run_forked(|| match nix::unistd::chroot("/") {
+                Err(nix::errno::Errno::EPERM) => 0,
+                _ => 1,
+            })
+            .unwrap();
+        assert_eq!(code, 0);
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_capabilities_with_user() {
+        let mut container = host_userland("/tmp/tiffin-userland");
+        // the user is looked up in the image, which is the host's /etc once mounted
+        container.mount().unwrap();
+        // without CAP_SETUID and CAP_SETGID, which switching to the user needs
+        container
+            .set_capabilities(CapabilitySet::none())
+            .set_user("nobody")
+            .unwrap();
+        let code = container
+            .run_forked(|| {
+                let setuid = Capability::Setuid as libc::c_ulong;
+                // SAFETY: prctl with integer arguments only
+                let bounding = unsafe { libc::prctl(libc::PR_CAPBSET_READ, setuid, 0, 0, 0) };
+                i32::from(nix::unistd::getuid().is_root() || bounding != 0)
+            })
+            .unwrap();
+        assert_eq!(code, 0);
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_rlimits() {
+        let before = nix::sys::resource::getrlimit(Resource::RLIMIT_NOFILE).unwrap();
+
+        std::fs::create_dir_all("/tmp/tiffin").unwrap();
+        let mut container = Container::new("/tmp/tiffin");
+        container
+            .limit(Resource::RLIMIT_NOFILE, 16u64, 16u64)
+            .unwrap();
+        let err = container
+            .run(|| {
+                (0..32)
+                    .map(|_| File::open("/"))
+                    .collect::<std::io::Result<Vec<_>>>()
+                    .unwrap_err()
+            })
+            .unwrap();
+        assert_eq!(err.raw_os_error(), Some(libc::EMFILE));
+
+        let after = nix::sys::resource::getrlimit(Resource::RLIMIT_NOFILE).unwrap();
+        assert_eq!(before, after);
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_command() {
+        let mut container = host_userland("/tmp/tiffin-userland");
+        container.workdir("/tmp").create_workdir(true);
+        let output = container
+            .command("/bin/sh")
+            .unwrap()
+            .arg("-c")
+            .arg("echo $PWD; cat /etc/os-release")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.starts_with("/tmp\n"));
+        assert_eq!(
+            &stdout[5..],
+            std::fs::read_to_string("/etc/os-release").unwrap()
+        );
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_rootfs_from_host_binaries() {
+        let root = Path::new("/tmp/tiffin-rootfs");
+        let ls = ["/usr/bin/ls", "/bin/ls"]
+            .into_iter()
+            .find(|ls| Path::new(ls).exists())
+            .unwrap();
+        rootfs::from_host_binaries(root, &["/bin/sh", ls]).unwrap();
+        let mut container = Container::new(root);
+        let output = container
+            .command("/bin/sh")
+            .unwrap()
+            .arg("-c")
+            .arg(format!("{ls} /etc"))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"group\npasswd\n");
+        drop(container);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_in_container() {
+        // a command configured by someone else
+        let mut command = std::process::Command::new("/bin/cat");
+        command.arg("/tiffin-marker");
+
+        let mut container = host_userland("/tmp/tiffin-userland");
+        std::fs::write("/tmp/tiffin-userland/tiffin-marker", "inside").unwrap();
+
+        // not mounted yet
+        let err = command.in_container(&container).output().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+
+        container.mount().unwrap();
+        let output = command.output().unwrap();
+        assert_eq!(output.stdout, b"inside");
+
+        // one container can't be entered from within another
+        let other = Container::new("/tmp/tiffin");
+        let err = command.in_container(&other).output().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_proc_hidepid() {
+        let mut container = host_userland("/tmp/tiffin-userland");
+        container
+            .set_proc_options(ProcMountOptions {
+                hidepid: HidePid::Invisible,
+                ..ProcMountOptions::default()
+            })
+            .set_user("nobody")
+            .unwrap();
+        let code = container
+            .run_forked(|| i32::from(Path::new("/proc/1").exists()))
+            .unwrap();
+        assert_eq!(code, 0);
+        // root still sees everything
+        container.user = None;
+        let code = container
+            .run_forked(|| i32::from(Path::new("/proc/1").exists()))
+            .unwrap();
+        assert_eq!(code, 1);
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_spawn() {
+        let mut container = host_userland("/tmp/tiffin-userland");
+        let mut child = container
+            .spawn(CommandSpec::new("/bin/sleep").arg("30"))
+            .unwrap();
+        assert!(child.try_wait().unwrap().is_none());
+
+        // the mounts can't be torn down while the child is running
+        assert!(container.umount().is_err());
+
+        child.kill(nix::sys::signal::Signal::SIGKILL).unwrap();
+        assert!(!child.wait().unwrap().success());
+        container.umount().unwrap();
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_timeout() {
+        let mut container = host_userland("/tmp/tiffin-userland");
+        let start = std::time::Instant::now();
+        let err = container
+            .run_forked_with_timeout(Duration::from_secs(1), || {
+                std::thread::sleep(Duration::from_secs(60));
+                0
+            })
+            .unwrap_err();
+        assert!(matches!(err, Error::TimedOut { output: None, .. }));
+        assert!(start.elapsed() < Duration::from_secs(3));
+
+        let start = std::time::Instant::now();
+        let spec = CommandSpec::new("/bin/sh")
+            .args(["-c", "echo $$; exec sleep 60"])
+            .timeout(Duration::from_secs(1));
+        let Err(Error::TimedOut {
+            output: Some(output),
+            ..
+        }) = container.run_output_with(spec, OutputOptions::default())
+        else {
+            panic!("expected a timeout");
+        };
+        assert!(start.elapsed() < Duration::from_secs(3));
+        let pid: i32 = String::from_utf8(output.stdout)
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        assert!(!process::is_running(Pid::from_raw(pid)));
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_cleanup_on_signals() {
+        std::fs::create_dir_all("/tmp/tiffin").unwrap();
+        let mut container = Container::new("/tmp/tiffin");
+        container.cleanup_on_signals().unwrap();
+        container.mount().unwrap();
+
+        // a forked child dies from the signal without touching the shared mounts
+        // SAFETY: the child only raises a signal
+        match unsafe { nix::unistd::fork() }.unwrap() {
+            nix::unistd::ForkResult::Child => process::run_child(|| {
+                nix::sys::signal::raise(nix::sys::signal::Signal::SIGTERM).ok();
+                0
+            }),
+            nix::unistd::ForkResult::Parent { child } => {
+                assert_eq!(process::wait_pid(child).unwrap(), 128 + libc::SIGTERM);
+            }
+        }
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(!mountinfo::mounts_under(&container.root).unwrap().is_empty());
+        container.umount().unwrap();
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_kill_lingering() {
+        let mut container = host_userland("/tmp/tiffin-userland");
+        container.set_unmount_policy(UnmountPolicy::KillUsers);
+        container.mount().unwrap();
+
+        // a daemon left behind, outside of tiffin's control
+        let mut sleeper = std::process::Command::new("/usr/bin/sleep")
+            .arg("60")
+            .current_dir("/tmp/tiffin-userland/usr")
+            .spawn()
+            .unwrap();
+
+        container.umount().unwrap();
+        assert!(!sleeper.wait().unwrap().success());
+        assert!(mountinfo::mounts_under(Path::new("/tmp/tiffin-userland"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_pid_namespace() {
+        std::fs::create_dir_all("/tmp/tiffin").unwrap();
+        let mut container = Container::new("/tmp/tiffin");
+        container.pid_namespace(true);
+        let code = container
+            .run_forked(|| {
+                if std::process::id() != 2 {
+                    return 1;
+                }
+                // double fork, so the grandchild is orphaned and left to init
+                let child = process::fork_child(|| {
+                    process::fork_child(|| 0).map_or(-1, |grandchild| grandchild.as_raw())
+                })
+                .unwrap();
+                let grandchild = Pid::from_raw(process::wait_pid(child).unwrap());
+                std::thread::sleep(Duration::from_millis(200));
+                // a zombie would still exist until reaped
+                match nix::sys::signal::kill(grandchild, None) {
+                    Err(nix::errno::Errno::ESRCH) => 0,
+                    _ => 2,
+                }
+            })
+            .unwrap();
+        assert_eq!(code, 0);
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_builder_namespaces() {
+        std::fs::create_dir_all("/tmp/tiffin").unwrap();
+        let host = nix::unistd::gethostname().unwrap();
+        let mut container = Container::builder()
+            .root("/tmp/tiffin")
+            .hostname("tiffin-test")
+            .namespaces(Namespaces {
+                net: true,
+                ..Namespaces::default()
+            })
+            .build()
+            .unwrap();
+        let code = container
+            .run_forked(|| {
+                let hostname = nix::unistd::gethostname().unwrap();
+                // only the loopback device exists in a new network namespace
+                let interfaces = std::fs::read_to_string("/proc/net/dev").unwrap();
+                i32::from(hostname != "tiffin-test" || interfaces.lines().count() != 3)
+            })
+            .unwrap();
+        assert_eq!(code, 0);
+        assert_eq!(nix::unistd::gethostname().unwrap(), host);
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_socket_passthrough() {
+        use std::{io::Write, os::unix::net::UnixListener};
+
+        let runtime_dir = TempDir::new("session");
+        let listener = UnixListener::bind(runtime_dir.join("bus")).unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"hello").unwrap();
+        });
+
+        std::fs::create_dir_all("/tmp/tiffin").unwrap();
+        let mut container = Container::new_bare("/tmp/tiffin");
+        let session = desktop::HostSession {
+            runtime_dir: runtime_dir.to_path_buf(),
+            x11: PathBuf::from("/tmp/.X11-unix"),
+            xauthority: None,
+        };
+        let (report, mounts) = session.bus(false);
+        assert_eq!(report.bound, [runtime_dir.join("bus")]);
+        container.add_session_mounts(&session, mounts).unwrap();
+
+        let bus = runtime_dir.join("bus");
+        let received = container
+            .run(|| {
+                let mut stream = std::os::unix::net::UnixStream::connect(&bus).unwrap();
+                let mut received = String::new();
+                std::io::Read::read_to_string(&mut stream, &mut received).unwrap();
+                received
+            })
+            .unwrap();
+        assert_eq!(received, "hello");
+        server.join().unwrap();
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_exec_into() {
+        let mut container = host_userland("/tmp/tiffin-userland");
+        container.workdir("/usr");
+        container.mount().unwrap();
+
+        // SAFETY: the child only execs or exits
+        match unsafe { nix::unistd::fork() }.unwrap() {
+            nix::unistd::ForkResult::Child => process::run_child(move || {
+                container
+                    .exec_into("sh", ["-c", "test $PWD = /usr && exit 7"])
+                    .unwrap_err();
+                1
+            }),
+            nix::unistd::ForkResult::Parent { child } => {
+                assert_eq!(process::wait_pid(child).unwrap(), 7);
+                container.umount().unwrap();
+            }
+        }
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_exec_into_missing() {
+        let mut container = host_userland("/tmp/tiffin-userland");
+        container.mount().unwrap();
+        // SAFETY: the child only execs or exits
+        match unsafe { nix::unistd::fork() }.unwrap() {
+            nix::unistd::ForkResult::Child => process::run_child(move || {
+                match container.exec_into("/no/such/program", Vec::<String>::new()) {
+                    Err(Error::Spawn { program, source })
+                        if program == "/no/such/program"
+                            && source.kind() == std::io::ErrorKind::NotFound =>
+                    {
+                        0
+                    }
+                    _ => 1,
+                }
+            }),
+            nix::unistd::ForkResult::Parent { child } => {
+                assert_eq!(process::wait_pid(child).unwrap(), 0);
+                container.umount().unwrap();
+            }
+        }
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_run_output() {
+        let mut container = host_userland("/tmp/tiffin-userland");
+        let output = container
+            .run_output("/bin/sh", ["-c", "echo out; echo err >&2; exit 3"])
+            .unwrap();
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
+
+        let output = container
+            .run_output_with(
+                CommandSpec::new("/bin/sh").args(["-c", "echo 123456789; echo err >&2"]),
+                OutputOptions {
+                    merge_stderr: true,
+                    limit: Some(14),
+                },
+            )
+            .unwrap();
+        assert_eq!(output.stdout, b"123456789\nerr\n");
+        assert!(!output.truncated);
+
+        let output = container
+            .run_output_with(
+                CommandSpec::new("/bin/echo").arg("123456789"),
+                OutputOptions {
+                    limit: Some(4),
+                    ..OutputOptions::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(output.stdout, b"1234");
+        assert!(output.truncated);
+
+        assert!(matches!(
+            container.run_output("/does/not/exist", [""; 0]),
+            Err(Error::Spawn { .. })
+        ));
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_workdir() {
+        let host_cwd = std::env::current_dir().unwrap();
+
+        std::fs::create_dir_all("/tmp/tiffin").unwrap();
+        let mut container = Container::new("/tmp/tiffin");
+        container.workdir("/builddir/build").create_workdir(true);
+        let cwd = container.run(|| std::env::current_dir().unwrap()).unwrap();
+        assert_eq!(cwd, PathBuf::from("/builddir/build"));
+        assert_eq!(std::env::current_dir().unwrap(), host_cwd);
+
+        let mut container = Container::new("/tmp/tiffin");
+        container.workdir("/does/not/exist");
+        assert!(container.run(|| ()).is_err());
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_env() {
+        let before: Vec<_> = std::env::vars_os().collect();
+
+        std::fs::create_dir_all("/tmp/tiffin").unwrap();
+        let mut container = Container::new("/tmp/tiffin");
+        container
+            .set_env_policy(EnvPolicy::ClearAll)
+            .env("TIFFIN_TEST", "1");
+        let vars = container
+            .run(|| std::env::vars().collect::<Vec<_>>())
+            .unwrap();
+        assert_eq!(vars, vec![("TIFFIN_TEST".to_string(), "1".to_string())]);
+
+        let after: Vec<_> = std::env::vars_os().collect();
+        assert_eq!(before, after);
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_cgroup() {
+        std::fs::create_dir_all("/tmp/tiffin").unwrap();
+        let mut container = Container::new("/tmp/tiffin");
+        container.set_cgroup(CgroupConfig {
+            pids_max: Some(5),
+            ..CgroupConfig::default()
+        });
+        let code = container
+            .run_forked(|| {
+                for _ in 0..10 {
+                    // SAFETY: the grandchildren only sleep and exit
+                    match unsafe { nix::unistd::fork() } {
+                        Ok(nix::unistd::ForkResult::Child) => {
+                            std::thread::sleep(std::time::Duration::from_secs(1));
+                            unsafe { libc::_exit(0) }
+                        }
+                        Ok(nix::unistd::ForkResult::Parent { .. }) => {}
+                        Err(nix::errno::Errno::EAGAIN) => return 0,
+                        Err(_) => return 2,
+                    }
+                }
+                1
+            })
+            .unwrap();
+        assert_eq!(code, 0);
+        assert!(container.cgroup().unwrap().path().exists());
+    }
+
+    #[cfg(feature = "seccomp")]
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_seccomp() {
+        let mount_tmpfs = || {
+            nix::mount::mount(
+                Some("tmpfs"),
+                "/tmp",
+                Some("tmpfs"),
+                nix::mount::MsFlags::empty(),
+                None::<&str>,
+            )
+        };
+
+        std::fs::create_dir_all("/tmp/tiffin/tmp").unwrap();
+        let mut container = Container::new("/tmp/tiffin");
+        container.set_seccomp(SeccompPolicy::deny_escapes());
+        let code = container
+            .run_forked(|| {
+                if mount_tmpfs() != Err(nix::errno::Errno::EPERM) {
+                    return 1;
+                }
+                // SAFETY: the path is a valid C string
+                let open_tree =
+                    unsafe { libc::syscall(libc::SYS_open_tree, libc::AT_FDCWD, c"/".as_ptr(), 0) };
+                if open_tree >= 0 || nix::errno::Errno::last() != nix::errno::Errno::EPERM {
+                    return 2;
+                }
+                // a new mount namespace through clone, as unshare would
+                // SAFETY: without CLONE_VM, the child gets a copy of the memory as with fork
+                let clone = unsafe {
+                    libc::syscall(
+                        libc::SYS_clone,
+                        libc::CLONE_NEWNS | libc::SIGCHLD,
+                        0,
+                        0,
+                        0,
+                        0,
+                    )
+                };
+                if clone == 0 {
+                    // SAFETY: only exits
+                    unsafe { libc::_exit(0) };
+                }
+                if clone >= 0 || nix::errno::Errno::last() != nix::errno::Errno::EPERM {
+                    return 3;
+                }
+                // threads are still created, as libc falls back from clone3
+                match std::thread::spawn(|| 7).join() {
+                    Ok(7) => 0,
+                    _ => 4,
+                }
+            })
+            .unwrap();
+        assert_eq!(code, 0);
+
+        // the same call without the policy still works
+        let mut container = Container::new("/tmp/tiffin");
+        let code = container
+            .run_forked(|| match mount_tmpfs() {
+                Ok(()) => nix::mount::umount("/tmp").map_or(1, |_| 0),
+                Err(_) => 1,
+            })
+            .unwrap();
+        assert_eq!(code, 0);
+    }
+}
@@ -0,0 +1,439 @@
+//! Async API on top of tokio, enabled with the `tokio` feature
+
+use super::{
+    process, CommandSpec, Container, Error, MountTable, Output, OutputOptions, Result, StdioMode,
+};
+use nix::{
+    sys::signal::{killpg, Signal},
+    unistd::Pid,
+};
+use std::{
+    ffi::OsString,
+    os::fd::{FromRawFd, OwnedFd},
+    process::Stdio,
+    sync::mpsc,
+};
+use tokio::io::{unix::AsyncFd, AsyncRead, AsyncReadExt};
+
+/// Kills and reaps a forked child if dropped before the child was reaped,
+/// then cleans up the container
+struct ForkGuard<'a> {
+    container: &'a mut Container,
+    child: Option<Pid>,
+}
+
+impl Drop for ForkGuard<'_> {
+    fn drop(&mut self) {
+        let Some(child) = self.child.take() else {
+            return;
+        };
+        tracing::debug!(?child, "Cancelled, killing forked child");
+        nix::sys::signal::kill(child, Signal::SIGKILL).ok();
+        if let Err(e) = process::wait_pid(child) {
+            tracing::error!(?e, "Failed to reap forked child");
+        }
+        if let Err(e) = self.container.finish_forked() {
+            tracing::error!(?e, "Failed to clean up container");
+        }
+    }
+}
+
+/// What [`Container::lend_blocking`] moved into a blocking task, and the outcome of the task
+type Lent = (MountTable, Vec<process::SpawnedChild>, std::io::Result<()>);
+
+/// Waits for a blocking task if dropped before it finished, then puts back the mount
+/// table and spawned children it was lent, and records its outcome with `cancelled`
+///
+/// This keeps the container from losing its mount table, including its configuration,
+/// journal and progress callback, when a future is dropped mid-way.
+struct LendGuard<'a> {
+    container: &'a mut Container,
+    returned: Option<mpsc::Receiver<Lent>>,
+    cancelled: fn(&mut Container, std::io::Result<()>),
+}
+
+impl Drop for LendGuard<'_> {
+    fn drop(&mut self) {
+        let Some(returned) = self.returned.take() else {
+            return;
+        };
+        tracing::debug!("Cancelled, waiting for blocking task");
+        match returned.recv() {
+            Ok((table, children, result)) => {
+                self.container.mount_table = table;
+                self.container.children = children;
+                (self.cancelled)(self.container, result);
+            }
+            // the task panicked
+            Err(e) => tracing::error!(?e, "Blocking task returned no mount table"),
+        }
+    }
+}
+
+fn pidfd_open(pid: Pid) -> std::io::Result<OwnedFd> {
+    // SAFETY: pidfd_open takes no pointers
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid.as_raw(), 0) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: the descriptor was just created and is owned by nobody else
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as i32) })
+}
+
+/// Wait for a specific child to exit without blocking the runtime, see [`process::wait_pid`]
+async fn wait_pid_async(pid: Pid) -> std::io::Result<i32> {
+    let pidfd = AsyncFd::new(pidfd_open(pid)?)?;
+    // a pidfd becomes readable once the process has exited
+    let _ready = pidfd.readable().await?;
+    process::wait_pid(pid)
+}
+
+/// Async version of [`process::read_limited`]
+async fn read_limited_async(
+    mut reader: impl AsyncRead + Unpin,
+    limit: Option<usize>,
+) -> process::Captured {
+    let mut out = Vec::new();
+    let mut truncated = false;
+    let mut buf = [0; 8192];
+    loop {
+        let n = match reader.read(&mut buf).await {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let keep = limit.map_or(n, |limit| limit.saturating_sub(out.len()).min(n));
+        out.extend_from_slice(&buf[..keep]);
+        truncated |= keep < n;
+    }
+    Ok((out, truncated))
+}
+
+fn join_error(e: tokio::task::JoinError) -> std::io::Error {
+    std::io::Error::other(e)
+}
+
+impl Container {
+    /// Like [`Container::run_forked`], but waits for the child without blocking the runtime
+    ///
+    /// If the future is dropped before the child exits, the child is killed
+    /// and the container is cleaned up as if it had exited.
+    pub async fn run_async<F>(&mut self, f: F) -> Result<i32>
+    where
+        F: FnOnce() -> i32,
+    {
+        if !self._initialized {
+            self.mount_async().await?;
+        }
+        let child = self.fork_payload(f, false)?;
+        let mut guard = ForkGuard {
+            container: self,
+            child: Some(child),
+        };
+        let code = wait_pid_async(child).await?;
+        guard.child = None;
+
+        if let Some(cgroup) = &guard.container.cgroup {
+            cgroup.kill()?;
+        }
+        if guard.container._initialized {
+            guard.container.umount_async().await?;
+        }
+        Ok(code)
+    }
+
+    /// Like [`Container::mount`], doing the mounting on a blocking thread
+    ///
+    /// If the future is dropped while mounting, dropping it waits for the mounting to
+    /// finish, and the container is left mounted without running the post-mount hooks.
+    pub async fn mount_async(&mut self) -> Result<()> {
+        self.hooks.check_reentrancy()?;
+        self.run_hooks(crate::Phase::PreMount)?;
+        self.write_marker();
+        let root = self.root.clone();
+        let result = self
+            .lend_blocking(
+                move |table, _| table.mount_chroot(&root),
+                |container, result| {
+                    if let Err(e) = container.mount_finished(result) {
+                        tracing::error!(?e, "Failed to mount container");
+                    }
+                },
+            )
+            .await?;
+        self.mount_finished(result)?;
+        Ok(self.run_post_mount_hooks()?)
+    }
+
+    /// Like [`Container::umount`], doing the unmounting on a blocking thread
+    ///
+    /// If the future is dropped while unmounting, dropping it waits for the unmounting
+    /// to finish, without running the post-unmount hooks.
+    pub async fn umount_async(&mut self) -> Result<()> {
+        self.hooks.check_reentrancy()?;
+        self.run_hooks(crate::Phase::PreUnmount)?;
+        let root = self.root.clone();
+        let (child_policy, unmount_policy) = (self.child_policy, self.unmount_policy);
+        let result = self
+            .lend_blocking(
+                move |table, children| {
+                    super::prepare_umount(&root, children, child_policy, unmount_policy)
+                        .and_then(|()| table.umount_chroot())
+                },
+                |container, result| {
+                    if let Err(e) = container.unmounted(result) {
+                        tracing::error!(?e, "Failed to unmount container");
+                    }
+                },
+            )
+            .await?;
+        self.unmounted(result)?;
+        Ok(self.run_hooks(crate::Phase::PostUnmount)?)
+    }
+
+    /// Run `f` on a blocking thread with the mount table and spawned children moved
+    /// into it, putting them back once it's done
+    ///
+    /// Returns the result of `f`. If the future is dropped first, see [`LendGuard`].
+    async fn lend_blocking<F>(
+        &mut self,
+        f: F,
+        cancelled: fn(&mut Container, std::io::Result<()>),
+    ) -> Result<std::io::Result<()>>
+    where
+        F: FnOnce(&mut MountTable, &mut Vec<process::SpawnedChild>) -> std::io::Result<()>
+            + Send
+            + 'static,
+    {
+        let mut table = std::mem::take(&mut self.mount_table);
+        let mut children = std::mem::take(&mut self.children);
+        let span = self.span.clone();
+        let (send, returned) = mpsc::sync_channel(1);
+        let task = tokio::task::spawn_blocking(move || {
+            let result = span.in_scope(|| f(&mut table, &mut children));
+            // the guard waits for this, so the receiver is still there
+            send.send((table, children, result)).ok();
+        });
+        let mut guard = LendGuard {
+            container: self,
+            returned: Some(returned),
+            cancelled,
+        };
+        task.await.map_err(join_error)?;
+        let returned = guard.returned.take().map(|returned| returned.try_recv());
+        let Some(Ok((table, children, result))) = returned else {
+            return Err(std::io::Error::other("Blocking task finished without returning").into());
+        };
+        guard.container.mount_table = table;
+        guard.container.children = children;
+        Ok(result)
+    }
+
+    /// Like [`Container::spawn`], returning a tokio child with async wait and stdio
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn spawn_async(&mut self, spec: CommandSpec) -> Result<tokio::process::Child> {
+        let child = {
+            let mut command = self.command(&spec.program)?;
+            spec.configure(&mut command);
+            tokio::process::Command::from(command.into_inner())
+                .spawn()
+                .map_err(|source| Error::Spawn {
+                    program: spec.program.clone(),
+                    source,
+                })?
+        };
+        if let Some(pid) = child.id() {
+            tracing::debug!(pid, program = ?spec.program, "Spawned process in container");
+            self.children.push(process::SpawnedChild::open(pid)?);
+        }
+        Ok(child)
+    }
+
+    /// Like [`Container::run_output`], without blocking the runtime
+    pub async fn run_output_async<I, S>(
+        &mut self,
+        program: impl Into<OsString>,
+        args: I,
+    ) -> Result<Output>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        let spec = CommandSpec::new(program).args(args).stdin(StdioMode::Null);
+        self.run_output_with_async(spec, OutputOptions::default())
+            .await
+    }
+
+    /// Like [`Container::run_output_with`], without blocking the runtime
+    ///
+    /// If the future is dropped, the program is killed.
+    pub async fn run_output_with_async(
+        &mut self,
+        spec: CommandSpec,
+        options: OutputOptions,
+    ) -> Result<Output> {
+        let (mut child, merged) = {
+            let mut command = self.command(&spec.program)?;
+            spec.configure(&mut command);
+            let merged = if options.merge_stderr {
+                let (read, write) = process::pipe()?;
+                command.stdout(write.try_clone()?).stderr(write);
+                Some(tokio::net::unix::pipe::Receiver::from_file(read)?)
+            } else {
+                command.stdout(Stdio::piped()).stderr(Stdio::piped());
+                None
+            };
+            let mut command = tokio::process::Command::from(command.into_inner());
+            command.kill_on_drop(true);
+            let child = command.spawn().map_err(|source| Error::Spawn {
+                program: spec.program.clone(),
+                source,
+            })?;
+            // dropping the command closes our copy of the write end of the pipe
+            (child, merged)
+        };
+
+        drop(child.stdin.take());
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+        let pgid = child.id().map(|pid| Pid::from_raw(pid as i32));
+
+        let read_stdout = async {
+            match (merged, stdout) {
+                (Some(merged), _) => read_limited_async(merged, options.limit).await,
+                (None, Some(stdout)) => read_limited_async(stdout, options.limit).await,
+                (None, None) => Ok((Vec::new(), false)),
+            }
+        };
+        let read_stderr = async {
+            match stderr {
+                Some(stderr) => read_limited_async(stderr, options.limit).await,
+                None => Ok((Vec::new(), false)),
+            }
+        };
+        let wait = async {
+            let Some(timeout) = spec.timeout else {
+                return Ok::<_, std::io::Error>((child.wait().await?, false));
+            };
+            if let Ok(status) = tokio::time::timeout(timeout, child.wait()).await {
+                return Ok((status?, false));
+            }
+            let Some(pgid) = pgid else {
+                return Ok((child.wait().await?, true));
+            };
+            tracing::debug!(?pgid, ?timeout, "Timed out, terminating process group");
+            killpg(pgid, Signal::SIGTERM).ok();
+            let grace = spec.grace_period.unwrap_or(process::DEFAULT_GRACE_PERIOD);
+            let status = match tokio::time::timeout(grace, child.wait()).await {
+                Ok(status) => status?,
+                Err(_) => {
+                    tracing::debug!(?pgid, "Process group survived SIGTERM, killing it");
+                    killpg(pgid, Signal::SIGKILL).ok();
+                    child.wait().await?
+                }
+            };
+            // don't leave behind processes that outlived the group leader
+            killpg(pgid, Signal::SIGKILL).ok();
+            Ok((status, true))
+        };
+
+        let (wait, stdout, stderr) = tokio::join!(wait, read_stdout, read_stderr);
+        let (status, timed_out) = wait?;
+        let (stdout, stdout_truncated) = stdout?;
+        let (stderr, stderr_truncated) = stderr?;
+
+        let output = Output {
+            status,
+            stdout,
+            stderr,
+            truncated: stdout_truncated || stderr_truncated,
+        };
+        match spec.timeout {
+            Some(timeout) if timed_out => Err(Error::TimedOut {
+                timeout,
+                output: Some(Box::new(output)),
+            }),
+            _ => Ok(output),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tempdir::TempDir;
+    use super::*;
+    use std::{
+        fs::File,
+        io::{Read, Write},
+        path::Path,
+        time::Duration,
+    };
+
+    #[tokio::test]
+    async fn test_read_limited_async() {
+        let input: &[u8] = b"hello world";
+        let (out, truncated) = read_limited_async(input, Some(5)).await.unwrap();
+        assert_eq!(out, b"hello");
+        assert!(truncated);
+    }
+
+    #[ignore = "This test requires root"]
+    #[tokio::test]
+    async fn test_run_async_cancel() {
+        std::fs::create_dir_all("/tmp/tiffin").unwrap();
+        let mut container = Container::new("/tmp/tiffin");
+        let (mut read, write) = process::pipe().unwrap();
+        let write = File::from(write);
+
+        let run = container.run_async(move || {
+            (&write).write_all(&std::process::id().to_ne_bytes()).ok();
+            std::thread::sleep(Duration::from_secs(60));
+            0
+        });
+        assert!(tokio::time::timeout(Duration::from_millis(500), run)
+            .await
+            .is_err());
+
+        let mut pid = [0; 4];
+        read.read_exact(&mut pid).unwrap();
+        let pid = Pid::from_raw(u32::from_ne_bytes(pid) as i32);
+        assert!(!process::is_running(pid));
+        assert!(crate::mountinfo::mounts_under(Path::new("/tmp/tiffin"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[ignore = "This test requires root"]
+    #[tokio::test]
+    async fn test_mount_async_cancel() {
+        let root = TempDir::new("mount-async-cancel");
+        let mut container = Container::new(&*root);
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        container.on_progress(move |event| {
+            if matches!(event, crate::ProgressEvent::MountStarted { .. }) {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            recorded.lock().unwrap().push(event);
+        });
+
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), container.mount_async())
+                .await
+                .is_err()
+        );
+        // dropping the future waited for the mounting, and put the mount table back
+        assert!(container._initialized);
+        assert!(!container.mount_table.is_empty());
+        let marker = container.marker.clone().unwrap();
+        assert!(marker.exists());
+
+        let mounted = events.lock().unwrap().len();
+        container.umount_async().await.unwrap();
+        assert!(events.lock().unwrap().len() > mounted);
+        assert!(!marker.exists());
+        assert!(crate::mountinfo::mounts_under(&root).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,89 @@
+//! Replacing files of the image for the container, and putting them back afterwards
+//!
+//! Files shipped by the image are moved aside rather than overwritten,
+//! so they survive even if the process dies before restoring them.
+
+use std::path::{Path, PathBuf};
+
+/// A file of the container replaced by tiffin
+#[derive(Debug)]
+struct Replaced {
+    path: PathBuf,
+    /// Where the image's own file was moved, if it had one
+    backup: Option<PathBuf>,
+}
+
+/// Files of the container replaced by tiffin, until they are restored
+#[derive(Debug, Default)]
+pub(crate) struct Backups {
+    replaced: Vec<Replaced>,
+}
+
+impl Backups {
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.replaced.is_empty()
+    }
+
+    /// Move the image's own file at `path` aside, if any, and record it for [`Backups::restore`]
+    pub fn replace(&mut self, path: &Path) -> std::io::Result<()> {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let backup = path.with_file_name(format!(".{name}.tiffin-orig"));
+        let backup = if exists(&backup)? {
+            // left behind by a process that died before restoring,
+            // so that is the image's file and the current one is ours
+            match std::fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => Some(backup),
+            }
+        } else if exists(path)? {
+            // renaming never follows symlinks
+            std::fs::rename(path, &backup)?;
+            Some(backup)
+        } else {
+            None
+        };
+        self.replaced.push(Replaced {
+            path: path.to_path_buf(),
+            backup,
+        });
+        Ok(())
+    }
+
+    /// Put back the image's own files, removing those created for the container
+    ///
+    /// Files that can't be restored are kept so this can be retried,
+    /// and the first error is returned. Mounts over them must be unmounted first.
+    pub fn restore(&mut self) -> std::io::Result<()> {
+        let mut result = Ok(());
+        let mut failed = Vec::new();
+        for replaced in self.replaced.drain(..).rev() {
+            tracing::trace!(path = ?replaced.path, "Restoring file of the image");
+            let restored = match std::fs::remove_file(&replaced.path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+                _ => match &replaced.backup {
+                    Some(backup) => std::fs::rename(backup, &replaced.path),
+                    None => Ok(()),
+                },
+            };
+            if let Err(e) = restored {
+                tracing::error!(?e, path = ?replaced.path, "Failed to restore file");
+                failed.push(replaced);
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        self.replaced = failed;
+        result
+    }
+}
+
+/// Whether something exists at `path`, without following symlinks
+pub(crate) fn exists(path: &Path) -> std::io::Result<bool> {
+    match path.symlink_metadata() {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,314 @@
+use super::danger;
+use crate::{Container, EnvPolicy, Error, MountTarget, Namespaces, Result};
+use std::path::{Path, PathBuf};
+use sys_mount::MountFlags;
+
+/// Configures a [`Container`] before creating it
+///
+/// Nothing touches the system until [`ContainerBuilder::build`], which does all
+/// the fallible setup at once. Mounts are only made when the container is mounted,
+/// as with [`Container::new`].
+///
+/// ```no_run
+/// # fn main() -> tiffin::Result<()> {
+/// let mut container = tiffin::Container::builder()
+///     .root("/var/lib/machines/fedora")
+///     .bind_ro("/etc/resolv.conf", "/etc/resolv.conf")
+///     .tmpfs("/tmp", "mode=1777")
+///     .hostname("builder")
+///     .build()?;
+/// container.run(|| std::fs::write("/tmp/hello", "world"))??;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ContainerBuilder {
+    root: Option<PathBuf>,
+    default_mounts: bool,
+    /// Sources and mounts, in the order they were added
+    mounts: Vec<(PathBuf, MountTarget)>,
+    hostname: Option<String>,
+    workdir: Option<PathBuf>,
+    env_policy: Option<EnvPolicy>,
+    rootless: bool,
+    namespaces: Namespaces,
+    allow_dangerous_root: bool,
+}
+
+impl Default for ContainerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ContainerBuilder {
+    pub fn new() -> Self {
+        Self {
+            root: None,
+            default_mounts: true,
+            mounts: Vec::new(),
+            hostname: None,
+            workdir: None,
+            env_policy: None,
+            rootless: false,
+            namespaces: Namespaces::default(),
+            allow_dangerous_root: false,
+        }
+    }
+
+    /// Sets the directory used as the root of the container, which must exist
+    pub fn root(mut self, path: impl Into<PathBuf>) -> Self {
+        self.root = Some(path.into());
+        self
+    }
+
+    /// Whether to add the mounts of [`Container::add_default_mounts`], enabled by default
+    pub fn include_default_mounts(mut self, include: bool) -> Self {
+        self.default_mounts = include;
+        self
+    }
+
+    /// Bind mount a host file or directory to `target` inside the container
+    pub fn bind(self, source: impl Into<PathBuf>, target: impl Into<PathBuf>) -> Self {
+        self.bind_with_flags(source, target, MountFlags::BIND)
+    }
+
+    /// Like [`ContainerBuilder::bind`], but the mount is read-only
+    pub fn bind_ro(self, source: impl Into<PathBuf>, target: impl Into<PathBuf>) -> Self {
+        self.bind_with_flags(source, target, MountFlags::BIND | MountFlags::RDONLY)
+    }
+
+    fn bind_with_flags(
+        mut self,
+        source: impl Into<PathBuf>,
+        target: impl Into<PathBuf>,
+        flags: MountFlags,
+    ) -> Self {
+        let mount = MountTarget {
+            target: target.into(),
+            flags,
+            ..MountTarget::default()
+        };
+        self.mounts.push((source.into(), mount));
+        self
+    }
+
+    /// Mount a tmpfs at `target` inside the container
+    ///
+    /// `options` is passed to the kernel as is, e.g. `"mode=1777,size=64m"`, and may be empty.
+    pub fn tmpfs(mut self, target: impl Into<PathBuf>, options: impl Into<String>) -> Self {
+        let target = target.into();
+        let options = options.into();
+        let source = tmpfs_source(&target);
+        let mount = MountTarget {
+            target,
+            fstype: Some("tmpfs".to_string()),
+            data: (!options.is_empty()).then_some(options),
+            ..MountTarget::default()
+        };
+        self.mounts.push((source, mount));
+        self
+    }
+
+    /// Sets the hostname seen inside the container, which implies a UTS namespace
+    pub fn hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.hostname = Some(hostname.into());
+        self
+    }
+
+    /// Sets the working directory inside the container, see [`Container::workdir`]
+    pub fn workdir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.workdir = Some(path.into());
+        self
+    }
+
+    /// Sets which host environment variables are visible, see [`Container::set_env_policy`]
+    pub fn env_policy(mut self, policy: EnvPolicy) -> Self {
+        self.env_policy = Some(policy);
+        self
+    }
+
+    /// Run code in the container in a user namespace, with the calling user mapped to root
+    ///
+    /// This lets an unprivileged user chroot and act as root inside the container.
+    /// Mounts are still made by the calling process though, so without privileges
+    /// combine this with [`ContainerBuilder::include_default_mounts`] set to `false`
+    /// and no other mounts.
+    pub fn rootless(mut self, rootless: bool) -> Self {
+        self.rootless = rootless;
+        self
+    }
+
+    /// Sets the namespaces that code running in the container is moved into
+    ///
+    /// They apply to forked children only, such as [`Container::run_forked`] and
+    /// [`Container::command`], not to [`Container::run`].
+    pub fn namespaces(mut self, namespaces: Namespaces) -> Self {
+        self.namespaces = namespaces;
+        self
+    }
+
+    /// Allow a root that resolves to `/` or contains critical host paths, disabled by default
+    ///
+    /// Mounting and unmounting such a container changes the mounts of the host, e.g.
+    /// unmounting its `/proc`. Only use this when that is what you want.
+    pub fn allow_dangerous_root(mut self, allow: bool) -> Self {
+        self.allow_dangerous_root = allow;
+        self
+    }
+
+    /// Create the container
+    ///
+    /// Fails with [`Error::InvalidRoot`] if no root was set or it isn't a directory, and
+    /// with [`Error::DangerousRoot`] for the roots refused by [`Container::try_new`],
+    /// unless [`ContainerBuilder::allow_dangerous_root`] is set.
+    pub fn build(self) -> Result<Container> {
+        let root = self.root.ok_or_else(|| Error::InvalidRoot {
+            root: PathBuf::new(),
+            reason: "no root directory was set".to_string(),
+        })?;
+        let invalid = |reason: String| Error::InvalidRoot {
+            root: root.clone(),
+            reason,
+        };
+        match std::fs::metadata(&root) {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(_) => return Err(invalid("not a directory".to_string())),
+            Err(e) => return Err(invalid(e.to_string())),
+        }
+        if !self.allow_dangerous_root {
+            danger::check_root(&root)?;
+        }
+
+        let mut container = Container::open(root)?;
+        if self.default_mounts {
+            container.add_default_mounts();
+        }
+        for (source, mount) in self.mounts {
+            container.add_mount(mount, source);
+        }
+
+        let mut namespaces = self.namespaces;
+        namespaces.uts |= self.hostname.is_some();
+        namespaces.user |= self.rootless;
+        container.pid_namespace = namespaces.pid;
+        container.namespaces = namespaces;
+        container.hostname = self.hostname;
+        container.workdir = self.workdir;
+        if let Some(policy) = self.env_policy {
+            container.set_env_policy(policy);
+        }
+        Ok(container)
+    }
+}
+
+/// Source of a tmpfs mounted at `target`
+///
+/// The kernel ignores the source of a tmpfs, but the mount table is keyed by source,
+/// so each tmpfs needs its own. It shows up in the host's mount table.
+pub(crate) fn tmpfs_source(target: &Path) -> PathBuf {
+    let target = target.strip_prefix("/").unwrap_or(target);
+    PathBuf::from(format!("tmpfs:/{}", target.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::tempdir::TempDir;
+    use itertools::Itertools;
+
+    fn targets(container: &Container) -> Vec<PathBuf> {
+        container
+            .mount_table
+            .inner
+            .values()
+            .map(|mount| mount.target.clone())
+            .sorted()
+            .collect()
+    }
+
+    #[test]
+    fn test_default_mounts() {
+        let container = Container::builder()
+            .root(std::env::temp_dir())
+            .build()
+            .unwrap();
+        assert_eq!(
+            targets(&container),
+            ["dev", "dev/pts", "proc", "sys"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn test_mounts() {
+        let container = Container::builder()
+            .root(std::env::temp_dir())
+            .include_default_mounts(false)
+            .bind("/home/user/project", "/build")
+            .bind_ro("/etc/resolv.conf", "/etc/resolv.conf")
+            .tmpfs("/tmp", "mode=1777")
+            .tmpfs("/var/tmp", "")
+            .build()
+            .unwrap();
+        let table = &container.mount_table.inner;
+        assert_eq!(table.len(), 4);
+        assert_eq!(
+            table[Path::new("/home/user/project")].flags,
+            MountFlags::BIND
+        );
+        assert_eq!(
+            table[Path::new("/etc/resolv.conf")].flags,
+            MountFlags::BIND | MountFlags::RDONLY
+        );
+        let tmp = &table[&tmpfs_source(Path::new("/tmp"))];
+        assert_eq!(tmp.target, Path::new("/tmp"));
+        assert_eq!(tmp.fstype.as_deref(), Some("tmpfs"));
+        assert_eq!(tmp.data.as_deref(), Some("mode=1777"));
+        assert_eq!(table[&tmpfs_source(Path::new("/var/tmp"))].data, None);
+    }
+
+    #[test]
+    fn test_namespaces() {
+        let container = Container::builder()
+            .root(std::env::temp_dir())
+            .hostname("tiffin")
+            .rootless(true)
+            .namespaces(Namespaces {
+                pid: true,
+                ..Namespaces::default()
+            })
+            .build()
+            .unwrap();
+        assert!(container.pid_namespace);
+        assert!(container.namespaces.uts && container.namespaces.user);
+        assert!(!container.namespaces.net);
+        assert_eq!(container.hostname.as_deref(), Some("tiffin"));
+    }
+
+    #[test]
+    fn test_dangerous_root() {
+        let err = Container::builder().root("/").build().unwrap_err();
+        assert!(matches!(err, Error::DangerousRoot { root } if root == Path::new("/")));
+        let container = Container::builder()
+            .root("/")
+            .include_default_mounts(false)
+            .allow_dangerous_root(true)
+            .build()
+            .unwrap();
+        assert_eq!(container.root, Path::new("/"));
+    }
+
+    #[test]
+    fn test_invalid_root() {
+        assert!(matches!(
+            Container::builder().build(),
+            Err(Error::InvalidRoot { .. })
+        ));
+        let dir = TempDir::new("builder");
+        let missing = dir.join("missing");
+        assert!(matches!(
+            Container::builder().root(&missing).build(),
+            Err(Error::InvalidRoot { root, .. }) if root == missing
+        ));
+    }
+}
@@ -0,0 +1,145 @@
+//! Sharing the host's package caches with containers,
+//! see [`crate::Container::share_package_cache`]
+
+use nix::fcntl::{flock, FlockArg};
+use std::{
+    fs::File,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+};
+
+/// Name of the lock file [`crate::Container::share_package_cache`] creates in a cache
+///
+/// Package managers lock files of their own in their caches, which this must not touch.
+pub(crate) const CACHE_LOCK_FILE: &str = ".tiffin.lock";
+
+/// A package cache of the host, see [`crate::Container::share_package_cache`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CacheKind {
+    /// `/var/cache/dnf`, of dnf 4
+    Dnf,
+    /// `/var/cache/libdnf5`, of dnf 5
+    Libdnf5,
+    /// `/var/cache/apt/archives`, the downloaded packages of apt
+    Apt,
+    /// `/var/cache/pacman/pkg`
+    Pacman,
+    /// Any other cache, from a path on the host to a path in the container
+    Custom(PathBuf, PathBuf),
+}
+
+impl CacheKind {
+    /// Where the cache is on the host
+    pub fn host_path(&self) -> &Path {
+        match self {
+            Self::Custom(host, _) => host,
+            kind => kind.container_path(),
+        }
+    }
+
+    /// Where the package manager of the container expects the cache
+    pub fn container_path(&self) -> &Path {
+        Path::new(match self {
+            Self::Dnf => "/var/cache/dnf",
+            Self::Libdnf5 => "/var/cache/libdnf5",
+            Self::Apt => "/var/cache/apt/archives",
+            Self::Pacman => "/var/cache/pacman/pkg",
+            Self::Custom(_, container) => return container,
+        })
+    }
+}
+
+/// A file on the host locked with `flock` for as long as a mount is active,
+/// see [`crate::MountTarget::lock`]
+///
+/// Any number of mounts may hold a shared lock on the same file at once, but only one
+/// an exclusive lock, so mounting waits until the others are unmounted.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MountLock {
+    /// Created if it doesn't exist
+    pub path: PathBuf,
+    pub exclusive: bool,
+}
+
+impl MountLock {
+    /// Wait for the lock, which is held until the returned file is closed
+    pub(crate) fn acquire(&self) -> std::io::Result<File> {
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&self.path)?;
+        let (blocking, nonblocking) = match self.exclusive {
+            true => (FlockArg::LockExclusive, FlockArg::LockExclusiveNonblock),
+            false => (FlockArg::LockShared, FlockArg::LockSharedNonblock),
+        };
+        match flock(file.as_raw_fd(), nonblocking) {
+            Err(nix::errno::Errno::EWOULDBLOCK) => {
+                tracing::debug!(path = ?self.path, "Waiting for the lock held by another mount");
+                flock(file.as_raw_fd(), blocking)?;
+            }
+            result => result?,
+        }
+        tracing::trace!(path = ?self.path, exclusive = self.exclusive, "Locked");
+        Ok(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::tempdir::TempDir;
+    use std::{sync::mpsc, time::Duration};
+
+    /// Whether anyone holds a lock on `path`, as seen from a separate open file
+    fn is_locked(path: &Path) -> bool {
+        let file = File::open(path).unwrap();
+        flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock).is_err()
+    }
+
+    #[test]
+    fn test_paths() {
+        assert_eq!(CacheKind::Dnf.host_path(), Path::new("/var/cache/dnf"));
+        assert_eq!(
+            CacheKind::Apt.container_path(),
+            Path::new("/var/cache/apt/archives")
+        );
+        let custom = CacheKind::Custom("/srv/cache/zypp".into(), "/var/cache/zypp".into());
+        assert_eq!(custom.host_path(), Path::new("/srv/cache/zypp"));
+        assert_eq!(custom.container_path(), Path::new("/var/cache/zypp"));
+    }
+
+    #[test]
+    fn test_lock() {
+        let dir = TempDir::new("cache");
+        let path = dir.join(CACHE_LOCK_FILE);
+        let lock = |exclusive| MountLock {
+            path: path.clone(),
+            exclusive,
+        };
+
+        let first = lock(false).acquire().unwrap();
+        let second = lock(false).acquire().unwrap();
+        assert!(is_locked(&path));
+        drop((first, second));
+        assert!(!is_locked(&path));
+
+        let writer = lock(true).acquire().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let second = lock(true);
+        let waiting = std::thread::spawn(move || {
+            let file = second.acquire().unwrap();
+            sender.send(()).unwrap();
+            file
+        });
+        // the second writer waits for the first one
+        assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+        drop(writer);
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        let writer = waiting.join().unwrap();
+        assert!(is_locked(&path));
+        drop(writer);
+        assert!(!is_locked(&path));
+    }
+}
@@ -0,0 +1,244 @@
+/// Linux capabilities, numbered as in `linux/capability.h`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Capability {
+    Chown = 0,
+    DacOverride = 1,
+    DacReadSearch = 2,
+    Fowner = 3,
+    Fsetid = 4,
+    Kill = 5,
+    Setgid = 6,
+    Setuid = 7,
+    Setpcap = 8,
+    LinuxImmutable = 9,
+    NetBindService = 10,
+    NetBroadcast = 11,
+    NetAdmin = 12,
+    NetRaw = 13,
+    IpcLock = 14,
+    IpcOwner = 15,
+    SysModule = 16,
+    SysRawio = 17,
+    SysChroot = 18,
+    SysPtrace = 19,
+    SysPacct = 20,
+    SysAdmin = 21,
+    SysBoot = 22,
+    SysNice = 23,
+    SysResource = 24,
+    SysTime = 25,
+    SysTtyConfig = 26,
+    Mknod = 27,
+    Lease = 28,
+    AuditWrite = 29,
+    AuditControl = 30,
+    Setfcap = 31,
+    MacOverride = 32,
+    MacAdmin = 33,
+    Syslog = 34,
+    WakeAlarm = 35,
+    BlockSuspend = 36,
+    AuditRead = 37,
+    Perfmon = 38,
+    Bpf = 39,
+    CheckpointRestore = 40,
+}
+
+impl Capability {
+    fn mask(self) -> u64 {
+        1 << self as u8
+    }
+}
+
+/// Capabilities to keep for code running inside the container
+///
+/// The default keeps everything, which is the same as not bounding capabilities at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapabilitySet {
+    /// Bitmask of capabilities to drop
+    drop: u64,
+    no_new_privs: bool,
+}
+
+impl Default for CapabilitySet {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+impl CapabilitySet {
+    /// Keep every capability
+    pub const fn all() -> Self {
+        Self {
+            drop: 0,
+            no_new_privs: false,
+        }
+    }
+
+    /// Drop every capability
+    pub const fn none() -> Self {
+        Self {
+            drop: u64::MAX,
+            no_new_privs: false,
+        }
+    }
+
+    /// Keep only the given capabilities, dropping everything else
+    pub fn only(caps: &[Capability]) -> Self {
+        let keep = caps.iter().fold(0, |mask, cap| mask | cap.mask());
+        Self {
+            drop: !keep,
+            no_new_privs: false,
+        }
+    }
+
+    /// Preset for running builds: keeps only CHOWN, DAC_OVERRIDE, FOWNER, SETUID and SETGID
+    ///
+    /// Notably this drops CAP_SYS_CHROOT and CAP_SYS_ADMIN, and sets `no_new_privs`.
+    pub fn build_sandbox() -> Self {
+        Self::only(&[
+            Capability::Chown,
+            Capability::DacOverride,
+            Capability::Fowner,
+            Capability::Setuid,
+            Capability::Setgid,
+        ])
+        .no_new_privs(true)
+    }
+
+    /// Drop a capability
+    pub fn without(mut self, cap: Capability) -> Self {
+        self.drop |= cap.mask();
+        self
+    }
+
+    /// Keep a capability
+    pub fn with(mut self, cap: Capability) -> Self {
+        self.drop &= !cap.mask();
+        self
+    }
+
+    /// Set `PR_SET_NO_NEW_PRIVS`, so exec can never grant more privileges (e.g. setuid binaries)
+    pub fn no_new_privs(mut self, enabled: bool) -> Self {
+        self.no_new_privs = enabled;
+        self
+    }
+
+    /// Whether the capability is kept
+    pub fn contains(&self, cap: Capability) -> bool {
+        self.drop & cap.mask() == 0
+    }
+
+    /// Whether this set changes anything at all when applied
+    pub fn is_noop(&self) -> bool {
+        self.drop == 0 && !self.no_new_privs
+    }
+
+    /// Apply the set to the current process
+    ///
+    /// `switch_user` runs once the bounding set is limited, while the capabilities
+    /// needed to change the user, which the set may drop, are still effective.
+    /// This is irreversible, so it should only ever be called in a forked child.
+    pub(crate) fn apply(
+        &self,
+        switch_user: impl FnOnce() -> std::io::Result<()>,
+    ) -> std::io::Result<()> {
+        if self.drop != 0 {
+            self.drop_bounding()?;
+        }
+        switch_user()?;
+        if self.drop != 0 {
+            self.drop_current()?;
+        }
+
+        if self.no_new_privs {
+            // SAFETY: prctl with integer arguments only
+            if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop capabilities from the bounding set
+    fn drop_bounding(&self) -> std::io::Result<()> {
+        for cap in 0..64u64 {
+            if self.drop & (1 << cap) == 0 {
+                continue;
+            }
+            // SAFETY: prctl with integer arguments only
+            if unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap, 0, 0, 0) } != 0 {
+                let err = std::io::Error::last_os_error();
+                // EINVAL means the kernel doesn't know about this capability, and neither do any higher ones
+                if err.raw_os_error() == Some(libc::EINVAL) {
+                    break;
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Clear capabilities from the effective, permitted and inheritable sets
+    fn drop_current(&self) -> std::io::Result<()> {
+        let mut header = CapUserHeader {
+            version: LINUX_CAPABILITY_VERSION_3,
+            pid: 0,
+        };
+        let header_ptr = &mut header as *mut CapUserHeader;
+        let mut data = [CapUserData::default(); 2];
+
+        // SAFETY: header and data are valid for the v3 ABI, which takes two data structs
+        let ret = unsafe { libc::syscall(libc::SYS_capget, header_ptr, data.as_mut_ptr()) };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        for (i, data) in data.iter_mut().enumerate() {
+            let keep = !(self.drop >> (32 * i)) as u32;
+            data.effective &= keep;
+            data.permitted &= keep;
+            data.inheritable &= keep;
+        }
+
+        // SAFETY: same as above
+        let ret = unsafe { libc::syscall(libc::SYS_capset, header_ptr, data.as_ptr()) };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+#[repr(C)]
+struct CapUserHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_sandbox() {
+        let caps = CapabilitySet::build_sandbox();
+        assert!(caps.contains(Capability::Chown));
+        assert!(caps.contains(Capability::Setgid));
+        assert!(!caps.contains(Capability::SysChroot));
+        assert!(!caps.contains(Capability::SysAdmin));
+        assert!(!caps.contains(Capability::CheckpointRestore));
+        assert!(CapabilitySet::default().is_noop());
+    }
+}
@@ -0,0 +1,218 @@
+//! Giving the container the host's CA certificates,
+//! see [`crate::Container::enable_host_ca_certs`]
+
+use super::{
+    relative,
+    resolve::{resolve_in_root, Create},
+};
+use std::path::{Path, PathBuf};
+
+/// Where a family of distributions keeps its CA certificates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CertLayout {
+    /// Fedora, RHEL and openSUSE-like, managed by `update-ca-trust`
+    Fedora,
+    /// Debian, Ubuntu and Arch-like, managed by `update-ca-certificates`
+    Debian,
+}
+
+impl CertLayout {
+    const ALL: [Self; 2] = [Self::Fedora, Self::Debian];
+
+    /// The `OPENSSLDIR` of the family, which holds its configuration
+    fn openssl_dir(self) -> &'static str {
+        match self {
+            Self::Fedora => "/etc/pki/tls",
+            Self::Debian => "/etc/ssl",
+        }
+    }
+
+    /// The directory openssl looks certificates up in, holding the bundle
+    fn cert_dir(self) -> &'static str {
+        match self {
+            Self::Fedora => "/etc/pki/tls/certs",
+            Self::Debian => "/etc/ssl/certs",
+        }
+    }
+
+    /// The bundle of every trusted certificate, in [`CertLayout::cert_dir`]
+    fn bundle(self) -> &'static str {
+        match self {
+            Self::Fedora => "ca-bundle.crt",
+            Self::Debian => "ca-certificates.crt",
+        }
+    }
+
+    /// Where the files in [`CertLayout::cert_dir`] link to
+    fn link_targets(self) -> &'static str {
+        match self {
+            Self::Fedora => "/etc/pki/ca-trust/extracted",
+            Self::Debian => "/usr/share/ca-certificates",
+        }
+    }
+
+    /// The layout whose bundle exists in `root`
+    fn of_certs(root: &Path) -> Option<Self> {
+        Self::ALL.into_iter().find(|layout| {
+            let bundle = Path::new(layout.cert_dir()).join(layout.bundle());
+            // follows the symlink of Fedora's bundle too
+            resolve_in_root(root, &bundle, Create::Nothing).is_ok_and(|path| path.is_file())
+        })
+    }
+
+    /// The layout the openssl of `root` expects, even if it has no certificates
+    ///
+    /// Fedora images also have `/etc/ssl`, so it is checked first.
+    fn of_openssl(root: &Path) -> Option<Self> {
+        Self::ALL.into_iter().find(|layout| {
+            resolve_in_root(root, Path::new(layout.openssl_dir()), Create::Nothing)
+                .is_ok_and(|path| path.is_dir())
+        })
+    }
+}
+
+/// How to give a container the certificates of a host, see [`plan`]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct CertMounts {
+    /// Read-only binds from the host to the container
+    pub binds: Vec<(PathBuf, PathBuf)>,
+    /// Values of `SSL_CERT_FILE` and `SSL_CERT_DIR`, if the container's openssl won't
+    /// find the certificates on its own
+    pub env: Option<(PathBuf, PathBuf)>,
+}
+
+/// Plan binding the certificates of the host at `host_root` into the image at `image_root`,
+/// or `None` if the host has none
+///
+/// Only the certificate directories are bound, never the rest of `OPENSSLDIR` with its
+/// private keys. They go where the image's openssl looks for them, or where the host
+/// keeps them if the image has no openssl.
+pub(crate) fn plan(host_root: &Path, image_root: &Path) -> Option<CertMounts> {
+    let host = CertLayout::of_certs(host_root)?;
+    let image = CertLayout::of_openssl(image_root).unwrap_or(host);
+    let host_path = |path: &str| host_root.join(relative(Path::new(path)));
+
+    let mut binds = vec![(host_path(host.cert_dir()), PathBuf::from(image.cert_dir()))];
+    let links = host_path(host.link_targets());
+    if links.is_dir() {
+        // the certificates link there by absolute paths
+        binds.push((links, PathBuf::from(host.link_targets())));
+    }
+    let env = (image != host).then(|| {
+        let dir = PathBuf::from(image.cert_dir());
+        (dir.join(host.bundle()), dir)
+    });
+    Some(CertMounts { binds, env })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::tempdir::TempDir;
+
+    /// A tree with the certificates of `layout`, or only its openssl configuration
+    fn fixture(name: &str, layout: CertLayout, certs: bool) -> TempDir {
+        let root = TempDir::new(&format!("certs-{name}"));
+        let path = |path: &str| root.join(relative(Path::new(path)));
+        std::fs::create_dir_all(path(layout.openssl_dir())).unwrap();
+        if !certs {
+            return root;
+        }
+        std::fs::create_dir_all(path(layout.cert_dir())).unwrap();
+        std::fs::create_dir_all(path(layout.link_targets())).unwrap();
+        let bundle = Path::new(layout.cert_dir()).join(layout.bundle());
+        match layout {
+            CertLayout::Fedora => {
+                std::fs::create_dir_all(path("/etc/pki/ca-trust/extracted/pem")).unwrap();
+                std::fs::write(
+                    path("/etc/pki/ca-trust/extracted/pem/tls-ca-bundle.pem"),
+                    "",
+                )
+                .unwrap();
+                std::os::unix::fs::symlink(
+                    "/etc/pki/ca-trust/extracted/pem/tls-ca-bundle.pem",
+                    root.join(relative(&bundle)),
+                )
+                .unwrap();
+                // as in Fedora, for software expecting the Debian layout
+                std::fs::create_dir_all(path("/etc/ssl")).unwrap();
+                std::os::unix::fs::symlink("/etc/pki/tls/certs", path("/etc/ssl/certs")).unwrap();
+            }
+            CertLayout::Debian => std::fs::write(root.join(relative(&bundle)), "").unwrap(),
+        }
+        root
+    }
+
+    #[test]
+    fn test_detect() {
+        let fedora = fixture("fedora", CertLayout::Fedora, true);
+        let debian = fixture("debian", CertLayout::Debian, true);
+        let bare = fixture("bare", CertLayout::Debian, false);
+        assert_eq!(CertLayout::of_certs(&fedora), Some(CertLayout::Fedora));
+        assert_eq!(CertLayout::of_openssl(&fedora), Some(CertLayout::Fedora));
+        assert_eq!(CertLayout::of_certs(&debian), Some(CertLayout::Debian));
+        assert_eq!(CertLayout::of_openssl(&debian), Some(CertLayout::Debian));
+        assert_eq!(CertLayout::of_certs(&bare), None);
+        assert_eq!(CertLayout::of_openssl(&bare), Some(CertLayout::Debian));
+    }
+
+    #[test]
+    fn test_plan() {
+        let fedora = fixture("plan-fedora", CertLayout::Fedora, true);
+        let debian = fixture("plan-debian", CertLayout::Debian, true);
+        let bare = fixture("plan-bare", CertLayout::Debian, false);
+        let empty = TempDir::new("certs-empty");
+
+        // same family, the certificates go where they are on the host
+        assert_eq!(
+            plan(&fedora, &fedora),
+            Some(CertMounts {
+                binds: vec![
+                    (
+                        fedora.join("etc/pki/tls/certs"),
+                        "/etc/pki/tls/certs".into()
+                    ),
+                    (
+                        fedora.join("etc/pki/ca-trust/extracted"),
+                        "/etc/pki/ca-trust/extracted".into()
+                    ),
+                ],
+                env: None,
+            })
+        );
+        // a Debian image without certificates on a Fedora host
+        assert_eq!(
+            plan(&fedora, &bare),
+            Some(CertMounts {
+                binds: vec![
+                    (fedora.join("etc/pki/tls/certs"), "/etc/ssl/certs".into()),
+                    (
+                        fedora.join("etc/pki/ca-trust/extracted"),
+                        "/etc/pki/ca-trust/extracted".into()
+                    ),
+                ],
+                env: Some((
+                    "/etc/ssl/certs/ca-bundle.crt".into(),
+                    "/etc/ssl/certs".into()
+                )),
+            })
+        );
+        // a Fedora image on a Debian host
+        let mounts = plan(&debian, &fedora).unwrap();
+        assert_eq!(
+            mounts.binds[0],
+            (debian.join("etc/ssl/certs"), "/etc/pki/tls/certs".into())
+        );
+        assert_eq!(
+            mounts.env,
+            Some((
+                "/etc/pki/tls/certs/ca-certificates.crt".into(),
+                "/etc/pki/tls/certs".into()
+            ))
+        );
+        // no openssl in the image, so the host's layout is used
+        assert_eq!(plan(&debian, &empty).unwrap().env, None);
+        // no certificates on the host
+        assert_eq!(plan(&bare, &debian), None);
+    }
+}
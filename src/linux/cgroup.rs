@@ -0,0 +1,211 @@
+use crate::{Error, Result};
+use itertools::Itertools;
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+/// Counter for unique cgroup names when several containers live in one process
+static CGROUP_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// cgroup v2 limits for code running in [`crate::Container::run_forked`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CgroupConfig {
+    /// Parent cgroup to create the transient cgroup under
+    pub parent: PathBuf,
+    /// Name of the transient cgroup, defaults to `tiffin-<pid>`
+    pub name: Option<String>,
+    /// `memory.max` in bytes
+    pub memory_max: Option<u64>,
+    /// `memory.high` in bytes
+    pub memory_high: Option<u64>,
+    /// `cpu.max` as quota and period in microseconds
+    pub cpu_max: Option<(u64, u64)>,
+    /// `pids.max`
+    pub pids_max: Option<u64>,
+}
+
+impl Default for CgroupConfig {
+    fn default() -> Self {
+        Self {
+            parent: PathBuf::from("/sys/fs/cgroup"),
+            name: None,
+            memory_max: None,
+            memory_high: None,
+            cpu_max: None,
+            pids_max: None,
+        }
+    }
+}
+
+impl CgroupConfig {
+    /// Controllers that need to be enabled in the parent for this configuration
+    fn controllers(&self) -> Vec<&'static str> {
+        let mut controllers = Vec::new();
+        if self.memory_max.is_some() || self.memory_high.is_some() {
+            controllers.push("memory");
+        }
+        if self.cpu_max.is_some() {
+            controllers.push("cpu");
+        }
+        if self.pids_max.is_some() {
+            controllers.push("pids");
+        }
+        controllers
+    }
+
+    /// Limit files and the values written to them
+    fn limits(&self) -> Vec<(&'static str, String)> {
+        let mut limits = Vec::new();
+        if let Some(max) = self.memory_max {
+            limits.push(("memory.max", max.to_string()));
+        }
+        if let Some(high) = self.memory_high {
+            limits.push(("memory.high", high.to_string()));
+        }
+        if let Some((quota, period)) = self.cpu_max {
+            limits.push(("cpu.max", format!("{quota} {period}")));
+        }
+        if let Some(max) = self.pids_max {
+            limits.push(("pids.max", max.to_string()));
+        }
+        limits
+    }
+}
+
+/// A transient cgroup created for a container
+///
+/// The cgroup is removed when this is dropped, killing any processes left in it.
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Create the cgroup and write its limits
+    ///
+    /// Returns [`Error::Unsupported`] if the parent is not on a cgroup v2 hierarchy.
+    pub(crate) fn create(config: &CgroupConfig) -> Result<Self> {
+        if !config.parent.join("cgroup.controllers").exists() {
+            return Err(Error::Unsupported(format!(
+                "{} is not a cgroup v2 hierarchy",
+                config.parent.display()
+            )));
+        }
+
+        let name = config.name.clone().unwrap_or_else(|| {
+            match CGROUP_COUNT.fetch_add(1, Ordering::Relaxed) {
+                0 => format!("tiffin-{}", std::process::id()),
+                n => format!("tiffin-{}-{n}", std::process::id()),
+            }
+        });
+
+        let controllers = config.controllers();
+        if !controllers.is_empty() {
+            let enable = controllers.iter().map(|c| format!("+{c}")).join(" ");
+            std::fs::write(config.parent.join("cgroup.subtree_control"), enable)?;
+        }
+
+        let path = config.parent.join(name);
+        tracing::debug!(?path, "Creating cgroup");
+        std::fs::create_dir(&path)?;
+        let cgroup = Self { path };
+
+        for (file, value) in config.limits() {
+            tracing::trace!(file, value, "Setting cgroup limit");
+            std::fs::write(cgroup.path.join(file), value)?;
+        }
+        Ok(cgroup)
+    }
+
+    /// Path of the cgroup, e.g. for reading `memory.current` or `cpu.stat`
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read a file of the cgroup, such as `memory.peak`
+    pub fn read(&self, file: &str) -> std::io::Result<String> {
+        std::fs::read_to_string(self.path.join(file))
+    }
+
+    /// Pids of all processes in the cgroup
+    fn procs(&self) -> std::io::Result<Vec<i32>> {
+        Ok(self
+            .read("cgroup.procs")?
+            .lines()
+            .filter_map(|pid| pid.parse().ok())
+            .collect())
+    }
+
+    /// Kill every process left in the cgroup
+    pub(crate) fn kill(&self) -> std::io::Result<()> {
+        let kill_file = self.path.join("cgroup.kill");
+        if kill_file.exists() {
+            std::fs::write(kill_file, "1")?;
+        } else {
+            // cgroup.kill is only available since Linux 5.14
+            for pid in self.procs()? {
+                let pid = nix::unistd::Pid::from_raw(pid);
+                nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGKILL).ok();
+            }
+        }
+
+        // killed processes take a moment to leave the cgroup
+        for _ in 0..50 {
+            if self.procs()?.is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        tracing::debug!(path = ?self.path, "Removing cgroup");
+        if let Err(e) = self.kill() {
+            tracing::error!(?e, "Failed to kill processes in cgroup");
+        }
+        if let Err(e) = std::fs::remove_dir(&self.path) {
+            tracing::error!(?e, "Failed to remove cgroup");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits() {
+        let config = CgroupConfig {
+            memory_max: Some(1 << 30),
+            cpu_max: Some((50000, 100000)),
+            pids_max: Some(5),
+            ..CgroupConfig::default()
+        };
+        assert_eq!(config.controllers(), vec!["memory", "cpu", "pids"]);
+        assert_eq!(
+            config.limits(),
+            vec![
+                ("memory.max", "1073741824".to_string()),
+                ("cpu.max", "50000 100000".to_string()),
+                ("pids.max", "5".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let config = CgroupConfig {
+            parent: std::env::temp_dir(),
+            ..CgroupConfig::default()
+        };
+        assert!(matches!(
+            Cgroup::create(&config),
+            Err(Error::Unsupported(_))
+        ));
+    }
+}
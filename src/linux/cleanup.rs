@@ -0,0 +1,166 @@
+//! Unmounting containers when the process is interrupted
+//!
+//! Signal handlers can't take locks or allocate, so the handler only writes the
+//! signal number to a pipe. A dedicated thread reads it, unmounts everything in the
+//! registry with `MNT_DETACH`, leaves any chroot, and re-raises the signal with its
+//! default disposition so the process still dies the way it would have.
+
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use std::{
+    fs::File,
+    io::Read,
+    os::fd::{AsRawFd, IntoRawFd},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicI32, AtomicUsize, Ordering},
+        Mutex, OnceLock,
+    },
+};
+
+/// Signals that trigger the cleanup
+const SIGNALS: [Signal; 2] = [Signal::SIGINT, Signal::SIGTERM];
+
+/// Mountpoints of each registered container, in mount order
+static REGISTRY: Mutex<Vec<(usize, Vec<PathBuf>)>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+static INSTALLED: Mutex<bool> = Mutex::new(false);
+/// Root and working directory of the process when the handlers were installed
+static HOST_DIRS: OnceLock<(File, File)> = OnceLock::new();
+/// Process that installed the handlers, forked children don't clean up after it
+static OWNER: AtomicI32 = AtomicI32::new(-1);
+static PIPE: AtomicI32 = AtomicI32::new(-1);
+
+/// Install the signal handlers and start the cleanup thread, once per process
+pub(crate) fn install() -> std::io::Result<()> {
+    let mut installed = INSTALLED.lock().unwrap_or_else(|e| e.into_inner());
+    if *installed {
+        return Ok(());
+    }
+
+    HOST_DIRS.get_or_init(|| {
+        (
+            File::open("/").expect("/ can always be opened"),
+            File::open(".").unwrap_or_else(|_| File::open("/").expect("/ can always be opened")),
+        )
+    });
+
+    let (mut read, write) = super::process::pipe()?;
+    std::thread::Builder::new()
+        .name("tiffin-cleanup".into())
+        .spawn(move || {
+            let mut signal = [0; 1];
+            while read.read_exact(&mut signal).is_ok() {
+                cleanup(signal[0].into());
+            }
+        })?;
+    PIPE.store(write.into_raw_fd(), Ordering::SeqCst);
+    OWNER.store(std::process::id() as i32, Ordering::SeqCst);
+
+    let action = SigAction::new(
+        SigHandler::Handler(on_signal),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    for signal in SIGNALS {
+        // SAFETY: the handler only calls async-signal-safe functions
+        unsafe { sigaction(signal, &action) }?;
+    }
+    tracing::debug!("Installed cleanup handlers for {SIGNALS:?}");
+    *installed = true;
+    Ok(())
+}
+
+/// Register a container, returning its id in the registry
+pub(crate) fn register() -> usize {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    lock().push((id, Vec::new()));
+    id
+}
+
+/// Update the mountpoints of a registered container
+pub(crate) fn set_mounts(id: usize, mounts: Vec<PathBuf>) {
+    if let Some((_, entry)) = lock().iter_mut().find(|(entry, _)| *entry == id) {
+        *entry = mounts;
+    }
+}
+
+pub(crate) fn unregister(id: usize) {
+    lock().retain(|(entry, _)| *entry != id);
+}
+
+fn lock() -> std::sync::MutexGuard<'static, Vec<(usize, Vec<PathBuf>)>> {
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Mountpoints to detach, the most recently mounted first
+fn pending() -> Vec<PathBuf> {
+    lock()
+        .iter()
+        .rev()
+        .flat_map(|(_, mounts)| mounts.iter().rev().cloned())
+        .collect()
+}
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    // SAFETY: getpid, write, signal and raise are async-signal-safe
+    unsafe {
+        let pipe = PIPE.load(Ordering::SeqCst);
+        if libc::getpid() == OWNER.load(Ordering::SeqCst) && pipe >= 0 {
+            let byte = signal as u8;
+            libc::write(pipe, (&byte as *const u8).cast(), 1);
+        } else {
+            // a forked child: the mounts are shared, so only the parent tears them down
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+}
+
+fn cleanup(signal: libc::c_int) {
+    tracing::info!(signal, "Interrupted, unmounting containers");
+    // mountpoints are host paths, so get out of any chroot first
+    if let Some((root, cwd)) = HOST_DIRS.get() {
+        let escaped = nix::unistd::fchdir(root.as_raw_fd())
+            .and_then(|()| nix::unistd::chroot("."))
+            .and_then(|()| nix::unistd::fchdir(cwd.as_raw_fd()));
+        if let Err(e) = escaped {
+            tracing::error!(?e, "Failed to restore the root directory");
+        }
+    }
+    for target in pending() {
+        tracing::trace!(?target, "Detaching mount");
+        if let Err(e) = nix::mount::umount2(&target, nix::mount::MntFlags::MNT_DETACH) {
+            tracing::error!(?e, ?target, "Failed to unmount");
+        }
+    }
+
+    // let the default disposition apply, as if we were never there
+    // SAFETY: resetting a disposition and signaling ourselves is always safe
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::kill(libc::getpid(), signal);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry() {
+        let first = register();
+        let second = register();
+        set_mounts(first, vec!["/a".into(), "/a/proc".into()]);
+        set_mounts(second, vec!["/b".into()]);
+
+        let order = pending();
+        let a = order.iter().position(|p| p == "/a").unwrap();
+        let a_proc = order.iter().position(|p| p == "/a/proc").unwrap();
+        let b = order.iter().position(|p| p == "/b").unwrap();
+        assert!(b < a_proc && a_proc < a);
+
+        unregister(first);
+        unregister(second);
+        assert!(!pending().iter().any(|p| p == "/a" || p == "/b"));
+    }
+}
@@ -0,0 +1,358 @@
+use super::{
+    process::{self, ChildSetup},
+    Container,
+};
+use nix::{sys::signal::Signal, unistd::Pid};
+use std::{
+    ffi::{CStr, CString, OsString},
+    fmt,
+    ops::{Deref, DerefMut},
+    os::unix::process::CommandExt,
+    process::{Child, ChildStderr, ChildStdin, ChildStdout, Command, ExitStatus, Stdio},
+    time::Duration,
+};
+
+/// A [`Command`] that runs inside a container
+///
+/// Created by [`Container::command`]. It dereferences to the underlying [`Command`],
+/// and holds a borrow of the container so it can't be unmounted while the command is in use.
+pub struct ContainerCommand<'a> {
+    container: &'a mut Container,
+    command: Command,
+}
+
+impl<'a> ContainerCommand<'a> {
+    pub(crate) fn new(container: &'a mut Container, command: Command) -> Self {
+        Self { container, command }
+    }
+
+    /// The container the command runs in
+    pub fn container(&self) -> &Container {
+        self.container
+    }
+
+    /// Release the container borrow, keeping only the command
+    ///
+    /// The mounts must stay alive until any process spawned from the command is reaped.
+    pub fn into_inner(self) -> Command {
+        self.command
+    }
+}
+
+impl fmt::Debug for ContainerCommand<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ContainerCommand")
+            .field("root", &self.container.root)
+            .field("command", &self.command)
+            .finish()
+    }
+}
+
+impl Deref for ContainerCommand<'_> {
+    type Target = Command;
+
+    fn deref(&self) -> &Command {
+        &self.command
+    }
+}
+
+impl DerefMut for ContainerCommand<'_> {
+    fn deref_mut(&mut self) -> &mut Command {
+        &mut self.command
+    }
+}
+
+/// What the hook installed by [`TiffinCommandExt::in_container`] needs, read before forking
+#[derive(Debug)]
+struct InContainer {
+    setup: std::io::Result<ChildSetup>,
+    /// Device and inode of the root directory when the hook was installed, which no
+    /// longer match once a hook has entered a container
+    host_root: (u64, u64),
+    /// Targets of the container's mounts on the host, one of which must be mounted
+    /// at spawn time
+    mountpoints: Vec<CString>,
+}
+
+impl InContainer {
+    /// Enter the container from the forked child, with raw syscalls only
+    fn enter(&self) -> std::io::Result<()> {
+        if root_id() != Some(self.host_root) {
+            return Err(std::io::Error::from_raw_os_error(libc::EEXIST));
+        }
+        let mounted = self.mountpoints.is_empty()
+            || self
+                .mountpoints
+                .iter()
+                .any(|path| is_mount_root(path) != Some(false));
+        if !mounted {
+            return Err(std::io::Error::from_raw_os_error(libc::ENOENT));
+        }
+        let setup = self
+            .setup
+            .as_ref()
+            .map_err(|e| std::io::Error::from(e.kind()))?;
+        setup.enter()
+    }
+}
+
+/// Device and inode of the root directory of the calling process
+fn root_id() -> Option<(u64, u64)> {
+    let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+    // SAFETY: the path is a valid C string, and stat is only read once filled
+    unsafe {
+        if libc::stat(c"/".as_ptr(), stat.as_mut_ptr()) != 0 {
+            return None;
+        }
+        let stat = stat.assume_init();
+        Some((stat.st_dev, stat.st_ino))
+    }
+}
+
+/// Whether `path` is the root of a mount, `None` if the kernel can't tell
+fn is_mount_root(path: &CStr) -> Option<bool> {
+    let mut stat = std::mem::MaybeUninit::<libc::statx>::uninit();
+    let attr = libc::STATX_ATTR_MOUNT_ROOT as u64;
+    // SAFETY: the path is a valid C string, and stat is only read once filled
+    unsafe {
+        let flags = libc::AT_SYMLINK_NOFOLLOW | libc::AT_NO_AUTOMOUNT;
+        if libc::statx(libc::AT_FDCWD, path.as_ptr(), flags, 0, stat.as_mut_ptr()) != 0 {
+            return Some(false);
+        }
+        let stat = stat.assume_init();
+        (stat.stx_attributes_mask & attr != 0).then_some(stat.stx_attributes & attr != 0)
+    }
+}
+
+/// Extension trait to run an existing [`Command`] inside a container
+pub trait TiffinCommandExt {
+    /// Make the command run inside the container
+    ///
+    /// This installs a `pre_exec` hook entering the container with its configured user,
+    /// working directory and sandboxing, and applies the container's environment policy
+    /// to the command right away. The command's arguments and stdio are left alone, and
+    /// environment variables already set on the command take precedence over the policy.
+    ///
+    /// Call this once per command: if the command already runs inside a container,
+    /// spawning it fails with [`std::io::ErrorKind::AlreadyExists`] rather than entering
+    /// one container from within another.
+    ///
+    /// The container must be mounted by the time the command is spawned, or spawning
+    /// fails with [`std::io::ErrorKind::NotFound`]. It must also stay mounted until the
+    /// spawned child has been reaped.
+    fn in_container(&mut self, container: &Container) -> &mut Command;
+}
+
+impl TiffinCommandExt for Command {
+    fn in_container(&mut self, container: &Container) -> &mut Command {
+        let setup = container.child_setup();
+        if let Ok(setup) = &setup {
+            let env = setup.environment();
+            if !env.is_inherit() {
+                // variables set on the command itself take precedence
+                let explicit: Vec<(OsString, Option<OsString>)> = self
+                    .get_envs()
+                    .map(|(key, value)| (key.to_owned(), value.map(ToOwned::to_owned)))
+                    .collect();
+                self.env_clear().envs(env.resolve(std::env::vars_os()));
+                for (key, value) in explicit {
+                    match value {
+                        Some(value) => self.env(key, value),
+                        None => self.env_remove(key),
+                    };
+                }
+            }
+        }
+
+        let root =
+            std::fs::canonicalize(&container.root).unwrap_or_else(|_| container.root.clone());
+        let hook = InContainer {
+            setup,
+            host_root: root_id().unwrap_or_default(),
+            mountpoints: container
+                .mount_table
+                .sort_mounts()
+                .filter_map(|(_, mount)| {
+                    let target = mount.target.strip_prefix("/").unwrap_or(&mount.target);
+                    process::c_path(&root.join(target)).ok()
+                })
+                .collect(),
+        };
+
+        // SAFETY: the hook only makes raw syscalls and reads memory owned by the child
+        unsafe { self.pre_exec(move || hook.enter()) }
+    }
+}
+
+/// How a standard stream of a [`CommandSpec`] is set up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StdioMode {
+    #[default]
+    Inherit,
+    Null,
+    Piped,
+}
+
+impl From<StdioMode> for Stdio {
+    fn from(mode: StdioMode) -> Self {
+        match mode {
+            StdioMode::Inherit => Stdio::inherit(),
+            StdioMode::Null => Stdio::null(),
+            StdioMode::Piped => Stdio::piped(),
+        }
+    }
+}
+
+/// Description of a program to run inside a container
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandSpec {
+    pub program: OsString,
+    pub args: Vec<OsString>,
+    /// Variables set on top of the container's environment
+    pub env: Vec<(OsString, OsString)>,
+    pub stdin: StdioMode,
+    pub stdout: StdioMode,
+    pub stderr: StdioMode,
+    /// How long [`Container::run_output_with`](crate::Container::run_output_with)
+    /// lets the program run before terminating it
+    pub timeout: Option<Duration>,
+    /// Time between SIGTERM and SIGKILL once the timeout is hit, 5 seconds by default
+    pub grace_period: Option<Duration>,
+}
+
+impl CommandSpec {
+    pub fn new(program: impl Into<OsString>) -> Self {
+        Self {
+            program: program.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn arg(mut self, arg: impl Into<OsString>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn env(mut self, key: impl Into<OsString>, value: impl Into<OsString>) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn stdin(mut self, mode: StdioMode) -> Self {
+        self.stdin = mode;
+        self
+    }
+
+    pub fn stdout(mut self, mode: StdioMode) -> Self {
+        self.stdout = mode;
+        self
+    }
+
+    pub fn stderr(mut self, mode: StdioMode) -> Self {
+        self.stderr = mode;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = Some(grace_period);
+        self
+    }
+
+    /// Apply the arguments, environment and stdio to a command
+    pub(crate) fn configure(&self, command: &mut Command) {
+        command
+            .args(&self.args)
+            .envs(self.env.iter().map(|(k, v)| (k, v)))
+            .stdin(self.stdin)
+            .stdout(self.stdout)
+            .stderr(self.stderr);
+        if self.timeout.is_some() {
+            // the whole process group is terminated on timeout
+            command.process_group(0);
+        }
+    }
+}
+
+/// A process spawned inside a container by [`Container::spawn`]
+///
+/// The container refuses to unmount while this process is running,
+/// according to its [`ChildPolicy`](crate::ChildPolicy).
+#[derive(Debug)]
+pub struct ContainerChild {
+    child: Child,
+    pub stdin: Option<ChildStdin>,
+    pub stdout: Option<ChildStdout>,
+    pub stderr: Option<ChildStderr>,
+}
+
+impl ContainerChild {
+    pub(crate) fn new(mut child: Child) -> Self {
+        Self {
+            stdin: child.stdin.take(),
+            stdout: child.stdout.take(),
+            stderr: child.stderr.take(),
+            child,
+        }
+    }
+
+    /// Process id of the child, in the host's pid namespace
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Wait for the child to exit
+    ///
+    /// Only this specific child is waited for, so other `wait` users in the process are unaffected.
+    pub fn wait(&mut self) -> std::io::Result<ExitStatus> {
+        drop(self.stdin.take());
+        self.child.wait()
+    }
+
+    /// Check whether the child has exited, without blocking
+    pub fn try_wait(&mut self) -> std::io::Result<Option<ExitStatus>> {
+        self.child.try_wait()
+    }
+
+    /// Send a signal to the child
+    pub fn kill(&mut self, signal: Signal) -> std::io::Result<()> {
+        // don't signal a pid that may have been reused once the child was reaped
+        if self.child.try_wait()?.is_some() {
+            return Ok(());
+        }
+        nix::sys::signal::kill(Pid::from_raw(self.child.id() as i32), signal)?;
+        Ok(())
+    }
+}
+
+/// Options for [`Container::run_output_with`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OutputOptions {
+    /// Send stderr to the same pipe as stdout, so they're captured interleaved in `stdout`
+    pub merge_stderr: bool,
+    /// Maximum number of bytes kept for each of stdout and stderr, the rest is discarded
+    pub limit: Option<usize>,
+}
+
+/// Output of a program run inside a container, like [`std::process::Output`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    /// Whether some output was discarded because of [`OutputOptions::limit`]
+    pub truncated: bool,
+}
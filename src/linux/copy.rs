@@ -0,0 +1,272 @@
+//! Copying files across the container boundary, see [`crate::Container::copy_in`]
+
+use super::{
+    resolve::{resolve_in_root, Create},
+    Result,
+};
+use nix::{
+    sys::{
+        stat::{utimensat, UtimensatFlags},
+        time::TimeSpec,
+    },
+    unistd::{Gid, Uid},
+};
+use std::{
+    fs::Metadata,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+/// Where files are copied to
+#[derive(Debug, Clone, Copy)]
+enum Dest<'a> {
+    /// The host, where symlinks are followed as usual
+    Host,
+    /// A container root, where symlinks are followed without leaving it
+    Root(&'a Path),
+}
+
+/// Copy `src` from the host to `dest` inside the container at `root`
+///
+/// Directories are copied recursively, and merged into existing ones. If `dest` is
+/// an existing directory and `src` a file, the file is copied into it. Missing parents
+/// of `dest` are created. Symlinks are copied as symlinks, and permissions and
+/// modification times are kept. Files are owned by `owner` if given, otherwise
+/// ownership is only kept when running as root. Sockets, FIFOs and device nodes
+/// are skipped.
+pub(crate) fn copy_in(
+    src: &Path,
+    root: &Path,
+    dest: &Path,
+    owner: Option<(Uid, Gid)>,
+) -> Result<()> {
+    let metadata = std::fs::metadata(src)?;
+    let dest = if metadata.is_dir() {
+        resolve_in_root(root, dest, Create::Dir)?
+    } else {
+        let resolved = resolve_in_root(root, dest, Create::File)?;
+        match src.file_name() {
+            Some(name) if resolved.is_dir() => {
+                resolve_in_root(root, &relative(root, &resolved).join(name), Create::File)?
+            }
+            _ => resolved,
+        }
+    };
+    copy(src, &metadata, &dest, Dest::Root(root), owner)
+}
+
+/// Copy `src` from the container at `root` to `dest` on the host,
+/// the other way around from [`copy_in`]
+pub(crate) fn copy_out(root: &Path, src: &Path, dest: &Path) -> Result<()> {
+    let src = resolve_in_root(root, src, Create::Nothing)?;
+    let metadata = std::fs::metadata(&src)?;
+    let dest = match src.file_name() {
+        Some(name) if !metadata.is_dir() && dest.is_dir() => dest.join(name),
+        _ => dest.to_path_buf(),
+    };
+    if metadata.is_dir() {
+        std::fs::create_dir_all(&dest)?;
+    }
+    copy(&src, &metadata, &dest, Dest::Host, None)
+}
+
+/// Copy the contents of the directory `src` into the existing directory `root`
+pub(crate) fn copy_tree(src: &Path, root: &Path) -> Result<()> {
+    let metadata = std::fs::metadata(src)?;
+    copy(src, &metadata, root, Dest::Root(root), None)
+}
+
+/// Copy `src` to `to`, which exists if `src` is a directory and has been resolved
+fn copy(
+    src: &Path,
+    metadata: &Metadata,
+    to: &Path,
+    dest: Dest,
+    owner: Option<(Uid, Gid)>,
+) -> Result<()> {
+    let file_type = metadata.file_type();
+    if file_type.is_dir() {
+        for entry in std::fs::read_dir(src)? {
+            let entry = entry?;
+            // doesn't follow symlinks
+            let child = entry.metadata()?;
+            let child_type = child.file_type();
+            let name = entry.file_name();
+            let child_to = match dest {
+                _ if child_type.is_symlink() => to.join(&name),
+                Dest::Host if child_type.is_dir() => {
+                    let child_to = to.join(&name);
+                    std::fs::create_dir_all(&child_to)?;
+                    child_to
+                }
+                Dest::Host => to.join(&name),
+                Dest::Root(root) => {
+                    let create = if child_type.is_dir() {
+                        Create::Dir
+                    } else {
+                        Create::File
+                    };
+                    resolve_in_root(root, &relative(root, to).join(&name), create)?
+                }
+            };
+            copy(&entry.path(), &child, &child_to, dest, owner)?;
+        }
+    } else if file_type.is_file() {
+        std::fs::copy(src, to)?;
+    } else if file_type.is_symlink() {
+        // an existing directory is an error, rather than deleted
+        match std::fs::remove_file(to) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        std::os::unix::fs::symlink(std::fs::read_link(src)?, to)?;
+    } else {
+        tracing::warn!(?src, "Skipping special file");
+        return Ok(());
+    }
+    apply_metadata(metadata, to, owner)
+}
+
+/// Give `to` the owner, permissions and times of `metadata`, or `owner`
+fn apply_metadata(metadata: &Metadata, to: &Path, owner: Option<(Uid, Gid)>) -> Result<()> {
+    let owner = match owner {
+        Some(owner) => Some(owner),
+        None if nix::unistd::geteuid().is_root() => {
+            Some((Uid::from_raw(metadata.uid()), Gid::from_raw(metadata.gid())))
+        }
+        None => None,
+    };
+    if let Some((uid, gid)) = owner {
+        nix::unistd::fchownat(
+            None,
+            to,
+            Some(uid),
+            Some(gid),
+            nix::unistd::FchownatFlags::NoFollowSymlink,
+        )?;
+    }
+    if !metadata.is_symlink() {
+        // after chown, which clears setuid bits
+        std::fs::set_permissions(
+            to,
+            std::fs::Permissions::from_mode(metadata.mode() & 0o7777),
+        )?;
+    }
+    utimensat(
+        None,
+        to,
+        &TimeSpec::new(metadata.atime(), metadata.atime_nsec()),
+        &TimeSpec::new(metadata.mtime(), metadata.mtime_nsec()),
+        UtimensatFlags::NoFollowSymlink,
+    )?;
+    Ok(())
+}
+
+/// `path`, resolved inside `root`, relative to it
+fn relative(root: &Path, path: &Path) -> PathBuf {
+    path.strip_prefix(root)
+        .expect("resolved paths are inside the root")
+        .to_path_buf()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::tempdir::TempDir;
+    use std::os::unix::fs::symlink;
+
+    fn fixture(name: &str) -> (TempDir, PathBuf) {
+        let base = TempDir::new(&format!("copy-{name}"));
+        let (host, root) = (base.join("host"), base.join("root"));
+        std::fs::create_dir_all(host.join("src/usr/bin")).unwrap();
+        std::fs::create_dir_all(root.join("usr")).unwrap();
+        std::fs::write(host.join("src/usr/bin/hello"), "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(
+            host.join("src/usr/bin/hello"),
+            std::fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+        symlink("usr/bin", host.join("src/bin")).unwrap();
+        std::fs::write(root.join("usr/existing"), "").unwrap();
+        (base, root)
+    }
+
+    #[test]
+    fn test_copy_tree() {
+        let (base, root) = fixture("tree");
+        let src = base.join("host/src");
+        let old = TimeSpec::new(1_000_000_000, 0);
+        utimensat(
+            None,
+            &src.join("usr/bin/hello"),
+            &old,
+            &old,
+            UtimensatFlags::NoFollowSymlink,
+        )
+        .unwrap();
+
+        copy_tree(&src, &root).unwrap();
+        let hello = std::fs::metadata(root.join("usr/bin/hello")).unwrap();
+        assert_eq!(hello.mode() & 0o777, 0o755);
+        assert_eq!(hello.mtime(), 1_000_000_000);
+        assert_eq!(
+            std::fs::read_link(root.join("bin")).unwrap(),
+            Path::new("usr/bin")
+        );
+        // existing directories are merged
+        assert!(root.join("usr/existing").exists());
+    }
+
+    #[test]
+    fn test_copy_in() {
+        let (base, root) = fixture("in");
+        let script = base.join("host/src/usr/bin/hello");
+
+        // missing parents are created
+        copy_in(&script, &root, Path::new("/build/run.sh"), None).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.join("build/run.sh")).unwrap(),
+            "#!/bin/sh\n"
+        );
+        // into an existing directory
+        copy_in(&script, &root, Path::new("build"), None).unwrap();
+        assert!(root.join("build/hello").is_file());
+
+        // symlinks are followed inside the root only
+        let host_dir = base.join("host/outside");
+        std::fs::create_dir_all(&host_dir).unwrap();
+        symlink(&host_dir, root.join("out")).unwrap();
+        copy_in(&script, &root, Path::new("out/hello"), None).unwrap();
+        assert!(!host_dir.join("hello").exists());
+        assert!(root
+            .join(host_dir.strip_prefix("/").unwrap())
+            .join("hello")
+            .is_file());
+
+        // through a symlinked directory, merging
+        symlink("/usr", root.join("link")).unwrap();
+        copy_in(&base.join("host/src/usr"), &root, Path::new("link"), None).unwrap();
+        assert!(root.join("usr/bin/hello").is_file());
+        assert!(root.join("usr/existing").exists());
+        assert!(root.join("link").is_symlink());
+    }
+
+    #[test]
+    fn test_copy_out() {
+        let (base, root) = fixture("out");
+        copy_in(&base.join("host/src"), &root, Path::new("/"), None).unwrap();
+        let out = base.join("host/out");
+
+        copy_out(&root, Path::new("/usr"), &out).unwrap();
+        assert!(out.join("bin/hello").is_file());
+        assert!(out.join("existing").is_file());
+
+        std::fs::create_dir_all(base.join("host/file")).unwrap();
+        copy_out(&root, Path::new("bin/hello"), &base.join("host/file")).unwrap();
+        let hello = std::fs::metadata(base.join("host/file/hello")).unwrap();
+        assert_eq!(hello.mode() & 0o777, 0o755);
+
+        let err = copy_out(&root, Path::new("missing"), &out).unwrap_err();
+        assert!(matches!(err, crate::Error::Io(e) if e.kind() == std::io::ErrorKind::NotFound));
+    }
+}
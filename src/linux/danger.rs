@@ -0,0 +1,93 @@
+//! Refusing roots that would take the host down with the container,
+//! see [`crate::ContainerBuilder::allow_dangerous_root`]
+
+use super::{Error, Result};
+use std::{os::unix::fs::MetadataExt, path::Path};
+
+/// Host paths that a root must not contain, as unmounting under them breaks the host
+const CRITICAL_PATHS: [&str; 9] = [
+    "/proc", "/sys", "/dev", "/run", "/boot", "/usr", "/etc", "/var", "/home",
+];
+
+/// Whether `root` is `/` or contains a critical host path
+pub(crate) fn is_dangerous(root: &Path) -> bool {
+    CRITICAL_PATHS
+        .iter()
+        .any(|path| Path::new(path).starts_with(root))
+}
+
+/// Fail with [`Error::DangerousRoot`] if `root` resolves to the root of the process,
+/// even through a bind mount of it, or to a path containing a critical host path
+pub(crate) fn check_root(root: &Path) -> Result<()> {
+    let resolved = root.canonicalize()?;
+    let metadata = std::fs::metadata(&resolved)?;
+    let process_root = std::fs::metadata("/")?;
+    let same = metadata.dev() == process_root.dev() && metadata.ino() == process_root.ino();
+    if same || is_dangerous(&resolved) {
+        return Err(Error::DangerousRoot { root: resolved });
+    }
+    Ok(())
+}
+
+/// Whether `target` resolves to a path outside `root`, which must be canonical
+///
+/// A path that can't be resolved doesn't, as nothing can be unmounted there either.
+pub(crate) fn escapes(root: &Path, target: &Path) -> bool {
+    target
+        .canonicalize()
+        .is_ok_and(|target| !target.starts_with(root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::tempdir::TempDir;
+
+    #[test]
+    fn test_dangerous_roots() {
+        for root in ["/", "/usr", "/dev"] {
+            assert!(is_dangerous(Path::new(root)), "{root}");
+        }
+        for root in ["/tmp/tiffin", "/var/lib/tiffin", "/usr/local/chroot"] {
+            assert!(!is_dangerous(Path::new(root)), "{root}");
+        }
+    }
+
+    #[test]
+    fn test_check_root() {
+        let dir = TempDir::new("danger");
+        std::fs::create_dir_all(dir.join("root")).unwrap();
+        std::os::unix::fs::symlink("/", dir.join("host")).unwrap();
+        std::os::unix::fs::symlink("/usr", dir.join("usr")).unwrap();
+
+        for root in ["/", "/proc", "/sys", "/dev", "/run", "/boot", "/usr"] {
+            let root = Path::new(root);
+            if root.exists() {
+                let err = check_root(root).unwrap_err();
+                assert!(matches!(err, Error::DangerousRoot { .. }), "{root:?}");
+            }
+        }
+        // symlinks are followed before comparing
+        for (link, target) in [("host", "/"), ("usr", "/usr")] {
+            let err = check_root(&dir.join(link)).unwrap_err();
+            assert!(matches!(err, Error::DangerousRoot { root } if root == Path::new(target)));
+        }
+        check_root(&dir.join("root")).unwrap();
+        check_root(&dir.join("root/../root")).unwrap();
+    }
+
+    #[test]
+    fn test_escapes() {
+        let dir = TempDir::new("escapes");
+        std::fs::create_dir_all(dir.join("root/proc")).unwrap();
+        std::os::unix::fs::symlink("/proc", dir.join("root/escape")).unwrap();
+        let root = dir.join("root").canonicalize().unwrap();
+
+        assert!(!escapes(&root, &root));
+        assert!(!escapes(&root, &root.join("proc")));
+        assert!(!escapes(&root, &root.join("missing")));
+        assert!(escapes(&root, &root.join("..")));
+        assert!(escapes(&root, &root.join("proc/../..")));
+        assert!(escapes(&root, &root.join("escape")));
+    }
+}
@@ -0,0 +1,274 @@
+//! Passing the host's desktop session through to GUI applications in the container
+
+use super::{
+    resolve::{resolve_in_root, Create},
+    MountTarget,
+};
+use nix::unistd::{Gid, Uid};
+use std::{
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+use sys_mount::MountFlags;
+
+/// Environment variables passed through by [`crate::Container::enable_display_passthrough`]
+pub(crate) const DISPLAY_VARS: [&str; 3] = ["DISPLAY", "WAYLAND_DISPLAY", "XAUTHORITY"];
+
+/// What [`crate::Container::enable_display_passthrough`] found on the host and passed through
+///
+/// Paths are the same on the host and inside the container.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DisplayReport {
+    /// The runtime directory created inside the container
+    pub runtime_dir: PathBuf,
+    /// The X11 socket directory, if the host has one
+    pub x11: Option<PathBuf>,
+    /// Wayland sockets in the runtime directory
+    pub wayland: Vec<PathBuf>,
+    /// The file pointed to by `XAUTHORITY`, if set and it exists
+    pub xauthority: Option<PathBuf>,
+}
+
+/// Sockets found on the host and bound into the container by a passthrough helper
+/// such as [`crate::Container::enable_session_bus`]
+///
+/// Paths are the same on the host and inside the container.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SocketReport {
+    pub bound: Vec<PathBuf>,
+    /// Sockets that don't exist on the host, and were skipped
+    pub missing: Vec<PathBuf>,
+}
+
+/// The system D-Bus socket
+const SYSTEM_BUS: &str = "/run/dbus/system_bus_socket";
+
+/// Where the desktop session of the calling user lives on the host
+#[derive(Debug, Clone)]
+pub(crate) struct HostSession {
+    pub runtime_dir: PathBuf,
+    pub x11: PathBuf,
+    pub xauthority: Option<PathBuf>,
+}
+
+impl HostSession {
+    pub fn current() -> Self {
+        Self {
+            runtime_dir: runtime_dir(),
+            x11: PathBuf::from("/tmp/.X11-unix"),
+            xauthority: std::env::var_os("XAUTHORITY").map(PathBuf::from),
+        }
+    }
+
+    /// Find the display sockets of the session, returning the mounts that pass them through
+    pub fn display(&self) -> std::io::Result<(DisplayReport, Vec<(PathBuf, MountTarget)>)> {
+        let mut report = DisplayReport {
+            runtime_dir: self.runtime_dir.clone(),
+            ..DisplayReport::default()
+        };
+        if self.x11.is_dir() {
+            report.x11 = Some(self.x11.clone());
+        }
+        match std::fs::read_dir(&self.runtime_dir) {
+            Ok(entries) => {
+                for entry in entries {
+                    let entry = entry?;
+                    let name = entry.file_name();
+                    let name = name.to_string_lossy();
+                    if name.starts_with("wayland-") && entry.file_type()?.is_socket() {
+                        report.wayland.push(entry.path());
+                    }
+                }
+                report.wayland.sort();
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                tracing::debug!(runtime_dir = ?self.runtime_dir, "No runtime directory on the host");
+            }
+            Err(e) => return Err(e),
+        }
+        report.xauthority = self.xauthority.clone().filter(|path| path.is_file());
+
+        let mounts = report
+            .x11
+            .iter()
+            .chain(&report.wayland)
+            .chain(&report.xauthority)
+            .map(|path| (path.clone(), bind(path)))
+            .collect();
+        tracing::debug!(?report, "Passing display through");
+        Ok((report, mounts))
+    }
+
+    /// The session bus socket, and the system bus socket if `system_bus` is set
+    pub fn bus(&self, system_bus: bool) -> (SocketReport, Vec<(PathBuf, MountTarget)>) {
+        let mut sockets = vec![self.session_bus()];
+        if system_bus {
+            sockets.push(PathBuf::from(SYSTEM_BUS));
+        }
+        find_sockets(sockets)
+    }
+
+    pub fn session_bus(&self) -> PathBuf {
+        self.runtime_dir.join("bus")
+    }
+
+    /// The PipeWire and PulseAudio sockets
+    pub fn audio(&self) -> (SocketReport, Vec<(PathBuf, MountTarget)>) {
+        find_sockets([
+            self.runtime_dir.join("pipewire-0"),
+            self.runtime_dir.join("pulse/native"),
+        ])
+    }
+}
+
+/// Sort out which of `sockets` exist, returning the mounts that pass them through
+fn find_sockets(
+    sockets: impl IntoIterator<Item = PathBuf>,
+) -> (SocketReport, Vec<(PathBuf, MountTarget)>) {
+    let mut report = SocketReport::default();
+    for socket in sockets {
+        match std::fs::metadata(&socket) {
+            Ok(metadata) if metadata.file_type().is_socket() => report.bound.push(socket),
+            _ => report.missing.push(socket),
+        }
+    }
+    tracing::debug!(?report, "Passing sockets through");
+    let mounts = report
+        .bound
+        .iter()
+        .map(|socket| (socket.clone(), bind(socket)))
+        .collect();
+    (report, mounts)
+}
+
+/// A bind mount of `path` on the host to the same path in the container
+pub(crate) fn bind(path: &Path) -> MountTarget {
+    MountTarget {
+        target: path.strip_prefix("/").unwrap_or(path).to_path_buf(),
+        flags: MountFlags::BIND,
+        ..MountTarget::default()
+    }
+}
+
+/// Runtime directory of the calling user: `$XDG_RUNTIME_DIR`, or `/run/user/<uid>` if it isn't set
+pub(crate) fn runtime_dir() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| format!("/run/user/{}", nix::unistd::getuid()).into())
+}
+
+/// Create `runtime_dir` inside `root`, owned by the given user with mode 0700 as
+/// the XDG base directory specification requires, fixing it up if it already exists
+pub(crate) fn create_runtime_dir(
+    root: &Path,
+    runtime_dir: &Path,
+    uid: Uid,
+    gid: Gid,
+) -> std::io::Result<()> {
+    let path = resolve_in_root(root, runtime_dir, Create::Dir)?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o700))?;
+    nix::unistd::chown(&path, Some(uid), Some(gid))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::tempdir::TempDir;
+    use std::os::unix::{fs::MetadataExt, net::UnixListener};
+
+    fn fixture(name: &str) -> TempDir {
+        let dir = TempDir::new(name);
+        std::fs::create_dir_all(dir.join("run")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_display() {
+        let dir = fixture("display");
+        let runtime_dir = dir.join("run");
+        let _wayland = UnixListener::bind(runtime_dir.join("wayland-0")).unwrap();
+        std::fs::write(runtime_dir.join("wayland-0.lock"), "").unwrap();
+        std::fs::create_dir(dir.join(".X11-unix")).unwrap();
+        std::fs::write(dir.join("xauth"), "").unwrap();
+
+        let session = HostSession {
+            runtime_dir: runtime_dir.clone(),
+            x11: dir.join(".X11-unix"),
+            xauthority: Some(dir.join("xauth")),
+        };
+        let (report, mounts) = session.display().unwrap();
+        assert_eq!(report.x11, Some(dir.join(".X11-unix")));
+        assert_eq!(report.wayland, [runtime_dir.join("wayland-0")]);
+        assert_eq!(report.xauthority, Some(dir.join("xauth")));
+        let sources: Vec<_> = mounts.iter().map(|(source, _)| source.clone()).collect();
+        assert_eq!(
+            sources,
+            [
+                dir.join(".X11-unix"),
+                runtime_dir.join("wayland-0"),
+                dir.join("xauth")
+            ]
+        );
+        assert!(mounts
+            .iter()
+            .all(|(source, mount)| Path::new("/").join(&mount.target) == *source));
+    }
+
+    #[test]
+    fn test_display_missing() {
+        let dir = fixture("display-missing");
+        let session = HostSession {
+            runtime_dir: dir.join("run/user/1000"),
+            x11: dir.join(".X11-unix"),
+            xauthority: Some(dir.join("xauth")),
+        };
+        let (report, mounts) = session.display().unwrap();
+        assert_eq!(report.x11, None);
+        assert!(report.wayland.is_empty());
+        assert_eq!(report.xauthority, None);
+        assert!(mounts.is_empty());
+    }
+
+    #[test]
+    fn test_audio() {
+        let dir = fixture("audio");
+        let runtime_dir = dir.join("run");
+        std::fs::create_dir(runtime_dir.join("pulse")).unwrap();
+        let _pulse = UnixListener::bind(runtime_dir.join("pulse/native")).unwrap();
+        // not a socket
+        std::fs::write(runtime_dir.join("pipewire-0"), "").unwrap();
+
+        let session = HostSession {
+            runtime_dir: runtime_dir.clone(),
+            x11: dir.join(".X11-unix"),
+            xauthority: None,
+        };
+        let (report, mounts) = session.audio();
+        assert_eq!(report.bound, [runtime_dir.join("pulse/native")]);
+        assert_eq!(report.missing, [runtime_dir.join("pipewire-0")]);
+        assert_eq!(mounts.len(), 1);
+        assert_eq!(
+            mounts[0].1.target,
+            runtime_dir.join("pulse/native").strip_prefix("/").unwrap()
+        );
+
+        let (report, _) = session.bus(false);
+        assert_eq!(report.missing, [runtime_dir.join("bus")]);
+    }
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_create_runtime_dir() {
+        let dir = fixture("runtime-dir");
+        let (uid, gid) = (Uid::from_raw(1000), Gid::from_raw(1000));
+        create_runtime_dir(&dir, Path::new("/run/user/1000"), uid, gid).unwrap();
+
+        let metadata = std::fs::metadata(dir.join("run/user/1000")).unwrap();
+        assert_eq!(metadata.mode() & 0o7777, 0o700);
+        assert_eq!((metadata.uid(), metadata.gid()), (1000, 1000));
+        // the parents are shared by every user
+        let parent = std::fs::metadata(dir.join("run/user")).unwrap();
+        assert_eq!(parent.uid(), 0);
+    }
+}
@@ -0,0 +1,227 @@
+//! Just enough of ELF to find what a dynamically linked program needs to run
+
+use std::path::{Path, PathBuf};
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_STRTAB: u64 = 5;
+const DT_RPATH: u64 = 15;
+const DT_RUNPATH: u64 = 29;
+
+/// The dynamic linking information of an ELF file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Elf {
+    /// Whether it is a 64-bit file
+    pub is_64: bool,
+    /// `e_machine`, the architecture
+    pub machine: u16,
+    /// The dynamic loader, `None` for static programs and libraries
+    pub interpreter: Option<PathBuf>,
+    /// Shared libraries needed, as in `DT_NEEDED`
+    pub needed: Vec<String>,
+    /// Library search path, from `DT_RUNPATH` or else `DT_RPATH`
+    pub runpath: Vec<String>,
+}
+
+impl Elf {
+    pub fn read(path: &Path) -> std::io::Result<Self> {
+        Self::parse(&std::fs::read(path)?).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} is not a valid ELF file", path.display()),
+            )
+        })
+    }
+
+    /// Whether a library built like `other` can be loaded by this file
+    pub fn is_compatible(&self, other: &Self) -> bool {
+        self.is_64 == other.is_64 && self.machine == other.machine
+    }
+
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.get(..4)? != b"\x7fELF" {
+            return None;
+        }
+        let reader = Reader {
+            data,
+            is_64: match data.get(4)? {
+                1 => false,
+                2 => true,
+                _ => return None,
+            },
+            le: match data.get(5)? {
+                1 => true,
+                2 => false,
+                _ => return None,
+            },
+        };
+        let mut elf = Self {
+            is_64: reader.is_64,
+            machine: reader.u16(18)?,
+            ..Self::default()
+        };
+
+        let (phoff, phentsize, phnum) = if reader.is_64 {
+            (reader.u64(32)?, reader.u16(54)?, reader.u16(56)?)
+        } else {
+            (reader.u32(28)?.into(), reader.u16(42)?, reader.u16(44)?)
+        };
+        let mut loads = Vec::new();
+        let mut dynamic = None;
+        for i in 0..u64::from(phnum) {
+            let ph = usize::try_from(phoff + i * u64::from(phentsize)).ok()?;
+            let segment = reader.segment(ph)?;
+            match segment.kind {
+                PT_LOAD => loads.push(segment),
+                PT_DYNAMIC => dynamic = Some(segment),
+                PT_INTERP => {
+                    let interp = reader.str_at(usize::try_from(segment.offset).ok()?)?;
+                    elf.interpreter = Some(PathBuf::from(interp));
+                }
+                _ => {}
+            }
+        }
+        let Some(dynamic) = dynamic else {
+            return Some(elf);
+        };
+
+        // the dynamic section refers to strings by address, which the segments map to the file
+        let entry_size = if reader.is_64 { 16 } else { 8 };
+        let mut entries = Vec::new();
+        for i in 0..dynamic.filesz / entry_size {
+            let at = usize::try_from(dynamic.offset + i * entry_size).ok()?;
+            let (tag, value) = if reader.is_64 {
+                (reader.u64(at)?, reader.u64(at + 8)?)
+            } else {
+                (reader.u32(at)?.into(), reader.u32(at + 4)?.into())
+            };
+            if tag == DT_NULL {
+                break;
+            }
+            entries.push((tag, value));
+        }
+        let strtab = entries.iter().find(|(tag, _)| *tag == DT_STRTAB)?.1;
+        let strtab = loads
+            .iter()
+            .find(|load| (load.vaddr..load.vaddr + load.filesz).contains(&strtab))
+            .map(|load| strtab - load.vaddr + load.offset)?;
+        let string = |offset: u64| reader.str_at(usize::try_from(strtab + offset).ok()?);
+
+        let mut rpath = Vec::new();
+        for (tag, value) in entries {
+            match tag {
+                DT_NEEDED => elf.needed.push(string(value)?),
+                DT_RUNPATH => elf
+                    .runpath
+                    .extend(string(value)?.split(':').map(String::from)),
+                DT_RPATH => rpath.extend(string(value)?.split(':').map(String::from)),
+                _ => {}
+            }
+        }
+        // RPATH is ignored when there is a RUNPATH
+        if elf.runpath.is_empty() {
+            elf.runpath = rpath;
+        }
+        Some(elf)
+    }
+}
+
+struct Segment {
+    kind: u32,
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    is_64: bool,
+    le: bool,
+}
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&self, at: usize) -> Option<[u8; N]> {
+        self.data.get(at..at.checked_add(N)?)?.try_into().ok()
+    }
+
+    fn u16(&self, at: usize) -> Option<u16> {
+        let bytes = self.bytes(at)?;
+        Some(if self.le {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, at: usize) -> Option<u32> {
+        let bytes = self.bytes(at)?;
+        Some(if self.le {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    fn u64(&self, at: usize) -> Option<u64> {
+        let bytes = self.bytes(at)?;
+        Some(if self.le {
+            u64::from_le_bytes(bytes)
+        } else {
+            u64::from_be_bytes(bytes)
+        })
+    }
+
+    /// A program header, whose fields are laid out differently for 32 and 64 bits
+    fn segment(&self, at: usize) -> Option<Segment> {
+        Some(if self.is_64 {
+            Segment {
+                kind: self.u32(at)?,
+                offset: self.u64(at + 8)?,
+                vaddr: self.u64(at + 16)?,
+                filesz: self.u64(at + 32)?,
+            }
+        } else {
+            Segment {
+                kind: self.u32(at)?,
+                offset: self.u32(at + 4)?.into(),
+                vaddr: self.u32(at + 8)?.into(),
+                filesz: self.u32(at + 16)?.into(),
+            }
+        })
+    }
+
+    /// The NUL-terminated string at `at`
+    fn str_at(&self, at: usize) -> Option<String> {
+        let bytes = self.data.get(at..)?;
+        let len = bytes.iter().position(|&b| b == 0)?;
+        String::from_utf8(bytes[..len].to_vec()).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_invalid() {
+        assert_eq!(Elf::parse(b""), None);
+        assert_eq!(Elf::parse(b"#!/bin/sh\n"), None);
+        assert_eq!(Elf::parse(b"\x7fELF\x03\x01"), None);
+        // truncated headers
+        assert_eq!(Elf::parse(b"\x7fELF\x02\x01\x01\x00"), None);
+    }
+
+    #[test]
+    fn test_parse_self() {
+        let exe = Elf::read(&std::env::current_exe().unwrap()).unwrap();
+        assert_eq!(exe.is_64, cfg!(target_pointer_width = "64"));
+        if let Some(interpreter) = &exe.interpreter {
+            assert!(interpreter.is_absolute());
+            assert!(exe.needed.iter().any(|lib| lib.starts_with("libc")));
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::{collections::BTreeMap, ffi::OsString};
+
+/// Which variables of the host environment are visible inside the container
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum EnvPolicy {
+    /// Keep the whole host environment
+    #[default]
+    Inherit,
+    /// Start from an empty environment
+    ClearAll,
+    /// Only keep the listed variables
+    Whitelist(Vec<String>),
+}
+
+/// Environment for code running inside the container
+///
+/// The policy decides what is kept from the host environment,
+/// then the explicit variables are set on top.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Environment {
+    pub policy: EnvPolicy,
+    pub vars: BTreeMap<String, String>,
+}
+
+impl Environment {
+    pub fn new(policy: EnvPolicy) -> Self {
+        Self {
+            policy,
+            vars: BTreeMap::new(),
+        }
+    }
+
+    /// Preset with a clean environment: a standard `PATH`, `HOME=/root`,
+    /// and `TERM` passed through from the host
+    pub fn minimal() -> Self {
+        let mut env = Self::new(EnvPolicy::Whitelist(vec!["TERM".to_string()]));
+        env.set("PATH", "/usr/sbin:/usr/bin:/sbin:/bin")
+            .set("HOME", "/root");
+        env
+    }
+
+    /// Sets a variable, overriding the host's value
+    pub fn set(&mut self, key: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.vars.insert(key.into(), value.into());
+        self
+    }
+
+    /// Whether applying this environment would change anything
+    pub fn is_inherit(&self) -> bool {
+        self.policy == EnvPolicy::Inherit && self.vars.is_empty()
+    }
+
+    /// Compute the environment from the given host environment
+    pub fn resolve<I>(&self, host: I) -> Vec<(OsString, OsString)>
+    where
+        I: IntoIterator<Item = (OsString, OsString)>,
+    {
+        let mut env: Vec<(OsString, OsString)> = match &self.policy {
+            EnvPolicy::Inherit => host.into_iter().collect(),
+            EnvPolicy::ClearAll => Vec::new(),
+            EnvPolicy::Whitelist(keys) => host
+                .into_iter()
+                .filter(|(key, _)| keys.iter().any(|k| key == k.as_str()))
+                .collect(),
+        };
+
+        for (key, value) in &self.vars {
+            env.retain(|(k, _)| k != key.as_str());
+            env.push((key.into(), value.into()));
+        }
+        env
+    }
+
+    /// Replace the environment of the current process, returning a guard that restores it
+    ///
+    /// Modifying the environment is not thread-safe: no other thread may read or write
+    /// environment variables until the guard is dropped.
+    pub(crate) fn apply(&self) -> EnvGuard {
+        if self.is_inherit() {
+            return EnvGuard { saved: None };
+        }
+        let saved: Vec<_> = std::env::vars_os().collect();
+        self.replace();
+        EnvGuard { saved: Some(saved) }
+    }
+
+    /// Replace the environment of the current process for good, e.g. in a forked child
+    pub(crate) fn replace(&self) {
+        if !self.is_inherit() {
+            replace_env(self.resolve(std::env::vars_os()));
+        }
+    }
+}
+
+/// Restores the previous environment when dropped
+#[must_use]
+pub(crate) struct EnvGuard {
+    saved: Option<Vec<(OsString, OsString)>>,
+}
+
+impl Drop for EnvGuard {
+    fn drop(&mut self) {
+        if let Some(saved) = self.saved.take() {
+            replace_env(saved);
+        }
+    }
+}
+
+fn replace_env(env: Vec<(OsString, OsString)>) {
+    for (key, _) in std::env::vars_os() {
+        std::env::remove_var(key);
+    }
+    for (key, value) in env {
+        std::env::set_var(key, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host() -> Vec<(OsString, OsString)> {
+        [
+            ("PATH", "/home/user/.cargo/bin:/usr/bin"),
+            ("TERM", "xterm-256color"),
+            ("http_proxy", "http://proxy:3128"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.into(), v.into()))
+        .collect()
+    }
+
+    #[test]
+    fn test_resolve() {
+        let inherit = Environment::default();
+        assert_eq!(inherit.resolve(host()), host());
+
+        let mut clear = Environment::new(EnvPolicy::ClearAll);
+        clear.set("FOO", "bar");
+        assert_eq!(clear.resolve(host()), vec![("FOO".into(), "bar".into())]);
+
+        let minimal = Environment::minimal();
+        assert_eq!(
+            minimal.resolve(host()),
+            vec![
+                ("TERM".into(), "xterm-256color".into()),
+                ("HOME".into(), "/root".into()),
+                ("PATH".into(), "/usr/sbin:/usr/bin:/sbin:/bin".into()),
+            ]
+        );
+    }
+}
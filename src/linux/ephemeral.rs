@@ -0,0 +1,297 @@
+//! Containers in a temporary directory, see [`EphemeralContainer`]
+
+use super::{copy::copy_tree, Container, Error, Result};
+use std::{
+    ops::{Deref, DerefMut},
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+};
+
+/// What to fill a new ephemeral root with
+#[derive(Debug, Clone)]
+enum Populate {
+    /// Copy the contents of a directory
+    Dir(PathBuf),
+    /// Unpack a tarball
+    Tarball(PathBuf),
+}
+
+/// Configures an [`EphemeralContainer`] before creating it
+#[derive(Debug, Clone)]
+pub struct EphemeralBuilder {
+    base: Option<PathBuf>,
+    populate: Option<Populate>,
+    default_mounts: bool,
+}
+
+impl Default for EphemeralBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EphemeralBuilder {
+    pub fn new() -> Self {
+        Self {
+            base: None,
+            populate: None,
+            default_mounts: true,
+        }
+    }
+
+    /// Sets the directory the root is created in, [`std::env::temp_dir`] by default
+    pub fn base(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.base = Some(dir.into());
+        self
+    }
+
+    /// Fill the root with a copy of the contents of `dir`
+    pub fn copy_from(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.populate = Some(Populate::Dir(dir.into()));
+        self
+    }
+
+    /// Fill the root by unpacking `tarball`, keeping permissions, and ownership when
+    /// running as root
+    ///
+    /// With the `tarball` feature, this is done with [`crate::unpack_tarball`],
+    /// otherwise with the `tar` command.
+    pub fn unpack(mut self, tarball: impl Into<PathBuf>) -> Self {
+        self.populate = Some(Populate::Tarball(tarball.into()));
+        self
+    }
+
+    /// Whether to add the mounts of [`Container::add_default_mounts`], enabled by default
+    pub fn include_default_mounts(mut self, include: bool) -> Self {
+        self.default_mounts = include;
+        self
+    }
+
+    /// Create the root directory, populate it and set up the container
+    ///
+    /// The directory is removed again if populating it fails.
+    pub fn build(self) -> Result<EphemeralContainer> {
+        let base = match self.base {
+            Some(base) => base,
+            None => std::env::temp_dir(),
+        };
+        // mountinfo has no symlinks, which matters when checking for mounts left behind
+        let base = std::fs::canonicalize(&base)?;
+        let dir = mkdtemp(&base.join("tiffin-XXXXXX"))?;
+        tracing::debug!(?dir, "Created ephemeral container root");
+
+        let populated = match &self.populate {
+            Some(Populate::Dir(src)) => copy_tree(src, &dir),
+            Some(Populate::Tarball(tarball)) => unpack(tarball, &dir),
+            None => Ok(()),
+        };
+        let container = populated.and_then(|()| Ok(Container::open(dir.clone())?));
+        let mut container = match container {
+            Ok(container) => container,
+            Err(e) => {
+                if let Err(e) = remove(&dir) {
+                    tracing::error!(?e, ?dir, "Failed to remove ephemeral container root");
+                }
+                return Err(e);
+            }
+        };
+        if self.default_mounts {
+            container.add_default_mounts();
+        }
+        Ok(EphemeralContainer {
+            container: Some(container),
+            dir,
+        })
+    }
+}
+
+/// A container whose root is a fresh temporary directory, deleted along with the container
+///
+/// Dereferences to the [`Container`]. When dropped, the container is torn down as usual,
+/// then its root is deleted recursively, unless anything is still mounted under it:
+/// deleting through a bind mount would delete files on the host, so the directory is
+/// left behind instead, and the error is logged. See [`EphemeralContainer::close`] to
+/// get the error.
+///
+/// ```no_run
+/// # fn main() -> tiffin::Result<()> {
+/// let mut container = tiffin::Container::ephemeral()?;
+/// container.run(|| std::fs::write("/hello", "world"))??;
+/// drop(container); // the root is gone
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct EphemeralContainer {
+    /// Only `None` while being closed
+    container: Option<Container>,
+    dir: PathBuf,
+}
+
+impl EphemeralContainer {
+    /// Configure a new ephemeral container, see [`EphemeralBuilder`]
+    pub fn builder() -> EphemeralBuilder {
+        EphemeralBuilder::new()
+    }
+
+    /// Create an ephemeral container with the default mounts from a rootfs archive,
+    /// see [`EphemeralBuilder::unpack`]
+    pub fn from_tarball(archive: impl Into<PathBuf>) -> Result<Self> {
+        Self::builder().unpack(archive).build()
+    }
+
+    /// The root directory of the container
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Tear down the container and delete its root, returning any error
+    ///
+    /// If tearing down fails, the container is dropped anyway, which tries again,
+    /// and the root is only deleted if nothing is left mounted under it.
+    pub fn close(mut self) -> Result<()> {
+        let container = self.container.take().expect("only taken when closing");
+        let closed = container.close().map_err(|(container, e)| {
+            drop(container);
+            e
+        });
+        let removed = remove(&self.dir);
+        closed.and(removed)
+    }
+}
+
+impl Deref for EphemeralContainer {
+    type Target = Container;
+
+    fn deref(&self) -> &Container {
+        self.container.as_ref().expect("only taken when closing")
+    }
+}
+
+impl DerefMut for EphemeralContainer {
+    fn deref_mut(&mut self) -> &mut Container {
+        self.container.as_mut().expect("only taken when closing")
+    }
+}
+
+impl Drop for EphemeralContainer {
+    fn drop(&mut self) {
+        let Some(container) = self.container.take() else {
+            return;
+        };
+        drop(container);
+        if let Err(e) = remove(&self.dir) {
+            tracing::error!(?e, dir = ?self.dir, "Failed to remove ephemeral container root");
+        }
+    }
+}
+
+/// Create a directory only accessible to its owner, named by replacing the trailing
+/// `XXXXXX` of `template` with random characters
+fn mkdtemp(template: &Path) -> std::io::Result<PathBuf> {
+    let mut template =
+        std::ffi::CString::new(template.as_os_str().as_bytes())?.into_bytes_with_nul();
+    // SAFETY: template is a NUL-terminated buffer, which mkdtemp only changes in place
+    if unsafe { libc::mkdtemp(template.as_mut_ptr().cast()) }.is_null() {
+        return Err(std::io::Error::last_os_error());
+    }
+    template.pop();
+    Ok(PathBuf::from(std::ffi::OsString::from_vec(template)))
+}
+
+/// Delete `dir` recursively, unless something is mounted at or under it
+fn remove(dir: &Path) -> Result<()> {
+    let mounts: Vec<_> = crate::mountinfo::mounts_under(dir)?
+        .into_iter()
+        .map(|mount| mount.mount_point)
+        .collect();
+    if !mounts.is_empty() {
+        return Err(Error::MountsRemain {
+            root: dir.to_path_buf(),
+            mounts,
+        });
+    }
+    tracing::debug!(?dir, "Removing ephemeral container root");
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[cfg(feature = "tarball")]
+fn unpack(tarball: &Path, dest: &Path) -> Result<()> {
+    let report = crate::unpack_tarball(tarball, dest, |_| {})?;
+    if !report.skipped_nodes.is_empty() {
+        tracing::warn!(skipped = ?report.skipped_nodes, "Some device nodes were not created");
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "tarball"))]
+fn unpack(tarball: &Path, dest: &Path) -> Result<()> {
+    let status = std::process::Command::new("tar")
+        .arg("--extract")
+        .arg("--preserve-permissions")
+        .arg("--numeric-owner")
+        .arg("--file")
+        .arg(tarball)
+        .arg("--directory")
+        .arg(dest)
+        .status()?;
+    if !status.success() {
+        return Err(Error::Io(std::io::Error::other(format!(
+            "failed to unpack {}: tar {status}",
+            tarball.display()
+        ))));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::tempdir::TempDir;
+
+    #[test]
+    fn test_bare_ephemeral() {
+        let src = TempDir::new("ephemeral-src");
+        std::fs::create_dir_all(src.join("etc")).unwrap();
+        std::fs::write(src.join("etc/os-release"), "ID=tiffin\n").unwrap();
+
+        let container = EphemeralContainer::builder()
+            .include_default_mounts(false)
+            .copy_from(&*src)
+            .build()
+            .unwrap();
+        let dir = container.path().to_path_buf();
+        assert!(dir.starts_with(std::fs::canonicalize(std::env::temp_dir()).unwrap()));
+        assert!(container.mount_table.is_empty());
+        assert_eq!(
+            std::fs::read_to_string(dir.join("etc/os-release")).unwrap(),
+            "ID=tiffin\n"
+        );
+        std::fs::write(dir.join("etc/hostname"), "tiffin").unwrap();
+        drop(container);
+        assert!(!dir.exists());
+
+        let container = EphemeralContainer::builder()
+            .base(&*src)
+            .include_default_mounts(false)
+            .build()
+            .unwrap();
+        let dir = container.path().to_path_buf();
+        assert_eq!(dir.parent(), Some(&*src));
+        container.close().unwrap();
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn test_populate_fails() {
+        let base = TempDir::new("ephemeral-fail");
+        assert!(EphemeralContainer::builder()
+            .base(&*base)
+            .copy_from("/nonexistent/tiffin")
+            .build()
+            .is_err());
+        // the root was removed again
+        assert_eq!(std::fs::read_dir(&base).unwrap().count(), 0);
+    }
+}
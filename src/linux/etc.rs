@@ -0,0 +1,339 @@
+//! Preparing `/etc` of the container for programs that expect a booted system,
+//! see [`crate::Container::prepare_etc`]
+
+use super::{
+    backup::Backups,
+    resolve::{resolve_in_root, Create},
+    Error, Result,
+};
+use std::{
+    fs::OpenOptions,
+    io::{Read, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Component, Path},
+};
+
+/// Where `/etc/mtab` points, relative to `/etc` like systemd does
+const MTAB: &str = "../proc/self/mounts";
+/// Where timezones are looked up inside the container
+const ZONEINFO: &str = "usr/share/zoneinfo";
+
+/// What to write to `/etc/machine-id`, see [`EtcPrepOptions::machine_id`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MachineId {
+    /// A new random ID
+    Random,
+    /// `uninitialized`, which tells systemd to generate an ID on the first boot
+    Uninitialized,
+    /// A copy of the host's ID
+    Host,
+}
+
+/// How to set `/etc/localtime`, see [`EtcPrepOptions::localtime`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Localtime {
+    /// A copy of the timezone file the host's `/etc/localtime` points to
+    Host,
+    /// A symlink to a timezone of the container's own zoneinfo, such as `Europe/Paris`
+    Zone(String),
+}
+
+/// What [`crate::Container::prepare_etc`] sets up
+///
+/// By default, everything is set up with a random machine ID and the host's timezone,
+/// and undone when the container is torn down.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EtcPrepOptions {
+    /// Write `/etc/machine-id`, replacing the image's
+    pub machine_id: Option<MachineId>,
+    /// Make `/etc/mtab` a symlink to `/proc/self/mounts`
+    pub mtab: bool,
+    /// Set `/etc/localtime`
+    pub localtime: Option<Localtime>,
+    /// Put the image's own files back when the container is torn down, for images that
+    /// are kept around. Otherwise, the changes stay and the image's files are deleted.
+    pub undo: bool,
+}
+
+impl Default for EtcPrepOptions {
+    fn default() -> Self {
+        Self {
+            machine_id: Some(MachineId::Random),
+            mtab: true,
+            localtime: Some(Localtime::Host),
+            undo: true,
+        }
+    }
+}
+
+/// Set up the files of `options` in the `etc` directory of `root`, from `host_etc`
+///
+/// With [`EtcPrepOptions::undo`], the files replaced are recorded in `backups`,
+/// and restored again if anything fails.
+pub(crate) fn prepare(
+    host_etc: &Path,
+    root: &Path,
+    options: &EtcPrepOptions,
+    backups: &mut Backups,
+) -> Result<()> {
+    let etc = root.join("etc");
+    if etc.symlink_metadata()?.file_type().is_symlink() {
+        // writing through it could clobber files on the host
+        return Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} is a symlink", etc.display()),
+        )));
+    }
+    let mut files = Files {
+        backups: options.undo.then_some(backups),
+    };
+    let result = files.prepare(host_etc, root, &etc, options);
+    if result.is_err() {
+        if let Some(backups) = files.backups {
+            backups.restore().ok();
+        }
+    }
+    result
+}
+
+/// Files of `/etc` being replaced, recorded in `backups` if they are restored later
+struct Files<'a> {
+    backups: Option<&'a mut Backups>,
+}
+
+impl Files<'_> {
+    fn prepare(
+        &mut self,
+        host_etc: &Path,
+        root: &Path,
+        etc: &Path,
+        options: &EtcPrepOptions,
+    ) -> Result<()> {
+        if let Some(machine_id) = &options.machine_id {
+            let contents = match machine_id {
+                MachineId::Random => format!("{}\n", random_id()?),
+                MachineId::Uninitialized => "uninitialized\n".to_string(),
+                MachineId::Host => std::fs::read_to_string(host_etc.join("machine-id"))?,
+            };
+            self.write(&etc.join("machine-id"), contents.as_bytes(), 0o444)?;
+        }
+        if options.mtab {
+            self.symlink(&etc.join("mtab"), Path::new(MTAB))?;
+        }
+        match &options.localtime {
+            Some(Localtime::Host) => {
+                // follows the symlink on the host
+                let contents = std::fs::read(host_etc.join("localtime"))?;
+                self.write(&etc.join("localtime"), &contents, 0o644)?;
+            }
+            Some(Localtime::Zone(zone)) => {
+                let zone = Path::new(zone);
+                if !zone.components().all(|c| matches!(c, Component::Normal(_))) {
+                    return Err(Error::PathEscape {
+                        target: zone.to_path_buf(),
+                    });
+                }
+                let zoneinfo = Path::new(ZONEINFO).join(zone);
+                if !resolve_in_root(root, &zoneinfo, Create::Nothing)?.is_file() {
+                    return Err(Error::Io(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("timezone {} not found in the container", zone.display()),
+                    )));
+                }
+                self.symlink(&etc.join("localtime"), &Path::new("..").join(zoneinfo))?;
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Move the image's file at `path` aside, or delete it if it isn't restored later
+    fn replace(&mut self, path: &Path) -> std::io::Result<()> {
+        match &mut self.backups {
+            Some(backups) => backups.replace(path),
+            None => match std::fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            },
+        }
+    }
+
+    fn write(&mut self, path: &Path, contents: &[u8], mode: u32) -> std::io::Result<()> {
+        self.replace(path)?;
+        tracing::trace!(?path, "Writing file of /etc");
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(mode)
+            .open(path)?;
+        file.write_all(contents)
+    }
+
+    /// Make `path` a symlink to `target`, unless it already is one
+    fn symlink(&mut self, path: &Path, target: &Path) -> std::io::Result<()> {
+        if std::fs::read_link(path).is_ok_and(|link| link == target) {
+            return Ok(());
+        }
+        self.replace(path)?;
+        tracing::trace!(?path, ?target, "Linking file of /etc");
+        std::os::unix::fs::symlink(target, path)
+    }
+}
+
+/// A random machine ID, formatted like systemd as a version 4 UUID in lowercase hex
+fn random_id() -> std::io::Result<String> {
+    let mut id = [0; 16];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut id)?;
+    id[6] = (id[6] & 0x0f) | 0x40;
+    id[8] = (id[8] & 0x3f) | 0x80;
+    Ok(id.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::tempdir::TempDir;
+    use std::{os::unix::fs::symlink, path::PathBuf};
+
+    /// A host `etc` with a machine ID and a timezone, and an image with an empty
+    /// machine ID, a regular `mtab`, a dangling `localtime` and a zoneinfo of its own
+    fn fixture(name: &str) -> (TempDir, PathBuf, PathBuf) {
+        let dir = TempDir::new(&format!("etc-{name}"));
+        let (host, root) = (dir.join("host"), dir.join("root"));
+        std::fs::create_dir_all(host.join("etc")).unwrap();
+        std::fs::create_dir_all(host.join("zoneinfo")).unwrap();
+        std::fs::create_dir_all(root.join("etc")).unwrap();
+        std::fs::create_dir_all(root.join(ZONEINFO).join("Europe")).unwrap();
+        std::fs::write(
+            host.join("etc/machine-id"),
+            "0123456789abcdef0123456789abcdef\n",
+        )
+        .unwrap();
+        std::fs::write(host.join("zoneinfo/Tokyo"), "TZif host").unwrap();
+        symlink("../zoneinfo/Tokyo", host.join("etc/localtime")).unwrap();
+        std::fs::write(root.join("etc/machine-id"), "").unwrap();
+        std::fs::write(root.join("etc/mtab"), "stale\n").unwrap();
+        symlink("/usr/share/zoneinfo/UTC", root.join("etc/localtime")).unwrap();
+        std::fs::write(root.join(ZONEINFO).join("Europe/Paris"), "TZif paris").unwrap();
+        (dir, host.join("etc"), root)
+    }
+
+    /// Options setting up nothing, to be completed
+    fn nothing() -> EtcPrepOptions {
+        EtcPrepOptions {
+            machine_id: None,
+            mtab: false,
+            localtime: None,
+            undo: true,
+        }
+    }
+
+    #[test]
+    fn test_machine_id() {
+        let (_dir, host_etc, root) = fixture("machine-id");
+        let path = root.join("etc/machine-id");
+        type Check = fn(&str) -> bool;
+        let checks: [(MachineId, Check); 3] = [
+            (MachineId::Random, |id| {
+                id.len() == 33 && id.trim_end().chars().all(|c| c.is_ascii_hexdigit())
+            }),
+            (MachineId::Uninitialized, |id| id == "uninitialized\n"),
+            (MachineId::Host, |id| id.starts_with("0123456789abcdef")),
+        ];
+        for (machine_id, check) in checks {
+            let options = EtcPrepOptions {
+                machine_id: Some(machine_id.clone()),
+                ..nothing()
+            };
+            let mut backups = Backups::default();
+            prepare(&host_etc, &root, &options, &mut backups).unwrap();
+            let id = std::fs::read_to_string(&path).unwrap();
+            assert!(check(&id), "{machine_id:?}: {id:?}");
+
+            backups.restore().unwrap();
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+        }
+    }
+
+    #[test]
+    fn test_mtab() {
+        let (_dir, host_etc, root) = fixture("mtab");
+        let options = EtcPrepOptions {
+            mtab: true,
+            ..nothing()
+        };
+        let mut backups = Backups::default();
+        prepare(&host_etc, &root, &options, &mut backups).unwrap();
+        let mtab = root.join("etc/mtab");
+        assert_eq!(std::fs::read_link(&mtab).unwrap(), Path::new(MTAB));
+
+        // already a symlink, nothing to record
+        let mut again = Backups::default();
+        prepare(&host_etc, &root, &options, &mut again).unwrap();
+        assert!(again.is_empty());
+
+        backups.restore().unwrap();
+        assert_eq!(std::fs::read_to_string(&mtab).unwrap(), "stale\n");
+    }
+
+    #[test]
+    fn test_localtime() {
+        let (_dir, host_etc, root) = fixture("localtime");
+        let localtime = root.join("etc/localtime");
+        let mut backups = Backups::default();
+
+        let host = EtcPrepOptions {
+            localtime: Some(Localtime::Host),
+            ..nothing()
+        };
+        prepare(&host_etc, &root, &host, &mut backups).unwrap();
+        assert!(localtime.symlink_metadata().unwrap().is_file());
+        assert_eq!(std::fs::read(&localtime).unwrap(), b"TZif host");
+        backups.restore().unwrap();
+        assert_eq!(
+            std::fs::read_link(&localtime).unwrap(),
+            Path::new("/usr/share/zoneinfo/UTC")
+        );
+
+        let zone = |zone: &str| EtcPrepOptions {
+            localtime: Some(Localtime::Zone(zone.to_string())),
+            ..nothing()
+        };
+        prepare(&host_etc, &root, &zone("Europe/Paris"), &mut backups).unwrap();
+        assert_eq!(
+            std::fs::read_link(&localtime).unwrap(),
+            Path::new("../usr/share/zoneinfo/Europe/Paris")
+        );
+        assert_eq!(std::fs::read(&localtime).unwrap(), b"TZif paris");
+        backups.restore().unwrap();
+
+        let err = prepare(&host_etc, &root, &zone("Mars/Olympus"), &mut backups).unwrap_err();
+        assert!(matches!(err, Error::Io(e) if e.kind() == std::io::ErrorKind::NotFound));
+        let err = prepare(&host_etc, &root, &zone("../../etc/passwd"), &mut backups).unwrap_err();
+        assert!(matches!(err, Error::PathEscape { .. }));
+        assert!(backups.is_empty());
+        assert_eq!(
+            std::fs::read_link(&localtime).unwrap(),
+            Path::new("/usr/share/zoneinfo/UTC")
+        );
+    }
+
+    #[test]
+    fn test_without_undo() {
+        let (_dir, host_etc, root) = fixture("no-undo");
+        let options = EtcPrepOptions {
+            undo: false,
+            ..EtcPrepOptions::default()
+        };
+        let mut backups = Backups::default();
+        prepare(&host_etc, &root, &options, &mut backups).unwrap();
+        assert!(backups.is_empty());
+        let mut names: Vec<_> = std::fs::read_dir(root.join("etc"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        // no backups left behind either
+        assert_eq!(names, ["localtime", "machine-id", "mtab"]);
+    }
+}
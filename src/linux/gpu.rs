@@ -0,0 +1,317 @@
+//! Passing the host's GPUs through to the container
+
+use super::{
+    backup::Backups,
+    resolve::{resolve_in_root, Create},
+    MountTarget,
+};
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+use sys_mount::MountFlags;
+
+/// Drop-in making the dynamic linker find the driver libraries, inside the container
+const LD_CONF: &str = "etc/ld.so.conf.d/tiffin-gpu.conf";
+
+/// What [`crate::Container::enable_gpu`] passes through
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GpuOptions {
+    /// Pass the host's driver libraries through, for when the container's
+    /// userspace should use the host's drivers
+    pub driver_libs: Option<DriverLibs>,
+    /// Bind the host's Vulkan and EGL ICD directories, which tell the loaders
+    /// which driver libraries to use
+    pub icd_dirs: bool,
+}
+
+/// Host driver libraries to pass through, see [`GpuOptions::driver_libs`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DriverLibs {
+    /// Directory of the libraries on the host
+    pub host_dir: PathBuf,
+    /// Libraries whose file name starts with one of these are passed through
+    pub prefixes: Vec<String>,
+    /// Directory inside the container the libraries are bound into
+    pub target: PathBuf,
+}
+
+impl Default for DriverLibs {
+    /// The NVIDIA proprietary driver's libraries, on a Debian-style multiarch host,
+    /// bound into `/usr/lib/tiffin-gpu`
+    fn default() -> Self {
+        Self {
+            host_dir: PathBuf::from("/usr/lib/x86_64-linux-gnu"),
+            prefixes: [
+                "libGLX_nvidia",
+                "libEGL_nvidia",
+                "libGLESv1_CM_nvidia",
+                "libGLESv2_nvidia",
+                "libnvidia-",
+                "libcuda",
+                "libnvoptix",
+            ]
+            .map(String::from)
+            .to_vec(),
+            target: PathBuf::from("/usr/lib/tiffin-gpu"),
+        }
+    }
+}
+
+/// What [`crate::Container::enable_gpu`] found on the host and passed through
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GpuReport {
+    /// Device nodes and directories, as host paths
+    pub devices: Vec<PathBuf>,
+    /// Driver libraries, as host paths
+    pub libs: Vec<PathBuf>,
+    /// ICD directories, as host paths
+    pub icd_dirs: Vec<PathBuf>,
+    /// The dynamic linker drop-in written inside the container, if any libraries were found,
+    /// which is only picked up once `ldconfig` runs there
+    pub ld_conf: Option<PathBuf>,
+}
+
+/// Where the host keeps its GPU devices and loader configuration
+#[derive(Debug, Clone)]
+pub(crate) struct HostGpu {
+    pub dev: PathBuf,
+    pub icd_dirs: Vec<PathBuf>,
+}
+
+impl HostGpu {
+    pub fn current() -> Self {
+        Self {
+            dev: PathBuf::from("/dev"),
+            icd_dirs: [
+                "/usr/share/vulkan/icd.d",
+                "/etc/vulkan/icd.d",
+                "/usr/share/glvnd/egl_vendor.d",
+                "/etc/glvnd/egl_vendor.d",
+            ]
+            .map(PathBuf::from)
+            .to_vec(),
+        }
+    }
+
+    /// Find what to pass through, returning the mounts that do it
+    ///
+    /// Detection only looks at the filesystem.
+    pub fn find(
+        &self,
+        options: &GpuOptions,
+    ) -> std::io::Result<(GpuReport, Vec<(PathBuf, MountTarget)>)> {
+        let mut report = GpuReport::default();
+        let mut mounts = Vec::new();
+
+        let dri = self.dev.join("dri");
+        if dri.is_dir() {
+            mounts.push((
+                dri.clone(),
+                bind("dev/dri", MountFlags::BIND | MountFlags::REC),
+            ));
+            report.devices.push(dri);
+        }
+        let mut nvidia = Vec::new();
+        for entry in std::fs::read_dir(&self.dev)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if is_nvidia_device(&name) {
+                nvidia.push(name);
+            }
+        }
+        nvidia.sort();
+        for name in nvidia {
+            let source = self.dev.join(&name);
+            let flags = if source.is_dir() {
+                MountFlags::BIND | MountFlags::REC
+            } else {
+                MountFlags::BIND
+            };
+            mounts.push((source.clone(), bind(Path::new("dev").join(name), flags)));
+            report.devices.push(source);
+        }
+
+        if let Some(libs) = &options.driver_libs {
+            let target = libs.target.strip_prefix("/").unwrap_or(&libs.target);
+            let mut found = Vec::new();
+            match std::fs::read_dir(&libs.host_dir) {
+                Ok(entries) => {
+                    for entry in entries {
+                        let path = entry?.path();
+                        let name = path.file_name().unwrap_or_default().to_string_lossy();
+                        if libs
+                            .prefixes
+                            .iter()
+                            .any(|prefix| name.starts_with(prefix.as_str()))
+                        {
+                            found.push(path);
+                        }
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            found.sort();
+            for lib in found {
+                // symlinks such as libfoo.so.1 are followed, binding the library under their name
+                let name = lib.file_name().unwrap_or_default();
+                mounts.push((lib.clone(), bind(target.join(name), MountFlags::BIND)));
+                report.libs.push(lib);
+            }
+        }
+
+        if options.icd_dirs {
+            for dir in self.icd_dirs.iter().filter(|dir| dir.is_dir()) {
+                let target = dir.strip_prefix("/").unwrap_or(dir);
+                mounts.push((
+                    dir.clone(),
+                    bind(target, MountFlags::BIND | MountFlags::RDONLY),
+                ));
+                report.icd_dirs.push(dir.clone());
+            }
+        }
+
+        tracing::debug!(?report, "Passing GPU through");
+        Ok((report, mounts))
+    }
+}
+
+/// NVIDIA device nodes: `nvidia0`, `nvidiactl`, `nvidia-uvm`, `nvidia-modeset`, `nvidia-caps`...
+fn is_nvidia_device(name: &str) -> bool {
+    name.strip_prefix("nvidia").is_some_and(|rest| {
+        rest.starts_with('-')
+            || rest == "ctl"
+            || (!rest.is_empty() && rest.chars().all(|c| c.is_ascii_digit()))
+    })
+}
+
+fn bind(target: impl Into<PathBuf>, flags: MountFlags) -> MountTarget {
+    MountTarget {
+        target: target.into(),
+        flags,
+        ..MountTarget::default()
+    }
+}
+
+/// Write the drop-in adding `lib_dir` to the dynamic linker's search path inside `root`
+///
+/// Returns the path of the drop-in inside the container. It is only picked up once
+/// `ldconfig` runs inside the container. The image's own file at that path, if any,
+/// is moved aside and recorded in `backups`, along with the drop-in.
+pub(crate) fn write_ld_conf(
+    root: &Path,
+    lib_dir: &Path,
+    backups: &mut Backups,
+) -> std::io::Result<PathBuf> {
+    let conf = Path::new(LD_CONF);
+    let dir = resolve_in_root(root, conf.parent().unwrap_or(conf), Create::Dir)?;
+    let path = dir.join(conf.file_name().unwrap_or_default());
+    backups.replace(&path)?;
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o644)
+        .open(&path)?;
+    writeln!(file, "{}", Path::new("/").join(lib_dir).display())?;
+    Ok(Path::new("/").join(LD_CONF))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::tempdir::TempDir;
+
+    #[test]
+    fn test_is_nvidia_device() {
+        for name in [
+            "nvidia0",
+            "nvidia12",
+            "nvidiactl",
+            "nvidia-uvm",
+            "nvidia-modeset",
+            "nvidia-caps",
+        ] {
+            assert!(is_nvidia_device(name), "{name}");
+        }
+        for name in ["nvidia", "nvidiafb", "null", "dri"] {
+            assert!(!is_nvidia_device(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_find() {
+        let dir = TempDir::new("gpu");
+        let (dev, lib) = (dir.join("dev"), dir.join("lib"));
+        std::fs::create_dir_all(dev.join("dri")).unwrap();
+        std::fs::create_dir_all(&lib).unwrap();
+        for file in ["dri/card0", "nvidia0", "nvidiactl", "nvidia-uvm", "null"] {
+            std::fs::write(dev.join(file), "").unwrap();
+        }
+        for file in ["libGLX_nvidia.so.0", "libnvidia-glcore.so.550", "libc.so.6"] {
+            std::fs::write(lib.join(file), "").unwrap();
+        }
+
+        let host = HostGpu {
+            dev: dev.clone(),
+            icd_dirs: vec![dir.join("icd.d")],
+        };
+        let options = GpuOptions {
+            driver_libs: Some(DriverLibs {
+                host_dir: lib.clone(),
+                ..DriverLibs::default()
+            }),
+            icd_dirs: true,
+        };
+        let (report, mounts) = host.find(&options).unwrap();
+        assert_eq!(
+            report.devices,
+            ["dri", "nvidia-uvm", "nvidia0", "nvidiactl"].map(|name| dev.join(name))
+        );
+        assert_eq!(
+            report.libs,
+            ["libGLX_nvidia.so.0", "libnvidia-glcore.so.550"].map(|name| lib.join(name))
+        );
+        assert!(report.icd_dirs.is_empty());
+
+        let targets: Vec<_> = mounts
+            .iter()
+            .map(|(_, mount)| mount.target.clone())
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                "dev/dri",
+                "dev/nvidia-uvm",
+                "dev/nvidia0",
+                "dev/nvidiactl",
+                "usr/lib/tiffin-gpu/libGLX_nvidia.so.0",
+                "usr/lib/tiffin-gpu/libnvidia-glcore.so.550",
+            ]
+            .map(PathBuf::from)
+        );
+        assert!(mounts[0].1.flags.contains(MountFlags::REC));
+
+        let root = dir.join("root");
+        std::fs::create_dir(&root).unwrap();
+        let mut backups = Backups::default();
+        let ld_conf = write_ld_conf(&root, Path::new("usr/lib/tiffin-gpu"), &mut backups).unwrap();
+        assert_eq!(ld_conf, Path::new("/etc/ld.so.conf.d/tiffin-gpu.conf"));
+        assert_eq!(
+            std::fs::read_to_string(root.join(LD_CONF)).unwrap(),
+            "/usr/lib/tiffin-gpu\n"
+        );
+        backups.restore().unwrap();
+        assert!(!root.join(LD_CONF).exists());
+
+        // the image's own file is put back
+        std::fs::write(root.join(LD_CONF), "/opt/image\n").unwrap();
+        write_ld_conf(&root, Path::new("usr/lib/tiffin-gpu"), &mut backups).unwrap();
+        backups.restore().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.join(LD_CONF)).unwrap(),
+            "/opt/image\n"
+        );
+    }
+}
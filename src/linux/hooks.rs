@@ -0,0 +1,205 @@
+//! Callbacks at points of the lifecycle of a container, see [`crate::Container::on`]
+
+use crate::{Container, Error, Result};
+
+/// A point in the lifecycle of a container where hooks run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// Before the mount table is mounted
+    PreMount,
+    /// After the mount table was mounted. If a hook fails, everything is unmounted again.
+    PostMount,
+    /// Before entering the chroot, with the container mounted
+    PreEnter,
+    /// After exiting the chroot
+    PostExit,
+    /// Before unmounting, with the mounts still in place
+    PreUnmount,
+    /// After everything was unmounted
+    PostUnmount,
+}
+
+type Hook = Box<dyn FnMut(&mut Container) -> Result<()> + Send>;
+
+/// The hooks registered on a container
+#[derive(Default)]
+pub(crate) struct Hooks {
+    hooks: Vec<(Phase, Hook)>,
+    /// The phase whose hooks are running, while they are
+    running: Option<Phase>,
+    /// Set when the container is being dropped, so failing hooks don't stop the teardown
+    pub dropping: bool,
+}
+
+impl Hooks {
+    pub fn add(&mut self, phase: Phase, hook: Hook) {
+        self.hooks.push((phase, hook));
+    }
+
+    /// Fail if called from a hook, which would re-enter the operation running it
+    pub fn check_reentrancy(&self) -> Result<()> {
+        match self.running {
+            Some(phase) => Err(Error::ReentrantHook { phase }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The phases of the hooks, in order
+impl std::fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.hooks.iter().map(|(phase, _)| phase))
+            .finish()
+    }
+}
+
+impl Container {
+    /// Run the hooks of `phase` in registration order, stopping at the first failure
+    ///
+    /// When dropping, failures are logged instead.
+    pub(crate) fn run_hooks(&mut self, phase: Phase) -> std::io::Result<()> {
+        // the hooks borrow the container mutably, so take them out meanwhile
+        let mut hooks = std::mem::take(&mut self.hooks.hooks);
+        self.hooks.running = Some(phase);
+        let result = hooks
+            .iter_mut()
+            .filter(|(hook_phase, _)| *hook_phase == phase)
+            .try_for_each(|(_, hook)| {
+                tracing::trace!(?phase, "Running hook");
+                hook(self)
+            });
+        self.hooks.running = None;
+        // hooks registered by hooks come last
+        hooks.append(&mut self.hooks.hooks);
+        self.hooks.hooks = hooks;
+
+        match result {
+            Err(e) if self.hooks.dropping => {
+                tracing::error!(?e, ?phase, "Hook failed while dropping container");
+                Ok(())
+            }
+            result => Ok(result?),
+        }
+    }
+
+    /// Run the [`Phase::PostMount`] hooks, unmounting again if one fails
+    ///
+    /// The unmount hooks don't run then, and neither are processes using the container handled.
+    pub(crate) fn run_post_mount_hooks(&mut self) -> std::io::Result<()> {
+        let Err(e) = self.run_hooks(Phase::PostMount) else {
+            return Ok(());
+        };
+        tracing::debug!(?e, root = ?self.root, "Post-mount hook failed, unmounting");
+        let result = self.mount_table.umount_chroot();
+        if let Err(e) = self.unmounted(result) {
+            tracing::error!(?e, "Failed to unmount after a post-mount hook failed");
+        }
+        Err(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// A bare container recording the phases of its hooks in `log`
+    fn recording(log: &Arc<Mutex<Vec<(Phase, usize)>>>) -> Container {
+        let mut container = Container::new_bare(std::env::temp_dir());
+        for phase in [
+            Phase::PreMount,
+            Phase::PostMount,
+            Phase::PreUnmount,
+            Phase::PostUnmount,
+        ] {
+            for i in 0..2 {
+                let log = log.clone();
+                container.on(phase, move |_| {
+                    log.lock().unwrap().push((phase, i));
+                    Ok(())
+                });
+            }
+        }
+        container
+    }
+
+    #[test]
+    fn test_hook_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut container = recording(&log);
+        container.mount().unwrap();
+        container.umount().unwrap();
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                (Phase::PreMount, 0),
+                (Phase::PreMount, 1),
+                (Phase::PostMount, 0),
+                (Phase::PostMount, 1),
+                (Phase::PreUnmount, 0),
+                (Phase::PreUnmount, 1),
+                (Phase::PostUnmount, 0),
+                (Phase::PostUnmount, 1),
+            ]
+        );
+        assert!(format!("{container:?}").contains("hooks: [PreMount, PreMount, PostMount"));
+    }
+
+    #[test]
+    fn test_hook_aborts() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut container = recording(&log);
+        container.on(Phase::PreMount, |_| {
+            Err(Error::Unsupported("not today".to_string()))
+        });
+        let err = Error::from(container.mount().unwrap_err());
+        assert!(matches!(err, Error::Unsupported(_)));
+        assert!(!container.status().is_mounted);
+        assert_eq!(log.lock().unwrap().len(), 2);
+
+        // a failing post-mount hook rolls back the mount
+        let mut container = recording(&log);
+        container.on(Phase::PostMount, |_| {
+            Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied).into())
+        });
+        let err = container.mount().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+        assert!(!container.status().is_mounted);
+    }
+
+    #[test]
+    fn test_hook_reentrancy() {
+        let mut container = Container::new_bare(std::env::temp_dir());
+        let hook_result = Arc::new(Mutex::new(None));
+        let result = hook_result.clone();
+        container.on(Phase::PostMount, move |container| {
+            *result.lock().unwrap() = Some(container.umount().map_err(Error::from));
+            // registering from a hook is fine, the hook runs next time
+            container.on(Phase::PostMount, |_| Ok(()));
+            Ok(())
+        });
+        container.mount().unwrap();
+        assert!(matches!(
+            hook_result.lock().unwrap().take(),
+            Some(Err(Error::ReentrantHook {
+                phase: Phase::PostMount
+            }))
+        ));
+        assert!(container.status().is_mounted);
+        assert_eq!(format!("{:?}", container.hooks), "[PostMount, PostMount]");
+    }
+
+    #[test]
+    fn test_hook_fails_on_drop() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut container = recording(&log);
+        container.on(Phase::PreUnmount, |_| {
+            Err(Error::Unsupported("not today".to_string()))
+        });
+        container.mount().unwrap();
+        drop(container);
+        // unmounted anyway
+        assert!(log.lock().unwrap().contains(&(Phase::PostUnmount, 1)));
+    }
+}
@@ -0,0 +1,116 @@
+//! Giving the container access to the host filesystem,
+//! see [`crate::Container::host_bind_mount_at`]
+
+use super::{
+    resolve::{self, Create},
+    MountTarget, MountpointOptions,
+};
+use std::{
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+use sys_mount::MountFlags;
+
+/// Host paths commonly needed inside a container, for [`crate::Container::host_bind_paths`]
+pub const HOST_PATHS: [&str; 4] = [
+    "/etc/hosts",
+    "/etc/resolv.conf",
+    "/etc/localtime",
+    "/usr/share/zoneinfo",
+];
+
+/// A bind mount of a host path at `target`, whose mountpoint is created with mode 0755
+///
+/// Read-only binds are recursive, so everything mounted under the host path is
+/// visible, read-only too.
+pub(crate) fn bind(target: PathBuf, read_only: bool) -> MountTarget {
+    let flags = if read_only {
+        MountFlags::BIND | MountFlags::REC | MountFlags::RDONLY
+    } else {
+        MountFlags::BIND
+    };
+    MountTarget {
+        target,
+        flags,
+        mountpoint: MountpointOptions {
+            mode: Some(0o755),
+            ..MountpointOptions::default()
+        },
+        ..MountTarget::default()
+    }
+}
+
+/// Create the mountpoint for `source` at `target` inside `root`, returning what was created
+pub(crate) fn provision(
+    root: &Path,
+    source: &Path,
+    target: &Path,
+) -> std::io::Result<Vec<PathBuf>> {
+    let create = if source.is_dir() {
+        Create::Dir
+    } else {
+        Create::File
+    };
+    let created = resolve::resolve(root, target, create)?.created;
+    for path in &created {
+        if path.is_dir() {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+        }
+    }
+    Ok(created)
+}
+
+/// Remove the mountpoints created by [`provision`], deepest first
+///
+/// Directories that aren't empty anymore are kept.
+pub(crate) fn remove_provisioned(created: &mut Vec<PathBuf>) {
+    for path in created.drain(..).rev() {
+        let removed = match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => std::fs::remove_dir(&path),
+            Ok(_) => std::fs::remove_file(&path),
+            Err(e) => Err(e),
+        };
+        match removed {
+            Ok(()) => tracing::trace!(?path, "Removed mountpoint"),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!(?e, ?path, "Failed to remove mountpoint, keeping it"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::tempdir::TempDir;
+
+    #[test]
+    fn test_provision() {
+        let root = TempDir::new("provision");
+        std::fs::create_dir_all(root.join("run")).unwrap();
+        let mut created = provision(
+            &root,
+            Path::new("/etc/hosts"),
+            Path::new("/run/host/etc/hosts"),
+        )
+        .unwrap();
+        assert_eq!(
+            created,
+            [
+                root.join("run/host"),
+                root.join("run/host/etc"),
+                root.join("run/host/etc/hosts"),
+            ]
+        );
+        let mode = std::fs::metadata(root.join("run/host"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o755);
+        assert!(root.join("run/host/etc/hosts").is_file());
+
+        remove_provisioned(&mut created);
+        assert!(created.is_empty());
+        assert!(!root.join("run/host").exists());
+        assert!(root.join("run").exists());
+    }
+}
@@ -0,0 +1,198 @@
+//! Recording what a container does to the system, see [`crate::Container::record_to`]
+
+use super::MountTarget;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// What a [`JournalRecord`] is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalOp {
+    Mount,
+    /// An active mount was adopted rather than mounted again,
+    /// see [`crate::MountTable::mount_chroot`]
+    Adopt,
+    Unmount,
+    Remount,
+    ChrootEnter,
+    ChrootExit,
+}
+
+/// An operation of a container, as a line of its journal
+///
+/// The JSON form is stable, so journals can be read by
+/// [`crate::replay::read_journal`] on another machine.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalRecord {
+    /// Milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    pub op: JournalOp,
+    /// Source of the mount as configured, such as `UUID=...` or a host path
+    pub source: Option<PathBuf>,
+    /// Target of the mount inside the container, as configured
+    pub target: Option<PathBuf>,
+    /// Path on the host the operation applied to: the mountpoint once symlinks in the
+    /// container are resolved, or the root for chroots
+    pub path: PathBuf,
+    pub fstype: Option<String>,
+    /// Bits of the mount flags, as passed to the kernel
+    pub flags: u64,
+    /// Mount data, as passed to the kernel
+    pub data: Option<String>,
+    pub ok: bool,
+    pub errno: Option<i32>,
+    /// Description of the error, including errors that have no errno
+    pub error: Option<String>,
+}
+
+impl JournalRecord {
+    pub(crate) fn new(op: JournalOp, path: impl Into<PathBuf>) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_millis() as u64);
+        Self {
+            timestamp_ms,
+            op,
+            source: None,
+            target: None,
+            path: path.into(),
+            fstype: None,
+            flags: 0,
+            data: None,
+            ok: true,
+            errno: None,
+            error: None,
+        }
+    }
+
+    /// A record of mounting `source` as `mount` at `path`, with everything passed
+    /// to the kernel
+    pub(crate) fn mount(
+        op: JournalOp,
+        source: &Path,
+        mount: &MountTarget,
+        path: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            source: Some(source.to_path_buf()),
+            target: Some(mount.target.clone()),
+            fstype: mount.fstype.clone(),
+            flags: mount.all_flags().bits(),
+            data: mount.all_data(),
+            ..Self::new(op, path)
+        }
+    }
+
+    /// Set how the operation ended from its `result`
+    pub(crate) fn outcome<T>(mut self, result: &std::io::Result<T>) -> Self {
+        if let Err(e) = result {
+            self.ok = false;
+            self.errno = e.raw_os_error();
+            self.error = Some(e.to_string());
+        }
+        self
+    }
+}
+
+/// The file a mount table appends its [`JournalRecord`]s to, if any
+///
+/// Each record is written as a single line as soon as it's made, and records of failures
+/// are synced to disk, so the journal survives the process crashing right after.
+#[derive(Debug, Default)]
+pub(crate) struct Journal(Option<File>);
+
+impl Journal {
+    /// Append to the journal at `path`, creating it if needed
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(Self(Some(file)))
+    }
+
+    /// Write `record`, only logging why if it can't be
+    pub fn record(&self, record: &JournalRecord) {
+        let Some(mut file) = self.0.as_ref() else {
+            return;
+        };
+        let result = serde_json::to_string(record)
+            .map_err(std::io::Error::from)
+            .and_then(|mut line| {
+                line.push('\n');
+                file.write_all(line.as_bytes())?;
+                if !record.ok {
+                    file.sync_data()?;
+                }
+                Ok(())
+            });
+        if let Err(e) = result {
+            tracing::warn!(?e, op = ?record.op, "Failed to write to the journal");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::tempdir::TempDir;
+    use crate::MountTable;
+    use sys_mount::MountFlags;
+
+    #[test]
+    fn test_json() {
+        let mount = MountTarget::new(
+            "/tmp",
+            Some("tmpfs".to_string()),
+            MountFlags::NOSUID | MountFlags::NODEV,
+            Some("mode=1777".to_string()),
+        );
+        let record = JournalRecord::mount(JournalOp::Mount, Path::new("tmpfs"), &mount, "/a/tmp")
+            .outcome(&Err::<(), _>(std::io::Error::from_raw_os_error(
+                libc::EBUSY,
+            )));
+        let line = serde_json::to_string(&record).unwrap();
+        assert!(!line.contains('\n'));
+        assert!(line.contains(r#""op":"mount""#));
+        assert_eq!(
+            serde_json::from_str::<JournalRecord>(&line).unwrap(),
+            record
+        );
+        assert_eq!(record.errno, Some(libc::EBUSY));
+        assert_eq!(
+            record.flags,
+            (MountFlags::NOSUID | MountFlags::NODEV).bits()
+        );
+    }
+
+    #[test]
+    fn test_failure_recorded() {
+        let dir = TempDir::new("journal");
+        let path = dir.join("journal.jsonl");
+        let mut table = MountTable::new();
+        table.record_to(&path).unwrap();
+        table.add_mount(
+            MountTarget {
+                target: "/mnt".into(),
+                flags: MountFlags::BIND,
+                ..MountTarget::default()
+            },
+            &*dir,
+        );
+        // the root doesn't exist, so this fails before mounting anything
+        let root = dir.join("missing");
+        assert!(table.mount_chroot(&root).is_err());
+        drop(table);
+
+        let records = crate::replay::read_journal(&path).unwrap();
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record.op, JournalOp::Mount);
+        assert!(!record.ok);
+        assert_eq!(record.errno, Some(libc::ENOENT));
+        assert_eq!(record.source.as_deref(), Some(&*dir));
+        assert_eq!(record.path, root.join("mnt"));
+    }
+}
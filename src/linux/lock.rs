@@ -0,0 +1,102 @@
+use std::{
+    sync::{Condvar, Mutex, MutexGuard},
+    thread::ThreadId,
+};
+
+/// The thread using the process-wide root directory, and how many containers it has
+/// chrooted into each other
+static HELD: Mutex<Option<(ThreadId, usize)>> = Mutex::new(None);
+static RELEASED: Condvar = Condvar::new();
+
+fn held() -> MutexGuard<'static, Option<(ThreadId, usize)>> {
+    HELD.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Exclusive use of the root directory of the process, released when dropped
+///
+/// The root and working directory are shared by every thread, so only one
+/// container at a time may chroot the calling process. Unlike a `MutexGuard`,
+/// this can be sent to other threads along with the container holding it.
+///
+/// The thread holding it may acquire it again, for a container nested in the chroot
+/// of another one. See [`ChrootLock::depth`].
+#[derive(Debug)]
+pub(crate) struct ChrootLock(());
+
+impl ChrootLock {
+    /// Wait until no other container is chrooted, unless it was chrooted by this thread
+    pub fn acquire() -> Self {
+        let mut held = held();
+        while held.is_some_and(|(owner, _)| owner != std::thread::current().id()) {
+            held = RELEASED.wait(held).unwrap_or_else(|e| e.into_inner());
+        }
+        Self::take(&mut held)
+    }
+
+    /// Fails with [`std::io::ErrorKind::WouldBlock`] if another container is chrooted,
+    /// unless it was chrooted by this thread
+    pub fn try_acquire() -> std::io::Result<Self> {
+        let mut held = held();
+        if held.is_some_and(|(owner, _)| owner != std::thread::current().id()) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::WouldBlock,
+                "another container is chrooted in this process",
+            ));
+        }
+        Ok(Self::take(&mut held))
+    }
+
+    fn take(held: &mut Option<(ThreadId, usize)>) -> Self {
+        let depth = held.map_or(0, |(_, depth)| depth);
+        *held = Some((std::thread::current().id(), depth + 1));
+        Self(())
+    }
+
+    /// How many locks are held, i.e. how deeply containers are nested, 0 if none is chrooted
+    pub fn depth() -> usize {
+        held().map_or(0, |(_, depth)| depth)
+    }
+}
+
+impl Drop for ChrootLock {
+    fn drop(&mut self) {
+        let mut held = held();
+        *held = match *held {
+            Some((owner, depth)) if depth > 1 => Some((owner, depth - 1)),
+            _ => None,
+        };
+        if held.is_none() {
+            RELEASED.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_acquire() {
+        let lock = ChrootLock::acquire();
+        let other = std::thread::spawn(|| ChrootLock::try_acquire().map(drop));
+        assert_eq!(
+            other.join().unwrap().unwrap_err().kind(),
+            std::io::ErrorKind::WouldBlock
+        );
+        drop(lock);
+        ChrootLock::acquire();
+    }
+
+    #[test]
+    fn test_nested() {
+        let outer = ChrootLock::acquire();
+        // the same thread nests
+        let inner = ChrootLock::try_acquire().unwrap();
+        assert_eq!(ChrootLock::depth(), 2);
+        drop(inner);
+        assert_eq!(ChrootLock::depth(), 1);
+        drop(outer);
+        assert_eq!(ChrootLock::depth(), 0);
+        std::thread::spawn(ChrootLock::acquire).join().unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+//! Files telling which container mounted what at a root, see [`ContainerMarker`]
+
+use super::{PersistedMount, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+const PREFIX: &str = ".tiffin-";
+const SUFFIX: &str = ".json";
+
+/// Written at the root of a container while it's mounted, as `.tiffin-<id>.json`
+///
+/// It is removed once the container is unmounted, so one found by [`crate::cleanup_stale`]
+/// tells which container left its mounts behind.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContainerMarker {
+    /// [`crate::Container::id`]
+    pub id: String,
+    /// The process that mounted the container
+    pub pid: u32,
+    /// When the container was mounted, in seconds since the Unix epoch
+    pub mounted_at: u64,
+    /// The mount table, in mount order
+    pub mounts: Vec<PersistedMount>,
+}
+
+impl ContainerMarker {
+    /// A marker for the container `id` mounting `mounts` now, from this process
+    pub(crate) fn new(id: &str, mounts: Vec<PersistedMount>) -> Self {
+        let mounted_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        Self {
+            id: id.to_string(),
+            pid: std::process::id(),
+            mounted_at,
+            mounts,
+        }
+    }
+
+    /// Where the marker of the container `id` is written in `root`
+    pub fn path(root: &Path, id: &str) -> PathBuf {
+        root.join(format!("{PREFIX}{id}{SUFFIX}"))
+    }
+
+    /// Write the marker in `root`, returning its path
+    ///
+    /// The root is the image's, so a symlink in place of the marker is never followed,
+    /// which could truncate a file of the host.
+    pub(crate) fn write(&self, root: &Path) -> Result<PathBuf> {
+        let path = Self::path(root, &self.id);
+        let mut file = File::options()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(&path)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        Ok(path)
+    }
+
+    /// Read a marker file, which must not be a symlink
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::options()
+            .read(true)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(path)?;
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    /// Whether the marker lists a mount at `mount_point` on the host
+    pub fn contains(&self, mount_point: &Path) -> bool {
+        self.mounts.iter().any(|mount| mount.target == mount_point)
+    }
+}
+
+/// The markers found in `root` with their paths, skipping files that can't be read
+pub(crate) fn find(root: &Path) -> Vec<(PathBuf, ContainerMarker)> {
+    let Ok(entries) = std::fs::read_dir(root) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path.file_name()?.to_str()?;
+            if !(name.starts_with(PREFIX) && name.ends_with(SUFFIX)) {
+                return None;
+            }
+            match ContainerMarker::load(&path) {
+                Ok(marker) => Some((path, marker)),
+                Err(e) => {
+                    tracing::debug!(?e, ?path, "Ignoring unreadable marker");
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Whether `id` can be used as a container id, and so in the name of its marker
+pub(crate) fn valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::tempdir::TempDir;
+
+    #[test]
+    fn test_find() {
+        let root = TempDir::new("markers");
+        let marker = ContainerMarker::new(
+            "build-1",
+            vec![PersistedMount {
+                source: "proc".into(),
+                target: root.join("proc"),
+                fstype: Some("proc".into()),
+            }],
+        );
+        let path = marker.write(&root).unwrap();
+        assert_eq!(path, root.join(".tiffin-build-1.json"));
+        std::fs::write(root.join(".tiffin-broken.json"), "{").unwrap();
+        std::fs::write(root.join("other.json"), "{}").unwrap();
+
+        let found = find(&root);
+        assert_eq!(found, [(path, marker.clone())]);
+        assert!(marker.contains(&root.join("proc")));
+        assert!(!marker.contains(&root.join("sys")));
+    }
+
+    #[test]
+    fn test_symlink_not_followed() {
+        let dir = TempDir::new("marker-symlink");
+        let root = dir.join("root");
+        std::fs::create_dir(&root).unwrap();
+        let host = dir.join("shadow");
+        std::fs::write(&host, "root:x:::\n").unwrap();
+        let marker = ContainerMarker::new("build", Vec::new());
+        std::os::unix::fs::symlink(&host, ContainerMarker::path(&root, "build")).unwrap();
+
+        let err = marker.write(&root).unwrap_err();
+        assert!(matches!(err, crate::Error::Io(e) if e.raw_os_error() == Some(libc::ELOOP)));
+        assert_eq!(std::fs::read_to_string(&host).unwrap(), "root:x:::\n");
+        assert!(find(&root).is_empty());
+    }
+
+    #[test]
+    fn test_valid_id() {
+        assert!(valid_id("a1b2c3d4"));
+        assert!(valid_id("image-build_2.x86_64"));
+        for id in ["", "../etc", "a/b", "with space"] {
+            assert!(!valid_id(id), "{id}");
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! Composing mount tables, see [`crate::MountTable::merge`]
+
+use std::{
+    fmt,
+    path::{Component, Path, PathBuf},
+};
+
+/// What to do when an incoming mount conflicts with one already in the table
+///
+/// Mounts conflict when their targets are the same path inside the container, e.g. `/var`,
+/// `var` and `var/`, or when they have the same source, since the table holds one mount
+/// per source. Nested targets such as `/var` and `/var/cache` don't conflict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MergeStrategy {
+    /// The incoming mount replaces the existing ones
+    ReplaceExisting,
+    /// The incoming mount is dropped
+    KeepExisting,
+    /// Nothing is merged, and [`crate::Error::MountConflicts`] lists every conflict
+    Error,
+}
+
+/// An incoming mount conflicting with an existing one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeConflict {
+    /// The target of the incoming mount, relative to the container root
+    pub target: PathBuf,
+    /// The source of the mount in the table
+    pub existing: PathBuf,
+    /// The source of the incoming mount
+    pub incoming: PathBuf,
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (mounted on /{}) conflicts with {}",
+            self.incoming.display(),
+            self.target.display(),
+            self.existing.display()
+        )
+    }
+}
+
+/// What merging mounts into a table did, to log the effective configuration
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeReport {
+    /// Sources of the mounts added without conflict
+    pub added: Vec<PathBuf>,
+    /// Conflicts where the incoming mount replaced the existing one
+    pub replaced: Vec<MergeConflict>,
+    /// Conflicts where the existing mount was kept
+    pub kept: Vec<MergeConflict>,
+}
+
+/// `target` as compared for conflicts: relative, without `.` components or a trailing `/`
+pub(crate) fn sanitize(target: &Path) -> PathBuf {
+    target
+        .components()
+        .filter(|c| !matches!(c, Component::RootDir | Component::CurDir))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize() {
+        for target in ["/var/cache", "var/cache/", "./var//cache", "/var/./cache"] {
+            assert_eq!(sanitize(Path::new(target)), Path::new("var/cache"));
+        }
+        assert_eq!(sanitize(Path::new("/")), Path::new(""));
+    }
+}
@@ -0,0 +1,109 @@
+//! Parser for `/proc/self/mountinfo`
+
+use std::path::{Path, PathBuf};
+
+/// An entry of `/proc/self/mountinfo`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountInfo {
+    pub mount_id: u32,
+    pub parent_id: u32,
+    /// Path of the mounted directory within its filesystem, e.g. the source of a bind mount
+    pub root: PathBuf,
+    pub mount_point: PathBuf,
+    pub options: String,
+    pub fstype: String,
+    pub source: String,
+    pub super_options: String,
+}
+
+impl MountInfo {
+    /// Parse a single line of mountinfo
+    pub fn parse_line(line: &str) -> Option<Self> {
+        let (mount, fs) = line.split_once(" - ")?;
+        let mut mount = mount.split(' ');
+        let mount_id = mount.next()?.parse().ok()?;
+        let parent_id = mount.next()?.parse().ok()?;
+        let _major_minor = mount.next()?;
+        let root = unescape(mount.next()?).into();
+        let mount_point = unescape(mount.next()?).into();
+        let options = mount.next()?.to_string();
+
+        let mut fs = fs.split(' ');
+        let fstype = fs.next()?.to_string();
+        let source = unescape(fs.next()?);
+        let super_options = fs.next().unwrap_or_default().to_string();
+
+        Some(Self {
+            mount_id,
+            parent_id,
+            root,
+            mount_point,
+            options,
+            fstype,
+            source,
+            super_options,
+        })
+    }
+}
+
+/// Parse the contents of a mountinfo file, skipping malformed lines
+pub fn parse(contents: &str) -> Vec<MountInfo> {
+    contents.lines().filter_map(MountInfo::parse_line).collect()
+}
+
+/// Read the mount table of the current process
+pub fn read() -> std::io::Result<Vec<MountInfo>> {
+    Ok(parse(&std::fs::read_to_string("/proc/self/mountinfo")?))
+}
+
+/// Mounts whose mountpoint is at or under `root`, in mount order
+pub fn mounts_under(root: &Path) -> std::io::Result<Vec<MountInfo>> {
+    Ok(read()?
+        .into_iter()
+        .filter(|mount| mount.mount_point.starts_with(root))
+        .collect())
+}
+
+/// Undo the octal escaping of spaces, tabs, newlines and backslashes
+fn unescape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        let code: String = chars.clone().take(3).collect();
+        match u8::from_str_radix(&code, 8) {
+            Ok(byte) if code.len() == 3 => {
+                out.push(byte as char);
+                chars.nth(2);
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOUNTINFO: &str = "\
+22 1 0:21 / /proc rw,nosuid,nodev,noexec,relatime shared:5 - proc proc rw
+61 22 259:2 /home/user/my\\040project /tmp/tiffin/build rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw
+garbage
+";
+
+    #[test]
+    fn test_parse() {
+        let mounts = parse(MOUNTINFO);
+        assert_eq!(mounts.len(), 2);
+        assert_eq!(mounts[0].mount_point, PathBuf::from("/proc"));
+        assert_eq!(mounts[0].fstype, "proc");
+        assert_eq!(mounts[1].root, PathBuf::from("/home/user/my project"));
+        assert_eq!(mounts[1].parent_id, 22);
+        assert_eq!(mounts[1].source, "/dev/nvme0n1p2");
+        assert_eq!(mounts[1].super_options, "rw");
+    }
+}
@@ -0,0 +1,112 @@
+use super::process::write_file;
+use nix::{
+    sched::{unshare, CloneFlags},
+    unistd::{getegid, geteuid},
+};
+
+/// Linux namespaces that code running inside the container is moved into
+///
+/// Namespaces are only entered by forked children, such as [`crate::Container::run_forked`]
+/// and [`crate::Container::command`], never by the calling process.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Namespaces {
+    /// Private mount namespace, so mounts made inside don't propagate to the host
+    pub mount: bool,
+    /// Private hostname and domain name
+    pub uts: bool,
+    /// Private System V IPC objects and POSIX message queues
+    pub ipc: bool,
+    /// Private network stack, with only a loopback device that is down
+    pub net: bool,
+    /// Private process ids, see [`crate::Container::pid_namespace`]
+    pub pid: bool,
+    /// Private user ids, with the calling user mapped to root
+    pub user: bool,
+}
+
+impl Namespaces {
+    /// Every namespace
+    pub fn all() -> Self {
+        Self {
+            mount: true,
+            uts: true,
+            ipc: true,
+            net: true,
+            pid: true,
+            user: true,
+        }
+    }
+
+    /// Whether no namespace is enabled
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Flags of the namespaces that are entered with `unshare`
+    ///
+    /// The user and pid namespaces are left out, as they need special handling.
+    fn unshare_flags(&self) -> CloneFlags {
+        [
+            (self.mount, CloneFlags::CLONE_NEWNS),
+            (self.uts, CloneFlags::CLONE_NEWUTS),
+            (self.ipc, CloneFlags::CLONE_NEWIPC),
+            (self.net, CloneFlags::CLONE_NEWNET),
+        ]
+        .into_iter()
+        .filter(|(enabled, _)| *enabled)
+        .fold(CloneFlags::empty(), |flags, (_, flag)| flags | flag)
+    }
+
+    /// Enter the namespaces other than the pid namespace, and set the hostname
+    ///
+    /// Only ever call this in a forked child, as the changes are irreversible.
+    pub(crate) fn enter(&self, hostname: Option<&str>, id_maps: &IdMaps) -> std::io::Result<()> {
+        if self.user {
+            unshare(CloneFlags::CLONE_NEWUSER)?;
+            // unprivileged processes may only map their gid once setgroups is denied
+            write_file(c"/proc/self/setgroups", b"deny")?;
+            write_file(c"/proc/self/uid_map", &id_maps.uid)?;
+            write_file(c"/proc/self/gid_map", &id_maps.gid)?;
+        }
+        let flags = self.unshare_flags();
+        if !flags.is_empty() {
+            unshare(flags)?;
+        }
+        if let Some(hostname) = hostname {
+            nix::unistd::sethostname(hostname)?;
+        }
+        Ok(())
+    }
+}
+
+/// The uid and gid maps of a user namespace, mapping the calling user to root
+///
+/// They're formatted before forking, as [`Namespaces::enter`] may run where allocating
+/// isn't safe, and the ids are unmapped once inside anyway.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct IdMaps {
+    uid: Vec<u8>,
+    gid: Vec<u8>,
+}
+
+impl IdMaps {
+    pub fn current() -> Self {
+        Self {
+            uid: format!("0 {} 1", geteuid()).into_bytes(),
+            gid: format!("0 {} 1", getegid()).into_bytes(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unshare_flags() {
+        assert!(Namespaces::default().unshare_flags().is_empty());
+        let flags = Namespaces::all().unshare_flags();
+        assert!(flags.contains(CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWNET));
+        assert!(!flags.intersects(CloneFlags::CLONE_NEWUSER | CloneFlags::CLONE_NEWPID));
+    }
+}
@@ -0,0 +1,240 @@
+//! Making the host's DNS and hosts configuration available inside the container
+//!
+//! Files shipped by the image are moved aside rather than overwritten,
+//! and moved back when the container is torn down.
+
+use super::{
+    backup::{exists, Backups},
+    MountTarget,
+};
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+use sys_mount::MountFlags;
+
+/// Files always taken from the host
+const FILES: [&str; 2] = ["resolv.conf", "hosts"];
+/// Taken from the host only if the image doesn't have it
+const NSSWITCH: &str = "nsswitch.conf";
+
+/// How [`crate::Container::setup_network_config`] makes the host's network configuration available
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkConfig {
+    /// Write copies of the host's files into the container
+    ///
+    /// Symlinks are followed on the host, so a `resolv.conf` pointing at
+    /// systemd-resolved's stub file is copied with the stub's contents.
+    CopyFromHost,
+    /// Bind mount the host's files over the container's when it's mounted,
+    /// so changes on the host are seen live
+    BindFromHost,
+}
+
+/// Network configuration files set up in a container, until they are restored
+#[derive(Debug, Default)]
+pub(crate) struct NetworkFiles {
+    backups: Backups,
+    /// Mounts to add to the mount table, for [`NetworkConfig::BindFromHost`]
+    pub mounts: Vec<(PathBuf, MountTarget)>,
+}
+
+impl NetworkFiles {
+    /// Set up the files of `host_etc` in the `etc` directory of `root`
+    pub fn setup(host_etc: &Path, root: &Path, mode: NetworkConfig) -> std::io::Result<Self> {
+        let etc = root.join("etc");
+        if etc.symlink_metadata()?.file_type().is_symlink() {
+            // writing through it could clobber files on the host
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} is a symlink", etc.display()),
+            ));
+        }
+
+        let mut files = Self::default();
+        let nsswitch_missing = !exists(&etc.join(NSSWITCH))?;
+        let names = FILES
+            .into_iter()
+            .chain(nsswitch_missing.then_some(NSSWITCH));
+        for name in names {
+            let source = host_etc.join(name);
+            if !source.exists() {
+                tracing::debug!(?source, "Not on the host, skipping");
+                continue;
+            }
+            let path = etc.join(name);
+            let result = match mode {
+                NetworkConfig::CopyFromHost => files.copy(&source, &path),
+                NetworkConfig::BindFromHost => files.prepare_bind(&path).and_then(|()| {
+                    let mount = MountTarget {
+                        target: Path::new("etc").join(name),
+                        flags: MountFlags::BIND,
+                        ..MountTarget::default()
+                    };
+                    files.mounts.push((source.canonicalize()?, mount));
+                    Ok(())
+                }),
+            };
+            if let Err(e) = result {
+                files.restore().ok();
+                return Err(e);
+            }
+        }
+        Ok(files)
+    }
+
+    /// Replace `path` with a copy of `source`
+    fn copy(&mut self, source: &Path, path: &Path) -> std::io::Result<()> {
+        let contents = std::fs::read(source)?;
+        self.backups.replace(path)?;
+        tracing::trace!(?source, ?path, "Copying network configuration");
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o644)
+            .open(path)?;
+        file.write_all(&contents)
+    }
+
+    /// Make sure `path` is a regular file that can be bind mounted over
+    fn prepare_bind(&mut self, path: &Path) -> std::io::Result<()> {
+        match path.symlink_metadata() {
+            Ok(metadata) if !metadata.file_type().is_symlink() => return Ok(()),
+            // a mount would follow the symlink, possibly out of the container
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        self.backups.replace(path)?;
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o644)
+            .open(path)?;
+        Ok(())
+    }
+
+    /// Put back the image's own files, see [`Backups::restore`]
+    pub fn restore(&mut self) -> std::io::Result<()> {
+        self.backups.restore()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::tempdir::TempDir;
+    use std::os::unix::fs::symlink;
+
+    /// A host `etc` with `resolv.conf` pointing at a resolver stub,
+    /// and an image whose `resolv.conf` is a dangling absolute symlink
+    fn fixture(name: &str) -> (TempDir, PathBuf) {
+        let dir = TempDir::new(name);
+        let (host, root) = (dir.join("host"), dir.join("root"));
+        std::fs::create_dir_all(host.join("etc")).unwrap();
+        std::fs::create_dir_all(host.join("run/resolve")).unwrap();
+        std::fs::create_dir_all(root.join("etc")).unwrap();
+        std::fs::write(
+            host.join("run/resolve/stub-resolv.conf"),
+            "nameserver 127.0.0.53\n",
+        )
+        .unwrap();
+        symlink(
+            "../run/resolve/stub-resolv.conf",
+            host.join("etc/resolv.conf"),
+        )
+        .unwrap();
+        std::fs::write(host.join("etc/hosts"), "127.0.0.1 host\n").unwrap();
+        symlink(
+            "/run/systemd/resolve/stub-resolv.conf",
+            root.join("etc/resolv.conf"),
+        )
+        .unwrap();
+        std::fs::write(root.join("etc/hosts"), "127.0.0.1 image\n").unwrap();
+        std::fs::write(root.join("etc/nsswitch.conf"), "hosts: files\n").unwrap();
+        (dir, root)
+    }
+
+    #[test]
+    fn test_copy() {
+        let (dir, root) = fixture("netconf-copy");
+        let mut files =
+            NetworkFiles::setup(&dir.join("host/etc"), &root, NetworkConfig::CopyFromHost).unwrap();
+        assert!(files.mounts.is_empty());
+
+        let resolv = root.join("etc/resolv.conf");
+        assert!(resolv.symlink_metadata().unwrap().is_file());
+        assert_eq!(
+            std::fs::read_to_string(&resolv).unwrap(),
+            "nameserver 127.0.0.53\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.join("etc/hosts")).unwrap(),
+            "127.0.0.1 host\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.join("etc/nsswitch.conf")).unwrap(),
+            "hosts: files\n"
+        );
+
+        files.restore().unwrap();
+        assert!(files.backups.is_empty());
+        assert_eq!(
+            std::fs::read_link(&resolv).unwrap(),
+            Path::new("/run/systemd/resolve/stub-resolv.conf")
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.join("etc/hosts")).unwrap(),
+            "127.0.0.1 image\n"
+        );
+        assert_eq!(std::fs::read_dir(root.join("etc")).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn test_bind() {
+        let (dir, root) = fixture("netconf-bind");
+        std::fs::remove_file(root.join("etc/nsswitch.conf")).unwrap();
+        std::fs::write(dir.join("host/etc/nsswitch.conf"), "hosts: files dns\n").unwrap();
+        let mut files =
+            NetworkFiles::setup(&dir.join("host/etc"), &root, NetworkConfig::BindFromHost).unwrap();
+
+        let targets: Vec<_> = files
+            .mounts
+            .iter()
+            .map(|(_, mount)| &mount.target)
+            .collect();
+        assert_eq!(
+            targets,
+            ["etc/resolv.conf", "etc/hosts", "etc/nsswitch.conf"].map(Path::new)
+        );
+        assert_eq!(
+            files.mounts[0].0,
+            dir.join("host/run/resolve/stub-resolv.conf")
+                .canonicalize()
+                .unwrap()
+        );
+        // the dangling symlink is replaced so the mount can't follow it,
+        // while the image's regular file is left for the mount to cover
+        let resolv = root.join("etc/resolv.conf");
+        assert!(resolv.symlink_metadata().unwrap().is_file());
+        assert_eq!(
+            std::fs::read_to_string(root.join("etc/hosts")).unwrap(),
+            "127.0.0.1 image\n"
+        );
+
+        files.restore().unwrap();
+        assert!(std::fs::read_link(&resolv).is_ok());
+        assert!(!root.join("etc/nsswitch.conf").exists());
+    }
+
+    #[test]
+    fn test_etc_symlink() {
+        let dir = TempDir::new("netconf-link");
+        symlink("/etc", dir.join("etc")).unwrap();
+        let err =
+            NetworkFiles::setup(Path::new("/etc"), &dir, NetworkConfig::CopyFromHost).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,353 @@
+//! Filesystem options passed to the kernel when mounting, see [`MountOptions`]
+
+use crate::{Error, Result};
+use nix::unistd::{Gid, Uid};
+use std::fmt;
+use sys_mount::MountFlags;
+
+/// Options handled by the kernel as mount flags rather than filesystem data,
+/// as written in fstab
+const FLAGS: [(&str, MountFlags); 10] = [
+    ("ro", MountFlags::RDONLY),
+    ("nosuid", MountFlags::NOSUID),
+    ("nodev", MountFlags::NODEV),
+    ("noexec", MountFlags::NOEXEC),
+    ("sync", MountFlags::SYNCHRONOUS),
+    ("dirsync", MountFlags::DIRSYNC),
+    ("noatime", MountFlags::NOATIME),
+    ("nodiratime", MountFlags::NODIRATIME),
+    ("relatime", MountFlags::RELATIME),
+    ("strictatime", MountFlags::STRICTATIME),
+];
+
+/// Options clearing a flag, which is the default
+const CLEARING: [(&str, MountFlags); 5] = [
+    ("rw", MountFlags::RDONLY),
+    ("suid", MountFlags::NOSUID),
+    ("dev", MountFlags::NODEV),
+    ("exec", MountFlags::NOEXEC),
+    ("async", MountFlags::SYNCHRONOUS),
+];
+
+/// Options of a mount, such as `mode=1777,size=64m` for a tmpfs
+///
+/// Filesystem options are kept in the order they were set, and rendered as the
+/// comma-separated data string the kernel expects, see [`MountOptions::render`].
+/// Options that are mount flags, like `nodev`, go into [`MountOptions::flags`] instead.
+///
+/// ```
+/// use tiffin::MountOptions;
+///
+/// let mut options = MountOptions::new();
+/// options.mode(0o1777).size(64 << 20).nodev();
+/// assert_eq!(options.render(), "mode=1777,size=67108864");
+/// assert_eq!(options.to_string(), "nodev,mode=1777,size=67108864");
+/// assert_eq!(MountOptions::parse(&options.to_string()).unwrap(), options);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MountOptions {
+    /// Flags applied together with the mount's own flags
+    pub flags: MountFlags,
+    options: Vec<(String, Option<String>)>,
+}
+
+impl Default for MountOptions {
+    fn default() -> Self {
+        Self {
+            flags: MountFlags::empty(),
+            options: Vec::new(),
+        }
+    }
+}
+
+impl MountOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse options as written in fstab, e.g. `nodev,mode=1777,context="a,b"`
+    ///
+    /// Flags such as `ro` are set in [`MountOptions::flags`], and cleared by their
+    /// opposite such as `rw`, in order. `defaults` is ignored, as are empty options.
+    pub fn parse(options: &str) -> Result<Self> {
+        let mut parsed = Self::new();
+        for option in split(options)? {
+            if option.is_empty() || option == "defaults" {
+                continue;
+            }
+            if let Some((_, flag)) = FLAGS.iter().find(|(name, _)| *name == option) {
+                parsed.flags |= *flag;
+                continue;
+            }
+            if let Some((_, flag)) = CLEARING.iter().find(|(name, _)| *name == option) {
+                parsed.flags &= !*flag;
+                continue;
+            }
+            match option.split_once('=') {
+                Some((key, value)) => {
+                    let value = match value.strip_prefix('"') {
+                        Some(quoted) => quoted
+                            .strip_suffix('"')
+                            .ok_or_else(|| invalid(option, "unterminated quote"))?,
+                        None => value,
+                    };
+                    parsed.set(key, value)?
+                }
+                None => parsed.enable(option)?,
+            };
+        }
+        Ok(parsed)
+    }
+
+    /// Set `key` to `value`, replacing its previous value in place
+    ///
+    /// Values containing commas are quoted when rendered. Keys can't contain commas,
+    /// `=`, quotes or whitespace, and values can't contain quotes or control characters.
+    pub fn set(&mut self, key: impl Into<String>, value: impl Into<String>) -> Result<&mut Self> {
+        let (key, value) = (key.into(), value.into());
+        check_key(&key)?;
+        if value.contains('"') || value.chars().any(char::is_control) {
+            return Err(invalid(
+                &format!("{key}={value}"),
+                "values can't contain quotes or control characters",
+            ));
+        }
+        Ok(self.insert(key, Some(value)))
+    }
+
+    /// Set `key` without a value, like `noquota`
+    pub fn enable(&mut self, key: impl Into<String>) -> Result<&mut Self> {
+        let key = key.into();
+        check_key(&key)?;
+        Ok(self.insert(key, None))
+    }
+
+    /// The value of `key`: `None` if it isn't set, `Some(None)` if it is set without a value
+    pub fn get(&self, key: &str) -> Option<Option<&str>> {
+        self.options
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_deref())
+    }
+
+    /// Unset `key`
+    pub fn remove(&mut self, key: &str) -> &mut Self {
+        self.options.retain(|(k, _)| k != key);
+        self
+    }
+
+    /// Owner of the root of the filesystem, for filesystems like tmpfs
+    pub fn uid(&mut self, uid: Uid) -> &mut Self {
+        self.insert("uid".to_string(), Some(uid.to_string()))
+    }
+
+    /// Group of the root of the filesystem, for filesystems like tmpfs
+    pub fn gid(&mut self, gid: Gid) -> &mut Self {
+        self.insert("gid".to_string(), Some(gid.to_string()))
+    }
+
+    /// Permissions of the root of the filesystem, rendered in octal
+    pub fn mode(&mut self, mode: u32) -> &mut Self {
+        self.insert("mode".to_string(), Some(format!("{mode:o}")))
+    }
+
+    /// Size limit in bytes, for filesystems like tmpfs
+    pub fn size(&mut self, bytes: u64) -> &mut Self {
+        self.insert("size".to_string(), Some(bytes.to_string()))
+    }
+
+    /// Mount read-only
+    pub fn read_only(&mut self) -> &mut Self {
+        self.flags |= MountFlags::RDONLY;
+        self
+    }
+
+    /// Don't allow access to device nodes
+    pub fn nodev(&mut self) -> &mut Self {
+        self.flags |= MountFlags::NODEV;
+        self
+    }
+
+    /// Ignore set-user-ID and set-group-ID bits
+    pub fn nosuid(&mut self) -> &mut Self {
+        self.flags |= MountFlags::NOSUID;
+        self
+    }
+
+    /// Whether no filesystem options are set, ignoring flags
+    pub fn is_empty(&self) -> bool {
+        self.options.is_empty()
+    }
+
+    /// The data string passed to the kernel, without the flags
+    pub fn render(&self) -> String {
+        self.options
+            .iter()
+            .map(|(key, value)| match value {
+                Some(value) if value.contains(',') => format!("{key}=\"{value}\""),
+                Some(value) => format!("{key}={value}"),
+                None => key.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    pub(crate) fn insert(&mut self, key: String, value: Option<String>) -> &mut Self {
+        match self.options.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, old)) => *old = value,
+            None => self.options.push((key, value)),
+        }
+        self
+    }
+}
+
+/// Options as written in fstab: flags first, then the filesystem options
+impl fmt::Display for MountOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = FLAGS
+            .iter()
+            .filter(|(_, flag)| self.flags.contains(*flag))
+            .map(|(name, _)| name.to_string());
+        let data = (!self.is_empty()).then(|| self.render());
+        let all: Vec<_> = flags.chain(data).collect();
+        f.write_str(&all.join(","))
+    }
+}
+
+/// Split options on commas outside of quotes
+fn split(options: &str) -> Result<Vec<&str>> {
+    let mut parts = Vec::new();
+    let (mut start, mut quoted) = (0, false);
+    for (i, c) in options.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                parts.push(&options[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if quoted {
+        return Err(invalid(options, "unterminated quote"));
+    }
+    parts.push(&options[start..]);
+    Ok(parts)
+}
+
+fn check_key(key: &str) -> Result<()> {
+    if key.is_empty() {
+        return Err(invalid(key, "empty option name"));
+    }
+    if key
+        .chars()
+        .any(|c| matches!(c, ',' | '=' | '"') || c.is_whitespace() || c.is_control())
+    {
+        return Err(invalid(
+            key,
+            "option names can't contain commas, `=`, quotes or whitespace",
+        ));
+    }
+    Ok(())
+}
+
+fn invalid(option: &str, reason: &'static str) -> Error {
+    Error::InvalidMountOption {
+        option: option.to_string(),
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut options = MountOptions::new();
+        options
+            .uid(Uid::from_raw(1000))
+            .gid(Gid::from_raw(100))
+            .mode(0o700)
+            .set("context", "system_u:object_r:tmp_t:s0:c1,c2")
+            .unwrap()
+            .enable("noswap")
+            .unwrap();
+        assert_eq!(
+            options.render(),
+            "uid=1000,gid=100,mode=700,context=\"system_u:object_r:tmp_t:s0:c1,c2\",noswap"
+        );
+        assert_eq!(options.get("mode"), Some(Some("700")));
+        assert_eq!(options.get("noswap"), Some(None));
+        assert_eq!(options.get("size"), None);
+    }
+
+    #[test]
+    fn test_set_replaces() {
+        let mut options = MountOptions::new();
+        options.size(1).mode(0o755).size(2);
+        assert_eq!(options.render(), "size=2,mode=755");
+        options.remove("size");
+        assert_eq!(options.render(), "mode=755");
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut options = MountOptions::new();
+        for key in ["", "a b", "a,b", "a=b", "a\"b"] {
+            assert!(matches!(
+                options.set(key, "1"),
+                Err(Error::InvalidMountOption { .. })
+            ));
+        }
+        assert!(options.set("context", "\"quoted\"").is_err());
+        assert!(options.set("a", "line\nbreak").is_err());
+        assert!(options.enable("mode=1777").is_err());
+        assert!(options.is_empty());
+
+        assert!(MountOptions::parse("mode=1777, size=64m").is_err());
+        assert!(MountOptions::parse("context=\"a,b").is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        let options =
+            MountOptions::parse("defaults,ro,nodev,mode=1777,,size=64m,context=\"a,b\",noswap")
+                .unwrap();
+        assert_eq!(options.flags, MountFlags::RDONLY | MountFlags::NODEV);
+        assert_eq!(
+            options.render(),
+            "mode=1777,size=64m,context=\"a,b\",noswap"
+        );
+        assert_eq!(options.get("context"), Some(Some("a,b")));
+
+        // later options win, as with mount(8)
+        let options = MountOptions::parse("ro,nosuid,rw").unwrap();
+        assert_eq!(options.flags, MountFlags::NOSUID);
+        assert_eq!(MountOptions::parse("").unwrap(), MountOptions::new());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for input in [
+            "",
+            "mode=1777",
+            "nosuid,nodev,mode=755,size=64m",
+            "ro,noatime,lowerdir=/a:/b,upperdir=/c,workdir=/d",
+            "context=\"system_u:object_r:tmp_t:s0:c1,c2\",noswap",
+            "uid=0,gid=0,key=,other=a=b",
+        ] {
+            let options = MountOptions::parse(input).unwrap();
+            assert_eq!(options.to_string(), input);
+            assert_eq!(MountOptions::parse(&options.to_string()).unwrap(), options);
+        }
+
+        let mut options = MountOptions::new();
+        options
+            .read_only()
+            .nosuid()
+            .uid(Uid::from_raw(1))
+            .size(4096);
+        assert_eq!(MountOptions::parse(&options.to_string()).unwrap(), options);
+    }
+}
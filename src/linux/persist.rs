@@ -0,0 +1,87 @@
+use crate::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// A mount left in place by [`crate::Container::persist`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PersistedMount {
+    pub source: PathBuf,
+    /// Mountpoint on the host
+    pub target: PathBuf,
+    pub fstype: Option<String>,
+}
+
+/// Record of the mounts of a container that outlived it
+///
+/// Can be saved as JSON and passed to [`unmount_persisted`] later,
+/// even from another process.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PersistedMounts {
+    pub root: PathBuf,
+    /// Mounts in the order they were mounted
+    pub mounts: Vec<PersistedMount>,
+}
+
+impl PersistedMounts {
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Write the record to a JSON file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+
+    /// Read a record from a JSON file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+}
+
+/// Unmount everything in a record from [`crate::Container::persist`], in reverse mount order
+///
+/// Every mount is attempted even if some fail, and the first error is returned.
+pub fn unmount_persisted(record: &PersistedMounts) -> Result<()> {
+    let mut result = Ok(());
+    for mount in record.mounts.iter().rev() {
+        tracing::trace!(target = ?mount.target, "Unmounting persisted mount");
+        if let Err(e) = nix::mount::umount(&mount.target) {
+            tracing::error!(?e, target = ?mount.target, "Failed to unmount");
+            if result.is_ok() {
+                result = Err(e.into());
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_roundtrip() {
+        let record = PersistedMounts {
+            root: "/tmp/tiffin".into(),
+            mounts: vec![
+                PersistedMount {
+                    source: "proc".into(),
+                    target: "/tmp/tiffin/proc".into(),
+                    fstype: Some("proc".into()),
+                },
+                PersistedMount {
+                    source: "/home/user/project".into(),
+                    target: "/tmp/tiffin/build".into(),
+                    fstype: None,
+                },
+            ],
+        };
+        let json = record.to_json().unwrap();
+        assert_eq!(PersistedMounts::from_json(&json).unwrap(), record);
+    }
+}
@@ -0,0 +1,301 @@
+//! Running programs in several containers at once, see [`Pool`]
+
+use super::{process, CommandSpec, Container, Error, Output, OutputOptions, Result};
+use nix::sys::signal::Signal;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// A container of a [`Pool`] and the program it runs
+#[derive(Debug)]
+struct Job {
+    container: Container,
+    payload: CommandSpec,
+}
+
+/// Runs a program in each of several containers concurrently, then unmounts them
+///
+/// Programs are started with [`Container::run_output_with`], so this process never
+/// chroots, and their output is captured. Each container is unmounted as soon as its
+/// program is done, whether it succeeded or not.
+///
+/// ```no_run
+/// # fn main() -> tiffin::Result<()> {
+/// use tiffin::{CommandSpec, Container, Pool};
+///
+/// let mut pool = Pool::new();
+/// pool.set_parallelism(2).set_fail_fast(true);
+/// for image in ["fedora", "debian", "arch"] {
+///     let container = Container::try_new(format!("/var/lib/images/{image}"))?;
+///     pool.add(container, CommandSpec::new("/usr/bin/make").arg("image"));
+/// }
+/// let report = pool.run();
+/// for failure in report.failures() {
+///     eprintln!("{} failed: {:?}", failure.id, failure.output);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Pool {
+    jobs: Vec<Job>,
+    parallelism: usize,
+    fail_fast: bool,
+    output_options: OutputOptions,
+}
+
+impl Default for Pool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pool {
+    /// An empty pool, running as many programs at once as there are CPUs
+    pub fn new() -> Self {
+        Self {
+            jobs: Vec::new(),
+            parallelism: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            fail_fast: false,
+            output_options: OutputOptions::default(),
+        }
+    }
+
+    /// Add a container, running `payload` when the pool is run
+    pub fn add(&mut self, container: Container, payload: CommandSpec) -> &mut Self {
+        self.jobs.push(Job { container, payload });
+        self
+    }
+
+    /// Sets how many programs run at once, at least one
+    pub fn set_parallelism(&mut self, parallelism: usize) -> &mut Self {
+        self.parallelism = parallelism.max(1);
+        self
+    }
+
+    /// Sets whether the first failure kills the programs still running, and skips
+    /// those not started yet, disabled by default
+    pub fn set_fail_fast(&mut self, fail_fast: bool) -> &mut Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// Sets how the output of the programs is captured
+    pub fn set_output_options(&mut self, options: OutputOptions) -> &mut Self {
+        self.output_options = options;
+        self
+    }
+
+    /// The containers of the pool, in the order they were added
+    pub fn containers(&self) -> impl Iterator<Item = &Container> {
+        self.jobs.iter().map(|job| &job.container)
+    }
+
+    /// Take the containers back, in the order they were added
+    pub fn into_containers(self) -> Vec<Container> {
+        self.jobs.into_iter().map(|job| job.container).collect()
+    }
+
+    /// Run the program of every container, returning once all of them are done and
+    /// every container is unmounted
+    ///
+    /// The containers are registered with [`Container::cleanup_on_signals`] first, so
+    /// they are unmounted even if the process is interrupted. The pool can be run again.
+    pub fn run(&mut self) -> PoolReport {
+        let span = tracing::info_span!(
+            "pool",
+            count = self.jobs.len(),
+            parallelism = self.parallelism
+        );
+        let _entered = span.enter();
+        for job in &mut self.jobs {
+            if let Err(e) = job.container.cleanup_on_signals() {
+                tracing::warn!(
+                    ?e,
+                    id = job.container.id(),
+                    "Failed to register for cleanup"
+                );
+            }
+        }
+
+        let count = self.jobs.len();
+        let state = State {
+            queue: Mutex::new(self.jobs.iter_mut().enumerate().collect()),
+            running: Mutex::new(HashMap::new()),
+            killed: Mutex::new(HashSet::new()),
+            failed: AtomicBool::new(false),
+            reports: Mutex::new((0..count).map(|_| None).collect()),
+        };
+        std::thread::scope(|scope| {
+            for _ in 0..self.parallelism.min(count) {
+                let span = span.clone();
+                let state = &state;
+                let (fail_fast, options) = (self.fail_fast, self.output_options);
+                scope.spawn(move || span.in_scope(|| state.work(fail_fast, options)));
+            }
+        });
+
+        let containers = (state.reports.into_inner())
+            .unwrap_or_else(|e| e.into_inner())
+            .into_iter()
+            .map(|report| report.expect("every job is run or skipped"))
+            .collect();
+        PoolReport { containers }
+    }
+}
+
+/// What the workers of [`Pool::run`] share
+struct State<'a> {
+    /// Jobs not started yet, with their index in the pool
+    queue: Mutex<VecDeque<(usize, &'a mut Job)>>,
+    /// Roots of the containers whose program is running, by index
+    running: Mutex<HashMap<usize, PathBuf>>,
+    /// Jobs whose program was killed because another one failed
+    killed: Mutex<HashSet<usize>>,
+    failed: AtomicBool,
+    reports: Mutex<Vec<Option<ContainerReport>>>,
+}
+
+impl State<'_> {
+    /// Run jobs from the queue until it's empty
+    fn work(&self, fail_fast: bool, options: OutputOptions) {
+        loop {
+            let Some((index, job)) = lock(&self.queue).pop_front() else {
+                return;
+            };
+            let report = if fail_fast && self.failed.load(Ordering::SeqCst) {
+                tracing::debug!(
+                    id = job.container.id(),
+                    "Skipping, another container failed"
+                );
+                ContainerReport::new(&job.container, None, true, None, Duration::ZERO)
+            } else {
+                self.run(index, job, fail_fast, options)
+            };
+            lock(&self.reports)[index] = Some(report);
+        }
+    }
+
+    fn run(
+        &self,
+        index: usize,
+        job: &mut Job,
+        fail_fast: bool,
+        options: OutputOptions,
+    ) -> ContainerReport {
+        let container = &mut job.container;
+        let start = Instant::now();
+        lock(&self.running).insert(index, container.root.clone());
+        let output = container.run_output_with(job.payload.clone(), options);
+        lock(&self.running).remove(&index);
+        let duration = start.elapsed();
+
+        let unmount_error = match container.status().is_mounted {
+            true => container.umount().err().map(Error::from),
+            false => None,
+        };
+        let mut report =
+            ContainerReport::new(container, Some(output), false, unmount_error, duration);
+        if report.is_success() {
+            return report;
+        }
+        report.cancelled = lock(&self.killed).contains(&index);
+        if report.cancelled {
+            return report;
+        }
+        tracing::warn!(id = report.id, "Container failed");
+        if fail_fast && !self.failed.swap(true, Ordering::SeqCst) {
+            self.kill_running();
+        }
+        report
+    }
+
+    /// Kill the programs still running in the other containers
+    fn kill_running(&self) {
+        let running = lock(&self.running).clone();
+        for (index, root) in running {
+            lock(&self.killed).insert(index);
+            tracing::debug!(?root, "Killing processes, another container failed");
+            if let Err(e) = process::signal_processes_using(&root, Signal::SIGKILL) {
+                tracing::error!(?e, ?root, "Failed to kill processes");
+            }
+        }
+    }
+}
+
+/// Lock `mutex`, even if a worker panicked while holding it
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// What [`Pool::run`] did, with a report for each container in the order they were added
+#[derive(Debug)]
+pub struct PoolReport {
+    pub containers: Vec<ContainerReport>,
+}
+
+impl PoolReport {
+    /// Whether every program succeeded and every container was unmounted
+    pub fn is_success(&self) -> bool {
+        self.containers.iter().all(ContainerReport::is_success)
+    }
+
+    /// The reports of the containers that failed, or were cancelled as another one failed
+    pub fn failures(&self) -> impl Iterator<Item = &ContainerReport> {
+        self.containers.iter().filter(|report| !report.is_success())
+    }
+
+    /// The report of the container `id`, see [`Container::id`]
+    pub fn get(&self, id: &str) -> Option<&ContainerReport> {
+        self.containers.iter().find(|report| report.id == id)
+    }
+}
+
+/// What happened in a container of a [`Pool`]
+#[derive(Debug)]
+pub struct ContainerReport {
+    /// See [`Container::id`]
+    pub id: String,
+    pub root: PathBuf,
+    /// Output of the program, or why it couldn't be run,
+    /// `None` if it was skipped as another container failed
+    pub output: Option<Result<Output>>,
+    /// Whether the program was killed or skipped as another container failed,
+    /// see [`Pool::set_fail_fast`]
+    pub cancelled: bool,
+    /// Why the container couldn't be unmounted once the program was done
+    pub unmount_error: Option<Error>,
+    /// How long the program ran
+    pub duration: Duration,
+}
+
+impl ContainerReport {
+    fn new(
+        container: &Container,
+        output: Option<Result<Output>>,
+        cancelled: bool,
+        unmount_error: Option<Error>,
+        duration: Duration,
+    ) -> Self {
+        Self {
+            id: container.id().to_string(),
+            root: container.root.clone(),
+            output,
+            cancelled,
+            unmount_error,
+            duration,
+        }
+    }
+
+    /// Whether the program exited successfully and the container was unmounted
+    pub fn is_success(&self) -> bool {
+        let succeeded = matches!(&self.output, Some(Ok(output)) if output.status.success());
+        succeeded && self.unmount_error.is_none()
+    }
+}
@@ -0,0 +1,151 @@
+use super::{builder::tmpfs_source, desktop::runtime_dir, MountTarget};
+use std::path::{Path, PathBuf};
+use sys_mount::MountFlags;
+
+/// A named set of mounts for a kind of workload, see [`crate::Container::apply_preset`]
+///
+/// Targets are relative to the container root. Bind mount sources must exist on the host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MountPreset {
+    /// `/proc` and `/sys`, plus bind mounts of the host's `/dev` and `/dev/pts`
+    Minimal,
+    /// [`MountPreset::Minimal`], plus a bind mount of the host's `/dev/shm`
+    /// and a fresh tmpfs on `/tmp` with mode 1777, for building packages
+    Build,
+    /// [`MountPreset::Minimal`], plus bind mounts of the host's `/dev/shm`, the calling
+    /// user's runtime directory and the read-only fontconfig cache in `/var/cache/fontconfig`,
+    /// for running desktop applications
+    ///
+    /// The runtime directory is `$XDG_RUNTIME_DIR`, or `/run/user/<uid>` if it isn't set,
+    /// and is mounted at the same path inside the container.
+    DesktopInteg,
+    /// Arbitrary mounts, as pairs of source and mount
+    Custom(Vec<(PathBuf, MountTarget)>),
+}
+
+impl MountPreset {
+    /// The mounts added by the preset, as pairs of source and mount
+    pub fn entries(&self) -> Vec<(PathBuf, MountTarget)> {
+        match self {
+            Self::Minimal => vec![
+                fs("/proc", "proc", "proc"),
+                fs("/sys", "sys", "sysfs"),
+                bind("/dev", "dev", MountFlags::BIND),
+                bind("/dev/pts", "dev/pts", MountFlags::BIND),
+            ],
+            Self::Build => {
+                let mut entries = Self::Minimal.entries();
+                entries.push(bind("/dev/shm", "dev/shm", MountFlags::BIND));
+                entries.push((
+                    tmpfs_source(Path::new("tmp")),
+                    MountTarget {
+                        target: "tmp".into(),
+                        fstype: Some("tmpfs".to_string()),
+                        data: Some("mode=1777".to_string()),
+                        ..MountTarget::default()
+                    },
+                ));
+                entries
+            }
+            Self::DesktopInteg => {
+                let runtime_dir = runtime_dir();
+                let target = runtime_dir.strip_prefix("/").unwrap_or(&runtime_dir);
+                let mut entries = Self::Minimal.entries();
+                entries.push(bind("/dev/shm", "dev/shm", MountFlags::BIND));
+                entries.push(bind(&runtime_dir, target, MountFlags::BIND));
+                entries.push(bind(
+                    "/var/cache/fontconfig",
+                    "var/cache/fontconfig",
+                    MountFlags::BIND | MountFlags::RDONLY,
+                ));
+                entries
+            }
+            Self::Custom(entries) => entries.clone(),
+        }
+    }
+}
+
+fn fs(source: &str, target: &str, fstype: &str) -> (PathBuf, MountTarget) {
+    let mount = MountTarget {
+        target: target.into(),
+        fstype: Some(fstype.to_string()),
+        ..MountTarget::default()
+    };
+    (source.into(), mount)
+}
+
+fn bind(
+    source: impl AsRef<Path>,
+    target: impl AsRef<Path>,
+    flags: MountFlags,
+) -> (PathBuf, MountTarget) {
+    let mount = MountTarget {
+        target: target.as_ref().to_path_buf(),
+        flags,
+        ..MountTarget::default()
+    };
+    (source.as_ref().to_path_buf(), mount)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn targets(preset: MountPreset) -> Vec<String> {
+        preset
+            .entries()
+            .into_iter()
+            .map(|(_, mount)| mount.target.display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_minimal() {
+        assert_eq!(
+            targets(MountPreset::Minimal),
+            ["proc", "sys", "dev", "dev/pts"]
+        );
+    }
+
+    #[test]
+    fn test_build() {
+        assert_eq!(
+            targets(MountPreset::Build),
+            ["proc", "sys", "dev", "dev/pts", "dev/shm", "tmp"]
+        );
+    }
+
+    #[test]
+    fn test_desktop_integ() {
+        let runtime_dir = runtime_dir().display().to_string();
+        assert_eq!(
+            targets(MountPreset::DesktopInteg),
+            [
+                "proc",
+                "sys",
+                "dev",
+                "dev/pts",
+                "dev/shm",
+                runtime_dir.trim_start_matches('/'),
+                "var/cache/fontconfig"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply_twice() {
+        let mut container = crate::Container::new_bare(std::env::temp_dir());
+        container.apply_preset(MountPreset::Build);
+        let table = container.mount_table.inner.clone();
+        container
+            .apply_preset(MountPreset::Build)
+            .apply_preset(MountPreset::Minimal);
+        assert_eq!(container.mount_table.inner, table);
+    }
+
+    #[test]
+    fn test_custom() {
+        let entries = vec![bind("/srv", "srv", MountFlags::BIND)];
+        assert_eq!(MountPreset::Custom(entries.clone()).entries(), entries);
+    }
+}
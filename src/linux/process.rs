@@ -0,0 +1,678 @@
+#[cfg(feature = "seccomp")]
+use super::seccomp::SeccompFilter;
+use super::{danger, namespace::IdMaps};
+use crate::{
+    Capability, CapabilitySet, ChildPolicy, Environment, Error, Namespaces, Rlimits, SealOptions,
+    User,
+};
+use nix::{
+    sched::CloneFlags,
+    sys::{
+        signal::{killpg, sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal},
+        wait::{waitpid, WaitPidFlag, WaitStatus},
+    },
+    unistd::{Gid, Pid},
+};
+use std::{
+    ffi::{CStr, CString},
+    fs::File,
+    io::Read,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::ffi::OsStrExt,
+    },
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicI32, Ordering},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+/// Pid that signals received by [`init`] are forwarded to
+static FORWARD_TO: AtomicI32 = AtomicI32::new(0);
+
+/// Time between SIGTERM and SIGKILL when a timeout is hit, unless configured otherwise
+pub(crate) const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Wait for a specific child to exit and return its exit code
+///
+/// Deaths by signal are mapped to `128 + signal`, like a shell does.
+pub(crate) fn wait_pid(pid: Pid) -> std::io::Result<i32> {
+    loop {
+        match waitpid(pid, None) {
+            Ok(status) => {
+                if let Some(code) = exit_code(status) {
+                    return Ok(code);
+                }
+            }
+            Err(nix::errno::Errno::EINTR) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Check whether a specific child has exited, without blocking
+pub(crate) fn try_wait_pid(pid: Pid) -> std::io::Result<Option<i32>> {
+    match waitpid(pid, Some(WaitPidFlag::WNOHANG)) {
+        Ok(status) => Ok(exit_code(status)),
+        Err(nix::errno::Errno::EINTR) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Wait for a child that leads its own process group for at most `timeout`
+///
+/// Once the timeout is hit, the process group gets SIGTERM, then SIGKILL if it's
+/// still running after `grace`. `try_wait` polls the child without blocking.
+/// Returns the result of `try_wait` and whether the timeout was hit.
+pub(crate) fn wait_timeout<T>(
+    pgid: Pid,
+    timeout: Duration,
+    grace: Duration,
+    mut try_wait: impl FnMut() -> std::io::Result<Option<T>>,
+) -> std::io::Result<(T, bool)> {
+    let mut poll_until = |deadline: Option<Instant>| -> std::io::Result<Option<T>> {
+        loop {
+            if let Some(result) = try_wait()? {
+                return Ok(Some(result));
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(None);
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    };
+
+    if let Some(result) = poll_until(Some(Instant::now() + timeout))? {
+        return Ok((result, false));
+    }
+    tracing::debug!(?pgid, ?timeout, "Timed out, terminating process group");
+    killpg(pgid, Signal::SIGTERM).ok();
+    let result = match poll_until(Some(Instant::now() + grace))? {
+        Some(result) => result,
+        None => {
+            tracing::debug!(?pgid, "Process group survived SIGTERM, killing it");
+            killpg(pgid, Signal::SIGKILL).ok();
+            poll_until(None)?.expect("polling without a deadline only stops once done")
+        }
+    };
+    // don't leave behind processes that outlived the group leader
+    killpg(pgid, Signal::SIGKILL).ok();
+    Ok((result, true))
+}
+
+/// Exit code of a wait status, if the process has terminated
+pub(crate) fn exit_code(status: WaitStatus) -> Option<i32> {
+    match status {
+        WaitStatus::Exited(_, code) => Some(code),
+        WaitStatus::Signaled(_, signal, _) => Some(128 + signal as i32),
+        _ => None,
+    }
+}
+
+/// Run `f` in a forked child and exit with its return code
+///
+/// Panics are caught so they never unwind into the parent's stack frames,
+/// and the child exits without running any of the parent's destructors.
+pub(crate) fn run_child<F>(f: F) -> !
+where
+    F: FnOnce() -> i32,
+{
+    let code = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).unwrap_or(101);
+    // SAFETY: _exit is always safe to call, and skips atexit handlers and destructors
+    unsafe { libc::_exit(code) }
+}
+
+/// Fork a child that runs `f` and exits with its return code
+pub(crate) fn fork_child(f: impl FnOnce() -> i32) -> nix::Result<Pid> {
+    // SAFETY: the child only runs `f` before exiting
+    match unsafe { nix::unistd::fork() }? {
+        nix::unistd::ForkResult::Child => run_child(f),
+        nix::unistd::ForkResult::Parent { child } => Ok(child),
+    }
+}
+
+/// Run `f` as pid 2 of a new pid namespace, under a minimal init as pid 1
+///
+/// The calling process forwards signals to the init and returns its exit code,
+/// which is the exit code of `f`. Only call this in a forked child, as
+/// the children of the calling process end up in the new namespace for good.
+pub(crate) fn run_in_pid_namespace(f: impl FnOnce() -> i32) -> i32 {
+    if let Err(e) = nix::sched::unshare(CloneFlags::CLONE_NEWPID) {
+        tracing::error!(?e, "Failed to create pid namespace");
+        return 127;
+    }
+    let init_pid = fork_child(|| match fork_child(f) {
+        Ok(payload) => init(payload),
+        Err(e) => {
+            tracing::error!(?e, "Failed to fork payload");
+            127
+        }
+    });
+    match init_pid {
+        Ok(pid) => init(pid),
+        Err(e) => {
+            tracing::error!(?e, "Failed to fork init");
+            127
+        }
+    }
+}
+
+extern "C" fn forward_signal(signal: libc::c_int) {
+    let pid = FORWARD_TO.load(Ordering::SeqCst);
+    if pid > 0 {
+        // SAFETY: kill is async-signal-safe
+        unsafe { libc::kill(pid, signal) };
+    }
+}
+
+/// A minimal init: forward SIGTERM, SIGINT and SIGHUP to `payload`, reap every
+/// child including orphans, and return the exit code of `payload` once it exits
+pub(crate) fn init(payload: Pid) -> i32 {
+    FORWARD_TO.store(payload.as_raw(), Ordering::SeqCst);
+    let action = SigAction::new(
+        SigHandler::Handler(forward_signal),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    for signal in [Signal::SIGTERM, Signal::SIGINT, Signal::SIGHUP] {
+        // SAFETY: the handler only calls kill
+        if let Err(e) = unsafe { sigaction(signal, &action) } {
+            tracing::warn!(?e, ?signal, "Failed to forward signal");
+        }
+    }
+
+    loop {
+        match waitpid(Pid::from_raw(-1), None) {
+            Ok(status) if status.pid() == Some(payload) => {
+                if let Some(code) = exit_code(status) {
+                    return code;
+                }
+            }
+            // an orphan that was reparented to us
+            Ok(_) => {}
+            Err(nix::errno::Errno::EINTR) => {}
+            Err(e) => {
+                tracing::error!(?e, "Failed to wait for payload");
+                return 127;
+            }
+        }
+    }
+}
+
+/// Everything needed to enter the container from a forked child
+///
+/// This is an owned snapshot of the container's configuration, so it can be moved
+/// into a `pre_exec` hook that outlives the borrow of the container. Everything is
+/// prepared up front, paths as C strings included, as [`ChildSetup::enter`] may run
+/// between fork and exec where only async-signal-safe calls are allowed.
+#[derive(Debug, Clone)]
+pub(crate) struct ChildSetup {
+    pub root: CString,
+    /// Absolute path of the working directory inside the container
+    pub workdir: Option<CString>,
+    /// Directories created before changing to the working directory, outermost first
+    pub create_dirs: Vec<CString>,
+    pub env: Environment,
+    pub rlimits: Rlimits,
+    pub capabilities: CapabilitySet,
+    pub user: Option<User>,
+    /// Every group of the user, see [`User::all_groups`]
+    pub groups: Vec<Gid>,
+    /// The `cgroup.procs` file of the cgroup to join
+    pub cgroup_procs: Option<CString>,
+    pub namespaces: Namespaces,
+    pub id_maps: IdMaps,
+    pub hostname: Option<String>,
+    pub seal: Option<SealOptions>,
+    /// Descriptors of the host root and working directory, closed when sealed
+    pub escape_fds: Vec<RawFd>,
+    #[cfg(feature = "seccomp")]
+    pub seccomp: Option<SeccompFilter>,
+}
+
+impl ChildSetup {
+    /// The environment of the container, with the `HOME` and `SHELL` of the user
+    /// unless it sets them itself
+    ///
+    /// [`ChildSetup::enter`] leaves the environment alone, so this is set by the caller.
+    pub fn environment(&self) -> Environment {
+        let mut env = self.env.clone();
+        if let Some(user) = &self.user {
+            for (key, value) in [("HOME", &user.home), ("SHELL", &user.shell)] {
+                env.vars
+                    .entry(key.to_string())
+                    .or_insert_with(|| value.to_string_lossy().into_owned());
+            }
+        }
+        env
+    }
+
+    /// Enter the container and apply its sandboxing configuration
+    ///
+    /// Only ever call this in a forked child, as the changes are irreversible.
+    /// It neither allocates nor takes locks, so it's safe in a `pre_exec` hook.
+    pub fn enter(&self) -> std::io::Result<()> {
+        if self.seal.is_some() {
+            super::seal::seal_fds(&self.escape_fds)?;
+        }
+        // join the cgroup before chrooting, while its path is still reachable
+        if let Some(procs) = &self.cgroup_procs {
+            write_file(procs, b"0")?;
+        }
+        self.namespaces
+            .enter(self.hostname.as_deref(), &self.id_maps)?;
+        nix::unistd::chroot(self.root.as_c_str())?;
+        nix::unistd::chdir(c"/")?;
+        if let Some(workdir) = &self.workdir {
+            for dir in &self.create_dirs {
+                create_dir(dir)?;
+            }
+            nix::unistd::chdir(workdir.as_c_str())?;
+        }
+        self.rlimits.apply()?;
+        // the bounding set can only be changed while we're still root
+        let capabilities = match self.seal {
+            Some(seal) if seal.drop_chroot => self.capabilities.without(Capability::SysChroot),
+            _ => self.capabilities,
+        };
+        capabilities.apply(|| self.switch_user())?;
+        // the filter goes last, as it may block the syscalls used above
+        #[cfg(feature = "seccomp")]
+        if let Some(filter) = &self.seccomp {
+            filter.apply()?;
+        }
+        Ok(())
+    }
+
+    /// Switch to the user of the container, if any
+    fn switch_user(&self) -> std::io::Result<()> {
+        if let Some(user) = &self.user {
+            nix::unistd::setgroups(&self.groups)?;
+            nix::unistd::setgid(user.gid)?;
+            nix::unistd::setuid(user.uid)?;
+        }
+        Ok(())
+    }
+}
+
+/// `path` as a C string, for use where allocating isn't safe
+pub(crate) fn c_path(path: &Path) -> std::io::Result<CString> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
+
+/// The directories to create for the working directory `workdir`, outermost first
+pub(crate) fn workdir_dirs(workdir: &Path) -> std::io::Result<Vec<CString>> {
+    let mut dirs: Vec<_> = workdir
+        .ancestors()
+        .filter(|dir| dir.parent().is_some())
+        .map(c_path)
+        .collect::<std::io::Result<_>>()?;
+    dirs.reverse();
+    Ok(dirs)
+}
+
+/// Write `data` to the existing file at `path` with raw syscalls, so it's safe in a
+/// `pre_exec` hook
+pub(crate) fn write_file(path: &CStr, data: &[u8]) -> std::io::Result<()> {
+    // SAFETY: path is a valid C string, data a valid buffer of its length,
+    // and the descriptor is closed right after
+    unsafe {
+        let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let written = libc::write(fd, data.as_ptr().cast(), data.len());
+        let e = std::io::Error::last_os_error();
+        libc::close(fd);
+        match written {
+            n if n < 0 => Err(e),
+            n if n as usize != data.len() => Err(std::io::ErrorKind::WriteZero.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Create the directory at `path` unless it exists, with a raw syscall so it's safe in a
+/// `pre_exec` hook
+fn create_dir(path: &CStr) -> std::io::Result<()> {
+    // SAFETY: path is a valid C string
+    if unsafe { libc::mkdir(path.as_ptr(), 0o777) } != 0 {
+        let e = std::io::Error::last_os_error();
+        if e.raw_os_error() != Some(libc::EEXIST) {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Change to the working directory, once inside the chroot
+pub(crate) fn enter_workdir(workdir: Option<&Path>, create: bool) -> std::io::Result<()> {
+    let Some(workdir) = workdir else {
+        return Ok(());
+    };
+    let workdir = Path::new("/").join(workdir);
+    if create {
+        std::fs::create_dir_all(&workdir)?;
+    }
+    std::env::set_current_dir(&workdir).map_err(|e| {
+        std::io::Error::new(
+            e.kind(),
+            format!(
+                "cannot change to working directory {} inside the container: {e}",
+                workdir.display()
+            ),
+        )
+    })
+}
+
+/// Whether a process exists and hasn't exited yet
+///
+/// Zombies count as exited, so this doesn't race with whoever reaps them.
+pub(crate) fn is_running(pid: Pid) -> bool {
+    match std::fs::read_to_string(format!("/proc/{pid}/stat")) {
+        // the state follows the command name, which is in parentheses and may contain spaces
+        Ok(stat) => stat
+            .rsplit_once(')')
+            .and_then(|(_, rest)| rest.split_whitespace().next())
+            .is_some_and(|state| state != "Z" && state != "X"),
+        Err(_) => false,
+    }
+}
+
+/// A process found using a container, see [`crate::Container::kill_lingering`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LingeringProcess {
+    pub pid: Pid,
+    /// Command name, from `/proc/<pid>/comm`
+    pub comm: String,
+}
+
+/// Processes whose root, working directory or open files are under `root`
+///
+/// The calling process and pid 1 are never included. Processes whose `/proc`
+/// entries can't be read, e.g. because they belong to another user, are skipped.
+/// Fails with [`Error::DangerousRoot`] for a root that is, or contains, a critical host
+/// path, as every process on the host would be found under `/`.
+pub(crate) fn processes_using(root: &Path) -> std::io::Result<Vec<LingeringProcess>> {
+    if let Err(e @ Error::DangerousRoot { .. }) = danger::check_root(root) {
+        return Err(e.into());
+    }
+    let root = std::fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+    let own = std::process::id() as i32;
+    let uses_root =
+        |link: PathBuf| std::fs::read_link(link).is_ok_and(|target| target.starts_with(&root));
+
+    let mut found = Vec::new();
+    for entry in std::fs::read_dir("/proc")? {
+        let entry = entry?;
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|pid| pid.parse::<i32>().ok())
+        else {
+            continue;
+        };
+        if pid == own || pid == 1 {
+            continue;
+        }
+
+        let dir = entry.path();
+        let using = uses_root(dir.join("root"))
+            || uses_root(dir.join("cwd"))
+            || std::fs::read_dir(dir.join("fd"))
+                .is_ok_and(|fds| fds.filter_map(|fd| fd.ok()).any(|fd| uses_root(fd.path())));
+        if using {
+            let comm = std::fs::read_to_string(dir.join("comm")).unwrap_or_default();
+            found.push(LingeringProcess {
+                pid: Pid::from_raw(pid),
+                comm: comm.trim_end().to_string(),
+            });
+        }
+    }
+    Ok(found)
+}
+
+/// Send a signal to every process using `root`, then wait up to a second for them to exit
+pub(crate) fn signal_processes_using(
+    root: &Path,
+    signal: Signal,
+) -> std::io::Result<Vec<LingeringProcess>> {
+    let lingering = processes_using(root)?;
+    for process in &lingering {
+        tracing::debug!(pid = ?process.pid, comm = process.comm, ?signal, "Signalling process using container");
+        // it may have exited in the meantime
+        nix::sys::signal::kill(process.pid, signal).ok();
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while lingering.iter().any(|process| is_running(process.pid)) && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    Ok(lingering)
+}
+
+/// A process spawned in a container, tracked through a pidfd
+///
+/// Unlike its pid, the pidfd keeps referring to the same process once it's reaped,
+/// e.g. by [`crate::ContainerChild::wait`], so an unrelated process reusing the pid is
+/// never mistaken for it or signalled.
+#[derive(Debug)]
+pub(crate) struct SpawnedChild {
+    pid: Pid,
+    fd: OwnedFd,
+}
+
+impl SpawnedChild {
+    /// Track the child `pid`, which must not have been reaped yet
+    pub fn open(pid: u32) -> std::io::Result<Self> {
+        // SAFETY: pidfd_open takes no pointers, and the fd is owned right after creation
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Self {
+            pid: Pid::from_raw(pid as i32),
+            // SAFETY: the descriptor was just created and is owned by nobody else
+            fd: unsafe { OwnedFd::from_raw_fd(fd as RawFd) },
+        })
+    }
+
+    /// Whether the process hasn't exited yet, zombies count as exited
+    pub fn is_running(&self) -> bool {
+        let mut fds = [libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        }];
+        // SAFETY: fds is a valid array of one pollfd, and a pidfd is readable once it exits
+        unsafe { libc::poll(fds.as_mut_ptr(), 1, 0) == 0 }
+    }
+
+    /// Send `signal` to the process, if it's still around
+    fn kill(&self, signal: Signal) {
+        // SAFETY: pidfd_send_signal is given no siginfo
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_pidfd_send_signal,
+                self.fd.as_raw_fd(),
+                signal as libc::c_int,
+                std::ptr::null::<libc::siginfo_t>(),
+                0,
+            )
+        };
+        if ret < 0 {
+            let e = std::io::Error::last_os_error();
+            tracing::trace!(?e, pid = ?self.pid, ?signal, "Failed to signal spawned process");
+        }
+    }
+}
+
+/// Deal with spawned processes that are still running, according to `policy`
+pub(crate) fn settle_children(
+    children: &mut Vec<SpawnedChild>,
+    policy: ChildPolicy,
+) -> std::io::Result<()> {
+    children.retain(SpawnedChild::is_running);
+    if children.is_empty() {
+        return Ok(());
+    }
+
+    match policy {
+        ChildPolicy::Refuse => {}
+        ChildPolicy::Kill => {
+            for child in children.iter() {
+                tracing::debug!(pid = ?child.pid, "Killing process spawned in container");
+                child.kill(Signal::SIGKILL);
+            }
+            wait_children(children, Duration::from_secs(5));
+        }
+        ChildPolicy::Wait(timeout) => wait_children(children, timeout),
+    }
+
+    if children.is_empty() {
+        Ok(())
+    } else {
+        let pids: Vec<_> = children.iter().map(|child| child.pid).collect();
+        Err(std::io::Error::other(format!(
+            "processes spawned in the container are still running: {pids:?}"
+        )))
+    }
+}
+
+/// Wait for spawned processes to exit, up to `timeout`
+fn wait_children(children: &mut Vec<SpawnedChild>, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    while !children.is_empty() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+        children.retain(SpawnedChild::is_running);
+    }
+}
+
+/// Create a pipe, with both ends close-on-exec
+pub(crate) fn pipe() -> std::io::Result<(File, OwnedFd)> {
+    let mut fds = [0; 2];
+    // SAFETY: fds has room for the two descriptors
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: both descriptors were just created and are owned by nobody else
+    unsafe { Ok((File::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1]))) }
+}
+
+/// Read everything from `reader`, keeping at most `limit` bytes
+///
+/// The rest is still drained, so the writer never blocks on a full pipe.
+/// Returns whether anything was discarded.
+pub(crate) fn read_limited(
+    mut reader: impl Read,
+    limit: Option<usize>,
+) -> std::io::Result<(Vec<u8>, bool)> {
+    let mut out = Vec::new();
+    let mut truncated = false;
+    let mut buf = [0; 8192];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let keep = limit.map_or(n, |limit| limit.saturating_sub(out.len()).min(n));
+        out.extend_from_slice(&buf[..keep]);
+        truncated |= keep < n;
+    }
+    Ok((out, truncated))
+}
+
+/// Captured output and whether it was truncated, see [`read_limited`]
+pub(crate) type Captured = std::io::Result<(Vec<u8>, bool)>;
+
+/// Run [`read_limited`] on a separate thread
+pub(crate) fn read_limited_thread(
+    reader: impl Read + Send + 'static,
+    limit: Option<usize>,
+) -> JoinHandle<Captured> {
+    std::thread::spawn(move || read_limited(reader, limit))
+}
+
+/// Wait for a reader thread, if there is one
+pub(crate) fn join_reader(thread: Option<JoinHandle<Captured>>) -> Captured {
+    match thread {
+        Some(thread) => thread
+            .join()
+            .map_err(|_| std::io::Error::other("output reader panicked"))?,
+        None => Ok((Vec::new(), false)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::{CommandExt, ExitStatusExt};
+
+    #[ignore = "This test requires root"]
+    #[test]
+    fn test_init_forwards_signals() {
+        let start = Instant::now();
+        let leader = fork_child(|| {
+            run_in_pid_namespace(|| {
+                std::thread::sleep(Duration::from_secs(60));
+                0
+            })
+        })
+        .unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+        nix::sys::signal::kill(leader, Signal::SIGTERM).unwrap();
+        assert_eq!(wait_pid(leader).unwrap(), 128 + libc::SIGTERM);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_wait_timeout() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("60")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let pid = Pid::from_raw(child.id() as i32);
+        let start = Instant::now();
+        let (status, timed_out) = wait_timeout(
+            pid,
+            Duration::from_millis(100),
+            DEFAULT_GRACE_PERIOD,
+            || child.try_wait(),
+        )
+        .unwrap();
+        assert!(timed_out);
+        assert_eq!(status.signal(), Some(libc::SIGTERM));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_spawned_child_reaped() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("60")
+            .spawn()
+            .unwrap();
+        let mut children = vec![SpawnedChild::open(child.id()).unwrap()];
+        assert!(children[0].is_running());
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        // reaped elsewhere, so its pid may already belong to another process
+        assert!(!children[0].is_running());
+        settle_children(&mut children, ChildPolicy::Refuse).unwrap();
+        assert!(children.is_empty());
+    }
+
+    #[test]
+    fn test_processes_using_host_root() {
+        for root in ["/", "/usr"] {
+            let err = Error::from(processes_using(Path::new(root)).unwrap_err());
+            assert!(matches!(err, Error::DangerousRoot { .. }), "{root}");
+        }
+        // nothing uses a root that doesn't exist
+        let missing = Path::new("/nonexistent/tiffin");
+        assert!(processes_using(missing).unwrap().is_empty());
+    }
+}
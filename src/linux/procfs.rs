@@ -0,0 +1,165 @@
+//! Options of procfs mounts, see [`ProcMountOptions`]
+
+use super::{Error, MountTarget};
+use nix::unistd::Gid;
+
+/// Kernel from which each procfs mount has its own options, and `hidepid` takes names
+const PRIVATE_INSTANCES: (u32, u32) = (5, 8);
+
+/// Who can see the `/proc/<pid>` directories of other users' processes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HidePid {
+    /// Everyone, as without the option
+    #[default]
+    Off,
+    /// Everyone can list them, but only their owner can access them
+    NoAccess,
+    /// Only their owner can see them at all
+    Invisible,
+    /// Only processes allowed to ptrace them can see them
+    Ptraceable,
+}
+
+impl HidePid {
+    /// The value of the `hidepid=` option, by name or as the number older kernels expect
+    fn value(self, names: bool) -> &'static str {
+        match (self, names) {
+            (Self::Off, true) => "off",
+            (Self::NoAccess, true) => "noaccess",
+            (Self::Invisible, true) => "invisible",
+            (Self::Ptraceable, true) => "ptraceable",
+            (Self::Off, false) => "0",
+            (Self::NoAccess, false) => "1",
+            (Self::Invisible, false) => "2",
+            (Self::Ptraceable, false) => "4",
+        }
+    }
+}
+
+/// Options of a procfs mount, see [`MountTarget::proc`]
+///
+/// ```
+/// use tiffin::{HidePid, ProcMountOptions};
+///
+/// let options = ProcMountOptions {
+///     hidepid: HidePid::Invisible,
+///     subset_pid: true,
+///     ..ProcMountOptions::default()
+/// };
+/// assert_eq!(options.render_for((6, 1)), "hidepid=invisible,subset=pid");
+/// assert_eq!(options.render_for((5, 4)), "hidepid=2");
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProcMountOptions {
+    pub hidepid: HidePid,
+    /// Group whose members can see every process despite `hidepid`
+    pub gid: Option<Gid>,
+    /// Only show processes, hiding everything else such as `/proc/sys`.
+    /// Ignored by kernels older than 5.8
+    pub subset_pid: bool,
+}
+
+impl ProcMountOptions {
+    /// The data string for the running kernel, see [`ProcMountOptions::render_for`]
+    pub fn render(&self) -> String {
+        self.render_for(kernel_version().unwrap_or(PRIVATE_INSTANCES))
+    }
+
+    /// The data string for a kernel of the given major and minor version
+    ///
+    /// `hidepid` is written by name from 5.8, and as a number before.
+    pub fn render_for(&self, kernel: (u32, u32)) -> String {
+        let modern = kernel >= PRIVATE_INSTANCES;
+        let mut options = Vec::new();
+        if self.hidepid != HidePid::Off {
+            options.push(format!("hidepid={}", self.hidepid.value(modern)));
+        }
+        if let Some(gid) = self.gid {
+            options.push(format!("gid={gid}"));
+        }
+        if self.subset_pid && modern {
+            options.push("subset=pid".to_string());
+        }
+        options.join(",")
+    }
+}
+
+impl MountTarget {
+    /// A procfs mount at `/proc` with `options`
+    pub fn proc(options: ProcMountOptions) -> Self {
+        let data = options.render();
+        Self {
+            target: "proc".into(),
+            fstype: Some("proc".to_string()),
+            data: (!data.is_empty()).then_some(data),
+            ..Self::default()
+        }
+    }
+}
+
+/// Major and minor version of the running kernel
+pub(crate) fn kernel_version() -> Option<(u32, u32)> {
+    let release = std::fs::read_to_string("/proc/sys/kernel/osrelease").ok()?;
+    parse_version(&release)
+}
+
+fn parse_version(release: &str) -> Option<(u32, u32)> {
+    let mut parts = release.trim().split(['.', '-']);
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+/// Explain why the kernel refused to mount a procfs with options, if that is what failed
+pub(crate) fn explain_error(mount: &MountTarget, err: std::io::Error) -> std::io::Error {
+    let options = match (mount.fstype.as_deref(), &mount.data) {
+        (Some("proc"), Some(options)) => options.clone(),
+        _ => return err,
+    };
+    let reason = match err.raw_os_error() {
+        Some(libc::EBUSY) => {
+            "procfs is already mounted with other options in this pid namespace, \
+             which kernels before 5.8 don't allow"
+        }
+        Some(libc::EPERM) => {
+            "mounting a new procfs was denied, which happens in a user namespace when \
+             the host's /proc has mounts over it, or kernels before 5.8 without a pid \
+             namespace of its own"
+        }
+        _ => return err,
+    };
+    std::io::Error::new(
+        err.kind(),
+        Error::ProcMount {
+            options,
+            reason: reason.to_string(),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let options = ProcMountOptions {
+            hidepid: HidePid::NoAccess,
+            gid: Some(Gid::from_raw(10)),
+            subset_pid: true,
+        };
+        assert_eq!(
+            options.render_for((6, 8)),
+            "hidepid=noaccess,gid=10,subset=pid"
+        );
+        assert_eq!(options.render_for((4, 19)), "hidepid=1,gid=10");
+        assert_eq!(ProcMountOptions::default().render_for((6, 8)), "");
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("6.5.6-300.fc39.x86_64\n"), Some((6, 5)));
+        assert_eq!(parse_version("5.10-rc1"), Some((5, 10)));
+        assert_eq!(parse_version("garbage"), None);
+    }
+}
@@ -0,0 +1,71 @@
+//! Progress of long operations, see [`crate::Container::on_progress`]
+
+use std::{path::PathBuf, time::Instant};
+
+/// A step of a long operation, passed to the callback of [`crate::Container::on_progress`]
+///
+/// Every started step is followed by its finished event, even when it fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgressEvent {
+    /// A mount of the table is about to be mounted
+    MountStarted {
+        source: PathBuf,
+        target: PathBuf,
+    },
+    /// A mount was mounted, adopted or failed, see [`crate::MountTable::mount_chroot`]
+    MountFinished {
+        source: PathBuf,
+        target: PathBuf,
+        ok: bool,
+    },
+    /// An active mount is about to be unmounted, `target` being its path on the host
+    UnmountStarted {
+        target: PathBuf,
+    },
+    UnmountFinished {
+        target: PathBuf,
+        ok: bool,
+    },
+    /// Unmounting failed and is tried again another way, see [`crate::cleanup_stale_with`]
+    UnmountRetry {
+        target: PathBuf,
+        attempt: u32,
+    },
+    /// An entry of an archive was unpacked, see [`crate::Container::unpack_tarball`]
+    ExtractEntry {
+        n: u64,
+        total: u64,
+    },
+}
+
+/// The progress callback of a mount table, if any
+#[derive(Default)]
+pub(crate) struct Progress(Option<Box<dyn FnMut(ProgressEvent) + Send>>);
+
+impl Progress {
+    pub fn new(callback: impl FnMut(ProgressEvent) + Send + 'static) -> Self {
+        Self(Some(Box::new(callback)))
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub fn emit(&mut self, event: ProgressEvent) {
+        if let Some(callback) = &mut self.0 {
+            callback(event);
+        }
+    }
+}
+
+impl std::fmt::Debug for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Progress").field(&self.is_set()).finish()
+    }
+}
+
+/// Record how long the current step of `span` took and how it ended
+pub(crate) fn finish(span: &tracing::Span, start: Instant, outcome: &'static str) {
+    span.record("duration_ms", start.elapsed().as_millis() as u64);
+    span.record("outcome", outcome);
+}
@@ -0,0 +1,251 @@
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use std::{
+    ffi::CStr,
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    os::{
+        fd::{AsRawFd, FromRawFd, RawFd},
+        unix::fs::OpenOptionsExt,
+    },
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Set by the SIGWINCH handler while a [`WinchForwarder`] is installed
+static WINDOW_CHANGED: AtomicBool = AtomicBool::new(false);
+
+/// The master side of a pseudo-terminal
+#[derive(Debug)]
+pub(crate) struct Pty {
+    master: File,
+}
+
+impl Pty {
+    /// Allocate a pseudo-terminal, returning the master and the slave
+    pub fn open() -> std::io::Result<(Self, File)> {
+        // SAFETY: plain libc calls, the fd is owned by the File right after creation
+        let master = unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC);
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            File::from_raw_fd(fd)
+        };
+
+        // SAFETY: master is a valid pty master
+        if unsafe { libc::grantpt(master.as_raw_fd()) } != 0
+            || unsafe { libc::unlockpt(master.as_raw_fd()) } != 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        let mut name = [0 as libc::c_char; 64];
+        // SAFETY: name is large enough for any /dev/pts path and ptsname_r NUL-terminates it
+        let ret = unsafe { libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len()) };
+        if ret != 0 {
+            return Err(std::io::Error::from_raw_os_error(ret));
+        }
+        // SAFETY: ptsname_r succeeded, so name is a valid C string
+        let name = unsafe { CStr::from_ptr(name.as_ptr()) };
+
+        let slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_CLOEXEC)
+            .open(name.to_string_lossy().as_ref())?;
+
+        Ok((Self { master }, slave))
+    }
+
+    /// Copy the window size of the terminal `from` to the pty
+    pub fn copy_window_size(&self, from: RawFd) -> std::io::Result<()> {
+        // SAFETY: winsize is plain old data filled in by the kernel
+        unsafe {
+            let mut size: libc::winsize = std::mem::zeroed();
+            if libc::ioctl(from, libc::TIOCGWINSZ, &mut size) != 0
+                || libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Proxy bytes from `input` to the pty and from the pty to `output`,
+    /// until the slave side is closed by everyone
+    ///
+    /// When `input` reaches end of file, an EOF character is sent to the pty.
+    /// If `input` is a terminal, window size changes are forwarded while a
+    /// [`WinchForwarder`] is installed.
+    pub fn proxy(&self, input: RawFd, output: &mut impl Write) -> std::io::Result<()> {
+        let master = self.master.as_raw_fd();
+        // SAFETY: isatty only inspects the fd
+        let input_is_tty = unsafe { libc::isatty(input) } == 1;
+        let mut input_open = true;
+        let mut buf = [0; 4096];
+
+        loop {
+            if input_is_tty && WINDOW_CHANGED.swap(false, Ordering::Relaxed) {
+                self.copy_window_size(input).ok();
+            }
+
+            let mut fds = [
+                libc::pollfd {
+                    fd: if input_open { input } else { -1 },
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+                libc::pollfd {
+                    fd: master,
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+            ];
+            // SAFETY: fds is a valid array of pollfds
+            if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, 100) } < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+
+            if fds[1].revents != 0 {
+                match (&self.master).read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        output.write_all(&buf[..n])?;
+                        output.flush()?;
+                    }
+                    // the slave has been closed, which is how the pty signals the end
+                    Err(e) if e.raw_os_error() == Some(libc::EIO) => break,
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if fds[0].revents != 0 {
+                // SAFETY: buf is valid for writes of its length
+                let n = unsafe { libc::read(input, buf.as_mut_ptr().cast(), buf.len()) };
+                match n {
+                    0 => {
+                        input_open = false;
+                        (&self.master).write_all(&[4])?;
+                    }
+                    n if n > 0 => (&self.master).write_all(&buf[..n as usize])?,
+                    _ => {
+                        let err = std::io::Error::last_os_error();
+                        if err.kind() != std::io::ErrorKind::Interrupted {
+                            input_open = false;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Puts a terminal into raw mode, restoring its previous settings when dropped
+pub(crate) struct RawMode {
+    fd: RawFd,
+    saved: libc::termios,
+}
+
+impl RawMode {
+    pub fn enable(fd: RawFd) -> std::io::Result<Self> {
+        // SAFETY: termios is plain old data filled in by tcgetattr
+        unsafe {
+            let mut saved: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(fd, &mut saved) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let mut raw = saved;
+            libc::cfmakeraw(&mut raw);
+            if libc::tcsetattr(fd, libc::TCSANOW, &raw) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(Self { fd, saved })
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: restores settings previously returned by tcgetattr
+        if unsafe { libc::tcsetattr(self.fd, libc::TCSANOW, &self.saved) } != 0 {
+            tracing::error!(
+                e = ?std::io::Error::last_os_error(),
+                "Failed to restore terminal settings"
+            );
+        }
+    }
+}
+
+extern "C" fn on_sigwinch(_: libc::c_int) {
+    WINDOW_CHANGED.store(true, Ordering::Relaxed);
+}
+
+/// Records SIGWINCH for [`Pty::proxy`], restoring the previous handler when dropped
+pub(crate) struct WinchForwarder {
+    previous: SigAction,
+}
+
+impl WinchForwarder {
+    pub fn install() -> std::io::Result<Self> {
+        let action = SigAction::new(
+            SigHandler::Handler(on_sigwinch),
+            SaFlags::SA_RESTART,
+            SigSet::empty(),
+        );
+        // SAFETY: the handler only stores to an atomic
+        let previous = unsafe { sigaction(Signal::SIGWINCH, &action) }?;
+        Ok(Self { previous })
+    }
+}
+
+impl Drop for WinchForwarder {
+    fn drop(&mut self) {
+        // SAFETY: restores the handler that was installed before
+        unsafe { sigaction(Signal::SIGWINCH, &self.previous) }.ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::{Command, Stdio};
+
+    #[test]
+    fn test_pty_roundtrip() {
+        let (pty, mut slave) = Pty::open().unwrap();
+        slave.write_all(b"hello").unwrap();
+        let mut buf = [0; 5];
+        (&pty.master).read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+
+    #[test]
+    fn test_proxy() {
+        let (pty, slave) = Pty::open().unwrap();
+        let mut child = Command::new("/bin/sh")
+            .stdin(slave.try_clone().unwrap())
+            .stdout(slave.try_clone().unwrap())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        drop(slave);
+
+        // scripted input instead of a terminal
+        let (input, script) = crate::linux::process::pipe().unwrap();
+        let mut script = File::from(script);
+        script
+            .write_all(b"echo tiffin-$((40 + 2))\nexit\n")
+            .unwrap();
+        drop(script);
+
+        let mut output = Vec::new();
+        pty.proxy(input.as_raw_fd(), &mut output).unwrap();
+        assert!(child.wait().unwrap().success());
+        assert!(String::from_utf8_lossy(&output).contains("tiffin-42"));
+    }
+}
@@ -0,0 +1,100 @@
+//! Making a mount and everything mounted under it read-only
+
+use super::mountinfo;
+use nix::mount::MsFlags;
+use std::{ffi::CString, os::unix::ffi::OsStrExt, path::Path};
+
+/// `AT_RECURSIVE` from `linux/fcntl.h`
+const AT_RECURSIVE: libc::c_uint = 0x8000;
+/// `MOUNT_ATTR_RDONLY` from `linux/mount.h`
+const MOUNT_ATTR_RDONLY: u64 = 0x1;
+
+/// `struct mount_attr` from `linux/mount.h`
+#[repr(C)]
+struct MountAttr {
+    attr_set: u64,
+    attr_clr: u64,
+    propagation: u64,
+    userns_fd: u64,
+}
+
+/// Make the mounts under `target`, already a read-only mount itself, read-only too
+///
+/// A read-only remount only applies to the mount it's given, so submounts of a recursive
+/// bind mount would stay writable. Before Linux 5.12, each of them is remounted in turn.
+pub(crate) fn make_submounts_read_only(target: &Path) -> std::io::Result<()> {
+    let path = CString::new(target.as_os_str().as_bytes())?;
+    let attr = MountAttr {
+        attr_set: MOUNT_ATTR_RDONLY,
+        attr_clr: 0,
+        propagation: 0,
+        userns_fd: 0,
+    };
+    // SAFETY: both pointers are valid for the duration of the call, and the size is theirs
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_mount_setattr,
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            AT_RECURSIVE,
+            &attr as *const MountAttr,
+            std::mem::size_of::<MountAttr>(),
+        )
+    };
+    if ret == 0 {
+        return Ok(());
+    }
+    let err = std::io::Error::last_os_error();
+    if err.raw_os_error() != Some(libc::ENOSYS) {
+        return Err(err);
+    }
+
+    let canonical = target.canonicalize()?;
+    for mount in mountinfo::mounts_under(&canonical)? {
+        if mount.mount_point == canonical {
+            continue;
+        }
+        tracing::trace!(mount_point = ?mount.mount_point, "Remounting submount read-only");
+        let flags = MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY | kept(&mount);
+        nix::mount::mount(
+            None::<&str>,
+            &mount.mount_point,
+            None::<&str>,
+            flags,
+            None::<&str>,
+        )?;
+    }
+    Ok(())
+}
+
+/// Flags of `mount` that a remount must keep, as dropping them is refused when they're locked
+fn kept(mount: &mountinfo::MountInfo) -> MsFlags {
+    mount
+        .options
+        .split(',')
+        .fold(MsFlags::empty(), |flags, option| match option {
+            "nosuid" => flags | MsFlags::MS_NOSUID,
+            "nodev" => flags | MsFlags::MS_NODEV,
+            "noexec" => flags | MsFlags::MS_NOEXEC,
+            "noatime" => flags | MsFlags::MS_NOATIME,
+            "nodiratime" => flags | MsFlags::MS_NODIRATIME,
+            "relatime" => flags | MsFlags::MS_RELATIME,
+            _ => flags,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kept() {
+        let mount = &mountinfo::parse(
+            "61 1 0:6 / /tmp/tiffin/dev rw,nosuid,noexec,relatime - devtmpfs devtmpfs rw\n",
+        )[0];
+        assert_eq!(
+            kept(mount),
+            MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC | MsFlags::MS_RELATIME
+        );
+    }
+}
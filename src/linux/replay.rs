@@ -0,0 +1,208 @@
+//! Reproducing what a container did from its journal, see [`crate::Container::record_to`]
+//!
+//! A journal sent along with a bug report can be turned back into the mount table that
+//! made it with [`plan_from_journal`], and mounted again against a scratch root with
+//! [`verify`] to find where the two machines behave differently.
+
+use super::{generate_id, JournalOp, JournalRecord, MountTable, MountTarget};
+use crate::Result;
+use std::path::Path;
+use sys_mount::MountFlags;
+
+/// Read the records of a journal, in the order they were made
+///
+/// A last line cut short, as left by a crash while writing it, is ignored.
+pub fn read_journal(path: impl AsRef<Path>) -> Result<Vec<JournalRecord>> {
+    let journal = std::fs::read_to_string(path)?;
+    let mut records = Vec::new();
+    let mut lines = journal.lines().peekable();
+    while let Some(line) = lines.next() {
+        match serde_json::from_str(line) {
+            Ok(record) => records.push(record),
+            Err(e) if lines.peek().is_none() && !journal.ends_with('\n') => {
+                tracing::warn!(?e, "Ignoring the truncated last record of the journal");
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(records)
+}
+
+/// The mount table that made the mounts of a journal, see [`plan_from_records`]
+pub fn plan_from_journal(path: impl AsRef<Path>) -> Result<MountTable> {
+    Ok(plan_from_records(&read_journal(path)?))
+}
+
+/// The mount table that made the mounts of `records`, failed ones included
+///
+/// Mounts are keyed by source as in any [`MountTable`], so a source mounted at several
+/// targets keeps the last one. The flags and data are those passed to the kernel, and
+/// missing mountpoints are created.
+pub fn plan_from_records(records: &[JournalRecord]) -> MountTable {
+    let mut table = MountTable::new();
+    for record in records.iter().filter(|record| is_mount(record)) {
+        let (Some(source), Some(target)) = (&record.source, &record.target) else {
+            continue;
+        };
+        let mount = MountTarget::new(
+            target,
+            record.fstype.clone(),
+            MountFlags::from_bits_truncate(record.flags as _),
+            record.data.clone(),
+        );
+        table.add_mount(mount, source);
+    }
+    table
+}
+
+/// The first difference between a journal and its replay, see [`verify`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Position of the record among the mounts of the journal
+    pub index: usize,
+    /// The record of the journal, `None` if the replay mounted more
+    pub expected: Option<JournalRecord>,
+    /// The record of the replay, `None` if it stopped before
+    pub actual: Option<JournalRecord>,
+}
+
+/// Mount the table of a journal to `root` and unmount it again, returning the first
+/// mount that went differently, if any
+///
+/// Mounts are compared by source, target, filesystem, flags, data and outcome, not by
+/// when or where on the host they were made. A journal may hold several runs, so only
+/// as many of its mounts as the replay made are compared. This needs root, as mounting.
+pub fn verify(path: impl AsRef<Path>, root: impl AsRef<Path>) -> Result<Option<Divergence>> {
+    let records = read_journal(path)?;
+    let expected: Vec<_> = records.iter().filter(|record| is_mount(record)).collect();
+
+    let replay = std::env::temp_dir().join(format!("tiffin-replay-{}.jsonl", generate_id()));
+    let mut table = plan_from_records(&records);
+    table.record_to(&replay)?;
+    let mounted = table.mount_chroot(root.as_ref());
+    if let Err(e) = table.umount_chroot() {
+        tracing::warn!(?e, "Failed to unmount the replay");
+    }
+    drop(table);
+    let actual = read_journal(&replay);
+    std::fs::remove_file(&replay)?;
+    let actual: Vec<_> = actual?.into_iter().filter(is_mount).collect();
+
+    for (index, (expected, actual)) in expected.iter().zip(&actual).enumerate() {
+        if !same_mount(expected, actual) {
+            return Ok(Some(Divergence {
+                index,
+                expected: Some((*expected).clone()),
+                actual: Some(actual.clone()),
+            }));
+        }
+    }
+    let index = actual.len();
+    if index > expected.len() {
+        return Ok(Some(Divergence {
+            index: expected.len(),
+            expected: None,
+            actual: Some(actual[expected.len()].clone()),
+        }));
+    }
+    // the replay stopped without a failed mount, e.g. as the table was invalid here
+    let stopped = mounted.is_err() && actual.last().is_none_or(|record| record.ok);
+    match expected.get(index) {
+        Some(expected) if stopped => Ok(Some(Divergence {
+            index,
+            expected: Some((*expected).clone()),
+            actual: None,
+        })),
+        _ => Ok(None),
+    }
+}
+
+fn is_mount(record: &JournalRecord) -> bool {
+    matches!(record.op, JournalOp::Mount | JournalOp::Adopt)
+}
+
+/// Whether two mounts are the same, even if one was adopted and the other mounted
+fn same_mount(a: &JournalRecord, b: &JournalRecord) -> bool {
+    let key = |record: &JournalRecord| {
+        (
+            record.source.clone(),
+            record.target.clone(),
+            record.fstype.clone(),
+            record.flags,
+            record.data.clone(),
+            record.ok,
+            record.errno,
+        )
+    };
+    key(a) == key(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::{journal::Journal, tempdir::TempDir};
+
+    #[test]
+    fn test_plan_round_trip() {
+        let mounts = [
+            (
+                "tmpfs",
+                MountTarget::new(
+                    "/tmp",
+                    Some("tmpfs".to_string()),
+                    MountFlags::NOSUID,
+                    Some("mode=1777".to_string()),
+                ),
+            ),
+            (
+                "/var/cache/dnf",
+                MountTarget::new(
+                    "/var/cache/dnf",
+                    None,
+                    MountFlags::BIND | MountFlags::RDONLY,
+                    None,
+                ),
+            ),
+        ];
+        let dir = TempDir::new("plan");
+        let path = dir.join("journal.jsonl");
+        let journal = Journal::open(&path).unwrap();
+        for (source, mount) in &mounts {
+            let path = Path::new("/srv/root").join(mount.target.strip_prefix("/").unwrap());
+            journal.record(&JournalRecord::mount(
+                JournalOp::Mount,
+                Path::new(source),
+                mount,
+                path,
+            ));
+        }
+        journal.record(&JournalRecord::new(JournalOp::ChrootEnter, "/srv/root"));
+        drop(journal);
+        // a crash while writing the last record
+        std::fs::write(
+            &path,
+            std::fs::read_to_string(&path).unwrap() + r#"{"timestamp_ms":17"#,
+        )
+        .unwrap();
+
+        assert_eq!(read_journal(&path).unwrap().len(), 3);
+        let table = plan_from_journal(&path).unwrap();
+        let mut expected = MountTable::new();
+        for (source, mount) in mounts {
+            expected.add_mount(mount, source);
+        }
+        assert_eq!(table.inner, expected.inner);
+    }
+
+    #[test]
+    fn test_same_mount() {
+        let mount = MountTarget::new("/proc", Some("proc".to_string()), MountFlags::empty(), None);
+        let mounted = JournalRecord::mount(JournalOp::Mount, Path::new("proc"), &mount, "/a/proc");
+        let adopted = JournalRecord::mount(JournalOp::Adopt, Path::new("proc"), &mount, "/b/proc");
+        assert!(same_mount(&mounted, &adopted));
+        let failed = adopted.outcome(&Err::<(), _>(std::io::Error::from_raw_os_error(
+            libc::EPERM,
+        )));
+        assert!(!same_mount(&mounted, &failed));
+    }
+}
@@ -0,0 +1,340 @@
+//! Resolving paths inside the container root without ever leaving it
+//!
+//! The root is an untrusted image: a symlink such as `etc -> /etc` must not make
+//! tiffin create directories or mount filesystems on the host. Paths are walked
+//! one component at a time with `O_NOFOLLOW`, and symlinks are resolved as if
+//! the root was the root directory, like `openat2` with `RESOLVE_IN_ROOT`.
+
+use crate::Error;
+use std::{
+    collections::VecDeque,
+    ffi::{CString, OsStr, OsString},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::ffi::{OsStrExt, OsStringExt},
+    },
+    path::{Component, Path, PathBuf},
+};
+
+/// Symlinks followed before giving up, as the kernel does
+const MAX_SYMLINKS: usize = 40;
+
+/// What to create if the path doesn't exist yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Create {
+    Nothing,
+    /// Directories all the way down
+    Dir,
+    /// Directories, and an empty file for the last component
+    File,
+}
+
+/// Resolve `target` inside `root`, returning the host path it refers to
+///
+/// Symlinks are followed relative to `root`, and `..` in their contents never goes
+/// above it. `target` itself may not contain `..` at all. Missing components are
+/// created according to `create`, with mode 0755 for directories and 0644 for files.
+///
+/// The returned path has no symlinks in it, at least until someone changes the tree.
+pub(crate) fn resolve_in_root(
+    root: &Path,
+    target: &Path,
+    create: Create,
+) -> std::io::Result<PathBuf> {
+    resolve(root, target, create).map(|resolved| resolved.path)
+}
+
+/// A path resolved by [`resolve`]
+#[derive(Debug)]
+pub(crate) struct Resolved {
+    pub path: PathBuf,
+    /// The file at `path`, opened with `O_PATH`, which still refers to it if the tree
+    /// changes, see [`Resolved::fd_path`]
+    pub fd: OwnedFd,
+    /// What was created on the way, parents first
+    pub created: Vec<PathBuf>,
+    /// The directory the file is in and its name there, `None` for the root itself
+    entry: Option<(OwnedFd, OsString)>,
+}
+
+impl Resolved {
+    /// The path of the opened file in procfs, for syscalls that only take paths, such as
+    /// `mount`, to act on it rather than walking `path` again
+    pub fn fd_path(&self) -> PathBuf {
+        fd_path(&self.fd)
+    }
+
+    /// Open the file by its name again, without following symlinks
+    ///
+    /// Unlike [`Resolved::fd`], this is the root of a filesystem mounted on it since.
+    pub fn reopen(&self) -> std::io::Result<OwnedFd> {
+        match &self.entry {
+            Some((dir, name)) => open(dir.as_raw_fd(), name, libc::O_NOFOLLOW),
+            None => open(libc::AT_FDCWD, self.path.as_os_str(), 0),
+        }
+    }
+}
+
+/// The path of `fd` in procfs, which refers to whatever it was opened as
+pub(crate) fn fd_path(fd: &impl AsRawFd) -> PathBuf {
+    PathBuf::from(format!("/proc/self/fd/{}", fd.as_raw_fd()))
+}
+
+/// [`resolve_in_root`], also returning the opened file and what was created
+pub(crate) fn resolve(root: &Path, target: &Path, create: Create) -> std::io::Result<Resolved> {
+    if target.components().any(|c| c == Component::ParentDir) {
+        return Err(escape(target));
+    }
+
+    // the open directories from the root down, so `..` can go back up
+    let mut dirs = vec![open(libc::AT_FDCWD, root.as_os_str(), libc::O_DIRECTORY)?];
+    let mut names: Vec<OsString> = Vec::new();
+    let mut pending = normal_components(target);
+    let mut symlinks = 0;
+    let mut created = Vec::new();
+    // the last component, if it isn't a directory
+    let mut file = None;
+
+    while let Some(name) = pending.pop_front() {
+        let Some(name) = name else {
+            if dirs.len() > 1 {
+                dirs.pop();
+                names.pop();
+            }
+            continue;
+        };
+        let last = pending.is_empty();
+        let dir = dirs.last().expect("the root is never popped").as_raw_fd();
+        let fd = match open(dir, &name, libc::O_NOFOLLOW) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && create != Create::Nothing => {
+                if make(dir, &name, last && create == Create::File)? {
+                    created.push(join(root, &names).join(&name));
+                }
+                open(dir, &name, libc::O_NOFOLLOW)?
+            }
+            result => result?,
+        };
+
+        match file_type(&fd)? {
+            libc::S_IFLNK => {
+                symlinks += 1;
+                if symlinks > MAX_SYMLINKS {
+                    return Err(std::io::Error::from_raw_os_error(libc::ELOOP));
+                }
+                let link = PathBuf::from(read_link(&fd)?);
+                tracing::trace!(?name, ?link, "Following symlink inside the container");
+                if link.is_absolute() {
+                    dirs.truncate(1);
+                    names.clear();
+                }
+                for component in normal_components(&link).into_iter().rev() {
+                    pending.push_front(component);
+                }
+            }
+            libc::S_IFDIR => {
+                names.push(name);
+                dirs.push(fd);
+            }
+            _ if last => {
+                names.push(name);
+                file = Some(fd);
+            }
+            _ => return Err(std::io::Error::from_raw_os_error(libc::ENOTDIR)),
+        }
+    }
+    let path = join(root, &names);
+    let fd = match file {
+        Some(fd) => fd,
+        None => dirs.pop().expect("the root is never popped"),
+    };
+    let entry = dirs.pop().zip(names.pop());
+    Ok(Resolved {
+        path,
+        fd,
+        created,
+        entry,
+    })
+}
+
+fn join(root: &Path, names: &[OsString]) -> PathBuf {
+    names
+        .iter()
+        .fold(root.to_path_buf(), |path, name| path.join(name))
+}
+
+/// An error for a target that would leave the container root
+pub(crate) fn escape(target: &Path) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        Error::PathEscape {
+            target: target.to_path_buf(),
+        },
+    )
+}
+
+/// The names in `path`, with `None` for `..`
+fn normal_components(path: &Path) -> VecDeque<Option<OsString>> {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(Some(name.to_os_string())),
+            Component::ParentDir => Some(None),
+            _ => None,
+        })
+        .collect()
+}
+
+fn cstring(name: &OsStr) -> std::io::Result<CString> {
+    CString::new(name.as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+}
+
+/// Open `name` in `dir` with `O_PATH`, which works on symlinks with `O_NOFOLLOW`
+fn open(dir: RawFd, name: &OsStr, flags: libc::c_int) -> std::io::Result<OwnedFd> {
+    let name = cstring(name)?;
+    // SAFETY: name is a valid C string, and the fd is owned right after creation
+    unsafe {
+        let fd = libc::openat(dir, name.as_ptr(), flags | libc::O_PATH | libc::O_CLOEXEC);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(OwnedFd::from_raw_fd(fd))
+    }
+}
+
+/// Create a directory, or an empty file, at `name` in `dir`, returning whether it was created
+fn make(dir: RawFd, name: &OsStr, file: bool) -> std::io::Result<bool> {
+    let name = cstring(name)?;
+    // SAFETY: name is a valid C string, and the file is closed right away
+    let ret = unsafe {
+        if file {
+            let fd = libc::openat(
+                dir,
+                name.as_ptr(),
+                libc::O_CREAT | libc::O_EXCL | libc::O_WRONLY | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+                0o644,
+            );
+            if fd >= 0 {
+                libc::close(fd);
+            }
+            fd
+        } else {
+            libc::mkdirat(dir, name.as_ptr(), 0o755)
+        }
+    };
+    if ret >= 0 {
+        return Ok(true);
+    }
+    match std::io::Error::last_os_error() {
+        // someone else created it in the meantime, which gets checked once it's opened
+        e if e.raw_os_error() == Some(libc::EEXIST) => Ok(false),
+        e => Err(e),
+    }
+}
+
+fn file_type(fd: &OwnedFd) -> std::io::Result<libc::mode_t> {
+    // SAFETY: stat is plain old data filled in by the kernel
+    unsafe {
+        let mut stat: libc::stat = std::mem::zeroed();
+        if libc::fstat(fd.as_raw_fd(), &mut stat) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(stat.st_mode & libc::S_IFMT)
+    }
+}
+
+/// Read the symlink opened as `fd`
+fn read_link(fd: &OwnedFd) -> std::io::Result<OsString> {
+    let mut buf = vec![0u8; libc::PATH_MAX as usize];
+    // SAFETY: buf is valid for writes of its length, and an empty path refers to fd itself
+    let len = unsafe {
+        libc::readlinkat(
+            fd.as_raw_fd(),
+            c"".as_ptr(),
+            buf.as_mut_ptr().cast(),
+            buf.len(),
+        )
+    };
+    if len < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    buf.truncate(len as usize);
+    Ok(OsString::from_vec(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::tempdir::TempDir;
+    use std::os::unix::fs::symlink;
+
+    fn fixture(name: &str) -> TempDir {
+        let root = TempDir::new(name);
+        std::fs::create_dir_all(root.join("usr/lib")).unwrap();
+        root
+    }
+
+    #[test]
+    fn test_absolute_symlink() {
+        let root = fixture("resolve-absolute");
+        symlink("/etc", root.join("etc")).unwrap();
+        let target = format!("etc/tiffin-escape-{}", std::process::id());
+
+        // etc resolves to itself inside the root, which is a loop
+        let err = resolve_in_root(&root, Path::new(&target), Create::Dir).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ELOOP));
+        assert!(!Path::new("/").join(&target).exists());
+    }
+
+    #[test]
+    fn test_relative_symlinks() {
+        let root = fixture("resolve-relative");
+        symlink("usr/lib", root.join("lib")).unwrap();
+        symlink("../../../../../tmp", root.join("usr/escape")).unwrap();
+
+        assert_eq!(
+            resolve_in_root(&root, Path::new("/lib/modules"), Create::Dir).unwrap(),
+            root.join("usr/lib/modules")
+        );
+        assert!(root.join("usr/lib/modules").is_dir());
+
+        // only what didn't exist is reported as created
+        let resolved = resolve(&root, Path::new("lib/modules/6.1/kernel"), Create::Dir).unwrap();
+        assert_eq!(
+            resolved.created,
+            [
+                root.join("usr/lib/modules/6.1"),
+                root.join("usr/lib/modules/6.1/kernel")
+            ]
+        );
+        // the opened file is the one at the path, even when moved away
+        std::fs::rename(root.join("usr/lib/modules/6.1"), root.join("moved")).unwrap();
+        assert_eq!(
+            std::fs::read_link(resolved.fd_path()).unwrap(),
+            root.join("moved/kernel")
+        );
+
+        // .. in a symlink stops at the root
+        assert_eq!(
+            resolve_in_root(&root, Path::new("usr/escape/file"), Create::File).unwrap(),
+            root.join("tmp/file")
+        );
+        assert!(root.join("tmp/file").is_file());
+    }
+
+    #[test]
+    fn test_missing() {
+        let root = fixture("resolve-missing");
+        let err = resolve_in_root(&root, Path::new("srv/data"), Create::Nothing).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        std::fs::write(root.join("file"), "").unwrap();
+        let err = resolve_in_root(&root, Path::new("file/data"), Create::Dir).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOTDIR));
+    }
+
+    #[test]
+    fn test_parent_dir() {
+        let err = resolve_in_root(Path::new("/"), Path::new("../../etc"), Create::Dir).unwrap_err();
+        let inner = err.into_inner().unwrap().downcast::<Error>().unwrap();
+        assert!(matches!(*inner, Error::PathEscape { target } if target == Path::new("../../etc")));
+    }
+}
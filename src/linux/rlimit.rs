@@ -0,0 +1,224 @@
+use crate::{Error, Result};
+pub use nix::sys::resource::Resource;
+use nix::sys::resource::{getrlimit, setrlimit};
+use std::{fmt, str::FromStr};
+
+/// A single resource limit value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Value(u64),
+    Unlimited,
+}
+
+impl Limit {
+    fn to_raw(self) -> libc::rlim_t {
+        match self {
+            Self::Value(value) => value,
+            Self::Unlimited => libc::RLIM_INFINITY,
+        }
+    }
+
+    fn from_raw(raw: libc::rlim_t) -> Self {
+        if raw == libc::RLIM_INFINITY {
+            Self::Unlimited
+        } else {
+            Self::Value(raw)
+        }
+    }
+}
+
+impl PartialOrd for Limit {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Limit {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.to_raw().cmp(&other.to_raw())
+    }
+}
+
+impl From<u64> for Limit {
+    fn from(value: u64) -> Self {
+        Self::from_raw(value)
+    }
+}
+
+impl FromStr for Limit {
+    type Err = std::num::ParseIntError;
+
+    /// Parses a number, or `unlimited`/`infinity`
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim() {
+            "unlimited" | "infinity" => Ok(Self::Unlimited),
+            value => value.parse().map(Self::Value),
+        }
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Value(value) => write!(f, "{value}"),
+            Self::Unlimited => f.write_str("unlimited"),
+        }
+    }
+}
+
+/// Resource limits applied to code running inside the container
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rlimits {
+    limits: Vec<(Resource, Limit, Limit)>,
+}
+
+impl Rlimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the soft and hard limit for a resource, replacing any previous value
+    ///
+    /// Returns [`Error::InvalidRlimit`] if the soft limit is above the hard limit.
+    pub fn set(
+        &mut self,
+        resource: Resource,
+        soft: impl Into<Limit>,
+        hard: impl Into<Limit>,
+    ) -> Result<&mut Self> {
+        let (soft, hard) = (soft.into(), hard.into());
+        if soft > hard {
+            return Err(Error::InvalidRlimit {
+                resource,
+                soft,
+                hard,
+            });
+        }
+
+        match self.limits.iter_mut().find(|(r, _, _)| *r == resource) {
+            Some(limit) => *limit = (resource, soft, hard),
+            None => self.limits.push((resource, soft, hard)),
+        }
+        Ok(self)
+    }
+
+    /// Gets the soft and hard limit configured for a resource
+    pub fn get(&self, resource: Resource) -> Option<(Limit, Limit)> {
+        self.limits
+            .iter()
+            .find(|(r, _, _)| *r == resource)
+            .map(|(_, soft, hard)| (*soft, *hard))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.limits.is_empty()
+    }
+
+    /// Apply the limits to the current process
+    ///
+    /// Nothing is logged, so this is safe in a `pre_exec` hook.
+    pub(crate) fn apply(&self) -> std::io::Result<()> {
+        for (resource, soft, hard) in &self.limits {
+            setrlimit(*resource, soft.to_raw(), hard.to_raw())?;
+        }
+        Ok(())
+    }
+
+    /// Apply the limits to the current process, until the returned guard restores the
+    /// previous ones
+    ///
+    /// Raising a hard limit back up requires CAP_SYS_RESOURCE.
+    pub(crate) fn apply_saved(&self) -> std::io::Result<RlimitGuard> {
+        let mut saved = Self::new();
+        for (resource, new_soft, new_hard) in &self.limits {
+            tracing::trace!(?resource, %new_soft, %new_hard, "Setting resource limit");
+            let (soft, hard) = getrlimit(*resource)?;
+            saved
+                .limits
+                .push((*resource, Limit::from_raw(soft), Limit::from_raw(hard)));
+        }
+        // restores those already set if one fails
+        let guard = RlimitGuard { saved: Some(saved) };
+        self.apply()?;
+        Ok(guard)
+    }
+}
+
+/// Restores the limits of the process replaced by [`Rlimits::apply_saved`] when dropped,
+/// even if the code running with them panicked
+pub(crate) struct RlimitGuard {
+    saved: Option<Rlimits>,
+}
+
+impl RlimitGuard {
+    /// Restore the previous limits, returning why if they can't be
+    pub fn restore(mut self) -> std::io::Result<()> {
+        match self.saved.take() {
+            Some(saved) => saved.apply(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for RlimitGuard {
+    fn drop(&mut self) {
+        if let Some(Err(e)) = self.saved.take().map(|saved| saved.apply()) {
+            tracing::error!(?e, "Failed to restore resource limits");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_limit() {
+        assert_eq!("unlimited".parse::<Limit>().unwrap(), Limit::Unlimited);
+        assert_eq!("1024".parse::<Limit>().unwrap(), Limit::Value(1024));
+        assert!("lots".parse::<Limit>().is_err());
+        assert!(Limit::Value(u64::MAX - 1) < Limit::Unlimited);
+    }
+
+    #[test]
+    fn test_set() {
+        let mut limits = Rlimits::new();
+        limits
+            .set(Resource::RLIMIT_NOFILE, 16u64, 32u64)
+            .unwrap()
+            .set(Resource::RLIMIT_CORE, 0u64, Limit::Unlimited)
+            .unwrap()
+            .set(Resource::RLIMIT_NOFILE, 64u64, 64u64)
+            .unwrap();
+        assert_eq!(
+            limits.get(Resource::RLIMIT_NOFILE),
+            Some((Limit::Value(64), Limit::Value(64)))
+        );
+        assert!(matches!(
+            limits.set(Resource::RLIMIT_NPROC, Limit::Unlimited, 10u64),
+            Err(Error::InvalidRlimit { .. })
+        ));
+    }
+
+    #[test]
+    fn test_guard_restores() {
+        // the core size, as the other tests don't care about it
+        let before = getrlimit(Resource::RLIMIT_CORE).unwrap();
+        let mut limits = Rlimits::new();
+        limits
+            .set(Resource::RLIMIT_CORE, 0u64, Limit::from_raw(before.1))
+            .unwrap();
+
+        let guard = limits.apply_saved().unwrap();
+        assert_eq!(getrlimit(Resource::RLIMIT_CORE).unwrap().0, 0);
+        guard.restore().unwrap();
+        assert_eq!(getrlimit(Resource::RLIMIT_CORE).unwrap(), before);
+
+        let panicked = std::panic::catch_unwind(|| {
+            let _guard = limits.apply_saved().unwrap();
+            panic!("in the container");
+        });
+        assert!(panicked.is_err());
+        assert_eq!(getrlimit(Resource::RLIMIT_CORE).unwrap(), before);
+    }
+}
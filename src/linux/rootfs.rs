@@ -0,0 +1,302 @@
+//! Assembling a minimal rootfs from programs of the host, see [`from_host_binaries`]
+
+use super::{elf::Elf, Error, Result};
+use std::{
+    collections::HashSet,
+    os::unix::fs::PermissionsExt,
+    path::{Component, Path, PathBuf},
+};
+
+/// Directories of the skeleton, with their mode
+const SKELETON: [(&str, u32); 9] = [
+    ("etc", 0o755),
+    ("tmp", 0o1777),
+    ("proc", 0o555),
+    ("sys", 0o555),
+    ("dev", 0o755),
+    ("root", 0o700),
+    ("usr/bin", 0o755),
+    ("usr/lib", 0o755),
+    ("var/tmp", 0o1777),
+];
+
+const PASSWD: &str = "root:x:0:0:root:/root:/bin/sh\nnobody:x:65534:65534:nobody:/:/bin/false\n";
+const GROUP: &str = "root:x:0:\nnobody:x:65534:\n";
+
+/// Library directories searched after the `RUNPATH` of a program and `/etc/ld.so.conf`
+const LIB_DIRS: [&str; 4] = ["/lib64", "/usr/lib64", "/lib", "/usr/lib"];
+
+/// Create a rootfs at `dest` able to run `binaries`, absolute paths of programs on the host
+///
+/// The standard directories are created, with `bin` a symlink to `usr/bin`, along with
+/// a minimal `/etc/passwd` and `/etc/group` for root and nobody. Each binary is copied
+/// to the same path inside `dest`, along with its dynamic loader and the shared libraries
+/// it needs, recursively. Symlinks on the way, such as `libc.so.6 -> libc-2.31.so` or
+/// `/lib -> usr/lib`, are recreated too.
+///
+/// Libraries are looked up like the glibc and musl loaders do, though without
+/// `/etc/ld.so.cache`. Returns the host files copied, in order.
+pub fn from_host_binaries(
+    dest: impl AsRef<Path>,
+    binaries: &[impl AsRef<Path>],
+) -> Result<Vec<PathBuf>> {
+    let dest = dest.as_ref();
+    skeleton(dest)?;
+    let search = SearchPath::host();
+
+    let mut copied = Vec::new();
+    let mut seen = HashSet::new();
+    // paths to copy, and whether they are dependencies rather than one of `binaries`
+    let mut pending: Vec<(PathBuf, bool)> = binaries
+        .iter()
+        .rev()
+        .map(|binary| (binary.as_ref().to_path_buf(), false))
+        .collect();
+    while let Some((path, dependency)) = pending.pop() {
+        if !path.is_absolute() {
+            return Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} is not an absolute path", path.display()),
+            )));
+        }
+        std::fs::metadata(&path)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {e}", path.display())))?;
+        let path = normalize(path)?;
+        if !seen.insert(path.clone()) {
+            continue;
+        }
+        mirror(dest, &path, &mut copied)?;
+
+        let elf = match Elf::read(&path) {
+            Ok(elf) => elf,
+            // scripts only need their interpreter, which has to be listed as well
+            Err(_) if !dependency => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(interpreter) = &elf.interpreter {
+            pending.push((interpreter.clone(), true));
+        }
+        let origin = path.parent().unwrap_or(Path::new("/"));
+        for library in elf.needed.iter().rev() {
+            let found =
+                search
+                    .find(library, &elf, origin)
+                    .ok_or_else(|| Error::MissingLibrary {
+                        library: library.clone(),
+                        needed_by: path.clone(),
+                    })?;
+            pending.push((found, true));
+        }
+    }
+    Ok(copied)
+}
+
+fn skeleton(dest: &Path) -> Result<()> {
+    for (dir, mode) in SKELETON {
+        let dir = dest.join(dir);
+        std::fs::create_dir_all(&dir)?;
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(mode))?;
+    }
+    if std::fs::symlink_metadata(dest.join("bin")).is_err() {
+        std::os::unix::fs::symlink("usr/bin", dest.join("bin"))?;
+    }
+    for (file, contents) in [("etc/passwd", PASSWD), ("etc/group", GROUP)] {
+        if !dest.join(file).exists() {
+            std::fs::write(dest.join(file), contents)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copy the host file at the absolute `path` to the same path in `dest`,
+/// recreating the symlinks found on the way
+fn mirror(dest: &Path, path: &Path, copied: &mut Vec<PathBuf>) -> Result<()> {
+    let mut current = PathBuf::from("/");
+    let mut components = path.components().filter_map(|c| match c {
+        Component::Normal(name) => Some(name),
+        _ => None,
+    });
+    while let Some(name) = components.next() {
+        current.push(name);
+        let inside = dest.join(current.strip_prefix("/").expect("absolute"));
+        let metadata = std::fs::symlink_metadata(&current)?;
+        if metadata.is_symlink() {
+            if std::fs::symlink_metadata(&inside).is_err() {
+                std::os::unix::fs::symlink(std::fs::read_link(&current)?, &inside)?;
+            }
+            // go on from where the symlink points
+            let mut target = std::fs::canonicalize(&current)?;
+            target.extend(components);
+            return mirror(dest, &target, copied);
+        }
+        if metadata.is_dir() {
+            if !inside.exists() {
+                std::fs::create_dir(&inside)?;
+                std::fs::set_permissions(&inside, metadata.permissions())?;
+            }
+        } else if !inside.exists() {
+            std::fs::copy(&current, &inside)?;
+            copied.push(current.clone());
+        }
+    }
+    Ok(())
+}
+
+/// Resolve `..` in `path`, as in a `RUNPATH` of `$ORIGIN/../lib`, keeping the file name
+/// so a symlink there is still mirrored
+fn normalize(path: PathBuf) -> Result<PathBuf> {
+    if !path.components().any(|c| c == Component::ParentDir) {
+        return Ok(path);
+    }
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => Ok(std::fs::canonicalize(parent)?.join(name)),
+        _ => Ok(std::fs::canonicalize(&path)?),
+    }
+}
+
+/// Where the dynamic loader looks for libraries
+#[derive(Debug, Clone)]
+struct SearchPath {
+    dirs: Vec<PathBuf>,
+}
+
+impl SearchPath {
+    fn host() -> Self {
+        let mut dirs = Vec::new();
+        ld_so_conf(Path::new("/etc/ld.so.conf"), &mut dirs);
+        // musl reads /etc/ld-musl-<arch>.path instead
+        if let Ok(entries) = std::fs::read_dir("/etc") {
+            for entry in entries.flatten() {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                if name.starts_with("ld-musl-") && name.ends_with(".path") {
+                    let contents = std::fs::read_to_string(entry.path()).unwrap_or_default();
+                    dirs.extend(
+                        contents
+                            .split(|c: char| c == ':' || c.is_whitespace())
+                            .filter(|dir| !dir.is_empty())
+                            .map(PathBuf::from),
+                    );
+                }
+            }
+        }
+        dirs.extend(LIB_DIRS.map(PathBuf::from));
+        Self { dirs }
+    }
+
+    /// Find `library` for the program `elf` in `origin`
+    fn find(&self, library: &str, elf: &Elf, origin: &Path) -> Option<PathBuf> {
+        if library.contains('/') {
+            return Some(origin.join(library));
+        }
+        let runpath = elf
+            .runpath
+            .iter()
+            .map(|dir| PathBuf::from(dir.replace("$ORIGIN", &origin.to_string_lossy())));
+        runpath
+            .chain(self.dirs.iter().cloned())
+            .map(|dir| dir.join(library))
+            .find(|candidate| {
+                Elf::read(candidate).is_ok_and(|candidate| elf.is_compatible(&candidate))
+            })
+    }
+}
+
+/// Read the directories of an `ld.so.conf` file, following its includes
+fn ld_so_conf(path: &Path, dirs: &mut Vec<PathBuf>) {
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return;
+    };
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if let Some(pattern) = line.strip_prefix("include") {
+            for file in glob(Path::new(pattern.trim()), path) {
+                ld_so_conf(&file, dirs);
+            }
+        } else if line.starts_with('/') {
+            dirs.push(PathBuf::from(line));
+        }
+    }
+}
+
+/// Expand a `*` in the file name of `pattern`, relative to the directory of `conf`
+fn glob(pattern: &Path, conf: &Path) -> Vec<PathBuf> {
+    let pattern = conf.parent().unwrap_or(Path::new("/")).join(pattern);
+    let (Some(dir), Some(name)) = (pattern.parent(), pattern.file_name()) else {
+        return Vec::new();
+    };
+    let name = name.to_string_lossy();
+    let Some((prefix, suffix)) = name.split_once('*') else {
+        return vec![pattern.clone()];
+    };
+    let mut files: Vec<_> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy())
+                .is_some_and(|name| name.starts_with(prefix) && name.ends_with(suffix))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::tempdir::TempDir;
+
+    #[test]
+    fn test_ld_so_conf() {
+        let dir = TempDir::new("ld-so-conf");
+        std::fs::create_dir_all(dir.join("ld.so.conf.d")).unwrap();
+        std::fs::write(
+            dir.join("ld.so.conf"),
+            "# comment\ninclude ld.so.conf.d/*.conf\n/opt/lib # trailing\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("ld.so.conf.d/b.conf"), "/b\n").unwrap();
+        std::fs::write(dir.join("ld.so.conf.d/a.conf"), "/a\n").unwrap();
+        std::fs::write(dir.join("ld.so.conf.d/ignored"), "/ignored\n").unwrap();
+
+        let mut dirs = Vec::new();
+        ld_so_conf(&dir.join("ld.so.conf"), &mut dirs);
+        assert_eq!(dirs, ["/a", "/b", "/opt/lib"].map(PathBuf::from));
+    }
+
+    #[test]
+    fn test_from_host_binaries() {
+        let dest = TempDir::new("rootfs");
+        let copied = from_host_binaries(&dest, &["/bin/sh"]).unwrap();
+        assert!(dest.join("bin/sh").exists());
+        assert_eq!(
+            std::fs::read_link(dest.join("bin")).unwrap(),
+            Path::new("usr/bin")
+        );
+        assert_eq!(
+            std::fs::read_to_string(dest.join("etc/passwd")).unwrap(),
+            PASSWD
+        );
+
+        let sh = Elf::read(Path::new("/bin/sh")).unwrap();
+        if let Some(interpreter) = sh.interpreter {
+            // the loader is found even through symlinks such as /lib64 -> usr/lib64
+            assert!(dest.join(interpreter.strip_prefix("/").unwrap()).exists());
+            assert!(copied.len() > 2);
+        }
+        for path in &copied {
+            let inside = dest.join(path.strip_prefix("/").unwrap());
+            assert!(inside.is_file(), "{inside:?}");
+        }
+
+        for missing in ["/nonexistent/tiffin", "sh"] {
+            assert!(matches!(
+                from_host_binaries(&dest, &[missing]),
+                Err(Error::Io(_))
+            ));
+        }
+    }
+}
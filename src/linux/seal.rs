@@ -0,0 +1,136 @@
+//! Leaving no way out of the chroot to forked payloads, see [`crate::Container::enter_sealed`]
+
+use nix::{
+    fcntl::{fcntl, FcntlArg, FdFlag},
+    sys::stat::fstat,
+};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+/// `CLOSE_RANGE_CLOEXEC` from `linux/close_range.h`
+const CLOSE_RANGE_CLOEXEC: libc::c_uint = 1 << 2;
+
+/// How [`crate::Container::enter_sealed`] seals forked payloads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SealOptions {
+    /// Drop `CAP_SYS_CHROOT` once inside, so the payload can't chroot again to get
+    /// its working directory outside its root. Defaults to true
+    pub drop_chroot: bool,
+}
+
+impl Default for SealOptions {
+    fn default() -> Self {
+        Self { drop_chroot: true }
+    }
+}
+
+/// Close `escape_fds` and every other inherited directory descriptor, which could be
+/// used to get back out of the chroot, and mark the remaining descriptors above stdio
+/// close-on-exec
+///
+/// Only ever call this in a forked child, before entering the container. Only raw
+/// syscalls are made, so it's safe in a `pre_exec` hook.
+pub(crate) fn seal_fds(escape_fds: &[RawFd]) -> std::io::Result<()> {
+    for fd in escape_fds {
+        match nix::unistd::close(*fd) {
+            Ok(()) | Err(nix::errno::Errno::EBADF) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    // SAFETY: close_range only changes flags of descriptors, whatever its arguments
+    let cloexec = unsafe {
+        libc::syscall(
+            libc::SYS_close_range,
+            3 as libc::c_uint,
+            libc::c_uint::MAX,
+            CLOSE_RANGE_CLOEXEC,
+        )
+    } == 0;
+
+    // listing them needs the host's /proc, which may not be mounted when nested,
+    // the parent warns about that before forking
+    // SAFETY: the path is a valid C string, and the descriptor is owned right after
+    let dir = unsafe {
+        libc::open(
+            c"/proc/self/fd".as_ptr(),
+            libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+        )
+    };
+    if dir < 0 {
+        return Ok(());
+    }
+    // SAFETY: the descriptor was just opened and is owned by nobody else
+    let dir = unsafe { OwnedFd::from_raw_fd(dir) };
+
+    let mut buf = [0u8; 4096];
+    loop {
+        // SAFETY: buf is a valid buffer of its length
+        let len = unsafe {
+            libc::syscall(
+                libc::SYS_getdents64,
+                dir.as_raw_fd(),
+                buf.as_mut_ptr(),
+                buf.len(),
+            )
+        };
+        if len < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if len == 0 {
+            return Ok(());
+        }
+        let mut offset = 0;
+        while offset < len as usize {
+            let entry = &buf[offset..len as usize];
+            let reclen = u16::from_ne_bytes([entry[DIRENT_RECLEN], entry[DIRENT_RECLEN + 1]]);
+            offset += reclen as usize;
+            let Some(fd) = parse_fd(&entry[DIRENT_NAME..reclen as usize]) else {
+                continue;
+            };
+            if fd <= 2 || fd == dir.as_raw_fd() {
+                continue;
+            }
+            match fstat(fd) {
+                Ok(stat) if stat.st_mode & libc::S_IFMT == libc::S_IFDIR => {
+                    nix::unistd::close(fd)?;
+                }
+                // before Linux 5.11
+                Ok(_) if !cloexec => {
+                    fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Offsets into `struct linux_dirent64`, as filled by `getdents64`
+const DIRENT_RECLEN: usize = 16;
+const DIRENT_NAME: usize = 19;
+
+/// The descriptor named by a NUL-terminated entry of `/proc/self/fd`, without allocating
+fn parse_fd(name: &[u8]) -> Option<RawFd> {
+    let name = name.split(|byte| *byte == 0).next()?;
+    if name.is_empty() {
+        return None;
+    }
+    name.iter().try_fold(0 as RawFd, |fd, byte| {
+        let digit = byte.checked_sub(b'0').filter(|digit| *digit < 10)?;
+        fd.checked_mul(10)?.checked_add(RawFd::from(digit))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fd() {
+        assert_eq!(parse_fd(b"42\0\0\0"), Some(42));
+        assert_eq!(parse_fd(b"0\0"), Some(0));
+        assert_eq!(parse_fd(b".\0"), None);
+        assert_eq!(parse_fd(b"..\0"), None);
+        assert_eq!(parse_fd(b"\0"), None);
+        assert_eq!(parse_fd(b"99999999999\0"), None);
+    }
+}
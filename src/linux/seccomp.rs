@@ -0,0 +1,490 @@
+use crate::{Error, Result};
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+compile_error!("the seccomp feature only supports x86_64 and aarch64");
+
+/// Syscalls that can be named in a [`SeccompPolicy`], resolved for the target architecture
+const SYSCALLS: &[(&str, libc::c_long)] = &[
+    ("read", libc::SYS_read),
+    ("write", libc::SYS_write),
+    ("openat", libc::SYS_openat),
+    ("close", libc::SYS_close),
+    ("fstat", libc::SYS_fstat),
+    ("lseek", libc::SYS_lseek),
+    ("mmap", libc::SYS_mmap),
+    ("mprotect", libc::SYS_mprotect),
+    ("munmap", libc::SYS_munmap),
+    ("brk", libc::SYS_brk),
+    ("rt_sigaction", libc::SYS_rt_sigaction),
+    ("rt_sigprocmask", libc::SYS_rt_sigprocmask),
+    ("rt_sigreturn", libc::SYS_rt_sigreturn),
+    ("ioctl", libc::SYS_ioctl),
+    ("pread64", libc::SYS_pread64),
+    ("pwrite64", libc::SYS_pwrite64),
+    ("readv", libc::SYS_readv),
+    ("writev", libc::SYS_writev),
+    ("sched_yield", libc::SYS_sched_yield),
+    ("mremap", libc::SYS_mremap),
+    ("madvise", libc::SYS_madvise),
+    ("dup", libc::SYS_dup),
+    ("dup3", libc::SYS_dup3),
+    ("nanosleep", libc::SYS_nanosleep),
+    ("getpid", libc::SYS_getpid),
+    ("socket", libc::SYS_socket),
+    ("connect", libc::SYS_connect),
+    ("accept", libc::SYS_accept),
+    ("sendto", libc::SYS_sendto),
+    ("recvfrom", libc::SYS_recvfrom),
+    ("sendmsg", libc::SYS_sendmsg),
+    ("recvmsg", libc::SYS_recvmsg),
+    ("bind", libc::SYS_bind),
+    ("listen", libc::SYS_listen),
+    ("clone", libc::SYS_clone),
+    ("clone3", libc::SYS_clone3),
+    ("execve", libc::SYS_execve),
+    ("exit", libc::SYS_exit),
+    ("exit_group", libc::SYS_exit_group),
+    ("wait4", libc::SYS_wait4),
+    ("kill", libc::SYS_kill),
+    ("uname", libc::SYS_uname),
+    ("fcntl", libc::SYS_fcntl),
+    ("flock", libc::SYS_flock),
+    ("fsync", libc::SYS_fsync),
+    ("ftruncate", libc::SYS_ftruncate),
+    ("getcwd", libc::SYS_getcwd),
+    ("chdir", libc::SYS_chdir),
+    ("fchdir", libc::SYS_fchdir),
+    ("fchmod", libc::SYS_fchmod),
+    ("fchown", libc::SYS_fchown),
+    ("umask", libc::SYS_umask),
+    ("getuid", libc::SYS_getuid),
+    ("getgid", libc::SYS_getgid),
+    ("geteuid", libc::SYS_geteuid),
+    ("getegid", libc::SYS_getegid),
+    ("setuid", libc::SYS_setuid),
+    ("setgid", libc::SYS_setgid),
+    ("setgroups", libc::SYS_setgroups),
+    ("setsid", libc::SYS_setsid),
+    ("capset", libc::SYS_capset),
+    ("prctl", libc::SYS_prctl),
+    ("ptrace", libc::SYS_ptrace),
+    ("chroot", libc::SYS_chroot),
+    ("pivot_root", libc::SYS_pivot_root),
+    ("mount", libc::SYS_mount),
+    ("umount2", libc::SYS_umount2),
+    ("open_tree", libc::SYS_open_tree),
+    ("move_mount", libc::SYS_move_mount),
+    ("fsopen", libc::SYS_fsopen),
+    ("fsconfig", libc::SYS_fsconfig),
+    ("fsmount", libc::SYS_fsmount),
+    ("fspick", libc::SYS_fspick),
+    ("mount_setattr", libc::SYS_mount_setattr),
+    ("swapon", libc::SYS_swapon),
+    ("swapoff", libc::SYS_swapoff),
+    ("reboot", libc::SYS_reboot),
+    ("sethostname", libc::SYS_sethostname),
+    ("setdomainname", libc::SYS_setdomainname),
+    ("init_module", libc::SYS_init_module),
+    ("finit_module", libc::SYS_finit_module),
+    ("delete_module", libc::SYS_delete_module),
+    ("kexec_load", libc::SYS_kexec_load),
+    ("unshare", libc::SYS_unshare),
+    ("setns", libc::SYS_setns),
+    ("futex", libc::SYS_futex),
+    ("set_tid_address", libc::SYS_set_tid_address),
+    ("set_robust_list", libc::SYS_set_robust_list),
+    ("clock_gettime", libc::SYS_clock_gettime),
+    ("clock_nanosleep", libc::SYS_clock_nanosleep),
+    ("tgkill", libc::SYS_tgkill),
+    ("mkdirat", libc::SYS_mkdirat),
+    ("mknodat", libc::SYS_mknodat),
+    ("fchownat", libc::SYS_fchownat),
+    ("unlinkat", libc::SYS_unlinkat),
+    ("renameat2", libc::SYS_renameat2),
+    ("linkat", libc::SYS_linkat),
+    ("symlinkat", libc::SYS_symlinkat),
+    ("readlinkat", libc::SYS_readlinkat),
+    ("fchmodat", libc::SYS_fchmodat),
+    ("faccessat", libc::SYS_faccessat),
+    ("pselect6", libc::SYS_pselect6),
+    ("ppoll", libc::SYS_ppoll),
+    ("epoll_ctl", libc::SYS_epoll_ctl),
+    ("epoll_pwait", libc::SYS_epoll_pwait),
+    ("epoll_create1", libc::SYS_epoll_create1),
+    ("eventfd2", libc::SYS_eventfd2),
+    ("pipe2", libc::SYS_pipe2),
+    ("getdents64", libc::SYS_getdents64),
+    ("prlimit64", libc::SYS_prlimit64),
+    ("getrandom", libc::SYS_getrandom),
+    ("memfd_create", libc::SYS_memfd_create),
+    ("bpf", libc::SYS_bpf),
+    ("execveat", libc::SYS_execveat),
+    ("userfaultfd", libc::SYS_userfaultfd),
+    ("statx", libc::SYS_statx),
+    ("seccomp", libc::SYS_seccomp),
+];
+
+/// Legacy syscalls only available on some architectures
+#[cfg(target_arch = "x86_64")]
+const ARCH_SYSCALLS: &[(&str, libc::c_long)] = &[
+    ("open", libc::SYS_open),
+    ("stat", libc::SYS_stat),
+    ("lstat", libc::SYS_lstat),
+    ("access", libc::SYS_access),
+    ("pipe", libc::SYS_pipe),
+    ("poll", libc::SYS_poll),
+    ("fork", libc::SYS_fork),
+    ("vfork", libc::SYS_vfork),
+    ("mkdir", libc::SYS_mkdir),
+    ("rmdir", libc::SYS_rmdir),
+    ("unlink", libc::SYS_unlink),
+    ("rename", libc::SYS_rename),
+    ("readlink", libc::SYS_readlink),
+    ("arch_prctl", libc::SYS_arch_prctl),
+    ("iopl", libc::SYS_iopl),
+    ("ioperm", libc::SYS_ioperm),
+];
+#[cfg(not(target_arch = "x86_64"))]
+const ARCH_SYSCALLS: &[(&str, libc::c_long)] = &[];
+
+/// Resolve a syscall name to its number on the target architecture
+pub fn syscall_number(name: &str) -> Option<libc::c_long> {
+    SYSCALLS
+        .iter()
+        .chain(ARCH_SYSCALLS)
+        .find(|(syscall, _)| *syscall == name)
+        .map(|(_, nr)| *nr)
+}
+
+/// Whether a [`SeccompPolicy`] lists the syscalls to block or the syscalls to permit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeccompMode {
+    /// Block the listed syscalls, allow everything else
+    Deny,
+    /// Allow the listed syscalls, block everything else
+    Allow,
+}
+
+/// Flags of `clone` creating namespaces, see [`SeccompPolicy::deny_new_namespaces`]
+const CLONE_NEW_FLAGS: libc::c_int = libc::CLONE_NEWNS
+    | libc::CLONE_NEWCGROUP
+    | libc::CLONE_NEWUTS
+    | libc::CLONE_NEWIPC
+    | libc::CLONE_NEWUSER
+    | libc::CLONE_NEWPID
+    | libc::CLONE_NEWNET
+    | libc::CLONE_NEWTIME;
+
+/// A seccomp-bpf filter installed in the forked child before running the payload
+///
+/// Blocked syscalls fail with `errno` (EPERM by default) instead of killing the process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeccompPolicy {
+    mode: SeccompMode,
+    syscalls: Vec<libc::c_long>,
+    errno: i32,
+    deny_new_namespaces: bool,
+}
+
+impl SeccompPolicy {
+    /// Create a policy for the given syscall names
+    ///
+    /// Returns [`Error::UnknownSyscall`] if a name can't be resolved on this architecture.
+    pub fn new<I, S>(mode: SeccompMode, syscalls: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let syscalls = syscalls
+            .into_iter()
+            .map(|name| {
+                let name = name.as_ref();
+                syscall_number(name).ok_or_else(|| Error::UnknownSyscall(name.to_string()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            mode,
+            syscalls,
+            errno: libc::EPERM,
+            deny_new_namespaces: false,
+        })
+    }
+
+    /// Block the given syscalls, allow everything else
+    pub fn deny<I, S>(syscalls: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self::new(SeccompMode::Deny, syscalls)
+    }
+
+    /// Allow the given syscalls, block everything else
+    pub fn allow<I, S>(syscalls: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self::new(SeccompMode::Allow, syscalls)
+    }
+
+    /// Preset blocking syscalls that could be used to modify the mount table
+    /// or otherwise break out of the container
+    ///
+    /// This includes the new mount API, and creating namespaces with `unshare` or `clone`,
+    /// see [`SeccompPolicy::deny_new_namespaces`].
+    pub fn deny_escapes() -> Self {
+        Self::deny([
+            "mount",
+            "umount2",
+            "open_tree",
+            "move_mount",
+            "fsopen",
+            "fsconfig",
+            "fsmount",
+            "fspick",
+            "mount_setattr",
+            "pivot_root",
+            "chroot",
+            "ptrace",
+            "kexec_load",
+            "init_module",
+            "finit_module",
+            "delete_module",
+            "unshare",
+            "setns",
+        ])
+        .expect("preset syscalls are available on every supported architecture")
+        .deny_new_namespaces()
+    }
+
+    /// Also block `clone` creating namespaces, even if the policy allows `clone`
+    ///
+    /// The flags of `clone3` are behind a pointer that seccomp can't follow, so it fails
+    /// with ENOSYS instead, which makes libc fall back to `clone`. This doesn't cover
+    /// `unshare` and `setns`, which are denied by name.
+    pub fn deny_new_namespaces(mut self) -> Self {
+        self.deny_new_namespaces = true;
+        self
+    }
+
+    /// Sets the errno returned by blocked syscalls
+    pub fn errno(mut self, errno: i32) -> Self {
+        self.errno = errno;
+        self
+    }
+
+    /// Build the BPF program for this policy
+    fn program(&self) -> Vec<SockFilter> {
+        let (matched, default) = match self.mode {
+            SeccompMode::Deny => (self.ret_errno(), SECCOMP_RET_ALLOW),
+            SeccompMode::Allow => (SECCOMP_RET_ALLOW, self.ret_errno()),
+        };
+
+        let mut program = vec![
+            // Kill anything running under a different syscall ABI, its numbers would mean something else
+            SockFilter::stmt(BPF_LD | BPF_W | BPF_ABS, SECCOMP_DATA_ARCH),
+            SockFilter::jump(BPF_JMP | BPF_JEQ | BPF_K, AUDIT_ARCH, 1, 0),
+            SockFilter::stmt(BPF_RET | BPF_K, SECCOMP_RET_KILL_PROCESS),
+            SockFilter::stmt(BPF_LD | BPF_W | BPF_ABS, SECCOMP_DATA_NR),
+        ];
+        // x32 syscalls pass the arch check with their own numbers, which have this bit set
+        #[cfg(target_arch = "x86_64")]
+        program.extend([
+            SockFilter::jump(BPF_JMP | BPF_JGE | BPF_K, X32_SYSCALL_BIT, 0, 1),
+            SockFilter::stmt(BPF_RET | BPF_K, SECCOMP_RET_KILL_PROCESS),
+        ]);
+        if self.deny_new_namespaces {
+            program.extend([
+                SockFilter::jump(BPF_JMP | BPF_JEQ | BPF_K, libc::SYS_clone3 as u32, 0, 1),
+                SockFilter::stmt(BPF_RET | BPF_K, SECCOMP_RET_ERRNO | libc::ENOSYS as u32),
+                SockFilter::jump(BPF_JMP | BPF_JEQ | BPF_K, libc::SYS_clone as u32, 0, 3),
+                // the flags are the first argument on every supported architecture,
+                // and all namespace flags are in its lower half
+                SockFilter::stmt(BPF_LD | BPF_W | BPF_ABS, SECCOMP_DATA_ARGS),
+                SockFilter::jump(BPF_JMP | BPF_JSET | BPF_K, CLONE_NEW_FLAGS as u32, 0, 1),
+                SockFilter::stmt(BPF_RET | BPF_K, self.ret_errno()),
+                SockFilter::stmt(BPF_LD | BPF_W | BPF_ABS, SECCOMP_DATA_NR),
+            ]);
+        }
+        for nr in &self.syscalls {
+            program.push(SockFilter::jump(
+                BPF_JMP | BPF_JEQ | BPF_K,
+                *nr as u32,
+                0,
+                1,
+            ));
+            program.push(SockFilter::stmt(BPF_RET | BPF_K, matched));
+        }
+        program.push(SockFilter::stmt(BPF_RET | BPF_K, default));
+        program
+    }
+
+    /// The action failing a syscall with `errno`, which only has room for 16 bits
+    fn ret_errno(&self) -> u32 {
+        SECCOMP_RET_ERRNO | (self.errno as u32 & SECCOMP_RET_DATA)
+    }
+
+    /// The compiled filter, to be installed in a forked child
+    pub(crate) fn filter(&self) -> SeccompFilter {
+        SeccompFilter(self.program())
+    }
+}
+
+/// The BPF program of a [`SeccompPolicy`], built before forking so installing it
+/// doesn't allocate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SeccompFilter(Vec<SockFilter>);
+
+impl SeccompFilter {
+    /// Install the filter in the current process
+    ///
+    /// This is irreversible and also sets `PR_SET_NO_NEW_PRIVS`,
+    /// so it should only ever be called in a forked child.
+    pub(crate) fn apply(&self) -> std::io::Result<()> {
+        let prog = SockFprog {
+            len: self.0.len() as u16,
+            filter: self.0.as_ptr(),
+        };
+
+        // SAFETY: prctl with integer arguments only
+        if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        // SAFETY: prog points to a valid filter program which outlives the call
+        let ret = unsafe {
+            libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &prog as *const SockFprog,
+            )
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+const BPF_LD: u16 = 0x00;
+const BPF_W: u16 = 0x00;
+const BPF_ABS: u16 = 0x20;
+const BPF_JMP: u16 = 0x05;
+const BPF_JEQ: u16 = 0x10;
+const BPF_JGE: u16 = 0x30;
+const BPF_JSET: u16 = 0x40;
+const BPF_K: u16 = 0x00;
+const BPF_RET: u16 = 0x06;
+
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+const SECCOMP_RET_DATA: u32 = 0x0000_ffff;
+
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// Offsets into `struct seccomp_data`
+const SECCOMP_DATA_NR: u32 = 0;
+const SECCOMP_DATA_ARCH: u32 = 4;
+/// The lower half of the first argument, on little endian architectures
+const SECCOMP_DATA_ARGS: u32 = 16;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SockFilter {
+    code: u16,
+    jt: u8,
+    jf: u8,
+    k: u32,
+}
+
+impl SockFilter {
+    const fn stmt(code: u16, k: u32) -> Self {
+        Self {
+            code,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    const fn jump(code: u16, k: u32, jt: u8, jf: u8) -> Self {
+        Self { code, jt, jf, k }
+    }
+}
+
+#[repr(C)]
+struct SockFprog {
+    len: u16,
+    filter: *const SockFilter,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_syscall() {
+        match SeccompPolicy::deny(["mount", "not_a_syscall"]) {
+            Err(Error::UnknownSyscall(name)) => assert_eq!(name, "not_a_syscall"),
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_program() {
+        let policy = SeccompPolicy::deny(["mount", "umount2"]).unwrap();
+        let program = policy.program();
+        // arch check, nr load, the x32 check, two instructions per syscall, default action
+        let header = if cfg!(target_arch = "x86_64") { 6 } else { 4 };
+        assert_eq!(program.len(), header + 2 * 2 + 1);
+        if cfg!(target_arch = "x86_64") {
+            assert_eq!(program[4].code, BPF_JMP | BPF_JGE | BPF_K);
+            assert_eq!(program[5].k, SECCOMP_RET_KILL_PROCESS);
+        }
+        assert_eq!(program[header].k, libc::SYS_mount as u32);
+        assert_eq!(
+            program[header + 1].k,
+            SECCOMP_RET_ERRNO | libc::EPERM as u32
+        );
+        assert_eq!(program.last().unwrap().k, SECCOMP_RET_ALLOW);
+
+        // errno can't spill into the action
+        let program = SeccompPolicy::allow(["read"]).unwrap().errno(-1).program();
+        assert_eq!(program.last().unwrap().k, SECCOMP_RET_ERRNO | 0xffff);
+    }
+
+    #[test]
+    fn test_deny_new_namespaces() {
+        for name in [
+            "clone3",
+            "open_tree",
+            "move_mount",
+            "fsmount",
+            "mount_setattr",
+        ] {
+            assert!(syscall_number(name).is_some(), "{name}");
+        }
+        let policy = SeccompPolicy::deny_escapes();
+        assert!(policy.syscalls.contains(&libc::SYS_fsopen));
+        let program = policy.program();
+        let header = if cfg!(target_arch = "x86_64") { 6 } else { 4 };
+        assert_eq!(program[header].k, libc::SYS_clone3 as u32);
+        assert_eq!(
+            program[header + 1].k,
+            SECCOMP_RET_ERRNO | libc::ENOSYS as u32
+        );
+        assert_eq!(program[header + 2].k, libc::SYS_clone as u32);
+        assert_eq!(program[header + 4].code, BPF_JMP | BPF_JSET | BPF_K);
+        assert_ne!(program[header + 4].k & libc::CLONE_NEWUSER as u32, 0);
+        // the syscall number is loaded again for the rest of the program
+        assert_eq!(program[header + 6].k, SECCOMP_DATA_NR);
+        assert_eq!(program.len(), header + 7 + 2 * policy.syscalls.len() + 1);
+    }
+}
@@ -0,0 +1,155 @@
+//! SELinux labels of mounted filesystems, see [`SelinuxContext`]
+
+use crate::{Error, MountTarget, Result};
+use std::{fmt, path::Path, str::FromStr};
+
+/// Mount options taking a context, see [`MountTarget::context`]
+pub(crate) const CONTEXT_OPTIONS: [&str; 4] = ["context", "fscontext", "defcontext", "rootcontext"];
+
+/// An SELinux security context, `user:role:type:level`
+///
+/// The level is optional, for policies without MLS. It may be a range with categories,
+/// such as `s0-s0:c0.c1023` or `s0:c1,c2`; commas are quoted when the context is
+/// passed to the kernel as a mount option.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SelinuxContext(String);
+
+impl SelinuxContext {
+    /// Validate `context`, see [`SelinuxContext`]
+    pub fn new(context: impl Into<String>) -> Result<Self> {
+        let context = context.into();
+        let invalid = |reason: &'static str| Error::InvalidSelinuxContext {
+            context: context.clone(),
+            reason,
+        };
+        let parts: Vec<_> = context.splitn(4, ':').collect();
+        if parts.len() < 3 {
+            return Err(invalid("expected user:role:type:level"));
+        }
+        let is_name = |part: &&str| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+        };
+        if !parts[..3].iter().all(is_name) {
+            return Err(invalid(
+                "user, role and type can't be empty, and can only contain letters, digits, `_`, `.` and `-`",
+            ));
+        }
+        if let Some(level) = parts.get(3) {
+            let is_level = level
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, ':' | ',' | '.' | '-'));
+            if level.is_empty() || !is_level {
+                return Err(invalid(
+                    "the level can't be empty, and can only contain letters, digits, `:`, `,`, `.` and `-`",
+                ));
+            }
+        }
+        Ok(Self(context))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for SelinuxContext {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::new(s)
+    }
+}
+
+impl fmt::Display for SelinuxContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Whether SELinux is enabled on the host, i.e. selinuxfs is mounted at `/sys/fs/selinux`
+pub(crate) fn enabled() -> bool {
+    Path::new("/sys/fs/selinux/enforce").exists()
+}
+
+/// Whether `mount` has any of the context options
+pub(crate) fn has_context(mount: &MountTarget) -> bool {
+    mount
+        .data_options
+        .as_ref()
+        .is_some_and(|options| CONTEXT_OPTIONS.iter().any(|key| options.get(key).is_some()))
+}
+
+/// Remove the context options of `mount`
+pub(crate) fn strip_context(mount: &mut MountTarget) {
+    if let Some(options) = &mut mount.data_options {
+        for key in CONTEXT_OPTIONS {
+            options.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MountOptions;
+
+    #[test]
+    fn test_valid() {
+        for context in [
+            "system_u:object_r:tmp_t:s0",
+            "system_u:object_r:container_file_t:s0:c1,c2",
+            "unconfined_u:unconfined_r:unconfined_t:s0-s0:c0.c1023",
+            "user_u:object_r:user_home_t",
+        ] {
+            assert_eq!(SelinuxContext::new(context).unwrap().as_str(), context);
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        for context in [
+            "",
+            "system_u",
+            "system_u:object_r",
+            "system_u::tmp_t:s0",
+            "system_u:object_r:tmp_t:",
+            "system_u:object_r:tmp t:s0",
+            "system_u:object_r:tmp_t:s0,\"c1\"",
+            "system_u:object_r:tmp_t:s0\n",
+        ] {
+            assert!(
+                matches!(
+                    context.parse::<SelinuxContext>(),
+                    Err(Error::InvalidSelinuxContext { .. })
+                ),
+                "{context:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_quoting() {
+        let mut mount = MountTarget::default();
+        mount
+            .context(
+                "system_u:object_r:container_file_t:s0:c1,c2"
+                    .parse()
+                    .unwrap(),
+            )
+            .rootcontext("system_u:object_r:tmp_t:s0".parse().unwrap());
+        let options = mount.data_options.clone().unwrap();
+        assert_eq!(
+            options.render(),
+            "context=\"system_u:object_r:container_file_t:s0:c1,c2\",rootcontext=system_u:object_r:tmp_t:s0"
+        );
+        assert_eq!(MountOptions::parse(&options.render()).unwrap(), options);
+
+        assert!(has_context(&mount));
+        strip_context(&mut mount);
+        assert!(!has_context(&mount));
+        assert!(mount.data_options.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+//! Saving and restoring the configuration of a mount table,
+//! see [`crate::MountTable::snapshot`]
+
+use super::{MountLock, MountOptions, MountTarget, MountpointOptions};
+use crate::{Error, Result};
+use nix::unistd::{Gid, Uid};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use sys_mount::MountFlags;
+
+/// The configured mounts of a [`crate::MountTable`] at some point,
+/// to go back to with [`crate::MountTable::restore`]
+///
+/// Only the configuration is kept, not what is mounted. It can be saved as JSON
+/// and restored by another process.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "Vec<Entry>", try_from = "Vec<Entry>")]
+pub struct MountTableSnapshot {
+    /// Sources and mounts, in mount order
+    pub(crate) entries: Vec<(PathBuf, MountTarget)>,
+}
+
+impl MountTableSnapshot {
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Write the snapshot to a JSON file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+
+    /// Read a snapshot from a JSON file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+}
+
+/// A mount as saved, with flags as their bits and options as the data they render to
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    source: PathBuf,
+    target: PathBuf,
+    fstype: Option<String>,
+    flags: u64,
+    data: Option<String>,
+    /// Flags and data of [`MountTarget::data_options`]
+    data_options: Option<(u64, String)>,
+    create: bool,
+    mode: Option<u32>,
+    owner: Option<(u32, u32)>,
+    tag: Option<String>,
+    /// Path and whether it is exclusive, of [`MountTarget::lock`]
+    lock: Option<(PathBuf, bool)>,
+    wait_for_source: Option<Duration>,
+}
+
+fn flags(bits: u64) -> MountFlags {
+    MountFlags::from_bits_truncate(bits as _)
+}
+
+impl From<MountTableSnapshot> for Vec<Entry> {
+    fn from(snapshot: MountTableSnapshot) -> Self {
+        snapshot
+            .entries
+            .into_iter()
+            .map(|(source, mount)| Entry {
+                source,
+                target: mount.target,
+                fstype: mount.fstype,
+                flags: mount.flags.bits(),
+                data: mount.data,
+                data_options: mount
+                    .data_options
+                    .map(|options| (options.flags.bits(), options.render())),
+                create: mount.mountpoint.create,
+                mode: mount.mountpoint.mode,
+                owner: mount
+                    .mountpoint
+                    .owner
+                    .map(|(uid, gid)| (uid.as_raw(), gid.as_raw())),
+                tag: mount.tag,
+                lock: mount.lock.map(|lock| (lock.path, lock.exclusive)),
+                wait_for_source: mount.wait_for_source,
+            })
+            .collect()
+    }
+}
+
+impl TryFrom<Vec<Entry>> for MountTableSnapshot {
+    type Error = Error;
+
+    fn try_from(entries: Vec<Entry>) -> Result<Self> {
+        let entries = entries
+            .into_iter()
+            .map(|entry| {
+                let data_options = match entry.data_options {
+                    Some((bits, data)) => {
+                        let mut options = MountOptions::parse(&data)?;
+                        options.flags = flags(bits);
+                        Some(options)
+                    }
+                    None => None,
+                };
+                let mount = MountTarget {
+                    target: entry.target,
+                    fstype: entry.fstype,
+                    flags: flags(entry.flags),
+                    data: entry.data,
+                    data_options,
+                    mountpoint: MountpointOptions {
+                        create: entry.create,
+                        mode: entry.mode,
+                        owner: entry
+                            .owner
+                            .map(|(uid, gid)| (Uid::from_raw(uid), Gid::from_raw(gid))),
+                    },
+                    tag: entry.tag,
+                    lock: entry
+                        .lock
+                        .map(|(path, exclusive)| MountLock { path, exclusive }),
+                    wait_for_source: entry.wait_for_source,
+                };
+                Ok((entry.source, mount))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { entries })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json() {
+        let mut options = MountOptions::parse("nodev,mode=1777,size=64m").unwrap();
+        options.flags |= MountFlags::NOSUID;
+        let snapshot = MountTableSnapshot {
+            entries: vec![
+                (
+                    "tmpfs".into(),
+                    MountTarget {
+                        target: "/tmp".into(),
+                        fstype: Some("tmpfs".to_string()),
+                        data_options: Some(options),
+                        ..MountTarget::default()
+                    }
+                    .tagged("payload"),
+                ),
+                (
+                    "/var/cache/dnf".into(),
+                    MountTarget {
+                        target: "/var/cache/dnf".into(),
+                        flags: MountFlags::BIND | MountFlags::RDONLY,
+                        mountpoint: MountpointOptions {
+                            create: false,
+                            mode: Some(0o700),
+                            owner: Some((Uid::from_raw(1000), Gid::from_raw(1000))),
+                        },
+                        ..MountTarget::default()
+                    },
+                ),
+            ],
+        };
+        let json = snapshot.to_json().unwrap();
+        assert_eq!(MountTableSnapshot::from_json(&json).unwrap(), snapshot);
+    }
+}
@@ -0,0 +1,261 @@
+//! Finding devices by the identifiers used in fstab, see [`MountSource`]
+
+use super::Error;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// Where udev links devices by their identifiers
+const DISK_DIR: &str = "/dev/disk";
+/// How often to look for a device again while waiting for it
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The source of a mount, either a path or a device found by an identifier, as in fstab
+///
+/// Identifiers are looked up in `/dev/disk` when mounting, so they still find the device
+/// when its name changes between boots or hosts. The mount table is keyed by path, so
+/// they are stored the way fstab writes them:
+///
+/// ```
+/// use std::path::PathBuf;
+/// use tiffin::MountSource;
+///
+/// let source = MountSource::Uuid("8f4ad2b5-3d0e-4b8e-9c4a-5c2f6d1e7a90".to_string());
+/// assert_eq!(
+///     PathBuf::from(source.clone()),
+///     PathBuf::from("UUID=8f4ad2b5-3d0e-4b8e-9c4a-5c2f6d1e7a90")
+/// );
+/// assert_eq!(MountSource::parse("UUID=8f4ad2b5-3d0e-4b8e-9c4a-5c2f6d1e7a90"), source);
+/// assert_eq!(MountSource::parse("/dev/sda1"), MountSource::Path("/dev/sda1".into()));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MountSource {
+    Path(PathBuf),
+    /// Filesystem UUID, `UUID=`
+    Uuid(String),
+    /// Filesystem label, `LABEL=`
+    Label(String),
+    /// GPT partition UUID, or the MBR disk signature and partition number, `PARTUUID=`
+    PartUuid(String),
+    /// GPT partition name, `PARTLABEL=`
+    PartLabel(String),
+}
+
+impl MountSource {
+    /// Parse a source as written in fstab, anything without a known prefix being a path
+    pub fn parse(source: &str) -> Self {
+        let Some((key, value)) = source.split_once('=') else {
+            return Self::Path(source.into());
+        };
+        let value = value.to_string();
+        match key {
+            "UUID" => Self::Uuid(value),
+            "LABEL" => Self::Label(value),
+            "PARTUUID" => Self::PartUuid(value),
+            "PARTLABEL" => Self::PartLabel(value),
+            _ => Self::Path(source.into()),
+        }
+    }
+
+    /// The device for the identifier, or the path as is
+    ///
+    /// Fails with [`Error::DeviceNotFound`] if no device has the identifier.
+    pub fn resolve(&self) -> std::io::Result<PathBuf> {
+        self.resolve_timeout(Duration::ZERO)
+    }
+
+    /// Like [`MountSource::resolve`], but waits up to `timeout` for the device to show up,
+    /// e.g. a loop device that was just attached, before udev has linked it
+    pub fn resolve_timeout(&self, timeout: Duration) -> std::io::Result<PathBuf> {
+        // the directory of /dev/disk listing devices by this kind of identifier
+        let (dir, id) = match self {
+            Self::Path(path) => return Ok(path.clone()),
+            Self::Uuid(id) => ("by-uuid", id),
+            Self::Label(id) => ("by-label", id),
+            Self::PartUuid(id) => ("by-partuuid", id),
+            Self::PartLabel(id) => ("by-partlabel", id),
+        };
+        let uuid = matches!(self, Self::Uuid(_) | Self::PartUuid(_));
+        let start = Instant::now();
+        loop {
+            if let Some(device) = find(&Path::new(DISK_DIR).join(dir), id, uuid) {
+                tracing::trace!(source = %self, ?device, "Found device");
+                return Ok(device);
+            }
+            if start.elapsed() >= timeout {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    Error::DeviceNotFound {
+                        device: self.to_string(),
+                    },
+                ));
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// The device linked as `id` in `dir`, ignoring the case of UUIDs, as FAT ones are
+/// listed in upper case but often written in lower case
+fn find(dir: &Path, id: &str, uuid: bool) -> Option<PathBuf> {
+    if matches!(id, "" | "." | "..") {
+        return None;
+    }
+    let name = escape(id);
+    if let Ok(device) = dir.join(&name).canonicalize() {
+        return Some(device);
+    }
+    if !uuid {
+        return None;
+    }
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .find(|entry| entry.file_name().eq_ignore_ascii_case(&name))
+        .and_then(|entry| entry.path().canonicalize().ok())
+}
+
+/// `id` as udev names its link, with the bytes of special characters written as `\xNN`
+fn escape(id: &str) -> String {
+    let mut escaped = String::with_capacity(id.len());
+    for c in id.chars() {
+        if c.is_ascii_alphanumeric() || "#+-.:=@_".contains(c) || !c.is_ascii() {
+            escaped.push(c);
+        } else {
+            escaped.push_str(&format!("\\x{:02x}", c as u8));
+        }
+    }
+    escaped
+}
+
+impl fmt::Display for MountSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Path(path) => write!(f, "{}", path.display()),
+            Self::Uuid(id) => write!(f, "UUID={id}"),
+            Self::Label(id) => write!(f, "LABEL={id}"),
+            Self::PartUuid(id) => write!(f, "PARTUUID={id}"),
+            Self::PartLabel(id) => write!(f, "PARTLABEL={id}"),
+        }
+    }
+}
+
+impl From<MountSource> for PathBuf {
+    fn from(source: MountSource) -> Self {
+        match source {
+            MountSource::Path(path) => path,
+            source => source.to_string().into(),
+        }
+    }
+}
+
+impl From<&Path> for MountSource {
+    fn from(path: &Path) -> Self {
+        match path.to_str() {
+            Some(source) => Self::parse(source),
+            None => Self::Path(path.to_path_buf()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::tempdir::TempDir;
+
+    /// A `/dev/disk` with devices linked by UUID and label, as udev does
+    fn fixture() -> TempDir {
+        let root = TempDir::new("disk");
+        for dir in ["dev", "disk/by-uuid", "disk/by-label"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for device in ["loop0", "vda1"] {
+            std::fs::write(root.join("dev").join(device), "").unwrap();
+        }
+        let link = |target: &str, link: &str| {
+            std::os::unix::fs::symlink(target, root.join("disk").join(link)).unwrap()
+        };
+        link(
+            "../../dev/vda1",
+            "by-uuid/8f4ad2b5-3d0e-4b8e-9c4a-5c2f6d1e7a90",
+        );
+        link("../../dev/loop0", "by-uuid/1A2B-3C4D");
+        link("../../dev/loop0", "by-label/tiffin\\x20test\\x2fdata");
+        root
+    }
+
+    #[test]
+    fn test_find() {
+        let root = fixture();
+        let dev = root.join("dev").canonicalize().unwrap();
+        let (by_uuid, by_label) = (root.join("disk/by-uuid"), root.join("disk/by-label"));
+        assert_eq!(
+            find(&by_uuid, "8f4ad2b5-3d0e-4b8e-9c4a-5c2f6d1e7a90", true),
+            Some(dev.join("vda1"))
+        );
+        // FAT UUIDs are upper case
+        assert_eq!(find(&by_uuid, "1a2b-3c4d", true), Some(dev.join("loop0")));
+        assert_eq!(
+            find(&by_label, "tiffin test/data", false),
+            Some(dev.join("loop0"))
+        );
+        assert_eq!(find(&by_label, "TIFFIN test/data", false), None);
+        assert_eq!(find(&by_uuid, "0000-0000", true), None);
+        assert_eq!(find(&by_uuid, "..", true), None);
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("EFI-SYSTEM_1.0"), "EFI-SYSTEM_1.0");
+        assert_eq!(escape("my disk/2"), "my\\x20disk\\x2f2");
+        assert_eq!(escape("back\\slash"), "back\\x5cslash");
+        assert_eq!(escape("données"), "données");
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            MountSource::parse("LABEL=tiffin test"),
+            MountSource::Label("tiffin test".to_string())
+        );
+        assert_eq!(
+            MountSource::parse("PARTLABEL=root"),
+            MountSource::PartLabel("root".to_string())
+        );
+        assert_eq!(
+            MountSource::parse("PARTUUID=6a2b1e7c-01"),
+            MountSource::PartUuid("6a2b1e7c-01".to_string())
+        );
+        assert_eq!(
+            MountSource::parse("tmpfs:/tmp"),
+            MountSource::Path("tmpfs:/tmp".into())
+        );
+        assert_eq!(
+            MountSource::parse("/srv/a=b"),
+            MountSource::Path("/srv/a=b".into())
+        );
+        for source in ["UUID=1A2B-3C4D", "LABEL=a b", "PARTLABEL=root", "/dev/vda1"] {
+            let path = PathBuf::from(MountSource::parse(source));
+            assert_eq!(MountSource::from(path.as_path()).to_string(), source);
+        }
+    }
+
+    #[test]
+    fn test_not_found() {
+        let source = MountSource::Uuid("tiffin-missing".to_string());
+        let start = Instant::now();
+        let err = crate::Error::from(
+            source
+                .resolve_timeout(Duration::from_millis(120))
+                .unwrap_err(),
+        );
+        assert!(start.elapsed() >= Duration::from_millis(120));
+        assert!(
+            matches!(err, crate::Error::DeviceNotFound { device } if device == "UUID=tiffin-missing")
+        );
+        let path = MountSource::Path("/dev/null".into());
+        assert_eq!(path.resolve().unwrap(), Path::new("/dev/null"));
+    }
+}
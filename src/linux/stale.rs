@@ -0,0 +1,276 @@
+//! Recovering from containers whose process died without unmounting them,
+//! see [`cleanup_stale`]
+
+use super::{
+    danger::is_dangerous, marker, mountinfo, process, progress, ContainerMarker, Error,
+    ProgressEvent, Result,
+};
+use nix::{
+    mount::{umount2, MntFlags},
+    unistd::Pid,
+};
+use std::{
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+/// How [`cleanup_stale`] unmounts what it finds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CleanupOptions {
+    /// Detach mounts that are busy, so they go away once they aren't used anymore.
+    /// Defaults to true
+    pub detach: bool,
+    /// Force mounts that still can't be unmounted, which only some filesystems such as
+    /// NFS support
+    pub force: bool,
+    /// Clean up even if the root is `/` or contains critical host paths such as `/proc`
+    pub force_dangerous: bool,
+    /// Also unmount the mounts of containers whose process, according to their
+    /// [`ContainerMarker`], is still running. Otherwise those are left in place and
+    /// listed in [`CleanupReport::skipped`]
+    pub force_running: bool,
+}
+
+impl Default for CleanupOptions {
+    fn default() -> Self {
+        Self {
+            detach: true,
+            force: false,
+            force_dangerous: false,
+            force_running: false,
+        }
+    }
+}
+
+/// How a mount was unmounted, see [`CleanupOptions`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnmountMethod {
+    Normal,
+    Detach,
+    Force,
+}
+
+/// A mount found by [`cleanup_stale`], and whether it could be unmounted
+#[derive(Debug)]
+pub struct StaleMount {
+    pub mount_point: PathBuf,
+    pub fstype: String,
+    pub source: String,
+    /// Id of the container that mounted it, if its marker lists it
+    pub container: Option<String>,
+    /// How it was unmounted, or the error of the last attempt
+    pub result: std::io::Result<UnmountMethod>,
+}
+
+/// What [`cleanup_stale`] found and unmounted
+#[derive(Debug, Default)]
+pub struct CleanupReport {
+    /// Mounts in the order they were unmounted, deepest first
+    pub mounts: Vec<StaleMount>,
+    /// Mounts left in place because the container that mounted them is still running,
+    /// see [`CleanupOptions::force_running`]
+    pub skipped: Vec<StaleMount>,
+    /// Markers of the containers that left mounts at the root, telling which process
+    /// mounted them and when
+    pub containers: Vec<ContainerMarker>,
+}
+
+impl CleanupReport {
+    /// Whether every mount was unmounted, none being skipped
+    pub fn is_clean(&self) -> bool {
+        self.skipped.is_empty() && self.mounts.iter().all(|mount| mount.result.is_ok())
+    }
+
+    /// Mounts that couldn't be unmounted
+    pub fn failed(&self) -> impl Iterator<Item = &StaleMount> {
+        self.mounts.iter().filter(|mount| mount.result.is_err())
+    }
+
+    /// The marker of the container that mounted `mount`, if known
+    pub fn container_of(&self, mount: &StaleMount) -> Option<&ContainerMarker> {
+        let id = mount.container.as_deref()?;
+        self.containers.iter().find(|marker| marker.id == id)
+    }
+}
+
+/// Unmount everything at or under `root`, such as the mounts of a container whose
+/// process was killed
+///
+/// Mounts are found in `/proc/self/mountinfo` and unmounted deepest first. Busy mounts
+/// are detached or forced according to `options`. Every mount is attempted even if some
+/// fail, so check [`CleanupReport::is_clean`].
+///
+/// The [`ContainerMarker`]s at the root tell which container left each mount. They are
+/// removed once everything is unmounted. Mounts of a container whose process is still
+/// running are skipped, unless [`CleanupOptions::force_running`] is set.
+///
+/// Fails with [`Error::DangerousRoot`] if `root` is `/` or contains critical host paths,
+/// unless [`CleanupOptions::force_dangerous`] is set.
+pub fn cleanup_stale(root: &Path, options: CleanupOptions) -> Result<CleanupReport> {
+    cleanup_stale_with(root, options, |_| {})
+}
+
+/// Like [`cleanup_stale`], calling `progress` as each mount is unmounted, and
+/// with [`ProgressEvent::UnmountRetry`] when it's detached or forced
+pub fn cleanup_stale_with(
+    root: &Path,
+    options: CleanupOptions,
+    mut progress: impl FnMut(ProgressEvent),
+) -> Result<CleanupReport> {
+    let root = root.canonicalize()?;
+    if is_dangerous(&root) && !options.force_dangerous {
+        return Err(Error::DangerousRoot { root });
+    }
+
+    let mut mounts = mountinfo::mounts_under(&root)?;
+    // mounts stacked on the same mountpoint are unmounted from the top
+    mounts.reverse();
+    mounts.sort_by_key(|mount| std::cmp::Reverse(mount.mount_point.components().count()));
+
+    let markers = marker::find(&root);
+    let mut report = CleanupReport::default();
+    for mount in mounts {
+        let span = tracing::debug_span!(
+            "unmount",
+            target = %mount.mount_point.display(),
+            source = %mount.source,
+            fstype = %mount.fstype,
+            duration_ms = tracing::field::Empty,
+            outcome = tracing::field::Empty,
+        );
+        let _entered = span.enter();
+        let start = Instant::now();
+        let owner = markers
+            .iter()
+            .map(|(_, marker)| marker)
+            .find(|marker| marker.contains(&mount.mount_point));
+        if let Some(marker) = owner.filter(|marker| is_running(marker) && !options.force_running) {
+            tracing::warn!(
+                container = %marker.id,
+                pid = marker.pid,
+                "Skipping mount of running container"
+            );
+            progress::finish(&span, start, "skipped");
+            report.skipped.push(StaleMount {
+                mount_point: mount.mount_point,
+                fstype: mount.fstype,
+                source: mount.source,
+                container: Some(marker.id.clone()),
+                result: Err(std::io::Error::new(
+                    std::io::ErrorKind::ResourceBusy,
+                    format!(
+                        "container {} is still running as pid {}",
+                        marker.id, marker.pid
+                    ),
+                )),
+            });
+            continue;
+        }
+        match owner {
+            Some(marker) => tracing::debug!(
+                container = %marker.id,
+                pid = marker.pid,
+                mounted_at = marker.mounted_at,
+                "Unmounting stale mount"
+            ),
+            None => tracing::debug!("Unmounting stale mount"),
+        }
+        progress(ProgressEvent::UnmountStarted {
+            target: mount.mount_point.clone(),
+        });
+        let result = unmount(&mount.mount_point, options, &mut progress);
+        if let Err(e) = &result {
+            tracing::error!(?e, "Failed to unmount");
+        }
+        let outcome = match &result {
+            Ok(UnmountMethod::Normal) => "unmounted",
+            Ok(UnmountMethod::Detach) => "detached",
+            Ok(UnmountMethod::Force) => "forced",
+            Err(_) => "failed",
+        };
+        progress::finish(&span, start, outcome);
+        progress(ProgressEvent::UnmountFinished {
+            target: mount.mount_point.clone(),
+            ok: result.is_ok(),
+        });
+        report.mounts.push(StaleMount {
+            mount_point: mount.mount_point,
+            fstype: mount.fstype,
+            source: mount.source,
+            container: owner.map(|marker| marker.id.clone()),
+            result,
+        });
+    }
+
+    let clean = report.is_clean();
+    for (path, marker) in markers {
+        if clean {
+            if let Err(e) = std::fs::remove_file(&path) {
+                tracing::warn!(?e, ?path, "Failed to remove container marker");
+            }
+        }
+        report.containers.push(marker);
+    }
+    Ok(report)
+}
+
+/// Whether the process that mounted the container of `marker` is still running
+///
+/// A process reusing its pid counts too, erring on the side of leaving mounts alone.
+fn is_running(marker: &ContainerMarker) -> bool {
+    i32::try_from(marker.pid).is_ok_and(|pid| pid > 0 && process::is_running(Pid::from_raw(pid)))
+}
+
+/// Try the methods allowed by `options` in turn, returning the one that worked
+fn unmount(
+    path: &Path,
+    options: CleanupOptions,
+    progress: &mut impl FnMut(ProgressEvent),
+) -> std::io::Result<UnmountMethod> {
+    let mut attempts = 0;
+    let mut attempt = |method, flags| {
+        attempts += 1;
+        if attempts > 1 {
+            progress(ProgressEvent::UnmountRetry {
+                target: path.to_path_buf(),
+                attempt: attempts,
+            });
+        }
+        umount2(path, flags).map(|()| method).inspect_err(|e| {
+            tracing::trace!(?e, ?path, ?method, "Unmounting failed");
+        })
+    };
+    let mut result = attempt(UnmountMethod::Normal, MntFlags::empty());
+    if result.is_err() && options.detach {
+        result = attempt(UnmountMethod::Detach, MntFlags::MNT_DETACH);
+    }
+    if result.is_err() && options.force {
+        result = attempt(UnmountMethod::Force, MntFlags::MNT_FORCE);
+    }
+    result.map_err(Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::tempdir::TempDir;
+
+    #[test]
+    fn test_reports_markers() {
+        let root = TempDir::new("stale-marker");
+        let marker = ContainerMarker::new("gone", Vec::new());
+        let path = marker.write(&root).unwrap();
+
+        let report = cleanup_stale(&root, CleanupOptions::default()).unwrap();
+        assert!(report.mounts.is_empty());
+        assert_eq!(report.containers, [marker]);
+        // nothing is left mounted, so neither is the marker
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_refuses_root() {
+        let err = cleanup_stale(Path::new("/"), CleanupOptions::default()).unwrap_err();
+        assert!(matches!(err, Error::DangerousRoot { .. }));
+    }
+}
@@ -0,0 +1,113 @@
+//! Introspecting a container, see [`crate::Container::status`]
+
+use crate::mountinfo::MountInfo;
+use std::path::{Path, PathBuf};
+
+/// State of a container, see [`crate::Container::status`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerStatus {
+    /// See [`crate::Container::id`]
+    pub id: String,
+    pub root: PathBuf,
+    pub is_mounted: bool,
+    pub is_chrooted: bool,
+    /// Number of mounts in the mount table
+    pub configured_mounts: usize,
+    /// Mounts made by tiffin and not unmounted yet, in mount order
+    pub active_mounts: Vec<ActiveMount>,
+    /// Mounts found by [`crate::Container::adopt`], which tiffin never unmounts
+    pub foreign_mounts: Vec<ActiveMount>,
+}
+
+/// A mount of the container, see [`ContainerStatus`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActiveMount {
+    /// Where it is mounted on the host
+    pub target: PathBuf,
+    /// Filesystem type from `/proc/self/mountinfo`, `None` if stale or unknown
+    pub fstype: Option<String>,
+    /// Whether it is missing from `/proc/self/mountinfo`,
+    /// i.e. something else unmounted it behind tiffin's back
+    pub stale: bool,
+}
+
+/// Cross-check the `mounted` paths against the `mountinfo` of the process, if known
+///
+/// While chrooted into `chroot`, mountinfo shows paths relative to it.
+pub(crate) fn active_mounts(
+    mounted: &[PathBuf],
+    mountinfo: Option<&[MountInfo]>,
+    chroot: Option<&Path>,
+) -> Vec<ActiveMount> {
+    mounted
+        .iter()
+        .map(|target| {
+            let seen_as = match chroot.and_then(|root| target.strip_prefix(root).ok()) {
+                Some(inside) => Path::new("/").join(inside),
+                None => target.clone(),
+            };
+            // the last one is on top when mounts are stacked
+            let info = mountinfo.map(|mountinfo| {
+                mountinfo
+                    .iter()
+                    .rev()
+                    .find(|info| info.mount_point == seen_as)
+            });
+            ActiveMount {
+                target: target.clone(),
+                fstype: info.flatten().map(|info| info.fstype.clone()),
+                stale: info.is_some_and(|info| info.is_none()),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOUNTINFO: &str = "\
+22 1 0:21 / /proc rw,nosuid - proc proc rw
+60 1 0:5 / /tmp/tiffin/proc rw - proc proc rw
+61 1 0:6 / /tmp/tiffin/dev rw - devtmpfs devtmpfs rw
+";
+
+    #[test]
+    fn test_active_mounts() {
+        let mountinfo = crate::mountinfo::parse(MOUNTINFO);
+        let mounted = ["/tmp/tiffin/proc", "/tmp/tiffin/sys"].map(PathBuf::from);
+        assert_eq!(
+            active_mounts(&mounted, Some(&mountinfo), None),
+            [
+                ActiveMount {
+                    target: mounted[0].clone(),
+                    fstype: Some("proc".to_string()),
+                    stale: false,
+                },
+                ActiveMount {
+                    target: mounted[1].clone(),
+                    fstype: None,
+                    stale: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_active_mounts_chrooted() {
+        let mountinfo = crate::mountinfo::parse("61 1 0:6 / /dev rw - devtmpfs devtmpfs rw\n");
+        let mounted = [PathBuf::from("/tmp/tiffin/dev")];
+        let active = active_mounts(&mounted, Some(&mountinfo), Some(Path::new("/tmp/tiffin")));
+        assert_eq!(active[0].target, mounted[0]);
+        assert_eq!(active[0].fstype.as_deref(), Some("devtmpfs"));
+        assert!(!active[0].stale);
+    }
+
+    #[test]
+    fn test_active_mounts_unknown() {
+        let mounted = [PathBuf::from("/tmp/tiffin/dev")];
+        let active = active_mounts(&mounted, None, None);
+        assert_eq!(active[0].fstype, None);
+        assert!(!active[0].stale);
+    }
+}
@@ -0,0 +1,279 @@
+//! Unpacking rootfs archives, see [`unpack_tarball`]
+
+use super::{
+    resolve::{resolve_in_root, Create},
+    Error, Result,
+};
+use nix::{
+    sys::stat::{Mode, SFlag},
+    unistd::{Gid, Uid},
+};
+use std::{
+    io::{BufRead, BufReader, Read},
+    path::{Component, Path, PathBuf},
+};
+use tar::EntryType;
+
+const GZIP: &[u8] = &[0x1f, 0x8b];
+const XZ: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+const ZSTD: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// What [`unpack_tarball`] did, also passed to its progress callback after every entry
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UnpackReport {
+    /// Entries processed so far
+    pub entries: u64,
+    /// Bytes of file contents written so far
+    pub bytes: u64,
+    /// Device nodes and FIFOs that couldn't be created, usually because
+    /// creating device nodes requires root
+    pub skipped_nodes: Vec<PathBuf>,
+}
+
+/// Unpack the tar archive at `archive` into `dest`, which is created if needed
+///
+/// The archive may be plain, or compressed with gzip, xz or zstd, as detected from
+/// its first bytes. Permissions including setuid bits, symlinks, hardlinks and
+/// modification times are kept, as are ownership and device nodes when running as root.
+/// Device nodes that can't be created are skipped and listed in the report.
+///
+/// Entries with `..` in their path, or hardlinks to such paths, fail with
+/// [`Error::PathEscape`], leaving the entries before them unpacked.
+///
+/// `progress` is called after every entry with the report so far.
+pub fn unpack_tarball(
+    archive: impl AsRef<Path>,
+    dest: impl AsRef<Path>,
+    mut progress: impl FnMut(&UnpackReport),
+) -> Result<UnpackReport> {
+    let (archive, dest) = (archive.as_ref(), dest.as_ref());
+    std::fs::create_dir_all(dest)?;
+    let mut archive = tar::Archive::new(decompress(std::fs::File::open(archive)?)?);
+    let root = nix::unistd::geteuid().is_root();
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_ownerships(root);
+    archive.set_overwrite(true);
+
+    let mut report = UnpackReport::default();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        check_path(&path)?;
+        if let Some(link) = entry.link_name()? {
+            if entry.header().entry_type() == EntryType::Link {
+                check_path(&link)?;
+            }
+        }
+
+        match entry.header().entry_type() {
+            kind @ (EntryType::Char | EntryType::Block | EntryType::Fifo) => {
+                if let Err(e) = make_node(dest, &path, entry.header(), kind, root) {
+                    tracing::warn!(?e, ?path, "Failed to create device node, skipping");
+                    report.skipped_nodes.push(path);
+                }
+            }
+            kind => {
+                entry.unpack_in(dest)?;
+                if kind.is_file() {
+                    report.bytes += entry.size();
+                }
+            }
+        }
+        report.entries += 1;
+        progress(&report);
+    }
+    Ok(report)
+}
+
+/// Count the entries of the tar archive at `archive`, decompressing it if needed
+pub(crate) fn count_entries(archive: impl AsRef<Path>) -> Result<u64> {
+    let mut archive = tar::Archive::new(decompress(std::fs::File::open(archive)?)?);
+    let mut count = 0;
+    for entry in archive.entries()? {
+        entry?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Wrap `file` in the decoder matching its magic bytes
+fn decompress(file: std::fs::File) -> std::io::Result<Box<dyn Read>> {
+    let mut reader = BufReader::new(file);
+    let magic = reader.fill_buf()?;
+    Ok(if magic.starts_with(GZIP) {
+        Box::new(flate2::bufread::MultiGzDecoder::new(reader))
+    } else if magic.starts_with(XZ) {
+        Box::new(xz2::bufread::XzDecoder::new(reader))
+    } else if magic.starts_with(ZSTD) {
+        Box::new(zstd::Decoder::with_buffer(reader)?)
+    } else {
+        Box::new(reader)
+    })
+}
+
+fn check_path(path: &Path) -> Result<()> {
+    if path.components().any(|c| c == Component::ParentDir) {
+        return Err(Error::PathEscape {
+            target: path.to_path_buf(),
+        });
+    }
+    Ok(())
+}
+
+/// Create the device node or FIFO of `header` at `path` inside `dest`
+fn make_node(
+    dest: &Path,
+    path: &Path,
+    header: &tar::Header,
+    kind: EntryType,
+    root: bool,
+) -> Result<()> {
+    let parent = path.parent().unwrap_or(Path::new(""));
+    let name = path.file_name().ok_or_else(|| Error::PathEscape {
+        target: path.to_path_buf(),
+    })?;
+    let target = resolve_in_root(dest, parent, Create::Dir)?.join(name);
+    let sflag = match kind {
+        EntryType::Char => SFlag::S_IFCHR,
+        EntryType::Block => SFlag::S_IFBLK,
+        _ => SFlag::S_IFIFO,
+    };
+    let dev = nix::sys::stat::makedev(
+        header.device_major()?.unwrap_or_default().into(),
+        header.device_minor()?.unwrap_or_default().into(),
+    );
+    let mode = Mode::from_bits_truncate(header.mode()? & 0o7777);
+    match std::fs::remove_file(&target) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    nix::sys::stat::mknod(&target, sflag, mode, dev)?;
+    if root {
+        nix::unistd::chown(
+            &target,
+            Some(Uid::from_raw(header.uid()? as libc::uid_t)),
+            Some(Gid::from_raw(header.gid()? as libc::gid_t)),
+        )?;
+    }
+    // mknod applies the umask
+    nix::sys::stat::fchmodat(
+        None,
+        &target,
+        mode,
+        nix::sys::stat::FchmodatFlags::FollowSymlink,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::tempdir::TempDir;
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+    fn header(path: &str, kind: EntryType, mode: u32, size: u64) -> tar::Header {
+        let mut header = tar::Header::new_gnu();
+        header.set_path(path).unwrap();
+        header.set_entry_type(kind);
+        header.set_mode(mode);
+        header.set_size(size);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(0);
+        header.set_cksum();
+        header
+    }
+
+    /// A gzipped rootfs with a setuid binary, symlinks, a hardlink and a device node
+    fn fixture(path: &Path) {
+        let file = std::fs::File::create(path).unwrap();
+        let gzip = flate2::write::GzEncoder::new(file, flate2::Compression::fast());
+        let mut builder = tar::Builder::new(gzip);
+        builder
+            .append(&header("usr/bin/", EntryType::Directory, 0o755, 0), &[][..])
+            .unwrap();
+        let contents = b"#!/bin/sh\necho su\n";
+        builder
+            .append(
+                &header(
+                    "usr/bin/su",
+                    EntryType::Regular,
+                    0o4755,
+                    contents.len() as u64,
+                ),
+                &contents[..],
+            )
+            .unwrap();
+        let mut link = header("bin", EntryType::Symlink, 0o777, 0);
+        builder.append_link(&mut link, "bin", "usr/bin").unwrap();
+        let mut absolute = header("usr/sbin", EntryType::Symlink, 0o777, 0);
+        builder
+            .append_link(&mut absolute, "usr/sbin", "/usr/bin")
+            .unwrap();
+        let mut hardlink = header("usr/bin/sudo", EntryType::Link, 0o4755, 0);
+        builder
+            .append_link(&mut hardlink, "usr/bin/sudo", "usr/bin/su")
+            .unwrap();
+        let mut null = header("dev/null", EntryType::Char, 0o666, 0);
+        null.set_device_major(1).unwrap();
+        null.set_device_minor(3).unwrap();
+        null.set_cksum();
+        builder.append(&null, &[][..]).unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[test]
+    fn test_unpack() {
+        let base = TempDir::new("tarball");
+        let (archive, dest) = (base.join("rootfs.tar.gz"), base.join("rootfs"));
+        fixture(&archive);
+
+        assert_eq!(count_entries(&archive).unwrap(), 6);
+        let mut calls = 0;
+        let report = unpack_tarball(&archive, &dest, |_| calls += 1).unwrap();
+        assert_eq!(report.entries, 6);
+        assert_eq!(calls, 6);
+        assert_eq!(report.bytes, 18);
+
+        let su = std::fs::metadata(dest.join("usr/bin/su")).unwrap();
+        assert_eq!(su.mode() & 0o7777, 0o4755);
+        assert_eq!(
+            std::fs::read_link(dest.join("bin")).unwrap(),
+            Path::new("usr/bin")
+        );
+        assert_eq!(
+            std::fs::read_link(dest.join("usr/sbin")).unwrap(),
+            Path::new("/usr/bin")
+        );
+        let sudo = std::fs::metadata(dest.join("usr/bin/sudo")).unwrap();
+        assert_eq!(sudo.ino(), su.ino());
+
+        match std::fs::symlink_metadata(dest.join("dev/null")) {
+            Ok(null) => {
+                assert!(null.file_type().is_char_device());
+                assert_eq!(null.rdev(), nix::sys::stat::makedev(1, 3));
+            }
+            // not root
+            Err(_) => assert_eq!(report.skipped_nodes, [PathBuf::from("dev/null")]),
+        }
+    }
+
+    #[test]
+    fn test_unpack_traversal() {
+        let base = TempDir::new("tarball-traversal");
+        let archive = base.join("evil.tar");
+        let mut builder = tar::Builder::new(std::fs::File::create(&archive).unwrap());
+        // set_path refuses `..`, so write the name directly
+        let mut evil = header("placeholder", EntryType::Regular, 0o644, 4);
+        let name = b"../evil";
+        evil.as_old_mut().name[..name.len()].copy_from_slice(name);
+        evil.as_old_mut().name[name.len()..].fill(0);
+        evil.set_cksum();
+        builder.append(&evil, &b"evil"[..]).unwrap();
+        builder.into_inner().unwrap();
+
+        let err = unpack_tarball(&archive, base.join("rootfs"), |_| {}).unwrap_err();
+        assert!(matches!(err, Error::PathEscape { target } if target == Path::new("../evil")));
+        assert!(!base.join("evil").exists());
+    }
+}
@@ -0,0 +1,58 @@
+//! Scratch directories for tests
+
+use super::{generate_id, mountinfo};
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+/// A new directory in the temporary directory, removed with everything in it when
+/// dropped, also when the test panics
+///
+/// Its name ends with a random id, so neither tests nor test runs in parallel share one.
+#[derive(Debug)]
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Create a directory named after `name`
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("tiffin-{name}-{}", generate_id()));
+        std::fs::create_dir(&path).unwrap();
+        // as mount points are listed, so they can be compared when dropped
+        Self(path.canonicalize().unwrap())
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        // a test failing halfway may leave a mount behind, such as a bind of the host,
+        // which must never be removed through
+        match mountinfo::mounts_under(&self.0) {
+            Ok(mounts) if mounts.is_empty() => {}
+            result => {
+                eprintln!(
+                    "Leaving {} behind, as it may have mounts: {result:?}",
+                    self.0.display()
+                );
+                return;
+            }
+        }
+        if let Err(e) = std::fs::remove_dir_all(&self.0) {
+            eprintln!("Failed to remove {}: {e}", self.0.display());
+        }
+    }
+}
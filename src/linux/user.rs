@@ -0,0 +1,228 @@
+use super::resolve::{self, Create};
+use crate::{Error, Result};
+use nix::unistd::{Gid, Uid};
+use std::path::{Path, PathBuf};
+
+/// A user entry resolved from the container's own user database
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    pub name: String,
+    pub uid: Uid,
+    /// Primary group of the user
+    pub gid: Gid,
+    pub home: PathBuf,
+    pub shell: PathBuf,
+    /// Supplementary groups, collected from `etc/group` membership lists
+    pub groups: Vec<Gid>,
+}
+
+impl User {
+    /// Look up a user in the `etc/passwd` and `etc/group` files of `root`
+    ///
+    /// This reads the files directly instead of going through `getpwnam`,
+    /// which would consult the host's NSS configuration rather than the container's.
+    /// Symlinks to them are resolved inside `root`, never to the host's files.
+    pub fn lookup(root: &Path, name: &str) -> Result<Self> {
+        let passwd_file = root.join("etc/passwd");
+        let group_file = root.join("etc/group");
+
+        let passwd = read_in_root(root, "etc/passwd")?;
+        let mut user = parse_passwd(&passwd, name).ok_or_else(|| Error::UserNotFound {
+            user: name.to_string(),
+            file: passwd_file,
+        })?;
+
+        // A missing group file just means there are no supplementary groups
+        match read_in_root(root, "etc/group") {
+            Ok(group) => user.groups = parse_groups(&group, name),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                tracing::debug!(?group_file, "No group file in container");
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        Ok(user)
+    }
+
+    /// All groups the user should be a member of, primary group first
+    pub fn all_groups(&self) -> Vec<Gid> {
+        let mut groups = vec![self.gid];
+        groups.extend(self.groups.iter().filter(|gid| **gid != self.gid));
+        groups
+    }
+}
+
+/// Read the file at `path` inside `root`
+fn read_in_root(root: &Path, path: &str) -> std::io::Result<String> {
+    let resolved = resolve::resolve(root, Path::new(path), Create::Nothing)?;
+    std::fs::read_to_string(resolved.fd_path())
+}
+
+/// Find `name` in the contents of a passwd file
+///
+/// Malformed lines are skipped rather than treated as fatal.
+fn parse_passwd(contents: &str, name: &str) -> Option<User> {
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .find_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() != 7 {
+                tracing::debug!(line, "Skipping malformed passwd line");
+                return None;
+            }
+            if fields[0] != name {
+                return None;
+            }
+            let (Ok(uid), Ok(gid)) = (fields[2].parse(), fields[3].parse()) else {
+                tracing::debug!(line, "Skipping passwd line with invalid ids");
+                return None;
+            };
+            Some(User {
+                name: name.to_string(),
+                uid: Uid::from_raw(uid),
+                gid: Gid::from_raw(gid),
+                home: PathBuf::from(fields[5]),
+                shell: PathBuf::from(fields[6]),
+                groups: Vec::new(),
+            })
+        })
+}
+
+/// Collect the groups listing `name` as a member in the contents of a group file
+fn parse_groups(contents: &str, name: &str) -> Vec<Gid> {
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() != 4 {
+                tracing::debug!(line, "Skipping malformed group line");
+                return None;
+            }
+            let gid = fields[2].parse().ok()?;
+            fields[3]
+                .split(',')
+                .any(|member| member.trim() == name)
+                .then(|| Gid::from_raw(gid))
+        })
+        .collect()
+}
+
+/// Run `f` with the effective uid, gid and supplementary groups switched
+///
+/// The previous identity is restored after `f` returns.
+pub(crate) fn with_identity<F, T>(uid: Uid, gid: Gid, groups: &[Gid], f: F) -> std::io::Result<T>
+where
+    F: FnOnce() -> T,
+{
+    let saved_uid = nix::unistd::geteuid();
+    let saved_gid = nix::unistd::getegid();
+    let saved_groups = nix::unistd::getgroups()?;
+
+    let restore = || -> std::io::Result<()> {
+        nix::unistd::seteuid(saved_uid)?;
+        nix::unistd::setegid(saved_gid)?;
+        nix::unistd::setgroups(&saved_groups)?;
+        Ok(())
+    };
+
+    // groups and gid must be changed while we're still privileged
+    let switch = || -> std::io::Result<()> {
+        nix::unistd::setgroups(groups)?;
+        nix::unistd::setegid(gid)?;
+        nix::unistd::seteuid(uid)?;
+        Ok(())
+    };
+
+    if let Err(e) = switch() {
+        restore()?;
+        return Err(e);
+    }
+
+    let ret = f();
+    restore()?;
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::tempdir::TempDir;
+
+    const PASSWD: &str = "\
+root:x:0:0:root:/root:/bin/bash
+# a comment
+this line is garbage
+broken:x:notanumber:0::/:/bin/sh
+builder:x:1000:1000:Builder:/builddir:/bin/bash
+";
+
+    const GROUP: &str = "\
+root:x:0:
+wheel:x:10:root,builder
+mock:x:135:builder
+garbage
+builder:x:1000:
+";
+
+    #[test]
+    fn test_parse_passwd() {
+        let user = parse_passwd(PASSWD, "builder").unwrap();
+        assert_eq!(user.uid, Uid::from_raw(1000));
+        assert_eq!(user.gid, Gid::from_raw(1000));
+        assert_eq!(user.home, PathBuf::from("/builddir"));
+        assert_eq!(user.shell, PathBuf::from("/bin/bash"));
+        assert!(parse_passwd(PASSWD, "broken").is_none());
+        assert!(parse_passwd(PASSWD, "nobody").is_none());
+    }
+
+    #[test]
+    fn test_parse_groups() {
+        assert_eq!(
+            parse_groups(GROUP, "builder"),
+            vec![Gid::from_raw(10), Gid::from_raw(135)]
+        );
+        assert_eq!(parse_groups(GROUP, "root"), vec![Gid::from_raw(10)]);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let root = TempDir::new("user");
+        std::fs::create_dir_all(root.join("etc")).unwrap();
+        std::fs::write(root.join("etc/passwd"), PASSWD).unwrap();
+        std::fs::write(root.join("etc/group"), GROUP).unwrap();
+
+        let user = User::lookup(&root, "builder").unwrap();
+        assert_eq!(
+            user.all_groups(),
+            vec![Gid::from_raw(1000), Gid::from_raw(10), Gid::from_raw(135)]
+        );
+
+        match User::lookup(&root, "nobody") {
+            Err(Error::UserNotFound { user, file }) => {
+                assert_eq!(user, "nobody");
+                assert_eq!(file, root.join("etc/passwd"));
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_lookup_symlinks() {
+        let root = TempDir::new("user-links");
+        std::fs::create_dir_all(root.join("usr/etc")).unwrap();
+        std::fs::write(root.join("usr/etc/passwd"), PASSWD).unwrap();
+        std::os::unix::fs::symlink("/usr/etc", root.join("etc")).unwrap();
+
+        // the absolute symlink points into the image, not the host's /usr/etc
+        let user = User::lookup(&root, "builder").unwrap();
+        assert_eq!(user.uid, Uid::from_raw(1000));
+        assert!(user.groups.is_empty());
+
+        // resolved inside the root, this is a loop rather than the host's /etc
+        std::fs::remove_file(root.join("etc")).unwrap();
+        std::os::unix::fs::symlink("/etc", root.join("etc")).unwrap();
+        assert!(User::lookup(&root, "root").is_err());
+    }
+}
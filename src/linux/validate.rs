@@ -0,0 +1,293 @@
+//! Checking the mount table before mounting anything, see [`crate::MountTable::validate`]
+
+use crate::{MountSource, MountTarget};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use sys_mount::MountFlags;
+
+/// A problem with an entry of the mount table, found by [`crate::MountTable::validate`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountDiagnostic {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub problem: MountProblem,
+}
+
+/// What is wrong with a mount, see [`MountDiagnostic`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MountProblem {
+    /// The source of a bind mount, or the device of a filesystem, doesn't exist on the host
+    MissingSource,
+    /// The source exists but can't be accessed by the calling user
+    UnreadableSource(String),
+    /// The filesystem needs a block device, or an image file to attach to a loop device,
+    /// but the source is something else
+    NotBlockDevice { fstype: String, kind: &'static str },
+    /// The filesystem is neither supported by the running kernel nor provided by a module
+    UnknownFilesystem(String),
+    /// The mount has SELinux context options, but SELinux is disabled on the host
+    /// and the mount table is strict about it
+    SelinuxDisabled,
+    /// The source doesn't exist yet, but mounting waits up to this long for it,
+    /// see [`crate::MountTarget::wait_for_source`]
+    PendingSource(Duration),
+}
+
+impl MountProblem {
+    /// Whether this isn't a problem yet, as the mount waits for its source
+    pub fn is_pending(&self) -> bool {
+        matches!(self, Self::PendingSource(_))
+    }
+}
+
+impl fmt::Display for MountDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (source, target) = (self.source.display(), self.target.display());
+        match &self.problem {
+            MountProblem::MissingSource => {
+                write!(f, "{source} (mounted on {target}) does not exist on the host")
+            }
+            MountProblem::UnreadableSource(reason) => {
+                write!(f, "{source} (mounted on {target}) can't be accessed: {reason}")
+            }
+            MountProblem::NotBlockDevice { fstype, kind } => write!(
+                f,
+                "{source} (mounted on {target}) is a {kind}, but {fstype} needs a block device or a filesystem image"
+            ),
+            MountProblem::UnknownFilesystem(fstype) => write!(
+                f,
+                "filesystem {fstype:?} (mounted on {target}) is not in /proc/filesystems, and no kernel module provides it"
+            ),
+            MountProblem::SelinuxDisabled => write!(
+                f,
+                "{source} (mounted on {target}) has SELinux context options, but SELinux is disabled on the host"
+            ),
+            MountProblem::PendingSource(timeout) => write!(
+                f,
+                "{source} (mounted on {target}) does not exist on the host yet, waiting up to {timeout:?} for it"
+            ),
+        }
+    }
+}
+
+/// Filesystems the running kernel supports
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Filesystems {
+    /// Registered filesystems, and whether they need a device
+    kernel: HashMap<String, bool>,
+    /// Filesystems that can be loaded from a module
+    modules: HashSet<String>,
+}
+
+impl Filesystems {
+    /// Read the host's filesystems, or `None` if `/proc/filesystems` isn't available
+    pub fn host() -> Option<Self> {
+        let kernel = std::fs::read_to_string("/proc/filesystems").ok()?;
+        let release = std::fs::read_to_string("/proc/sys/kernel/osrelease").unwrap_or_default();
+        let aliases = Path::new("/lib/modules")
+            .join(release.trim())
+            .join("modules.alias");
+        Some(Self::parse(
+            &kernel,
+            &std::fs::read_to_string(aliases).unwrap_or_default(),
+        ))
+    }
+
+    /// Parse `/proc/filesystems` and the `modules.alias` of the running kernel
+    pub fn parse(kernel: &str, aliases: &str) -> Self {
+        let kernel = kernel
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .map(|(flags, name)| (name.trim().to_string(), flags != "nodev"))
+            .collect();
+        // modules register their filesystems as `alias fs-<name> <module>`
+        let modules = aliases
+            .lines()
+            .filter_map(|line| line.strip_prefix("alias fs-"))
+            .filter_map(|alias| alias.split_whitespace().next())
+            .map(String::from)
+            .collect();
+        Self { kernel, modules }
+    }
+}
+
+/// Check a single entry of the mount table
+///
+/// Filesystems are only checked with `filesystems`, when known.
+pub(crate) fn check(
+    source: &Path,
+    mount: &MountTarget,
+    filesystems: Option<&Filesystems>,
+) -> Option<MountProblem> {
+    if mount.flags.contains(MountFlags::BIND) {
+        // anything can be bind mounted, be it a directory, a file, a socket or a device
+        return check_source(source).err();
+    }
+    let (fstype, filesystems) = (mount.fstype.as_deref()?, filesystems?);
+    // devices given by identifier may only show up when mounting
+    let identifier = !matches!(MountSource::from(source), MountSource::Path(_));
+    match filesystems.kernel.get(fstype) {
+        // the source of virtual filesystems is only a name
+        Some(false) => None,
+        Some(true) if identifier => None,
+        Some(true) => check_source(source)
+            .and_then(|file_type| match file_type {
+                t if t.is_block_device() || t.is_file() => Ok(()),
+                t => Err(MountProblem::NotBlockDevice {
+                    fstype: fstype.to_string(),
+                    kind: kind(t),
+                }),
+            })
+            .err(),
+        // whether it needs a device isn't known until the module is loaded
+        None if filesystems.modules.contains(fstype) => None,
+        None => Some(MountProblem::UnknownFilesystem(fstype.to_string())),
+    }
+}
+
+fn check_source(source: &Path) -> Result<std::fs::FileType, MountProblem> {
+    let metadata = match std::fs::metadata(source) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(MountProblem::MissingSource)
+        }
+        Err(e) => return Err(MountProblem::UnreadableSource(e.to_string())),
+    };
+    nix::unistd::access(source, nix::unistd::AccessFlags::R_OK)
+        .map_err(|e| MountProblem::UnreadableSource(std::io::Error::from(e).to_string()))?;
+    Ok(metadata.file_type())
+}
+
+fn kind(file_type: std::fs::FileType) -> &'static str {
+    if file_type.is_dir() {
+        "directory"
+    } else if file_type.is_char_device() {
+        "character device"
+    } else if file_type.is_socket() {
+        "socket"
+    } else if file_type.is_fifo() {
+        "FIFO"
+    } else {
+        "special file"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::tempdir::TempDir;
+    use std::os::unix::fs::PermissionsExt;
+
+    const PROC_FILESYSTEMS: &str = "nodev\tsysfs\nnodev\ttmpfs\nnodev\tproc\n\text4\n\tvfat\n";
+    const MODULES_ALIAS: &str = "alias fs-btrfs btrfs\nalias devname:btrfs-control btrfs\n";
+
+    fn fs(fstype: &str) -> MountTarget {
+        MountTarget {
+            target: "mnt".into(),
+            fstype: Some(fstype.to_string()),
+            ..MountTarget::default()
+        }
+    }
+
+    fn bind() -> MountTarget {
+        MountTarget {
+            target: "mnt".into(),
+            flags: MountFlags::BIND,
+            ..MountTarget::default()
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let filesystems = Filesystems::parse(PROC_FILESYSTEMS, MODULES_ALIAS);
+        assert_eq!(filesystems.kernel.get("proc"), Some(&false));
+        assert_eq!(filesystems.kernel.get("ext4"), Some(&true));
+        assert_eq!(filesystems.modules, HashSet::from(["btrfs".to_string()]));
+    }
+
+    #[test]
+    fn test_missing_source() {
+        let missing = Path::new("/nonexistent/tiffin");
+        assert_eq!(
+            check(missing, &bind(), None),
+            Some(MountProblem::MissingSource)
+        );
+        let filesystems = Filesystems::parse(PROC_FILESYSTEMS, "");
+        assert_eq!(
+            check(missing, &fs("ext4"), Some(&filesystems)),
+            Some(MountProblem::MissingSource)
+        );
+        // virtual filesystems don't need a source
+        assert_eq!(check(missing, &fs("tmpfs"), Some(&filesystems)), None);
+        // devices given by identifier are looked up when mounting
+        let uuid = Path::new("UUID=0000-0000");
+        assert_eq!(check(uuid, &fs("vfat"), Some(&filesystems)), None);
+        assert_eq!(
+            check(uuid, &fs("ext5"), Some(&filesystems)),
+            Some(MountProblem::UnknownFilesystem("ext5".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_unreadable_source() {
+        if nix::unistd::geteuid().is_root() {
+            // root can read anything
+            return;
+        }
+        let dir = TempDir::new("unreadable");
+        let source = dir.join("source");
+        std::fs::create_dir(&source).unwrap();
+        std::fs::set_permissions(&source, std::fs::Permissions::from_mode(0o000)).unwrap();
+        let problem = check(&source, &bind(), None);
+        std::fs::set_permissions(&source, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(matches!(problem, Some(MountProblem::UnreadableSource(_))));
+    }
+
+    #[test]
+    fn test_not_block_device() {
+        let filesystems = Filesystems::parse(PROC_FILESYSTEMS, "");
+        assert_eq!(
+            check(&std::env::temp_dir(), &fs("ext4"), Some(&filesystems)),
+            Some(MountProblem::NotBlockDevice {
+                fstype: "ext4".to_string(),
+                kind: "directory"
+            })
+        );
+        // attached to a loop device when mounted
+        let dir = TempDir::new("image");
+        let image = dir.join("image");
+        std::fs::write(&image, "").unwrap();
+        assert_eq!(check(&image, &fs("ext4"), Some(&filesystems)), None);
+    }
+
+    #[test]
+    fn test_unknown_filesystem() {
+        let filesystems = Filesystems::parse(PROC_FILESYSTEMS, MODULES_ALIAS);
+        let source = std::env::temp_dir();
+        assert_eq!(
+            check(&source, &fs("ext5"), Some(&filesystems)),
+            Some(MountProblem::UnknownFilesystem("ext5".to_string()))
+        );
+        assert_eq!(check(&source, &fs("btrfs"), Some(&filesystems)), None);
+        // nothing is known without /proc/filesystems
+        assert_eq!(check(&source, &fs("ext5"), None), None);
+    }
+
+    #[test]
+    fn test_display() {
+        let diagnostic = MountDiagnostic {
+            source: "/dev/sdz1".into(),
+            target: "boot".into(),
+            problem: MountProblem::MissingSource,
+        };
+        assert_eq!(
+            diagnostic.to_string(),
+            "/dev/sdz1 (mounted on boot) does not exist on the host"
+        );
+    }
+}
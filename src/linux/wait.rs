@@ -0,0 +1,130 @@
+//! Waiting for the source of a mount to show up, see [`crate::MountTarget::wait_for_source`]
+
+use super::Error;
+use std::{
+    ffi::CString,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::ffi::OsStrExt,
+    },
+    path::Path,
+    time::{Duration, Instant},
+};
+
+/// How often to look for the path without inotify
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How often to look for the path anyway with inotify, which only sees its parent
+/// directory, in case it appears some other way, such as through a symlink
+const RECHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Wait up to `timeout` for `path` to exist
+///
+/// Changes to its parent directory are watched with inotify, or if that isn't possible,
+/// e.g. as the parent doesn't exist either, the path is polled. Fails with
+/// [`Error::SourceTimeout`] if it's still missing by then.
+pub(crate) fn wait_for_path(path: &Path, timeout: Duration) -> std::io::Result<()> {
+    let start = Instant::now();
+    let watch = path.parent().and_then(|parent| match Watch::new(parent) {
+        Ok(watch) => Some(watch),
+        Err(e) => {
+            tracing::trace!(?e, ?parent, "Can't watch the directory, polling instead");
+            None
+        }
+    });
+    loop {
+        if path.exists() {
+            return Ok(());
+        }
+        let left = timeout.saturating_sub(start.elapsed());
+        if left.is_zero() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                Error::SourceTimeout {
+                    path: path.to_path_buf(),
+                    timeout,
+                },
+            ));
+        }
+        tracing::trace!(?path, ?left, "Waiting for the mount source");
+        match &watch {
+            Some(watch) => watch.wait(left.min(RECHECK_INTERVAL)),
+            None => std::thread::sleep(left.min(POLL_INTERVAL)),
+        }
+    }
+}
+
+/// An inotify watch of entries created in a directory
+struct Watch(OwnedFd);
+
+impl Watch {
+    fn new(dir: &Path) -> std::io::Result<Self> {
+        let dir = CString::new(dir.as_os_str().as_bytes())?;
+        // SAFETY: the fd is owned right after creation, and dir is a valid C string
+        unsafe {
+            let fd = libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC);
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let fd = OwnedFd::from_raw_fd(fd);
+            let mask = libc::IN_CREATE | libc::IN_MOVED_TO | libc::IN_ATTRIB;
+            if libc::inotify_add_watch(fd.as_raw_fd(), dir.as_ptr(), mask) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(Self(fd))
+        }
+    }
+
+    /// Wait up to `timeout` for an entry to be created in the directory
+    fn wait(&self, timeout: Duration) {
+        let mut fds = [libc::pollfd {
+            fd: self.0.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        }];
+        // rounded up, so this never returns right away until the time is up
+        let ms = timeout.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32;
+        // SAFETY: fds is a valid array of one pollfd
+        if unsafe { libc::poll(fds.as_mut_ptr(), 1, ms) } <= 0 {
+            return;
+        }
+        // only whether something happened matters, the path is checked again
+        let mut events = [0u8; 4096];
+        // SAFETY: events is a valid buffer of its length, and the fd doesn't block
+        while unsafe { libc::read(self.0.as_raw_fd(), events.as_mut_ptr().cast(), events.len()) }
+            > 0
+        {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::tempdir::TempDir;
+
+    #[test]
+    fn test_wait_for_path() {
+        let dir = TempDir::new("wait");
+        let path = dir.join("source");
+        let created = path.clone();
+        let creator = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            std::fs::write(created, "").unwrap();
+        });
+        let start = Instant::now();
+        wait_for_path(&path, Duration::from_secs(1)).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        creator.join().unwrap();
+
+        let missing = dir.join("missing");
+        let err = Error::from(wait_for_path(&missing, Duration::from_millis(50)).unwrap_err());
+        assert!(matches!(
+            err,
+            Error::SourceTimeout { path, timeout }
+                if path == missing && timeout == Duration::from_millis(50)
+        ));
+        // without a parent to watch
+        let err = wait_for_path(&dir.join("a/b"), Duration::from_millis(50)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,107 @@
+//! Command line tools around tiffin containers
+
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: tiffin cleanup [OPTIONS] <ROOT>
+
+Unmount everything left mounted at or under ROOT, deepest first
+
+Options:
+      --no-detach        Don't detach busy mounts
+      --force            Force mounts that still can't be unmounted
+      --force-dangerous  Clean up even if ROOT contains critical host paths
+      --force-running    Also unmount containers whose process is still running
+  -h, --help             Print this help";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.split_first() {
+        Some((command, args)) if command == "cleanup" => cleanup(args),
+        Some((help, _)) if help == "-h" || help == "--help" => {
+            println!("{USAGE}");
+            ExitCode::SUCCESS
+        }
+        _ => {
+            eprintln!("{USAGE}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn cleanup(args: &[String]) -> ExitCode {
+    let mut options = tiffin::CleanupOptions::default();
+    let mut root = None;
+    for arg in args {
+        match arg.as_str() {
+            "--no-detach" => options.detach = false,
+            "--force" => options.force = true,
+            "--force-dangerous" => options.force_dangerous = true,
+            "--force-running" => options.force_running = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            _ if root.is_none() && !arg.starts_with('-') => root = Some(arg),
+            _ => {
+                eprintln!("unexpected argument {arg}\n\n{USAGE}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let Some(root) = root else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+
+    let report = match tiffin::cleanup_stale(std::path::Path::new(root), options) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("error: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if report.mounts.is_empty() && report.skipped.is_empty() {
+        println!("nothing is mounted under {root}");
+    }
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    for marker in &report.containers {
+        let ago = now.saturating_sub(marker.mounted_at);
+        println!(
+            "container {} was mounted by pid {} {ago}s ago",
+            marker.id, marker.pid
+        );
+    }
+    for mount in &report.mounts {
+        let mount_point = mount.mount_point.display();
+        match &mount.result {
+            Ok(method) => match &mount.container {
+                Some(id) => {
+                    println!("unmounted {mount_point} ({method:?}), left by container {id}")
+                }
+                None => println!("unmounted {mount_point} ({method:?})"),
+            },
+            Err(e) => eprintln!("failed to unmount {mount_point}: {e}"),
+        }
+    }
+    for mount in &report.skipped {
+        let mount_point = mount.mount_point.display();
+        if let Err(e) = &mount.result {
+            eprintln!("skipped {mount_point}: {e}");
+        }
+    }
+    if report.is_clean() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn cleanup(_args: &[String]) -> ExitCode {
+    eprintln!("error: {}", tiffin::Error::UnsupportedPlatform);
+    ExitCode::FAILURE
+}